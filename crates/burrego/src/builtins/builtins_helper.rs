@@ -1,4 +1,4 @@
-use super::{get_builtins, BuiltinFunctionsMap};
+use super::{get_builtins, BuiltinContext, BuiltinFunctionsMap};
 use crate::errors::{BurregoError, Result};
 
 use lazy_static::lazy_static;
@@ -21,6 +21,7 @@ impl BuiltinsHelper {
         &self,
         builtin_name: &str,
         args: &[serde_json::Value],
+        ctx: &BuiltinContext,
     ) -> Result<serde_json::Value> {
         let builtin_fn = self
             .builtins
@@ -34,6 +35,7 @@ impl BuiltinsHelper {
                 .as_str(),
             "invoking builtin"
         );
-        builtin_fn(args)
+
+        builtin_fn.call(args, ctx)
     }
 }
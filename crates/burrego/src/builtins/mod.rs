@@ -1,3 +1,4 @@
+use crate::clock::Clock;
 use crate::errors::Result;
 use std::collections::HashMap;
 
@@ -13,53 +14,110 @@ mod time;
 
 pub(crate) use builtins_helper::BUILTINS_HELPER;
 
-pub(crate) type BuiltinFunctionsMap =
-    HashMap<&'static str, fn(&[serde_json::Value]) -> Result<serde_json::Value>>;
+/// The context of the evaluation, available to the builtins depending on it
+pub struct BuiltinContext<'a> {
+    /// The clock to be used by the builtins reading the current time
+    pub clock: &'a dyn Clock,
+}
+
+/// A builtin function implemented by the host
+#[derive(Clone, Copy)]
+pub enum BuiltinFunction {
+    /// A builtin whose result depends only on its arguments
+    Pure(fn(&[serde_json::Value]) -> Result<serde_json::Value>),
+    /// A builtin depending also on the context of the evaluation, like the current time
+    Contextual(fn(&[serde_json::Value], &BuiltinContext) -> Result<serde_json::Value>),
+}
+
+impl BuiltinFunction {
+    pub(crate) fn call(
+        &self,
+        args: &[serde_json::Value],
+        ctx: &BuiltinContext,
+    ) -> Result<serde_json::Value> {
+        match self {
+            BuiltinFunction::Pure(builtin_fn) => builtin_fn(args),
+            BuiltinFunction::Contextual(builtin_fn) => builtin_fn(args, ctx),
+        }
+    }
+}
+
+pub(crate) type BuiltinFunctionsMap = HashMap<&'static str, BuiltinFunction>;
 
 pub fn get_builtins() -> BuiltinFunctionsMap {
     let mut functions: BuiltinFunctionsMap = HashMap::new();
 
     // debugging
-    functions.insert("trace", debugging::trace);
+    functions.insert("trace", BuiltinFunction::Pure(debugging::trace));
 
     // encoding
     functions.insert(
         "base64url.encode_no_pad",
-        encoding::base64url::encode_no_pad,
+        BuiltinFunction::Pure(encoding::base64url::encode_no_pad),
+    );
+    functions.insert(
+        "urlquery.encode",
+        BuiltinFunction::Pure(encoding::urlquery::encode),
+    );
+    functions.insert(
+        "urlquery.decode",
+        BuiltinFunction::Pure(encoding::urlquery::decode),
+    );
+    functions.insert(
+        "urlquery.encode_object",
+        BuiltinFunction::Pure(encoding::urlquery::encode_object),
+    );
+    functions.insert(
+        "urlquery.decode_object",
+        BuiltinFunction::Pure(encoding::urlquery::decode_object),
+    );
+    functions.insert(
+        "json.is_valid",
+        BuiltinFunction::Pure(encoding::json::is_valid),
+    );
+    functions.insert(
+        "yaml.marshal",
+        BuiltinFunction::Pure(encoding::yaml::marshal),
+    );
+    functions.insert(
+        "yaml.unmarshal",
+        BuiltinFunction::Pure(encoding::yaml::unmarshal),
     );
-    functions.insert("urlquery.encode", encoding::urlquery::encode);
-    functions.insert("urlquery.decode", encoding::urlquery::decode);
-    functions.insert("urlquery.encode_object", encoding::urlquery::encode_object);
-    functions.insert("urlquery.decode_object", encoding::urlquery::decode_object);
-    functions.insert("json.is_valid", encoding::json::is_valid);
-    functions.insert("yaml.marshal", encoding::yaml::marshal);
-    functions.insert("yaml.unmarshal", encoding::yaml::unmarshal);
-    functions.insert("yaml.is_valid", encoding::yaml::is_valid);
-    functions.insert("hex.encode", encoding::hex::encode);
-    functions.insert("hex.decode", encoding::hex::decode);
+    functions.insert(
+        "yaml.is_valid",
+        BuiltinFunction::Pure(encoding::yaml::is_valid),
+    );
+    functions.insert("hex.encode", BuiltinFunction::Pure(encoding::hex::encode));
+    functions.insert("hex.decode", BuiltinFunction::Pure(encoding::hex::decode));
 
     // glob
-    functions.insert("glob.quote_meta", glob::quote_meta);
+    functions.insert("glob.quote_meta", BuiltinFunction::Pure(glob::quote_meta));
 
     // objects
-    functions.insert("json.patch", json::patch);
+    functions.insert("json.patch", BuiltinFunction::Pure(json::patch));
 
     // regex
-    functions.insert("regex.split", regex::split);
-    functions.insert("regex.template_match", regex::template_match);
-    functions.insert("regex.find_n", regex::find_n);
+    functions.insert("regex.split", BuiltinFunction::Pure(regex::split));
+    functions.insert(
+        "regex.template_match",
+        BuiltinFunction::Pure(regex::template_match),
+    );
+    functions.insert("regex.find_n", BuiltinFunction::Pure(regex::find_n));
 
     // semver
-    functions.insert("semver.is_valid", semver::is_valid);
-    functions.insert("semver.compare", semver::compare);
+    functions.insert("semver.is_valid", BuiltinFunction::Pure(semver::is_valid));
+    functions.insert("semver.compare", BuiltinFunction::Pure(semver::compare));
 
     // strings
-    functions.insert("sprintf", strings::sprintf);
+    functions.insert("sprintf", BuiltinFunction::Pure(strings::sprintf));
 
     // time
-    functions.insert("time.now_ns", time::now_ns);
-    functions.insert("parse_rfc3339_ns", time::parse_rfc3339_ns);
-    functions.insert("date", time::date);
+    functions.insert("time.now_ns", BuiltinFunction::Contextual(time::now_ns));
+    functions.insert(
+        "parse_rfc3339_ns",
+        BuiltinFunction::Pure(time::parse_rfc3339_ns),
+    );
+    functions.insert("date", BuiltinFunction::Pure(time::date));
 
     functions
 }
@@ -1,15 +1,16 @@
+use super::BuiltinContext;
 use crate::errors::{BurregoError, Result};
-use chrono::{self, DateTime, Datelike, Duration, Local};
+use chrono::{self, DateTime, Datelike, Duration};
 use std::str::FromStr;
 
-pub fn now_ns(args: &[serde_json::Value]) -> Result<serde_json::Value> {
+pub fn now_ns(args: &[serde_json::Value], ctx: &BuiltinContext) -> Result<serde_json::Value> {
     if !args.is_empty() {
         return Err(BurregoError::BuiltinError {
             name: "time.now_ns".to_string(),
             message: "wrong number of arguments given".to_string(),
         });
     }
-    let now = Local::now();
+    let now = ctx.clock.now();
     serde_json::to_value(now.timestamp_nanos_opt()).map_err(|e| BurregoError::BuiltinError {
         name: "time.now_ns".to_string(),
         message: format!("cannot convert value into JSON: {e:?}"),
@@ -116,9 +117,31 @@ pub fn date_local(ns: i64) -> Result<serde_json::Value> {
 #[cfg(test)]
 mod test {
     use super::*;
-    use chrono::TimeZone;
+    use crate::clock::Clock;
+    use chrono::{Local, TimeZone, Utc};
     use serde_json::json;
 
+    struct FrozenClock(DateTime<Utc>);
+
+    impl Clock for FrozenClock {
+        fn now(&self) -> DateTime<Utc> {
+            self.0
+        }
+    }
+
+    #[test]
+    fn now_ns_uses_the_given_clock() {
+        let frozen = Utc.with_ymd_and_hms(2023, 2, 21, 10, 30, 0).unwrap();
+
+        let ctx = BuiltinContext {
+            clock: &FrozenClock(frozen),
+        };
+
+        let now = now_ns(&[], &ctx).unwrap();
+
+        assert_eq!(json!(frozen.timestamp_nanos_opt()), now);
+    }
+
     #[test]
     fn test_parse_rfc3339_ns() {
         let input_dt = Local::now();
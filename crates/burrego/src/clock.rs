@@ -0,0 +1,19 @@
+use chrono::{DateTime, Utc};
+
+/// The source of the current time used by the builtins, like `time.now_ns`.
+///
+/// A custom implementation allows to evaluate policies at a given point in time,
+/// which is useful to write tests that do not depend on the real clock
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// A [`Clock`] reading the system time, this is the default one
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
@@ -0,0 +1,27 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use wasmtime::{StoreContextMut, UpdateDeadline};
+
+/// Build the callback invoked by wasmtime once the epoch deadline of a store is
+/// reached. The guest is interrupted once `deadline` ticks have elapsed, or as
+/// soon as the `cancellation_flag` is set.
+///
+/// The store must be given an epoch deadline of a single tick, the callback then
+/// extends it one tick at a time.
+pub fn epoch_deadline_callback<T>(
+    deadline: u64,
+    cancellation_flag: Option<Arc<AtomicBool>>,
+) -> impl FnMut(StoreContextMut<T>) -> wasmtime::Result<UpdateDeadline> + Send + Sync + 'static {
+    let mut ticks_left = deadline;
+
+    move |_| {
+        ticks_left = ticks_left.saturating_sub(1);
+        let cancelled = cancellation_flag
+            .as_ref()
+            .is_some_and(|flag| flag.load(Ordering::SeqCst));
+        if cancelled || ticks_left == 0 {
+            return Err(wasmtime::Trap::Interrupt.into());
+        }
+        Ok(UpdateDeadline::Continue(1))
+    }
+}
@@ -32,4 +32,12 @@ pub enum BurregoError {
     /// Wasmtime execution deadline exceeded
     #[error("guest code interrupted, execution deadline exceeded")]
     ExecutionDeadlineExceeded,
+
+    /// Wasmtime fuel exhausted
+    #[error("guest code interrupted, fuel exhausted")]
+    FuelExhausted,
+
+    /// The guest attempted to grow its memory beyond the limit
+    #[error("guest code interrupted, memory limit exceeded: {0}")]
+    MemoryLimitExceeded(String),
 }
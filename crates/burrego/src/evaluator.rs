@@ -1,67 +1,110 @@
 use crate::builtins;
+use crate::clock::Clock;
+use crate::epoch::epoch_deadline_callback;
 use crate::errors::{BurregoError, Result};
 use crate::host_callbacks::HostCallbacks;
 use crate::opa_host_functions;
 use crate::policy::Policy;
+use crate::resource_limiter::{ResourceLimiter, ResourceLimits};
 use crate::stack_helper::StackHelper;
 
 use itertools::Itertools;
 use std::collections::{HashMap, HashSet};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
 use tracing::debug;
 use wasmtime::{Engine, Instance, Linker, Memory, MemoryType, Module, Store};
 
-macro_rules! set_epoch_deadline_and_call_guest {
-    ($epoch_deadline:expr, $store:expr, $code:block) => {{
-        if let Some(deadline) = $epoch_deadline {
-            $store.set_epoch_deadline(deadline);
+macro_rules! set_execution_limits_and_call_guest {
+    ($config:expr, $fuel:expr, $store:expr, $code:block) => {{
+        if let Some(deadline) = $config.epoch_deadline {
+            // the deadline is extended one tick at a time by the callback
+            $store.set_epoch_deadline(1);
+            $store.epoch_deadline_callback(epoch_deadline_callback(
+                deadline,
+                $config.cancellation_flag.clone(),
+            ));
+        }
+        if let Some(fuel) = $fuel {
+            $store
+                .set_fuel(fuel)
+                .map_err(|e| BurregoError::WasmEngineError(format!("cannot set fuel: {e}")))?;
         }
         $code
     }};
 }
 
+/// The amount of [fuel](https://docs.rs/wasmtime/latest/wasmtime/struct.Config.html#method.consume_fuel)
+/// given to the guest code
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FuelLimits {
+    /// Fuel given to the initialization code of the policy
+    pub init: u64,
+
+    /// Fuel given to each evaluation of the policy
+    pub func: u64,
+}
+
+/// The data associated with the `wasmtime::Store` used by the evaluator
+pub(crate) struct StoreData {
+    /// This is set once the OPA module has been instantiated
+    pub(crate) stack_helper: Option<StackHelper>,
+    /// The clock used by the builtins reading the current time
+    pub(crate) clock: Arc<dyn Clock>,
+    limiter: ResourceLimiter,
+}
+
+/// Everything needed to create the stack of the evaluator, kept to be able
+/// to reset it
+#[derive(Clone)]
+pub(crate) struct EvaluatorConfig {
+    pub(crate) engine: Engine,
+    pub(crate) module: Module,
+    pub(crate) host_callbacks: HostCallbacks,
+    /// used to tune the [epoch
+    /// interruption](https://docs.rs/wasmtime/latest/wasmtime/struct.Config.html#method.epoch_interruption)
+    /// feature of wasmtime
+    pub(crate) epoch_deadline: Option<u64>,
+    /// used to tune the [fuel
+    /// consumption](https://docs.rs/wasmtime/latest/wasmtime/struct.Config.html#method.consume_fuel)
+    /// feature of wasmtime
+    pub(crate) fuel_limits: Option<FuelLimits>,
+    pub(crate) resource_limits: ResourceLimits,
+    pub(crate) clock: Arc<dyn Clock>,
+    /// When set, the guest code is interrupted at the next epoch tick
+    pub(crate) cancellation_flag: Option<Arc<AtomicBool>>,
+}
+
 struct EvaluatorStack {
-    store: Store<Option<StackHelper>>,
+    store: Store<StoreData>,
     instance: Instance,
     memory: Memory,
     policy: Policy,
 }
 
 pub struct Evaluator {
-    engine: Engine,
-    module: Module,
-    store: Store<Option<StackHelper>>,
+    config: EvaluatorConfig,
+    store: Store<StoreData>,
     instance: Instance,
     memory: Memory,
     policy: Policy,
-    host_callbacks: HostCallbacks,
-    /// used to tune the [epoch
-    /// interruption](https://docs.rs/wasmtime/latest/wasmtime/struct.Config.html#method.epoch_interruption)
-    /// feature of wasmtime
-    epoch_deadline: Option<u64>,
+    fuel_consumed: Option<u64>,
     entrypoints: HashMap<String, i32>,
     used_builtins: HashSet<String>,
 }
 
 impl Evaluator {
-    pub(crate) fn from_engine_and_module(
-        engine: Engine,
-        module: Module,
-        host_callbacks: HostCallbacks,
-        epoch_deadline: Option<u64>,
-    ) -> Result<Evaluator> {
-        let stack = Self::setup(
-            engine.clone(),
-            module.clone(),
-            host_callbacks.clone(),
-            epoch_deadline,
-        )?;
+    pub(crate) fn from_config(config: EvaluatorConfig) -> Result<Evaluator> {
+        let stack = Self::setup(&config)?;
         let mut store = stack.store;
         let instance = stack.instance;
         let memory = stack.memory;
         let policy = stack.policy;
 
+        let init_fuel = config.fuel_limits.map(|limits| limits.init);
+
         let used_builtins: HashSet<String> =
-            set_epoch_deadline_and_call_guest!(epoch_deadline, store, {
+            set_execution_limits_and_call_guest!(config, init_fuel, store, {
                 policy
                     .builtins(&mut store, &memory)?
                     .keys()
@@ -69,7 +112,7 @@ impl Evaluator {
                     .collect()
             });
 
-        let entrypoints = set_epoch_deadline_and_call_guest!(epoch_deadline, store, {
+        let entrypoints = set_execution_limits_and_call_guest!(config, init_fuel, store, {
             policy.entrypoints(&mut store, &memory)
         })?;
 
@@ -79,14 +122,12 @@ impl Evaluator {
         );
 
         let mut evaluator = Evaluator {
-            engine,
-            module,
+            config,
             store,
             instance,
             memory,
             policy,
-            host_callbacks,
-            epoch_deadline,
+            fuel_consumed: None,
             entrypoints,
             used_builtins,
         };
@@ -101,16 +142,18 @@ impl Evaluator {
         Ok(evaluator)
     }
 
-    fn setup(
-        engine: Engine,
-        module: Module,
-        host_callbacks: HostCallbacks,
-        epoch_deadline: Option<u64>,
-    ) -> Result<EvaluatorStack> {
-        let mut linker = Linker::<Option<StackHelper>>::new(&engine);
+    fn setup(config: &EvaluatorConfig) -> Result<EvaluatorStack> {
+        let mut linker = Linker::<StoreData>::new(&config.engine);
 
-        let opa_data_helper: Option<StackHelper> = None;
-        let mut store = Store::new(&engine, opa_data_helper);
+        let store_data = StoreData {
+            stack_helper: None,
+            clock: config.clock.clone(),
+            limiter: ResourceLimiter::new(config.resource_limits),
+        };
+        let mut store = Store::new(&config.engine, store_data);
+        // the limiter must be registered before the memory is created, otherwise
+        // its initial size would not be checked
+        store.limiter(|data| &mut data.limiter);
 
         let memory_ty = MemoryType::new(5, None);
         let memory = Memory::new(&mut store, memory_ty)
@@ -128,9 +171,10 @@ impl Evaluator {
         // `_initialize`.
         // When the engine is configured to use epoch_deadline, the invocation of this function
         // will cause an immediate failure unless the store has some "ticks" inside of it. Like
-        // any other function invocation
-        let instance = set_epoch_deadline_and_call_guest!(epoch_deadline, store, {
-            linker.instantiate(&mut store, &module).map_err(|e| {
+        // any other function invocation. The same applies to fuel consumption.
+        let init_fuel = config.fuel_limits.map(|limits| limits.init);
+        let instance = set_execution_limits_and_call_guest!(config, init_fuel, store, {
+            linker.instantiate(&mut store, &config.module).map_err(|e| {
                 BurregoError::WasmEngineError(format!("linker cannot create instance: {e}"))
             })
         })?;
@@ -139,11 +183,11 @@ impl Evaluator {
             &instance,
             &memory,
             &mut store,
-            host_callbacks.opa_abort,
-            host_callbacks.opa_println,
+            config.host_callbacks.opa_abort,
+            config.host_callbacks.opa_println,
         )?;
         let policy = Policy::new(&instance, &mut store, &memory)?;
-        _ = store.data_mut().insert(stack_helper);
+        _ = store.data_mut().stack_helper.insert(stack_helper);
 
         Ok(EvaluatorStack {
             memory,
@@ -154,12 +198,7 @@ impl Evaluator {
     }
 
     pub fn reset(&mut self) -> Result<()> {
-        let stack = Self::setup(
-            self.engine.clone(),
-            self.module.clone(),
-            self.host_callbacks.clone(),
-            self.epoch_deadline,
-        )?;
+        let stack = Self::setup(&self.config)?;
         self.store = stack.store;
         self.instance = stack.instance;
         self.memory = stack.memory;
@@ -168,6 +207,18 @@ impl Evaluator {
         Ok(())
     }
 
+    /// The amount of fuel consumed by the last evaluation. This is `None` when
+    /// fuel metering is not enabled, or when no evaluation has been done yet
+    pub fn fuel_consumed(&self) -> Option<u64> {
+        self.fuel_consumed
+    }
+
+    /// The peak size, in bytes, of the linear memory of the policy. The memory is
+    /// kept across evaluations, hence this is the peak since the creation of the evaluator
+    pub fn peak_memory(&self) -> usize {
+        self.store.data().limiter.peak_memory()
+    }
+
     pub fn opa_abi_version(&mut self) -> Result<(i32, i32)> {
         let major = self
             .instance
@@ -233,30 +284,52 @@ impl Evaluator {
         input: &serde_json::Value,
         data: &[u8],
     ) -> Result<serde_json::Value> {
-        set_epoch_deadline_and_call_guest!(self.epoch_deadline, self.store, {
-            if !self.has_entrypoint(entrypoint_id) {
-                return Err(BurregoError::RegoWasmError(format!(
-                    "Cannot find the specified entrypoint {entrypoint_id} inside of {:?}",
-                    self.entrypoints
-                )));
-            }
-
-            debug!(
-                data = serde_json::to_string(&data)
-                    .expect("cannot convert data back to json")
-                    .as_str(),
-                "setting policy data"
-            );
-            self.policy.set_data(&mut self.store, &self.memory, data)?;
-
-            debug!(
-                input = serde_json::to_string(&input)
-                    .expect("cannot convert input back to JSON")
-                    .as_str(),
-                "attempting evaluation"
-            );
-            self.policy
-                .evaluate(entrypoint_id, &mut self.store, &self.memory, input)
-        })
+        if !self.has_entrypoint(entrypoint_id) {
+            return Err(BurregoError::RegoWasmError(format!(
+                "Cannot find the specified entrypoint {entrypoint_id} inside of {:?}",
+                self.entrypoints
+            )));
+        }
+
+        let func_fuel = self.config.fuel_limits.map(|limits| limits.func);
+        self.fuel_consumed = None;
+
+        let result = set_execution_limits_and_call_guest!(self.config, func_fuel, self.store, {
+            self.set_data_and_evaluate(entrypoint_id, input, data)
+        });
+
+        // the store has no fuel left when the evaluation ran out of fuel
+        self.fuel_consumed = func_fuel.and_then(|fuel| {
+            self.store
+                .get_fuel()
+                .ok()
+                .map(|remaining| fuel.saturating_sub(remaining))
+        });
+
+        result
+    }
+
+    fn set_data_and_evaluate(
+        &mut self,
+        entrypoint_id: i32,
+        input: &serde_json::Value,
+        data: &[u8],
+    ) -> Result<serde_json::Value> {
+        debug!(
+            data = serde_json::to_string(&data)
+                .expect("cannot convert data back to json")
+                .as_str(),
+            "setting policy data"
+        );
+        self.policy.set_data(&mut self.store, &self.memory, data)?;
+
+        debug!(
+            input = serde_json::to_string(&input)
+                .expect("cannot convert input back to JSON")
+                .as_str(),
+            "attempting evaluation"
+        );
+        self.policy
+            .evaluate(entrypoint_id, &mut self.store, &self.memory, input)
     }
 }
@@ -1,8 +1,13 @@
 use crate::errors::{BurregoError, Result};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
 use wasmtime::{Engine, Module};
 
-use crate::{host_callbacks::HostCallbacks, Evaluator};
+use crate::evaluator::EvaluatorConfig;
+use crate::{
+    host_callbacks::HostCallbacks, Clock, Evaluator, FuelLimits, ResourceLimits, SystemClock,
+};
 
 #[derive(Default)]
 pub struct EvaluatorBuilder {
@@ -10,7 +15,11 @@ pub struct EvaluatorBuilder {
     module: Option<Module>,
     engine: Option<Engine>,
     epoch_deadline: Option<u64>,
+    fuel_limits: Option<FuelLimits>,
+    resource_limits: ResourceLimits,
     host_callbacks: Option<HostCallbacks>,
+    clock: Option<Arc<dyn Clock>>,
+    cancellation_flag: Option<Arc<AtomicBool>>,
 }
 
 impl EvaluatorBuilder {
@@ -38,12 +47,51 @@ impl EvaluatorBuilder {
         self
     }
 
+    /// Enable wasmtime fuel consumption. The `init_fuel` is given to the
+    /// initialization code of the policy, while `func_fuel` is given to
+    /// each evaluation.
+    ///
+    /// When providing a `wasmtime::Engine`, ensure it has been created with
+    /// fuel consumption enabled
+    #[must_use]
+    pub fn enable_fuel_metering(mut self, init_fuel: u64, func_fuel: u64) -> Self {
+        self.fuel_limits = Some(FuelLimits {
+            init: init_fuel,
+            func: func_fuel,
+        });
+        self
+    }
+
+    /// Limit the resources, like the memory, that can be allocated by the policy.
+    /// Exceeding the memory limit interrupts the guest code
+    #[must_use]
+    pub fn resource_limits(mut self, resource_limits: ResourceLimits) -> Self {
+        self.resource_limits = resource_limits;
+        self
+    }
+
     #[must_use]
     pub fn host_callbacks(mut self, host_callbacks: HostCallbacks) -> Self {
         self.host_callbacks = Some(host_callbacks);
         self
     }
 
+    /// Set the clock used by the builtins reading the current time, like `time.now_ns`.
+    /// When not set, the system clock is used
+    #[must_use]
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Interrupt the evaluation once the given flag is set. The guest is interrupted
+    /// at the next epoch tick, hence this requires epoch interruptions to be enabled
+    #[must_use]
+    pub fn cancellation_flag(mut self, flag: Arc<AtomicBool>) -> Self {
+        self.cancellation_flag = Some(flag);
+        self
+    }
+
     fn validate(&self) -> Result<()> {
         if self.policy_path.is_some() && self.module.is_some() {
             return Err(BurregoError::EvaluatorBuilderError(
@@ -75,6 +123,9 @@ impl EvaluatorBuilder {
                 if self.epoch_deadline.is_some() {
                     config.epoch_interruption(true);
                 }
+                if self.fuel_limits.is_some() {
+                    config.consume_fuel(true);
+                }
                 Engine::new(&config).map_err(|e| {
                     BurregoError::WasmEngineError(format!("cannot create wasmtime Engine: {e:?}"))
                 })?
@@ -96,7 +147,17 @@ impl EvaluatorBuilder {
             .host_callbacks
             .clone()
             .expect("host callbacks should be set");
+        let clock = self.clock.clone().unwrap_or_else(|| Arc::new(SystemClock));
 
-        Evaluator::from_engine_and_module(engine, module, host_callbacks, self.epoch_deadline)
+        Evaluator::from_config(EvaluatorConfig {
+            engine,
+            module,
+            host_callbacks,
+            epoch_deadline: self.epoch_deadline,
+            fuel_limits: self.fuel_limits,
+            resource_limits: self.resource_limits,
+            clock,
+            cancellation_flag: self.cancellation_flag.clone(),
+        })
     }
 }
@@ -1,13 +1,19 @@
 mod builtins;
+mod clock;
+mod epoch;
 pub mod errors;
 mod evaluator;
 mod evaluator_builder;
 pub mod host_callbacks;
 mod opa_host_functions;
 mod policy;
+mod resource_limiter;
 mod stack_helper;
 
 pub use builtins::get_builtins;
-pub use evaluator::Evaluator;
+pub use clock::{Clock, SystemClock};
+pub use epoch::epoch_deadline_callback;
+pub use evaluator::{Evaluator, FuelLimits};
 pub use evaluator_builder::EvaluatorBuilder;
 pub use host_callbacks::HostCallbacks;
+pub use resource_limiter::{MemoryLimitExceeded, ResourceLimiter, ResourceLimits};
@@ -2,12 +2,13 @@ use crate::errors::{BurregoError, Result};
 use tracing::{debug, error};
 use wasmtime::{AsContextMut, Caller, Linker};
 
-use crate::builtins::BUILTINS_HELPER;
+use crate::builtins::{BuiltinContext, BUILTINS_HELPER};
+use crate::evaluator::StoreData;
 use crate::stack_helper::StackHelper;
 
 /// Add OPA host callbacks to the linker.
 /// The callbackes are the one listed at https://www.openpolicyagent.org/docs/latest/wasm/#imports
-pub(crate) fn add_to_linker(linker: &mut Linker<Option<StackHelper>>) -> Result<()> {
+pub(crate) fn add_to_linker(linker: &mut Linker<StoreData>) -> Result<()> {
     register_opa_abort_func(linker)?;
     register_opa_println_func(linker)?;
     register_opa_builtin0_func(linker)?;
@@ -19,15 +20,13 @@ pub(crate) fn add_to_linker(linker: &mut Linker<Option<StackHelper>>) -> Result<
     Ok(())
 }
 
-fn register_opa_abort_func(
-    linker: &mut Linker<Option<StackHelper>>,
-) -> Result<&mut Linker<Option<StackHelper>>> {
+fn register_opa_abort_func(linker: &mut Linker<StoreData>) -> Result<&mut Linker<StoreData>> {
     linker
         .func_wrap(
             "env",
             "opa_abort",
-            |mut caller: Caller<'_, Option<StackHelper>>, addr: i32| {
-                let stack_helper = caller.data().as_ref().unwrap();
+            |mut caller: Caller<'_, StoreData>, addr: i32| {
+                let stack_helper = caller.data().stack_helper.as_ref().unwrap();
                 let opa_abort_host_callback = stack_helper.opa_abort_host_callback;
 
                 let memory_export = caller.get_export("memory").ok_or_else(|| BurregoError::RegoWasmError("cannot find 'memory' export".to_string()))?;
@@ -48,14 +47,12 @@ fn register_opa_abort_func(
         })
 }
 
-fn register_opa_println_func(
-    linker: &mut Linker<Option<StackHelper>>,
-) -> Result<&mut Linker<Option<StackHelper>>> {
+fn register_opa_println_func(linker: &mut Linker<StoreData>) -> Result<&mut Linker<StoreData>> {
     linker.func_wrap(
         "env",
         "opa_println",
-        |mut caller: Caller<'_, Option<StackHelper>>, addr: i32| {
-            let stack_helper = caller.data().as_ref().unwrap();
+        |mut caller: Caller<'_, StoreData>, addr: i32| {
+            let stack_helper = caller.data().stack_helper.as_ref().unwrap();
             let opa_println_host_callback = stack_helper.opa_println_host_callback;
 
             let memory_export = caller.get_export("memory").ok_or_else(|| BurregoError::RegoWasmError("cannot find 'memory' export".to_string()))?;
@@ -79,16 +76,14 @@ fn register_opa_println_func(
 /// env.opa_builtin0 (builtin_id, ctx) addr
 /// Called to dispatch the built-in function identified by the builtin_id.
 /// The ctx parameter reserved for future use. The result addr must refer to a value in the shared-memory buffer. The function accepts 0 arguments.
-fn register_opa_builtin0_func(
-    linker: &mut Linker<Option<StackHelper>>,
-) -> Result<&mut Linker<Option<StackHelper>>> {
+fn register_opa_builtin0_func(linker: &mut Linker<StoreData>) -> Result<&mut Linker<StoreData>> {
     linker.func_wrap(
         "env",
         "opa_builtin0",
-        |mut caller: Caller<'_, Option<StackHelper>>, builtin_id: i32, _ctx: i32| {
+        |mut caller: Caller<'_, StoreData>, builtin_id: i32, _ctx: i32| {
             debug!(builtin_id, "opa_builtin0");
 
-            let stack_helper = caller.data().as_ref().unwrap();
+            let stack_helper = caller.data().stack_helper.as_ref().unwrap();
             let opa_malloc_fn = stack_helper.opa_malloc_fn.clone();
             let opa_json_parse_fn = stack_helper.opa_json_parse_fn.clone();
             let builtin_name = stack_helper
@@ -108,7 +103,7 @@ fn register_opa_builtin0_func(
                 .map_err(|e| BurregoError::RegoWasmError(format!("Cannot access global builtin helper: {e:?}")))?;
 
             let builtin_result = builtin_helper
-                .invoke(&builtin_name, &args)?;
+                .invoke(&builtin_name, &args, &BuiltinContext { clock: caller.data().clock.as_ref() })?;
 
             let addr = StackHelper::push_json(
                 caller.as_context_mut(),
@@ -127,19 +122,17 @@ fn register_opa_builtin0_func(
 
 /// env.opa_builtin1(builtin_id, ctx, _1) addr
 /// Same as previous except the function accepts 1 argument.
-fn register_opa_builtin1_func(
-    linker: &mut Linker<Option<StackHelper>>,
-) -> Result<&mut Linker<Option<StackHelper>>> {
+fn register_opa_builtin1_func(linker: &mut Linker<StoreData>) -> Result<&mut Linker<StoreData>> {
     linker.func_wrap(
         "env",
         "opa_builtin1",
-            move |mut caller: Caller<'_, Option<StackHelper>>,
+            move |mut caller: Caller<'_, StoreData>,
                   builtin_id: i32,
                   _ctx: i32,
                   p1: i32| {
             debug!(builtin_id, p1, "opa_builtin1");
 
-            let stack_helper = caller.data().as_ref().unwrap();
+            let stack_helper = caller.data().stack_helper.as_ref().unwrap();
             let opa_malloc_fn = stack_helper.opa_malloc_fn.clone();
             let opa_json_parse_fn = stack_helper.opa_json_parse_fn.clone();
             let opa_json_dump_fn = stack_helper.opa_json_dump_fn.clone();
@@ -165,7 +158,7 @@ fn register_opa_builtin1_func(
                 .map_err(|e| BurregoError::RegoWasmError(format!("Cannot access global builtin helper: {e:?}")))?;
 
             let builtin_result = builtin_helper
-                .invoke(&builtin_name, &args)?;
+                .invoke(&builtin_name, &args, &BuiltinContext { clock: caller.data().clock.as_ref() })?;
 
             let addr = StackHelper::push_json(
                 caller.as_context_mut(),
@@ -185,20 +178,18 @@ fn register_opa_builtin1_func(
 
 /// env.opa_builtin2 (builtin_id, ctx, _1, _2) addr
 /// Same as previous except the function accepts 2 arguments.
-fn register_opa_builtin2_func(
-    linker: &mut Linker<Option<StackHelper>>,
-) -> Result<&mut Linker<Option<StackHelper>>> {
+fn register_opa_builtin2_func(linker: &mut Linker<StoreData>) -> Result<&mut Linker<StoreData>> {
     linker.func_wrap(
         "env",
         "opa_builtin2",
-            move |mut caller: Caller<'_, Option<StackHelper>>,
+            move |mut caller: Caller<'_, StoreData>,
                   builtin_id: i32,
                   _ctx: i32,
                   p1: i32,
                   p2: i32| {
             debug!(builtin_id, p1, p2, "opa_builtin2");
 
-            let stack_helper = caller.data().as_ref().unwrap();
+            let stack_helper = caller.data().stack_helper.as_ref().unwrap();
             let opa_malloc_fn = stack_helper.opa_malloc_fn.clone();
             let opa_json_parse_fn = stack_helper.opa_json_parse_fn.clone();
             let opa_json_dump_fn = stack_helper.opa_json_dump_fn.clone();
@@ -224,7 +215,7 @@ fn register_opa_builtin2_func(
                 .read()
                 .map_err(|e| BurregoError::RegoWasmError(format!("Cannot access global builtin helper: {e:?}")))?;
 
-            let builtin_result = builtin_helper.invoke(&builtin_name, &args)?;
+            let builtin_result = builtin_helper.invoke(&builtin_name, &args, &BuiltinContext { clock: caller.data().clock.as_ref() })?;
 
             let addr = StackHelper::push_json(
                 caller.as_context_mut(),
@@ -244,13 +235,11 @@ fn register_opa_builtin2_func(
 
 /// env.opa_builtin3 (builtin_id, ctx, _1, _2, _3) addr
 /// Same as previous except the function accepts 3 arguments.
-fn register_opa_builtin3_func(
-    linker: &mut Linker<Option<StackHelper>>,
-) -> Result<&mut Linker<Option<StackHelper>>> {
+fn register_opa_builtin3_func(linker: &mut Linker<StoreData>) -> Result<&mut Linker<StoreData>> {
     linker.func_wrap(
         "env",
         "opa_builtin3",
-            move |mut caller: Caller<'_, Option<StackHelper>>,
+            move |mut caller: Caller<'_, StoreData>,
                   builtin_id: i32,
                   _ctx: i32,
                   p1: i32,
@@ -258,7 +247,7 @@ fn register_opa_builtin3_func(
                   p3: i32| {
             debug!(builtin_id, p1, p2, p3, "opa_builtin3");
 
-            let stack_helper = caller.data().as_ref().unwrap();
+            let stack_helper = caller.data().stack_helper.as_ref().unwrap();
             let opa_malloc_fn = stack_helper.opa_malloc_fn.clone();
             let opa_json_parse_fn = stack_helper.opa_json_parse_fn.clone();
             let opa_json_dump_fn = stack_helper.opa_json_dump_fn.clone();
@@ -286,7 +275,7 @@ fn register_opa_builtin3_func(
                 .read()
                 .map_err(|e| BurregoError::RegoWasmError(format!("Cannot access global builtin helper: {e:?}")))?;
 
-            let builtin_result = builtin_helper.invoke(&builtin_name, &args)?;
+            let builtin_result = builtin_helper.invoke(&builtin_name, &args, &BuiltinContext { clock: caller.data().clock.as_ref() })?;
 
             let addr = StackHelper::push_json(
                 caller.as_context_mut(),
@@ -306,13 +295,11 @@ fn register_opa_builtin3_func(
 
 /// env.opa_builtin4 (builtin_id, ctx, _1, _2, _3, _4) addr
 /// Same as previous except the function accepts 4 arguments.
-fn register_opa_builtin4_func(
-    linker: &mut Linker<Option<StackHelper>>,
-) -> Result<&mut Linker<Option<StackHelper>>> {
+fn register_opa_builtin4_func(linker: &mut Linker<StoreData>) -> Result<&mut Linker<StoreData>> {
     linker.func_wrap(
         "env",
         "opa_builtin4",
-            move |mut caller: Caller<'_, Option<StackHelper>>,
+            move |mut caller: Caller<'_, StoreData>,
                   builtin_id: i32,
                   _ctx: i32,
                   p1: i32,
@@ -321,7 +308,7 @@ fn register_opa_builtin4_func(
                   p4: i32| {
             debug!(builtin_id, p1, p2, p3, p4, "opa_builtin4");
 
-            let stack_helper = caller.data().as_ref().unwrap();
+            let stack_helper = caller.data().stack_helper.as_ref().unwrap();
             let opa_malloc_fn = stack_helper.opa_malloc_fn.clone();
             let opa_json_parse_fn = stack_helper.opa_json_parse_fn.clone();
             let opa_json_dump_fn = stack_helper.opa_json_dump_fn.clone();
@@ -351,7 +338,7 @@ fn register_opa_builtin4_func(
                 .read()
                 .map_err(|e| BurregoError::RegoWasmError(format!("Cannot access global builtin helper: {e:?}")))?;
 
-            let builtin_result = builtin_helper.invoke(&builtin_name, &args)?;
+            let builtin_result = builtin_helper.invoke(&builtin_name, &args, &BuiltinContext { clock: caller.data().clock.as_ref() })?;
 
             let addr = StackHelper::push_json(
                 caller.as_context_mut(),
@@ -1,4 +1,5 @@
 use crate::errors::{BurregoError, Result};
+use crate::resource_limiter::MemoryLimitExceeded;
 use crate::stack_helper::StackHelper;
 use serde_json::json;
 use std::collections::HashMap;
@@ -7,17 +8,19 @@ use wasmtime::{AsContextMut, Instance, Memory, TypedFunc};
 
 /// Handle errors returned when calling a wasmtime function
 /// The macro looks into the error type and, when an epoch interruption
-/// happens, maps the error to BurregoError::ExecutionDeadlineExceeded
+/// happens, maps the error to BurregoError::ExecutionDeadlineExceeded.
+/// When the guest runs out of fuel, the error is mapped to BurregoError::FuelExhausted,
+/// while exceeding the memory limit maps to BurregoError::MemoryLimitExceeded
 macro_rules! map_call_error {
     ($err:expr, $msg:expr) => {{
-        if let Some(trap) = $err.downcast_ref::<wasmtime::Trap>() {
-            if matches!(trap, wasmtime::Trap::Interrupt) {
-                BurregoError::ExecutionDeadlineExceeded
-            } else {
-                BurregoError::WasmEngineError(format!("{}: {:?}", $msg, $err))
-            }
+        if let Some(limit_err) = $err.downcast_ref::<MemoryLimitExceeded>() {
+            BurregoError::MemoryLimitExceeded(limit_err.to_string())
         } else {
-            BurregoError::WasmEngineError(format!("{}: {:?}", $msg, $err))
+            match $err.downcast_ref::<wasmtime::Trap>() {
+                Some(wasmtime::Trap::Interrupt) => BurregoError::ExecutionDeadlineExceeded,
+                Some(wasmtime::Trap::OutOfFuel) => BurregoError::FuelExhausted,
+                _ => BurregoError::WasmEngineError(format!("{}: {:?}", $msg, $err)),
+            }
         }
     }};
 }
@@ -0,0 +1,111 @@
+use thiserror::Error;
+
+/// Limits enforced on the resources allocated by the policy
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ResourceLimits {
+    /// Maximum size, in bytes, of each linear memory
+    pub memory_size: Option<usize>,
+
+    /// Maximum number of elements of each table
+    pub table_elements: Option<usize>,
+
+    /// Maximum number of instances
+    pub instances: Option<usize>,
+}
+
+/// Error raised when the policy attempts to grow its memory beyond the limit.
+/// The store traps with this error, which can be found via `wasmtime::Error::downcast_ref`
+#[derive(Error, Debug)]
+#[error("cannot grow memory to {desired} bytes, the limit is {limit} bytes")]
+pub struct MemoryLimitExceeded {
+    pub desired: usize,
+    pub limit: usize,
+}
+
+/// Enforces the [`ResourceLimits`] on a `wasmtime::Store`
+#[derive(Debug, Default)]
+pub struct ResourceLimiter {
+    limits: ResourceLimits,
+    /// The largest size, in bytes, reached by a linear memory
+    peak_memory: usize,
+}
+
+impl ResourceLimiter {
+    pub fn new(limits: ResourceLimits) -> Self {
+        ResourceLimiter {
+            limits,
+            peak_memory: 0,
+        }
+    }
+
+    /// The largest size, in bytes, reached by a linear memory of the store
+    pub fn peak_memory(&self) -> usize {
+        self.peak_memory
+    }
+}
+
+impl wasmtime::ResourceLimiter for ResourceLimiter {
+    fn memory_growing(
+        &mut self,
+        _current: usize,
+        desired: usize,
+        _maximum: Option<usize>,
+    ) -> wasmtime::Result<bool> {
+        // Returning an error, instead of denying the growth, causes a trap. This allows
+        // to tell this failure apart from the ones raised by the policy
+        match self.limits.memory_size {
+            Some(limit) if desired > limit => Err(MemoryLimitExceeded { desired, limit }.into()),
+            _ => {
+                self.peak_memory = self.peak_memory.max(desired);
+                Ok(true)
+            }
+        }
+    }
+
+    fn table_growing(
+        &mut self,
+        _current: usize,
+        desired: usize,
+        _maximum: Option<usize>,
+    ) -> wasmtime::Result<bool> {
+        match self.limits.table_elements {
+            Some(limit) => Ok(desired <= limit),
+            None => Ok(true),
+        }
+    }
+
+    fn instances(&self) -> usize {
+        self.limits
+            .instances
+            .unwrap_or(wasmtime::DEFAULT_INSTANCE_LIMIT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasmtime::ResourceLimiter as _;
+
+    #[test]
+    fn memory_growth_beyond_limit_is_an_error() {
+        let mut limiter = ResourceLimiter::new(ResourceLimits {
+            memory_size: Some(1024),
+            ..Default::default()
+        });
+
+        assert!(limiter.memory_growing(0, 1024, None).unwrap());
+
+        let err = limiter.memory_growing(1024, 2048, None).unwrap_err();
+        assert!(err.downcast_ref::<MemoryLimitExceeded>().is_some());
+        assert_eq!(1024, limiter.peak_memory());
+    }
+
+    #[test]
+    fn no_limits() {
+        let mut limiter = ResourceLimiter::default();
+
+        assert!(limiter.memory_growing(0, usize::MAX, None).unwrap());
+        assert!(limiter.table_growing(0, usize::MAX, None).unwrap());
+        assert_eq!(wasmtime::DEFAULT_INSTANCE_LIMIT, limiter.instances());
+    }
+}
@@ -1,6 +1,10 @@
+use crate::constants::{
+    KUBEWARDEN_AUDIT_ANNOTATION_MONITOR_CODE, KUBEWARDEN_AUDIT_ANNOTATION_MONITOR_MESSAGE,
+    KUBEWARDEN_AUDIT_ANNOTATION_MONITOR_PATCH,
+};
 use crate::errors::ResponseError;
+use crate::patch::{self, ArrayKeys, GuestPatch, ProtectedPaths};
 
-use base64::{engine::general_purpose, Engine as _};
 use kubewarden_policy_sdk::response::ValidationResponse as PolicyValidationResponse;
 use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, result::Result};
@@ -43,6 +47,26 @@ pub struct AdmissionResponse {
     pub warnings: Option<Vec<String>>,
 }
 
+/// The outcome of [`AdmissionResponse::from_policy_validation_response_and_patch`]
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum PolicyResponseOutcome {
+    /// The response of the policy, including the mutation it requested
+    Evaluated(AdmissionResponse),
+    /// The request is rejected because the mutation requested by the policy is not
+    /// allowed: it changes some protected paths, or there's no object to be mutated
+    MutationRejected(AdmissionResponse),
+}
+
+impl PolicyResponseOutcome {
+    /// The `AdmissionResponse` to be returned, whatever the outcome
+    pub fn into_response(self) -> AdmissionResponse {
+        match self {
+            PolicyResponseOutcome::Evaluated(response)
+            | PolicyResponseOutcome::MutationRejected(response) => response,
+        }
+    }
+}
+
 /// PatchType is the type of patch being used to represent the mutated object
 #[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq, Clone)]
 pub enum PatchType {
@@ -107,41 +131,104 @@ impl AdmissionResponse {
         AdmissionResponse::reject(uid, format!("internal server error: {message}"), 500)
     }
 
+    /// Reject a request whose mutation changes some protected paths, see [`ProtectedPaths`]
+    pub(crate) fn reject_protected_paths_mutation(
+        uid: String,
+        violations: &[String],
+    ) -> AdmissionResponse {
+        let message = format!(
+            "the policy attempted to change protected paths: {}",
+            violations.join(", ")
+        );
+        AdmissionResponse::reject_mutation(uid, message)
+    }
+
+    /// Reject a request whose mutation is not allowed
+    fn reject_mutation(uid: String, message: String) -> AdmissionResponse {
+        AdmissionResponse {
+            uid,
+            allowed: false,
+            status: Some(AdmissionResponseStatus {
+                message: Some(message),
+                code: None,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    /// Build the `AdmissionResponse` out of the response of a waPC or WASI policy. The
+    /// mutated object, when present, is turned into a JSON Patch.
+    ///
+    /// No path is protected from the mutation, while the patch is computed using the
+    /// default [`ArrayKeys`]. Use
+    /// [`from_policy_validation_response_and_patch`](AdmissionResponse::from_policy_validation_response_and_patch)
+    /// to provide [`ProtectedPaths`] and different `ArrayKeys`.
     pub fn from_policy_validation_response(
         uid: String,
         req_obj: Option<&serde_json::Value>,
         pol_val_resp: &PolicyValidationResponse,
     ) -> Result<AdmissionResponse, ResponseError> {
-        if pol_val_resp.mutated_object.is_some() && req_obj.is_none() {
+        AdmissionResponse::from_policy_validation_response_and_patch(
+            uid,
+            req_obj,
+            pol_val_resp,
+            None,
+            &ProtectedPaths::default(),
+            &ArrayKeys::default(),
+        )
+        .map(PolicyResponseOutcome::into_response)
+    }
+
+    /// Same as [`from_policy_validation_response`](AdmissionResponse::from_policy_validation_response),
+    /// but the policy can provide a patch instead of the mutated object, see
+    /// [`GuestPatch::from_response`]. The patch must be applicable to the object of the request.
+    ///
+    /// The mutation is rejected when it changes any of the `protected_paths`, this is told
+    /// apart by the returned [`PolicyResponseOutcome`]. The `array_keys` define how the
+    /// elements of the arrays are aligned when computing the patch.
+    pub fn from_policy_validation_response_and_patch(
+        uid: String,
+        req_obj: Option<&serde_json::Value>,
+        pol_val_resp: &PolicyValidationResponse,
+        guest_patch: Option<&GuestPatch>,
+        protected_paths: &ProtectedPaths,
+        array_keys: &ArrayKeys,
+    ) -> Result<PolicyResponseOutcome, ResponseError> {
+        if pol_val_resp.mutated_object.is_some() && guest_patch.is_some() {
+            return Err(ResponseError::MutatedObjectAndPatch);
+        }
+
+        if (pol_val_resp.mutated_object.is_some() || guest_patch.is_some()) && req_obj.is_none() {
             let message = "Incoming object is null, which happens only with DELETE operations, but the policy is attempting a mutation. This is not allowed";
 
-            return Ok(AdmissionResponse {
-                uid,
-                allowed: false,
-                warnings: None,
-                audit_annotations: None,
-                patch_type: None,
-                patch: None,
-                status: Some(AdmissionResponseStatus {
-                    message: Some(message.to_string()),
-                    code: None,
-                    ..Default::default()
-                }),
-            });
+            return Ok(PolicyResponseOutcome::MutationRejected(
+                AdmissionResponse::reject_mutation(uid, message.to_string()),
+            ));
         }
 
-        let patch = match pol_val_resp.mutated_object.clone() {
-            Some(mut_obj) => {
-                let diff = json_patch::diff(req_obj.unwrap(), &mut_obj);
-                let empty_patch = json_patch::Patch(Vec::<json_patch::PatchOperation>::new());
-                if diff == empty_patch {
-                    None
-                } else {
-                    let diff_str = serde_json::to_string(&diff)
-                        .map(|s| general_purpose::STANDARD.encode(s))
-                        .map_err(ResponseError::Deserialize)?;
-                    Some(diff_str)
+        let mutation = match (&pol_val_resp.mutated_object, guest_patch) {
+            (Some(mut_obj), _) => {
+                let diff = patch::diff(req_obj.unwrap(), mut_obj, array_keys);
+                Some((mut_obj.clone(), diff))
+            }
+            (None, Some(guest_patch)) => Some(
+                guest_patch
+                    .apply(req_obj.unwrap(), array_keys)
+                    .map_err(ResponseError::GuestPatch)?,
+            ),
+            (None, None) => None,
+        };
+
+        let patch = match mutation {
+            Some((patched_obj, diff)) => {
+                let violations = protected_paths.violations(req_obj.unwrap(), &patched_obj);
+                if !violations.is_empty() {
+                    return Ok(PolicyResponseOutcome::MutationRejected(
+                        AdmissionResponse::reject_protected_paths_mutation(uid, &violations),
+                    ));
                 }
+                patch::encode(&diff).map_err(ResponseError::Deserialize)?
             }
             None => None,
         };
@@ -162,7 +249,7 @@ impl AdmissionResponse {
             None
         };
 
-        Ok(AdmissionResponse {
+        Ok(PolicyResponseOutcome::Evaluated(AdmissionResponse {
             uid,
             allowed: pol_val_resp.accepted,
             warnings: pol_val_resp.warnings.clone(),
@@ -170,7 +257,63 @@ impl AdmissionResponse {
             patch_type,
             patch,
             status,
-        })
+        }))
+    }
+
+    /// Attach the field-level causes of the rejection to the status of the response,
+    /// see [`StatusDetails::causes`]. The causes are ignored when the request is allowed.
+    #[must_use]
+    pub fn with_causes(mut self, causes: Vec<StatusCause>) -> AdmissionResponse {
+        if self.allowed || causes.is_empty() {
+            return self;
+        }
+
+        self.status
+            .get_or_insert_with(Default::default)
+            .details
+            .get_or_insert_with(Default::default)
+            .causes
+            .extend(causes);
+        self
+    }
+
+    /// Turn the response into the one of a policy running in monitor mode: the request
+    /// is always accepted and the patch is never returned.
+    ///
+    /// A rejection is reported via a warning, plus audit annotations holding the original
+    /// message and code. The patch is reported via an audit annotation.
+    pub fn into_monitor_response(mut self) -> AdmissionResponse {
+        let mut audit_annotations = self.audit_annotations.take().unwrap_or_default();
+
+        if !self.allowed {
+            let status = self.status.take().unwrap_or_default();
+            let message = status
+                .message
+                .unwrap_or_else(|| "no message provided".to_string());
+
+            self.warnings.get_or_insert_with(Vec::new).push(format!(
+                "monitor mode: the request would have been rejected: {message}"
+            ));
+            audit_annotations.insert(
+                KUBEWARDEN_AUDIT_ANNOTATION_MONITOR_MESSAGE.to_string(),
+                message,
+            );
+            if let Some(code) = status.code {
+                audit_annotations.insert(
+                    KUBEWARDEN_AUDIT_ANNOTATION_MONITOR_CODE.to_string(),
+                    code.to_string(),
+                );
+            }
+            self.allowed = true;
+        }
+
+        if let Some(patch) = self.patch.take() {
+            audit_annotations.insert(KUBEWARDEN_AUDIT_ANNOTATION_MONITOR_PATCH.to_string(), patch);
+        }
+        self.patch_type = None;
+
+        self.audit_annotations = (!audit_annotations.is_empty()).then_some(audit_annotations);
+        self
     }
 }
 
@@ -336,6 +479,18 @@ pub struct StatusCause {
     pub field: Option<String>,
 }
 
+/// Read the field-level causes of a rejection from the `causes` field of the response
+/// returned by a waPC or WASI policy. The `ValidationResponse` of the policy SDK does
+/// not define this field, policies not providing it have no causes.
+pub(crate) fn causes_from_response(
+    response: &serde_json::Value,
+) -> Result<Vec<StatusCause>, serde_json::Error> {
+    match response.get("causes") {
+        None | Some(serde_json::Value::Null) => Ok(Vec::new()),
+        Some(causes) => serde_json::from_value(causes.clone()),
+    }
+}
+
 /// CauseType is a machine readable value providing more detail about what occurred in a
 /// status response.
 /// An operation may have multiple causes for a status (whether Failure or Success).
@@ -394,6 +549,11 @@ pub enum CauseType {
     /// CauseTypeResourceVersionTooLarge is used to report that the requested resource version
     /// is newer than the data observed by the API server, so the request cannot be served.
     ResourceVersionTooLarge,
+
+    /// A cause type not listed above. Kubernetes treats the cause type as a free-form
+    /// string, hence the value provided by the policy is kept as it is.
+    #[serde(untagged)]
+    Other(String),
 }
 
 #[cfg(test)]
@@ -401,6 +561,8 @@ mod tests {
     use std::collections::HashMap;
 
     use super::*;
+    use base64::{engine::general_purpose, Engine as _};
+    use rstest::rstest;
     use serde_json::json;
 
     #[test]
@@ -420,6 +582,73 @@ mod tests {
         assert_eq!(status.message, Some(message));
     }
 
+    #[test]
+    fn monitor_mode_accepts_rejected_requests() {
+        let response = AdmissionResponse {
+            warnings: Some(vec!["a warning".to_string()]),
+            ..AdmissionResponse::reject("UID".to_string(), "not allowed".to_string(), 400)
+        }
+        .into_monitor_response();
+
+        assert!(response.allowed);
+        assert!(response.status.is_none());
+        assert_eq!(
+            Some(vec![
+                "a warning".to_string(),
+                "monitor mode: the request would have been rejected: not allowed".to_string()
+            ]),
+            response.warnings
+        );
+        assert_eq!(
+            Some(HashMap::from([
+                (
+                    KUBEWARDEN_AUDIT_ANNOTATION_MONITOR_MESSAGE.to_string(),
+                    "not allowed".to_string()
+                ),
+                (
+                    KUBEWARDEN_AUDIT_ANNOTATION_MONITOR_CODE.to_string(),
+                    "400".to_string()
+                ),
+            ])),
+            response.audit_annotations
+        );
+    }
+
+    #[test]
+    fn monitor_mode_does_not_return_patches() {
+        let response = AdmissionResponse {
+            uid: "UID".to_string(),
+            allowed: true,
+            patch_type: Some(PatchType::JSONPatch),
+            patch: Some("cGF0Y2g=".to_string()),
+            ..Default::default()
+        }
+        .into_monitor_response();
+
+        assert!(response.allowed);
+        assert!(response.patch.is_none());
+        assert!(response.patch_type.is_none());
+        assert!(response.warnings.is_none());
+        assert_eq!(
+            Some(HashMap::from([(
+                KUBEWARDEN_AUDIT_ANNOTATION_MONITOR_PATCH.to_string(),
+                "cGF0Y2g=".to_string()
+            )])),
+            response.audit_annotations
+        );
+    }
+
+    #[test]
+    fn monitor_mode_keeps_accepted_requests_untouched() {
+        let response = AdmissionResponse {
+            uid: "UID".to_string(),
+            allowed: true,
+            ..Default::default()
+        };
+
+        assert_eq!(response.clone(), response.into_monitor_response());
+    }
+
     #[test]
     fn create_from_policy_validation_response_and_mutated_object_is_none() {
         let uid = String::from("UID");
@@ -563,4 +792,272 @@ mod tests {
             serde_json::from_slice(patch_decoded_str.as_slice()).unwrap();
         assert_eq!(patch, expected_diff);
     }
+
+    #[test]
+    fn create_from_policy_validation_response_with_guest_patch() {
+        let req_obj = json!({"hello": "world"});
+        let guest_patch = GuestPatch::MergePatch(json!({"ciao": "mondo"}));
+        let pol_val_resp = PolicyValidationResponse {
+            accepted: true,
+            message: None,
+            code: None,
+            mutated_object: None,
+            audit_annotations: None,
+            warnings: None,
+        };
+
+        let response = AdmissionResponse::from_policy_validation_response_and_patch(
+            "UID".to_string(),
+            Some(&req_obj),
+            &pol_val_resp,
+            Some(&guest_patch),
+            &ProtectedPaths::default(),
+            &ArrayKeys::default(),
+        )
+        .unwrap()
+        .into_response();
+
+        assert_eq!(response.patch_type, Some(PatchType::JSONPatch));
+        let patch: serde_json::Value = serde_json::from_slice(
+            &general_purpose::STANDARD
+                .decode(response.patch.unwrap())
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            json!([{"op": "add", "path": "/ciao", "value": "mondo"}]),
+            patch
+        );
+    }
+
+    #[test]
+    fn reject_mutated_object_and_guest_patch() {
+        let req_obj = json!({"hello": "world"});
+        let pol_val_resp = PolicyValidationResponse {
+            accepted: true,
+            message: None,
+            code: None,
+            mutated_object: Some(json!({"hello": "mondo"})),
+            audit_annotations: None,
+            warnings: None,
+        };
+
+        let response = AdmissionResponse::from_policy_validation_response_and_patch(
+            "UID".to_string(),
+            Some(&req_obj),
+            &pol_val_resp,
+            Some(&GuestPatch::MergePatch(json!({"hello": "mondo"}))),
+            &ProtectedPaths::default(),
+            &ArrayKeys::default(),
+        );
+
+        assert!(matches!(
+            response,
+            Err(ResponseError::MutatedObjectAndPatch)
+        ));
+    }
+
+    #[rstest]
+    #[case::mutated_object(Some(json!({"metadata": {"name": "busybox", "namespace": "prod"}})), None)]
+    #[case::guest_patch(
+        None,
+        Some(GuestPatch::MergePatch(json!({"metadata": {"name": "busybox", "namespace": "prod"}})))
+    )]
+    fn reject_mutation_of_protected_paths(
+        #[case] mutated_object: Option<serde_json::Value>,
+        #[case] guest_patch: Option<GuestPatch>,
+    ) {
+        let req_obj = json!({"metadata": {"name": "nginx", "namespace": "default"}});
+        let pol_val_resp = PolicyValidationResponse {
+            accepted: true,
+            message: None,
+            code: None,
+            mutated_object,
+            audit_annotations: None,
+            warnings: None,
+        };
+
+        let response = AdmissionResponse::from_policy_validation_response_and_patch(
+            "UID".to_string(),
+            Some(&req_obj),
+            &pol_val_resp,
+            guest_patch.as_ref(),
+            &ProtectedPaths::recommended(),
+            &ArrayKeys::default(),
+        )
+        .unwrap();
+
+        let PolicyResponseOutcome::MutationRejected(response) = response else {
+            panic!("the mutation should be rejected, got {response:?}");
+        };
+        assert!(!response.allowed);
+        assert!(response.patch.is_none());
+        assert_eq!(
+            Some(
+                "the policy attempted to change protected paths: /metadata/name, /metadata/namespace"
+                    .to_string()
+            ),
+            response.status.unwrap().message
+        );
+    }
+
+    #[test]
+    fn paths_are_not_protected_by_default() {
+        let req_obj = json!({"metadata": {"name": "nginx", "namespace": "default"}});
+        let pol_val_resp = PolicyValidationResponse {
+            accepted: true,
+            message: None,
+            code: None,
+            mutated_object: Some(json!({
+                "metadata": {"name": "nginx", "namespace": "prod"},
+                "status": {"phase": "Running"}
+            })),
+            audit_annotations: None,
+            warnings: None,
+        };
+
+        let response = AdmissionResponse::from_policy_validation_response(
+            "UID".to_string(),
+            Some(&req_obj),
+            &pol_val_resp,
+        )
+        .unwrap();
+
+        assert!(response.allowed);
+        assert_eq!(Some(PatchType::JSONPatch), response.patch_type);
+    }
+
+    #[test]
+    fn mutation_of_unprotected_paths_is_allowed() {
+        let req_obj = json!({"metadata": {"name": "nginx"}});
+        let pol_val_resp = PolicyValidationResponse {
+            accepted: true,
+            message: None,
+            code: None,
+            mutated_object: Some(json!({"metadata": {"name": "busybox"}})),
+            audit_annotations: None,
+            warnings: None,
+        };
+
+        let response = AdmissionResponse::from_policy_validation_response_and_patch(
+            "UID".to_string(),
+            Some(&req_obj),
+            &pol_val_resp,
+            None,
+            &ProtectedPaths::new(["/status"]),
+            &ArrayKeys::default(),
+        )
+        .unwrap();
+
+        let PolicyResponseOutcome::Evaluated(response) = response else {
+            panic!("the mutation should be allowed, got {response:?}");
+        };
+        assert!(response.allowed);
+        assert!(response.patch.is_some());
+    }
+
+    #[test]
+    fn mutated_object_diff_follows_array_keys() {
+        let req_obj = json!({"ports": [{"port": 80}, {"port": 443}]});
+        let pol_val_resp = PolicyValidationResponse {
+            accepted: true,
+            message: None,
+            code: None,
+            mutated_object: Some(json!({"ports": [{"port": 443}]})),
+            audit_annotations: None,
+            warnings: None,
+        };
+
+        let response = AdmissionResponse::from_policy_validation_response_and_patch(
+            "UID".to_string(),
+            Some(&req_obj),
+            &pol_val_resp,
+            None,
+            &ProtectedPaths::default(),
+            &ArrayKeys::default().key("/ports", "port"),
+        )
+        .unwrap()
+        .into_response();
+
+        let patch: serde_json::Value = serde_json::from_slice(
+            &general_purpose::STANDARD
+                .decode(response.patch.unwrap())
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(json!([{"op": "remove", "path": "/ports/0"}]), patch);
+    }
+
+    #[test]
+    fn rejection_carries_causes() {
+        let cause = StatusCause {
+            reason: Some(CauseType::FieldValueInvalid),
+            message: Some("image must come from the trusted registry".to_string()),
+            field: Some("spec.containers[0].image".to_string()),
+        };
+
+        let response =
+            AdmissionResponse::reject("UID".to_string(), "untrusted image".to_string(), 400)
+                .with_causes(vec![cause.clone()]);
+
+        let status = response.status.unwrap();
+        assert_eq!(Some("untrusted image".to_string()), status.message);
+        assert_eq!(vec![cause], status.details.unwrap().causes);
+    }
+
+    #[test]
+    fn accepted_request_ignores_causes() {
+        let response = AdmissionResponse {
+            allowed: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            response.clone(),
+            response.with_causes(vec![StatusCause::default()])
+        );
+    }
+
+    #[rstest]
+    #[case::missing(json!({"accepted": false}), Ok(0))]
+    #[case::null(json!({"accepted": false, "causes": null}), Ok(0))]
+    #[case::provided(
+        json!({
+            "accepted": false,
+            "causes": [
+                {"field": "metadata.labels.owner", "reason": "FieldValueRequired", "message": "owner label is required"},
+                {"field": "spec.replicas", "message": "too many replicas"}
+            ]
+        }),
+        Ok(2)
+    )]
+    #[case::unknown_reason(
+        json!({"accepted": false, "causes": [{"reason": "Boom"}]}),
+        Ok(1)
+    )]
+    #[case::invalid_reason(
+        json!({"accepted": false, "causes": [{"reason": 42}]}),
+        Err(())
+    )]
+    fn read_causes_from_response(
+        #[case] response: serde_json::Value,
+        #[case] expected: Result<usize, ()>,
+    ) {
+        assert_eq!(
+            expected,
+            causes_from_response(&response)
+                .map(|causes| causes.len())
+                .map_err(|_| ())
+        );
+    }
+
+    #[rstest]
+    #[case::known("FieldValueInvalid", CauseType::FieldValueInvalid)]
+    #[case::unknown("Boom", CauseType::Other("Boom".to_string()))]
+    fn cause_type_round_trip(#[case] reason: &str, #[case] expected: CauseType) {
+        let cause_type: CauseType = serde_json::from_value(json!(reason)).unwrap();
+
+        assert_eq!(expected, cause_type);
+        assert_eq!(json!(reason), serde_json::to_value(&cause_type).unwrap());
+    }
 }
@@ -0,0 +1,242 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::admission_request::AdmissionRequest;
+use crate::admission_response::{AdmissionResponse, PatchType};
+use crate::errors::AdmissionReviewError;
+use crate::policy_evaluator::ValidateRequest;
+
+/// The `kind` of the AdmissionReview objects
+pub const ADMISSION_REVIEW_KIND: &str = "AdmissionReview";
+
+/// The versions of the AdmissionReview API sent by the Kubernetes API server.
+///
+/// The `AdmissionRequest` and `AdmissionResponse` objects have the same shape
+/// in both versions, only the `apiVersion` of the envelope changes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum AdmissionReviewVersion {
+    #[serde(rename = "admission.k8s.io/v1")]
+    #[default]
+    V1,
+    #[serde(rename = "admission.k8s.io/v1beta1")]
+    V1Beta1,
+}
+
+impl AdmissionReviewVersion {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AdmissionReviewVersion::V1 => "admission.k8s.io/v1",
+            AdmissionReviewVersion::V1Beta1 => "admission.k8s.io/v1beta1",
+        }
+    }
+}
+
+impl fmt::Display for AdmissionReviewVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for AdmissionReviewVersion {
+    type Err = AdmissionReviewError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "admission.k8s.io/v1" => Ok(AdmissionReviewVersion::V1),
+            "admission.k8s.io/v1beta1" => Ok(AdmissionReviewVersion::V1Beta1),
+            _ => Err(AdmissionReviewError::UnsupportedVersion(s.to_string())),
+        }
+    }
+}
+
+/// The AdmissionReview object sent by the Kubernetes API server to the webhooks
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AdmissionReviewRequest {
+    pub api_version: AdmissionReviewVersion,
+    pub kind: String,
+    pub request: AdmissionRequest,
+}
+
+/// The AdmissionReview object returned by the webhooks to the Kubernetes API server
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AdmissionReviewResponse {
+    pub api_version: AdmissionReviewVersion,
+    pub kind: String,
+    pub response: AdmissionResponse,
+}
+
+/// Used to peek at the envelope before deserializing the whole review, this
+/// allows to report unsupported versions and kinds with a meaningful error
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TypeMeta {
+    #[serde(default)]
+    api_version: String,
+    #[serde(default)]
+    kind: String,
+}
+
+impl AdmissionReviewRequest {
+    /// Parse the AdmissionReview sent by the Kubernetes API server
+    pub fn from_slice(data: &[u8]) -> Result<Self, AdmissionReviewError> {
+        let type_meta: TypeMeta =
+            serde_json::from_slice(data).map_err(AdmissionReviewError::Deserialize)?;
+        AdmissionReviewVersion::from_str(&type_meta.api_version)?;
+        if type_meta.kind != ADMISSION_REVIEW_KIND {
+            return Err(AdmissionReviewError::UnexpectedKind(type_meta.kind));
+        }
+
+        serde_json::from_slice(data).map_err(AdmissionReviewError::Deserialize)
+    }
+
+    /// Convert the review into the given version of the AdmissionReview API
+    pub fn convert(self, api_version: AdmissionReviewVersion) -> Self {
+        AdmissionReviewRequest {
+            api_version,
+            ..self
+        }
+    }
+
+    /// The request to be given to the policy evaluators
+    pub fn validate_request(&self) -> ValidateRequest {
+        ValidateRequest::AdmissionRequest(self.request.clone())
+    }
+
+    /// Wrap the response of the policy evaluators into the AdmissionReview to be
+    /// returned to the Kubernetes API server, using the same version of the request.
+    ///
+    /// The uid of the request is copied over, and the `patchType` is set only when
+    /// the response has a patch, as required by the Kubernetes API server.
+    pub fn respond(&self, response: AdmissionResponse) -> AdmissionReviewResponse {
+        AdmissionReviewResponse::new(
+            self.api_version,
+            AdmissionResponse {
+                uid: self.request.uid.clone(),
+                ..response
+            },
+        )
+    }
+}
+
+impl AdmissionReviewResponse {
+    pub fn new(api_version: AdmissionReviewVersion, response: AdmissionResponse) -> Self {
+        let patch_type = response.patch.as_ref().map(|_| PatchType::JSONPatch);
+
+        AdmissionReviewResponse {
+            api_version,
+            kind: ADMISSION_REVIEW_KIND.to_string(),
+            response: AdmissionResponse {
+                patch_type,
+                ..response
+            },
+        }
+    }
+
+    /// Convert the review into the given version of the AdmissionReview API
+    pub fn convert(self, api_version: AdmissionReviewVersion) -> Self {
+        AdmissionReviewResponse {
+            api_version,
+            ..self
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use serde_json::json;
+
+    fn admission_review(api_version: &str, kind: &str) -> Vec<u8> {
+        let request: serde_json::Value =
+            serde_json::from_str(include_str!("../tests/data/pod_creation_flux_cat.json")).unwrap();
+        serde_json::to_vec(&json!({
+            "apiVersion": api_version,
+            "kind": kind,
+            "request": request,
+        }))
+        .unwrap()
+    }
+
+    #[rstest]
+    #[case::v1("admission.k8s.io/v1", AdmissionReviewVersion::V1)]
+    #[case::v1beta1("admission.k8s.io/v1beta1", AdmissionReviewVersion::V1Beta1)]
+    fn response_echoes_the_version_of_the_request(
+        #[case] api_version: &str,
+        #[case] expected: AdmissionReviewVersion,
+    ) {
+        let review =
+            AdmissionReviewRequest::from_slice(&admission_review(api_version, "AdmissionReview"))
+                .unwrap();
+        assert_eq!(expected, review.api_version);
+
+        let response = review.respond(AdmissionResponse {
+            allowed: true,
+            ..Default::default()
+        });
+
+        let response = serde_json::to_value(response).unwrap();
+        assert_eq!(json!(api_version), response["apiVersion"]);
+        assert_eq!(json!("AdmissionReview"), response["kind"]);
+        assert_eq!(
+            json!("1299d386-525b-4032-98ae-1949f69f9cfc"),
+            response["response"]["uid"]
+        );
+    }
+
+    #[test]
+    fn reject_unsupported_version() {
+        assert!(matches!(
+            AdmissionReviewRequest::from_slice(&admission_review(
+                "admission.k8s.io/v2",
+                "AdmissionReview"
+            )),
+            Err(AdmissionReviewError::UnsupportedVersion(version)) if version == "admission.k8s.io/v2"
+        ));
+    }
+
+    #[test]
+    fn reject_unexpected_kind() {
+        assert!(matches!(
+            AdmissionReviewRequest::from_slice(&admission_review("admission.k8s.io/v1", "Pod")),
+            Err(AdmissionReviewError::UnexpectedKind(kind)) if kind == "Pod"
+        ));
+    }
+
+    #[test]
+    fn convert_between_versions() {
+        let review = AdmissionReviewRequest::from_slice(&admission_review(
+            "admission.k8s.io/v1beta1",
+            "AdmissionReview",
+        ))
+        .unwrap()
+        .convert(AdmissionReviewVersion::V1);
+
+        assert_eq!(AdmissionReviewVersion::V1, review.api_version);
+        assert_eq!("1299d386-525b-4032-98ae-1949f69f9cfc", review.request.uid);
+    }
+
+    #[rstest]
+    #[case::with_patch(Some("W10=".to_string()), Some(PatchType::JSONPatch))]
+    #[case::without_patch(None, None)]
+    fn patch_type_is_set_only_with_patch(
+        #[case] patch: Option<String>,
+        #[case] expected: Option<PatchType>,
+    ) {
+        let response = AdmissionReviewResponse::new(
+            AdmissionReviewVersion::V1,
+            AdmissionResponse {
+                allowed: true,
+                patch,
+                patch_type: Some(PatchType::JSONPatch),
+                ..Default::default()
+            },
+        );
+
+        assert_eq!(expected, response.response.patch_type);
+    }
+}
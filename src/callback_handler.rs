@@ -3,19 +3,21 @@ use std::sync::Arc;
 use anyhow::anyhow;
 use kubewarden_policy_sdk::host_capabilities::net::LookupResponse;
 use tokio::sync::{mpsc, oneshot};
-use tracing::{debug, warn};
+use tracing::{debug, error, warn};
 
 use crate::callback_requests::{CallbackRequest, CallbackRequestType, CallbackResponse};
 
 mod builder;
+mod cassette;
 mod crypto;
 mod kubernetes;
 mod oci;
 mod sigstore_verification;
 
 pub use builder::CallbackHandlerBuilder;
-pub(crate) use crypto::verify_certificate;
+pub(crate) use crypto::{verify_certificate, verify_sigstore_certificate_validity};
 
+use cassette::{cassette_request, CassettePlayer, CassetteRecorder};
 use sigstore_verification::{
     get_sigstore_certificate_verification_cached, get_sigstore_github_actions_verification_cached,
     get_sigstore_keyless_prefix_verification_cached, get_sigstore_keyless_verification_cached,
@@ -32,6 +34,16 @@ pub struct CallbackHandler {
     rx: mpsc::Receiver<CallbackRequest>,
     tx: mpsc::Sender<CallbackRequest>,
     shutdown_channel: oneshot::Receiver<()>,
+    cassette: Option<Cassette>,
+}
+
+/// Allows to capture the outcome of the requests and to evaluate them again later,
+/// without relying on the state of OCI registries, Kubernetes clusters and DNS
+enum Cassette {
+    /// Write all the requests, and their outcome, to a cassette file
+    Record(Arc<CassetteRecorder>),
+    /// Serve the requests using the outcomes stored inside of a cassette file
+    Replay(Arc<CassettePlayer>),
 }
 
 macro_rules! handle_callback {
@@ -46,7 +58,10 @@ macro_rules! handle_callback {
                 );
                 let payload = serde_json::to_vec(&response.value)
                     .map_err(|e| anyhow!("error serializing payload: {e:?}"))?;
-                Ok(CallbackResponse { payload })
+                Ok(CallbackResponse {
+                    payload,
+                    was_cached: response.was_cached,
+                })
             })
             .and_then(|r| r);
 
@@ -91,6 +106,15 @@ impl CallbackHandler {
     }
 
     async fn handle_request(&mut self, req: CallbackRequest) {
+        let req = match &self.cassette {
+            None => req,
+            Some(Cassette::Record(recorder)) => record_request(recorder.clone(), req),
+            Some(Cassette::Replay(player)) => {
+                replay_request(player, req);
+                return;
+            }
+        };
+
         let oci_client = self.oci_client.clone();
         let mut sigstore_client = self.sigstore_client.clone();
         let mut kubernetes_client = self.kubernetes_client.clone();
@@ -196,6 +220,7 @@ impl CallbackHandler {
                             };
                             CallbackResponse {
                                 payload: serde_json::to_vec(&res).unwrap(),
+                                was_cached: false,
                             }
                         })
                         .map_err(anyhow::Error::new);
@@ -328,3 +353,48 @@ impl CallbackHandler {
         });
     }
 }
+
+/// Intercept the response of the request to write it to the cassette, before
+/// giving it back to the requester
+fn record_request(recorder: Arc<CassetteRecorder>, req: CallbackRequest) -> CallbackRequest {
+    let request = match cassette_request(&req.request) {
+        Ok(request) => request,
+        Err(e) => {
+            warn!(error = ?e, "callback handler: cannot record request");
+            return req;
+        }
+    };
+
+    let (tx, rx) = oneshot::channel::<anyhow::Result<CallbackResponse>>();
+    let response_channel = req.response_channel;
+
+    tokio::spawn(async move {
+        let response = rx
+            .await
+            .unwrap_or_else(|_| Err(anyhow!("callback handler: response channel closed")));
+
+        if let Err(e) = recorder.record(request, &response) {
+            warn!(error = ?e, "callback handler: cannot write request to cassette");
+        }
+        if let Err(e) = response_channel.send(response) {
+            warn!("callback handler: cannot send response back: {:?}", e);
+        }
+    });
+
+    CallbackRequest {
+        request: req.request,
+        response_channel: tx,
+    }
+}
+
+/// Answer the request using the outcome stored inside of the cassette
+fn replay_request(player: &CassettePlayer, req: CallbackRequest) {
+    let response = cassette_request(&req.request).and_then(|request| player.replay(request));
+    if let Err(e) = &response {
+        error!(request = ?req.request, error = ?e, "callback handler: cannot replay request");
+    }
+
+    if let Err(e) = req.response_channel.send(response) {
+        warn!("callback handler: cannot send response back: {:?}", e);
+    }
+}
@@ -1,11 +1,13 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use policy_fetcher::sigstore::trust::ManualTrustRoot;
 use policy_fetcher::sources::Sources;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tokio::sync::{mpsc, oneshot};
 
-use super::CallbackHandler;
+use super::cassette::{CassettePlayer, CassetteRecorder};
 use super::{oci, sigstore_verification};
+use super::{CallbackHandler, Cassette};
 use crate::callback_requests::CallbackRequest;
 
 const DEFAULT_CHANNEL_BUFF_SIZE: usize = 100;
@@ -17,6 +19,8 @@ pub struct CallbackHandlerBuilder {
     shutdown_channel: oneshot::Receiver<()>,
     trust_root: Option<Arc<ManualTrustRoot<'static>>>,
     kube_client: Option<kube::Client>,
+    record_cassette: Option<PathBuf>,
+    replay_cassette: Option<PathBuf>,
 }
 
 impl CallbackHandlerBuilder {
@@ -27,6 +31,8 @@ impl CallbackHandlerBuilder {
             channel_buffer_size: DEFAULT_CHANNEL_BUFF_SIZE,
             trust_root: None,
             kube_client: None,
+            record_cassette: None,
+            replay_cassette: None,
         }
     }
 
@@ -56,8 +62,41 @@ impl CallbackHandlerBuilder {
         self
     }
 
+    /// Write all the requests handled by the CallbackHandler, together with
+    /// their outcome, to the given cassette file. The file is truncated when it
+    /// already exists. Optional
+    ///
+    /// The cassette can then be given to [`CallbackHandlerBuilder::replay_cassette`]
+    /// to evaluate the same requests again, without having access to the
+    /// OCI registries, the Kubernetes cluster and the DNS servers.
+    pub fn record_cassette(mut self, path: &Path) -> Self {
+        self.record_cassette = Some(path.to_path_buf());
+        self
+    }
+
+    /// Serve all the requests using the outcomes stored inside of the given cassette
+    /// file, which has been produced by [`CallbackHandlerBuilder::record_cassette`].
+    /// Requests that are not part of the cassette are answered with an error. Optional
+    pub fn replay_cassette(mut self, path: &Path) -> Self {
+        self.replay_cassette = Some(path.to_path_buf());
+        self
+    }
+
     /// Create a CallbackHandler object
     pub async fn build(self) -> Result<CallbackHandler> {
+        let cassette = match (self.record_cassette, self.replay_cassette) {
+            (Some(_), Some(_)) => {
+                return Err(anyhow!(
+                    "cannot record and replay a cassette at the same time"
+                ))
+            }
+            (Some(path), None) => {
+                Some(Cassette::Record(Arc::new(CassetteRecorder::create(&path)?)))
+            }
+            (None, Some(path)) => Some(Cassette::Replay(Arc::new(CassettePlayer::open(&path)?))),
+            (None, None) => None,
+        };
+
         let (tx, rx) = mpsc::channel::<CallbackRequest>(self.channel_buffer_size);
         let oci_client = Arc::new(oci::Client::new(self.oci_sources.clone()));
         let sigstore_client =
@@ -74,6 +113,7 @@ impl CallbackHandlerBuilder {
             tx,
             rx,
             shutdown_channel: self.shutdown_channel,
+            cassette,
         })
     }
 }
@@ -0,0 +1,273 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::callback_requests::{CallbackRequestType, CallbackResponse};
+
+/// Name of the field of [`CallbackRequestType::HasKubernetesListResourceAllResultChangedSinceInstant`]
+/// holding an instant in time. Its value is relative to the time the request is made,
+/// hence it's ignored when matching requests
+const SINCE_FIELD: &str = "since";
+
+/// A single line of a cassette file
+#[derive(Debug, Serialize, Deserialize)]
+struct CassetteEntry {
+    /// The serialized [`CallbackRequestType`]
+    request: serde_json::Value,
+    #[serde(flatten)]
+    outcome: CassetteOutcome,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum CassetteOutcome {
+    Response {
+        /// The JSON document given back to the guest
+        payload: String,
+        was_cached: bool,
+    },
+    Error(String),
+}
+
+impl CassetteOutcome {
+    fn new(response: &Result<CallbackResponse>) -> Result<Self> {
+        match response {
+            Ok(response) => Ok(CassetteOutcome::Response {
+                payload: String::from_utf8(response.payload.clone())
+                    .context("the response payload is not valid UTF-8")?,
+                was_cached: response.was_cached,
+            }),
+            Err(e) => Ok(CassetteOutcome::Error(format!("{e:#}"))),
+        }
+    }
+
+    fn into_response(self) -> Result<CallbackResponse> {
+        match self {
+            CassetteOutcome::Response {
+                payload,
+                was_cached,
+            } => Ok(CallbackResponse {
+                payload: payload.into_bytes(),
+                was_cached,
+            }),
+            CassetteOutcome::Error(e) => Err(anyhow!(e)),
+        }
+    }
+}
+
+/// Writes all the requests handled by the [`CallbackHandler`](super::CallbackHandler),
+/// together with their outcome, to a cassette file.
+///
+/// The cassette is a JSON Lines file, each line holds a request and either the
+/// `response` or the `error` produced by the host.
+pub(crate) struct CassetteRecorder {
+    file: Mutex<File>,
+}
+
+impl CassetteRecorder {
+    /// Create the cassette file, truncating it when it already exists
+    pub(crate) fn create(path: &Path) -> Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("cannot create cassette file {}", path.display()))?;
+
+        Ok(CassetteRecorder {
+            file: Mutex::new(file),
+        })
+    }
+
+    pub(crate) fn record(
+        &self,
+        request: serde_json::Value,
+        response: &Result<CallbackResponse>,
+    ) -> Result<()> {
+        let entry = CassetteEntry {
+            request,
+            outcome: CassetteOutcome::new(response)?,
+        };
+        let mut line = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+
+        let mut file = self
+            .file
+            .lock()
+            .map_err(|_| anyhow!("cassette file lock poisoned"))?;
+        file.write_all(&line)?;
+        file.flush()?;
+
+        Ok(())
+    }
+}
+
+/// Serves the responses stored inside of a cassette file written by [`CassetteRecorder`].
+///
+/// When the same request has been recorded multiple times, the outcomes are given back
+/// in the order they have been recorded. The last one is then served to all the
+/// subsequent requests.
+pub(crate) struct CassettePlayer {
+    outcomes: Mutex<HashMap<String, VecDeque<CassetteOutcome>>>,
+}
+
+impl CassettePlayer {
+    pub(crate) fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path)
+            .with_context(|| format!("cannot open cassette file {}", path.display()))?;
+
+        let mut outcomes: HashMap<String, VecDeque<CassetteOutcome>> = HashMap::new();
+        for (index, line) in BufReader::new(file).lines().enumerate() {
+            let line =
+                line.with_context(|| format!("cannot read cassette file {}", path.display()))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry: CassetteEntry = serde_json::from_str(&line).with_context(|| {
+                format!(
+                    "invalid entry at line {} of cassette file {}",
+                    index + 1,
+                    path.display()
+                )
+            })?;
+            outcomes
+                .entry(request_key(entry.request))
+                .or_default()
+                .push_back(entry.outcome);
+        }
+
+        Ok(CassettePlayer {
+            outcomes: Mutex::new(outcomes),
+        })
+    }
+
+    /// Returns the recorded outcome of the request. An error is returned when the
+    /// request has never been recorded
+    pub(crate) fn replay(&self, request: serde_json::Value) -> Result<CallbackResponse> {
+        let key = request_key(request);
+        let mut outcomes = self
+            .outcomes
+            .lock()
+            .map_err(|_| anyhow!("cassette lock poisoned"))?;
+
+        let outcome = match outcomes.get_mut(&key) {
+            Some(queue) if queue.len() > 1 => queue.pop_front(),
+            Some(queue) => queue.front().cloned(),
+            None => None,
+        };
+
+        outcome
+            .ok_or_else(|| anyhow!("cassette: no response recorded for request {key}"))?
+            .into_response()
+    }
+}
+
+/// Serialize the request to a form that can be used to look up its outcome
+fn request_key(mut request: serde_json::Value) -> String {
+    if let Some(fields) = request
+        .get_mut("HasKubernetesListResourceAllResultChangedSinceInstant")
+        .and_then(|fields| fields.as_object_mut())
+    {
+        fields.remove(SINCE_FIELD);
+    }
+
+    request.to_string()
+}
+
+/// Serialize the request the way it's stored inside of the cassette
+pub(crate) fn cassette_request(request: &CallbackRequestType) -> Result<serde_json::Value> {
+    serde_json::to_value(request).map_err(|e| anyhow!("cannot serialize callback request: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::time::Instant;
+
+    fn digest_request(image: &str) -> serde_json::Value {
+        cassette_request(&CallbackRequestType::OciManifestDigest {
+            image: image.to_string(),
+        })
+        .unwrap()
+    }
+
+    fn response(payload: &str) -> Result<CallbackResponse> {
+        Ok(CallbackResponse {
+            payload: payload.as_bytes().to_vec(),
+            was_cached: false,
+        })
+    }
+
+    #[test]
+    fn record_and_replay() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cassette.jsonl");
+
+        let recorder = CassetteRecorder::create(&path).unwrap();
+        recorder
+            .record(digest_request("busybox"), &response(r#""sha256:1""#))
+            .unwrap();
+        recorder
+            .record(digest_request("alpine"), &Err(anyhow!("not found")))
+            .unwrap();
+
+        let player = CassettePlayer::open(&path).unwrap();
+
+        let replayed = player.replay(digest_request("busybox")).unwrap();
+        assert_eq!(br#""sha256:1""#.as_slice(), replayed.payload.as_slice());
+        assert!(!replayed.was_cached);
+
+        let error = player.replay(digest_request("alpine")).unwrap_err();
+        assert_eq!("not found", error.to_string());
+
+        let error = player.replay(digest_request("nginx")).unwrap_err();
+        assert!(
+            error.to_string().contains("no response recorded"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn repeated_requests_are_replayed_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cassette.jsonl");
+
+        let recorder = CassetteRecorder::create(&path).unwrap();
+        recorder
+            .record(digest_request("busybox"), &response(r#""sha256:1""#))
+            .unwrap();
+        recorder
+            .record(digest_request("busybox"), &response(r#""sha256:2""#))
+            .unwrap();
+
+        let player = CassettePlayer::open(&path).unwrap();
+        for expected in [r#""sha256:1""#, r#""sha256:2""#, r#""sha256:2""#] {
+            let replayed = player.replay(digest_request("busybox")).unwrap();
+            assert_eq!(expected.as_bytes(), replayed.payload.as_slice());
+        }
+    }
+
+    #[test]
+    fn instants_are_ignored_when_matching() {
+        let request = |since: Instant| {
+            cassette_request(
+                &CallbackRequestType::HasKubernetesListResourceAllResultChangedSinceInstant {
+                    api_version: "v1".to_string(),
+                    kind: "Pod".to_string(),
+                    label_selector: None,
+                    field_selector: None,
+                    since,
+                },
+            )
+            .unwrap()
+        };
+
+        let now = Instant::now();
+        assert_eq!(
+            request_key(request(now)),
+            request_key(request(now - Duration::from_millis(10)))
+        );
+    }
+}
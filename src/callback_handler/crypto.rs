@@ -6,6 +6,8 @@ use kubewarden_policy_sdk::host_capabilities::crypto::{
 use kubewarden_policy_sdk::host_capabilities::crypto_v1::CertificateVerificationRequest;
 use tracing::debug;
 
+use crate::evaluation_context::EvaluationClock;
+
 /// A collection of trusted root certificates
 #[derive(Default, Debug)]
 struct CertificatePool {
@@ -16,7 +18,11 @@ struct CertificatePool {
 /// verify_certificate verifies the validity of the certificate, and if it is
 /// trusted with the provided certificate chain.
 /// If the provided certificate chain is empty, it is treated as trusted.
-pub fn verify_certificate(req: CertificateVerificationRequest) -> Result<BoolWithReason> {
+/// The validity period of the certificate is checked against the given clock.
+pub fn verify_certificate(
+    req: CertificateVerificationRequest,
+    clock: &EvaluationClock,
+) -> Result<BoolWithReason> {
     // verify validity:
     let pc = match req.cert.encoding {
         CertificateEncoding::Pem => {
@@ -54,7 +60,7 @@ pub fn verify_certificate(req: CertificateVerificationRequest) -> Result<BoolWit
         ),
     }
 
-    let now = picky::x509::date::UtcDate::now();
+    let now = picky::x509::date::UtcDate::from(clock.now());
     if pc.valid_not_before().gt(&now) {
         return Ok(BoolWithReason::False(
             "Certificate is being used before its validity date".to_string(),
@@ -76,6 +82,36 @@ pub fn verify_certificate(req: CertificateVerificationRequest) -> Result<BoolWit
     Ok(BoolWithReason::True)
 }
 
+/// Ensure the PEM encoded certificate used by a Sigstore verification is valid
+/// at the time reported by the given clock.
+///
+/// The expiration date is not checked when a Rekor bundle is required: in this case
+/// the certificate must be valid when the signature has been produced, which is
+/// checked against the timestamp of the bundle.
+pub fn verify_sigstore_certificate_validity(
+    certificate: &[u8],
+    require_rekor_bundle: bool,
+    clock: &EvaluationClock,
+) -> Result<()> {
+    let pem_str = std::str::from_utf8(certificate)
+        .map_err(|_| anyhow!("Certificate PEM data is not UTF8 encoded"))?;
+    let pc = picky::x509::Cert::from_pem_str(pem_str)?;
+
+    let now = picky::x509::date::UtcDate::from(clock.now());
+    if pc.valid_not_before().gt(&now) {
+        return Err(anyhow!(
+            "Certificate is being used before its validity date"
+        ));
+    }
+    if !require_rekor_bundle && pc.valid_not_after().lt(&now) {
+        return Err(anyhow!(
+            "Certificate is being used after its expiration date"
+        ));
+    }
+
+    Ok(())
+}
+
 impl CertificatePool {
     /// Build a `CertificatePool` instance using the provided list of [`Certificate`]
     fn from_certificates(certs: &[Certificate]) -> Result<Self> {
@@ -144,12 +180,14 @@ impl CertificatePool {
 
 #[cfg(test)]
 mod tests {
-    use crate::callback_handler::verify_certificate;
-    use chrono::Utc;
+    use crate::callback_handler::{verify_certificate, verify_sigstore_certificate_validity};
+    use crate::evaluation_context::EvaluationClock;
+    use chrono::{TimeZone, Utc};
     use kubewarden_policy_sdk::host_capabilities::crypto::{
         BoolWithReason, Certificate, CertificateEncoding,
     };
     use kubewarden_policy_sdk::host_capabilities::crypto_v1::CertificateVerificationRequest;
+    use rstest::rstest;
 
     // spellchecker:off
     const ROOT_CA1_PEM: &str = "-----BEGIN CERTIFICATE-----
@@ -256,7 +294,10 @@ iDAKBggqhkjOPQQDAgNIADBFAiEArSsdE5dDXqAU2vM3ThT8GvTnjkWhER3l9v1j
             cert_chain: Some(cert_chain),
             not_after: None,
         };
-        assert!(matches!(verify_certificate(req), Ok(BoolWithReason::True)));
+        assert!(matches!(
+            verify_certificate(req, &EvaluationClock::System),
+            Ok(BoolWithReason::True)
+        ));
     }
 
     #[test]
@@ -280,7 +321,7 @@ iDAKBggqhkjOPQQDAgNIADBFAiEArSsdE5dDXqAU2vM3ThT8GvTnjkWhER3l9v1j
         // compiler thinks 'reason' is unused, doesn't detect it's used in 'matches!()'
         let _reason = "Certificate is not trusted by the provided cert chain".to_string();
         assert!(matches!(
-            verify_certificate(req),
+            verify_certificate(req, &EvaluationClock::System),
             Ok(BoolWithReason::False(_reason))
         ));
     }
@@ -296,7 +337,10 @@ iDAKBggqhkjOPQQDAgNIADBFAiEArSsdE5dDXqAU2vM3ThT8GvTnjkWhER3l9v1j
             cert_chain: None,
             not_after: None,
         };
-        assert!(matches!(verify_certificate(req), Ok(BoolWithReason::True)));
+        assert!(matches!(
+            verify_certificate(req, &EvaluationClock::System),
+            Ok(BoolWithReason::True)
+        ));
     }
 
     #[test]
@@ -315,7 +359,10 @@ iDAKBggqhkjOPQQDAgNIADBFAiEArSsdE5dDXqAU2vM3ThT8GvTnjkWhER3l9v1j
             cert_chain: Some(cert_chain),
             not_after: None, // not checking expiration
         };
-        assert!(matches!(verify_certificate(req), Ok(BoolWithReason::True)));
+        assert!(matches!(
+            verify_certificate(req, &EvaluationClock::System),
+            Ok(BoolWithReason::True)
+        ));
     }
 
     #[test]
@@ -330,7 +377,9 @@ iDAKBggqhkjOPQQDAgNIADBFAiEArSsdE5dDXqAU2vM3ThT8GvTnjkWhER3l9v1j
             not_after: Some("malformed".to_string()),
         };
         assert_eq!(
-            verify_certificate(req).unwrap_err().to_string(),
+            verify_certificate(req, &EvaluationClock::System)
+                .unwrap_err()
+                .to_string(),
             "Timestamp not_after is not in RFC3339 format"
         );
     }
@@ -350,7 +399,7 @@ iDAKBggqhkjOPQQDAgNIADBFAiEArSsdE5dDXqAU2vM3ThT8GvTnjkWhER3l9v1j
         // compiler thinks 'reason' is unused, doesn't detect it's used in 'matches!()'
         let _reason = "Certificate is being used after its expiration date".to_string();
         assert!(matches!(
-            verify_certificate(req),
+            verify_certificate(req, &EvaluationClock::System),
             Ok(BoolWithReason::False(_reason))
         ));
     }
@@ -370,8 +419,50 @@ iDAKBggqhkjOPQQDAgNIADBFAiEArSsdE5dDXqAU2vM3ThT8GvTnjkWhER3l9v1j
         // compiler thinks 'reason' is unused, doesn't detect it's used in 'matches!()'
         let _reason = "Certificate is being used before its validity date".to_string();
         assert!(matches!(
-            verify_certificate(req),
+            verify_certificate(req, &EvaluationClock::System),
             Ok(BoolWithReason::False(_reason))
         ));
     }
+
+    #[test]
+    fn certificate_validity_is_checked_against_the_clock() {
+        let cert = Certificate {
+            encoding: CertificateEncoding::Pem,
+            data: INTERMEDIATE_CA1_PEM.as_bytes().to_vec(),
+        };
+        let req = CertificateVerificationRequest {
+            cert,
+            cert_chain: None,
+            not_after: None,
+        };
+        let clock = EvaluationClock::Frozen(Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap());
+
+        assert!(matches!(
+            verify_certificate(req, &clock),
+            Ok(BoolWithReason::False(reason)) if reason == "Certificate is being used before its validity date"
+        ));
+    }
+
+    #[rstest]
+    #[case::before_validity(Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap(), false, false)]
+    #[case::within_validity(Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, 0).unwrap(), false, true)]
+    #[case::expired(Utc.with_ymd_and_hms(2030, 1, 1, 0, 0, 0).unwrap(), false, false)]
+    #[case::expired_with_rekor_bundle(Utc.with_ymd_and_hms(2030, 1, 1, 0, 0, 0).unwrap(), true, true)]
+    fn sigstore_certificate_validity_is_checked_against_the_clock(
+        #[case] now: chrono::DateTime<Utc>,
+        #[case] require_rekor_bundle: bool,
+        #[case] valid: bool,
+    ) {
+        let clock = EvaluationClock::Frozen(now);
+
+        assert_eq!(
+            valid,
+            verify_sigstore_certificate_validity(
+                ROOT_CA1_PEM.as_bytes(),
+                require_rekor_bundle,
+                &clock
+            )
+            .is_ok()
+        );
+    }
 }
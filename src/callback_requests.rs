@@ -8,10 +8,34 @@ use std::collections::BTreeMap;
 use tokio::{sync::oneshot, time::Instant};
 
 /// Holds the response to a waPC evaluation request
+///
+/// Instances are built via [`CallbackResponse::new`] and [`CallbackResponse::cached`],
+/// this allows new fields to be added without breaking the API.
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub struct CallbackResponse {
     /// The data to be given back to the waPC guest
     pub payload: Vec<u8>,
+    /// Whether the response has been served from a cache
+    pub was_cached: bool,
+}
+
+impl CallbackResponse {
+    /// A response that has just been computed
+    pub fn new(payload: Vec<u8>) -> Self {
+        Self {
+            payload,
+            was_cached: false,
+        }
+    }
+
+    /// A response that has been served from a cache
+    pub fn cached(payload: Vec<u8>) -> Self {
+        Self {
+            payload,
+            was_cached: true,
+        }
+    }
 }
 
 /// A request sent by some synchronous code (usually waPC's host_callback)
@@ -1,4 +1,5 @@
 pub const KUBEWARDEN_CUSTOM_SECTION_METADATA: &str = "io.kubewarden.metadata";
+pub const KUBEWARDEN_CUSTOM_SECTION_SELF_TESTS: &str = "io.kubewarden.self_tests";
 
 pub const KUBEWARDEN_ANNOTATION_POLICY_TITLE: &str = "io.kubewarden.policy.title";
 pub const KUBEWARDEN_ANNOTATION_POLICY_DESCRIPTION: &str = "io.kubewarden.policy.description";
@@ -18,6 +19,10 @@ pub const KUBEWARDEN_ANNOTATION_ARTIFACTHUB_HIDDENUI: &str = "io.kubewarden.hidd
 
 pub const KUBEWARDEN_ANNOTATION_KWCTL_VERSION: &str = "io.kubewarden.kwctl";
 
+pub const KUBEWARDEN_AUDIT_ANNOTATION_MONITOR_MESSAGE: &str = "kubewarden-monitor-message";
+pub const KUBEWARDEN_AUDIT_ANNOTATION_MONITOR_CODE: &str = "kubewarden-monitor-code";
+pub const KUBEWARDEN_AUDIT_ANNOTATION_MONITOR_PATCH: &str = "kubewarden-monitor-patch";
+
 pub const ARTIFACTHUB_ANNOTATION_KUBEWARDEN_MUTATION: &str = "kubewarden/mutation";
 pub const ARTIFACTHUB_ANNOTATION_KUBEWARDEN_CONTEXTAWARE_RESOURCES: &str =
     "kubewarden/contextAwareResources";
@@ -1,6 +1,7 @@
 use thiserror::Error;
 
 use crate::policy_evaluator::errors::InvalidUserInputError;
+use crate::policy_evaluator::SelfTestFailure;
 
 #[derive(Error, Debug, PartialEq, Eq)]
 pub enum ArtifactHubError {
@@ -39,6 +40,12 @@ pub enum PolicyEvaluatorError {
 
     #[error("protocol_version is only applicable to a Kubewarden policy")]
     InvokeWapcProtocolVersion(#[source] crate::runtimes::wapc::errors::WapcRuntimeError),
+
+    #[error("cannot invoke 'protocol-version' on WASI policy: {0}")]
+    InvokeWasiProtocolVersion(#[source] crate::runtimes::wasi_cli::errors::WasiRuntimeError),
+
+    #[error("cannot read the OPA Wasm ABI version: {0}")]
+    OpaAbiVersion(#[source] burrego::errors::BurregoError),
 }
 
 #[derive(Error, Debug)]
@@ -52,12 +59,42 @@ pub enum PolicyEvaluatorBuilderError {
     #[error(transparent)]
     InvalidUserInput(#[from] InvalidUserInputError),
 
+    #[error("cannot read policy file: {0}")]
+    ReadPolicyFile(#[source] std::io::Error),
+
+    #[error("cannot read policy metadata: {0}")]
+    Metadata(#[source] MetadataError),
+
+    #[error("cannot read policy self tests: {0}")]
+    SelfTests(#[source] MetadataError),
+
+    #[error("policy failed its self tests: {0}")]
+    SelfTestFailed(#[source] PolicySelfTestError),
+
     #[error("error when creating wasmtime engine: {0}")]
     WasmtimeEngineBuild(#[source] wasmtime::Error),
 
     #[error("error when building wasm module: {0}")]
     WasmModuleBuild(#[source] wasmtime::Error),
 
+    #[error("cannot access precompiled module cache: {0}")]
+    PrecompiledCache(#[source] std::io::Error),
+
+    #[error("cannot start the epoch ticker thread: {0}")]
+    EpochTicker(#[source] std::io::Error),
+
+    #[error("invalid settings schema: {0}")]
+    SettingsSchema(String),
+
+    #[error("cannot read the exports of the policy: {0}")]
+    ReadModuleExports(#[source] wasmparser::BinaryReaderError),
+
+    #[error("cannot detect execution mode: OPA and Gatekeeper policies cannot be told apart, the execution mode must be set explicitly")]
+    AmbiguousExecutionMode,
+
+    #[error("cannot detect execution mode: the policy has no metadata and doesn't export any known entrypoint")]
+    UnknownExecutionMode,
+
     #[error("error when building wapc precompiled stack: {0}")]
     NewWapcStackPre(#[source] crate::runtimes::wapc::errors::WapcRuntimeError),
 
@@ -77,6 +114,42 @@ pub enum PolicyEvaluatorPreError {
     RehydrateRego(#[source] crate::runtimes::rego::errors::RegoRuntimeError),
 }
 
+#[derive(Error, Debug)]
+pub enum PolicySelfTestError {
+    #[error("cannot rehydrate policy evaluator: {0}")]
+    Rehydrate(#[source] PolicyEvaluatorPreError),
+
+    #[error("self test `{name}` has an invalid request: {error}")]
+    InvalidRequest {
+        name: String,
+        #[source]
+        error: serde_json::Error,
+    },
+
+    #[error("policy self tests failed: {}", join_failures(.0))]
+    Failed(Vec<SelfTestFailure>),
+}
+
+fn join_failures(failures: &[SelfTestFailure]) -> String {
+    failures
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[derive(Error, Debug)]
+pub enum PolicyEvaluatorPoolError {
+    #[error("all the policy evaluators of the pool are in use")]
+    Exhausted,
+
+    #[error("the policy evaluator pool has been closed")]
+    Closed,
+
+    #[error("cannot add a new policy evaluator to the pool: {0}")]
+    Rehydrate(#[source] PolicyEvaluatorPreError),
+}
+
 #[derive(Error, Debug)]
 pub enum MetadataError {
     #[error("cannot read metadata from path: {0}")]
@@ -97,4 +170,143 @@ pub enum MetadataError {
 pub enum ResponseError {
     #[error("cannot deserialize JSONPatch: {0}")]
     Deserialize(#[source] serde_json::Error),
+
+    #[error("the policy returned both a mutated object and a patch")]
+    MutatedObjectAndPatch,
+
+    #[error("invalid patch returned by the policy: {0}")]
+    GuestPatch(#[source] GuestPatchError),
+}
+
+#[derive(Error, Debug)]
+pub enum GuestPatchError {
+    #[error("unsupported patch type `{0}`, expected `JSONPatch` or `MergePatch`")]
+    UnsupportedType(String),
+
+    #[error("the patch is not base64 encoded: {0}")]
+    Base64(#[source] base64::DecodeError),
+
+    #[error("cannot deserialize patch: {0}")]
+    Deserialize(#[source] serde_json::Error),
+
+    #[error("cannot serialize patch: {0}")]
+    Serialize(#[source] serde_json::Error),
+
+    #[error("the request does not have an object to patch")]
+    MissingObject,
+
+    #[error("the patch cannot be applied to the object of the request: {0}")]
+    Apply(#[source] json_patch::PatchError),
+}
+
+#[derive(Error, Debug)]
+pub enum AdmissionReviewError {
+    #[error("cannot deserialize AdmissionReview: {0}")]
+    Deserialize(#[source] serde_json::Error),
+
+    #[error("unsupported AdmissionReview apiVersion `{0}`")]
+    UnsupportedVersion(String),
+
+    #[error("unexpected kind `{0}`, expected `AdmissionReview`")]
+    UnexpectedKind(String),
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum PolicyGroupExpressionError {
+    #[error("the expression is empty")]
+    Empty,
+
+    #[error("unexpected end of the expression")]
+    UnexpectedEnd,
+
+    #[error("unexpected token `{token}` at offset {offset}")]
+    UnexpectedToken { token: String, offset: usize },
+
+    #[error("invalid character `{character}` at offset {offset}")]
+    InvalidCharacter { character: char, offset: usize },
+
+    #[error("the expression is nested more than {limit} levels deep at offset {offset}")]
+    NestingTooDeep { limit: usize, offset: usize },
+}
+
+#[derive(Error, Debug)]
+pub enum PolicyGroupEvaluatorBuilderError {
+    #[error("invalid policy group expression: {0}")]
+    InvalidExpression(#[source] PolicyGroupExpressionError),
+
+    #[error("the policy group does not have any member")]
+    NoMembers,
+
+    #[error("invalid member name `{0}`: names must start with a letter or an underscore and contain only alphanumeric characters, `-` and `_`")]
+    InvalidMemberName(String),
+
+    #[error("member `{0}` is defined more than once")]
+    DuplicateMember(String),
+
+    #[error("the expression references the unknown member `{0}`")]
+    UnknownMember(String),
+
+    #[error(
+        "member `{0}` is a mutating policy, policy groups can only be made of validating policies"
+    )]
+    MutatingMember(String),
+}
+
+#[derive(Error, Debug)]
+pub enum PolicyChainEvaluatorBuilderError {
+    #[error("the policy chain does not have any policy")]
+    NoPolicies,
+
+    #[error("policy `{0}` is defined more than once")]
+    DuplicatePolicy(String),
+}
+
+#[derive(Error, Debug)]
+pub enum PolicySelectorsError {
+    #[error("unknown label selector operator `{0}`")]
+    UnknownOperator(String),
+
+    #[error("label selector operator `{operator}` requires at least one value for key `{key}`")]
+    MissingValues { key: String, operator: String },
+
+    #[error("label selector operator `{operator}` does not accept values for key `{key}`")]
+    UnexpectedValues { key: String, operator: String },
+
+    #[error("the labels of namespace `{0}` are required to evaluate the namespace selector")]
+    MissingNamespace(String),
+
+    #[error("a callback channel is required to fetch namespace `{0}`")]
+    MissingCallbackChannel(String),
+
+    #[error("error sending request over callback channel: {0}")]
+    CallbackSend(String),
+
+    #[error("error obtaining response from callback channel: {0}")]
+    CallbackResponse(String),
+
+    #[error("cannot fetch namespace `{name}`: {error}")]
+    FetchNamespace {
+        name: String,
+        #[source]
+        error: anyhow::Error,
+    },
+
+    #[error("cannot convert callback response into a namespace: {0}")]
+    CallbackConvertNamespace(#[source] serde_json::Error),
+}
+
+#[derive(Error, Debug)]
+pub enum SwappablePolicyError {
+    #[error("cannot rehydrate policy evaluator: {0}")]
+    Rehydrate(#[source] PolicyEvaluatorPreError),
+
+    #[error("policy version `{version}` failed its self tests: {error}")]
+    SelfTest {
+        version: String,
+        #[source]
+        error: PolicySelfTestError,
+    },
+
+    #[error("policy version `{version}` rejected the current settings: {message}")]
+    InvalidSettings { version: String, message: String },
 }
@@ -1,10 +1,57 @@
+use chrono::{DateTime, TimeDelta, Utc};
 use std::collections::BTreeSet;
 use std::fmt;
 use tokio::sync::mpsc;
 
 use crate::callback_requests::CallbackRequest;
+use crate::patch::{ArrayKeys, ProtectedPaths};
 use crate::policy_metadata::ContextAwareResource;
 
+/// The mode used to evaluate a policy
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PolicyMode {
+    /// Rejections and mutations are enforced
+    #[default]
+    Protect,
+    /// Rejections are turned into warnings and mutations are not returned, see
+    /// [`AdmissionResponse::into_monitor_response`](crate::admission_response::AdmissionResponse::into_monitor_response).
+    /// This allows to observe the behavior of a policy without affecting the cluster
+    Monitor,
+}
+
+/// The source of the current time used while evaluating a policy.
+///
+/// The clock is used by the Rego builtins reading the current time, like `time.now_ns`,
+/// and by the host capabilities verifying certificates. It does not affect the clock
+/// exposed to waPC and WASI guests.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EvaluationClock {
+    /// Use the system clock
+    #[default]
+    System,
+    /// The time is frozen at the given instant
+    Frozen(DateTime<Utc>),
+    /// Use the system clock, shifted by the given amount of time
+    Offset(TimeDelta),
+}
+
+impl EvaluationClock {
+    /// The current time, according to this clock
+    pub fn now(&self) -> DateTime<Utc> {
+        match self {
+            EvaluationClock::System => Utc::now(),
+            EvaluationClock::Frozen(instant) => *instant,
+            EvaluationClock::Offset(offset) => Utc::now() + *offset,
+        }
+    }
+}
+
+impl burrego::Clock for EvaluationClock {
+    fn now(&self) -> DateTime<Utc> {
+        EvaluationClock::now(self)
+    }
+}
+
 /// A struct that holds metadata and other data that are needed when a policy
 /// is being evaluated
 #[derive(Clone, Default)]
@@ -22,6 +69,19 @@ pub struct EvaluationContext {
 
     /// List of ContextAwareResource the policy is granted access to.
     pub ctx_aware_resources_allow_list: BTreeSet<ContextAwareResource>,
+
+    /// The mode used to evaluate the policy
+    pub policy_mode: PolicyMode,
+
+    /// The clock used to evaluate the policy, see [`EvaluationClock`]
+    pub clock: EvaluationClock,
+
+    /// The fields of the object that the policy is not allowed to mutate, see [`ProtectedPaths`]
+    pub protected_paths: ProtectedPaths,
+
+    /// How the elements of the arrays are aligned when the object mutated by the policy
+    /// is turned into a patch, see [`ArrayKeys`]
+    pub array_keys: ArrayKeys,
 }
 
 impl EvaluationContext {
@@ -47,8 +107,14 @@ impl fmt::Debug for EvaluationContext {
 
         write!(
             f,
-            r#"EvaluationContext {{ policy_id: "{}", callback_channel: {}, allowed_kubernetes_resources: {:?} }}"#,
-            self.policy_id, callback_channel, self.ctx_aware_resources_allow_list,
+            r#"EvaluationContext {{ policy_id: "{}", callback_channel: {}, allowed_kubernetes_resources: {:?}, policy_mode: {:?}, clock: {:?}, protected_paths: {:?}, array_keys: {:?} }}"#,
+            self.policy_id,
+            callback_channel,
+            self.ctx_aware_resources_allow_list,
+            self.policy_mode,
+            self.clock,
+            self.protected_paths,
+            self.array_keys,
         )
     }
 }
@@ -94,6 +160,10 @@ mod tests {
             policy_id: name.to_string(),
             callback_channel: None,
             ctx_aware_resources_allow_list: allowed_resources,
+            policy_mode: Default::default(),
+            clock: Default::default(),
+            protected_paths: Default::default(),
+            array_keys: Default::default(),
         };
 
         let requested_resource = ContextAwareResource {
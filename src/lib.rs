@@ -3,16 +3,23 @@ extern crate wasmparser;
 
 pub mod admission_request;
 pub mod admission_response;
+pub mod admission_review;
 pub mod callback_handler;
 pub mod callback_requests;
 pub mod constants;
 pub mod errors;
 pub mod evaluation_context;
+pub mod patch;
 pub mod policy_artifacthub;
+pub mod policy_chain_evaluator;
 pub mod policy_evaluator;
+pub mod policy_group_evaluator;
 pub mod policy_metadata;
+pub mod policy_selectors;
 mod policy_tracing;
 pub mod runtimes;
+#[cfg(test)]
+mod test_fixtures;
 
 // API's that expose other crate types (such as Kubewarden Policy SDK
 // or `policy_fetcher`) can either implement their own exposed types,
@@ -30,4 +37,4 @@ pub use kubewarden_policy_sdk::metadata::ProtocolVersion;
 pub use policy_evaluator::policy_evaluator_builder;
 pub use policy_fetcher;
 pub use validator;
-pub use wasmtime_provider::wasmtime;
+pub use wasmtime;
@@ -0,0 +1,616 @@
+use std::collections::{HashMap, HashSet};
+
+use base64::{engine::general_purpose, Engine as _};
+use json_patch::jsonptr::PointerBuf;
+use json_patch::{AddOperation, MoveOperation, PatchOperation, RemoveOperation, ReplaceOperation};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::errors::GuestPatchError;
+
+/// The key used to align the elements of the arrays when none is configured
+/// for their path. This covers containers, volumes, env vars and the other
+/// lists of named objects found inside of Kubernetes resources
+pub const DEFAULT_ARRAY_KEY: &str = "name";
+
+/// Configure how the elements of the arrays are aligned by [`diff`].
+///
+/// The elements of an array are aligned by key when they are all objects with
+/// a unique value for the key, otherwise they are compared by position.
+///
+/// The paths are JSON pointers where the array indexes are replaced by `*`,
+/// like `/spec/template/spec/containers/*/ports`.
+#[derive(Clone, Debug)]
+pub struct ArrayKeys {
+    default_key: Option<String>,
+    keys: HashMap<String, Option<String>>,
+}
+
+impl Default for ArrayKeys {
+    fn default() -> Self {
+        ArrayKeys {
+            default_key: Some(DEFAULT_ARRAY_KEY.to_string()),
+            keys: HashMap::new(),
+        }
+    }
+}
+
+impl ArrayKeys {
+    /// Align the elements of the array found at `path` by `key`
+    #[must_use]
+    pub fn key(mut self, path: &str, key: &str) -> Self {
+        self.keys.insert(path.to_string(), Some(key.to_string()));
+        self
+    }
+
+    /// Compare the elements of the array found at `path` by position
+    #[must_use]
+    pub fn positional(mut self, path: &str) -> Self {
+        self.keys.insert(path.to_string(), None);
+        self
+    }
+
+    fn key_for(&self, path: &str) -> Option<&str> {
+        self.keys.get(path).unwrap_or(&self.default_key).as_deref()
+    }
+}
+
+/// Compute the JSONPatch (RFC 6902) turning `from` into `to`.
+///
+/// Unlike [`json_patch::diff`], the elements of the arrays are aligned by key,
+/// see [`ArrayKeys`]. Adding, removing or changing an element of a keyed array
+/// produces operations targeting only that element, instead of replacing the
+/// elements following it.
+pub fn diff(from: &Value, to: &Value, array_keys: &ArrayKeys) -> json_patch::Patch {
+    let mut operations = Vec::new();
+    Differ {
+        array_keys,
+        operations: &mut operations,
+    }
+    .diff(from, to, &mut Path::default());
+
+    json_patch::Patch(operations)
+}
+
+/// A patch returned by the policy, as an alternative to the mutated object
+#[derive(Clone, Debug, PartialEq)]
+pub enum GuestPatch {
+    /// A JSON Patch, see RFC 6902
+    JsonPatch(json_patch::Patch),
+    /// A JSON Merge Patch, see RFC 7386
+    MergePatch(Value),
+}
+
+/// The fields holding the patch inside of the response of the policy. The `patchType`
+/// spelling is the one used by the `AdmissionResponse` returned by OPA policies
+#[derive(Deserialize)]
+struct GuestPatchFields {
+    #[serde(default)]
+    patch: Option<Value>,
+    #[serde(default, alias = "patchType")]
+    patch_type: Option<String>,
+}
+
+impl GuestPatch {
+    /// Read the patch from the response of the policy. The `patch` field holds either
+    /// a JSON document or its base64 encoding, while the `patch_type` field is either
+    /// `JSONPatch`, the default, or `MergePatch`.
+    ///
+    /// Returns `None` when the response doesn't have a patch.
+    pub fn from_response(response: &Value) -> Result<Option<GuestPatch>, GuestPatchError> {
+        let fields =
+            GuestPatchFields::deserialize(response).map_err(GuestPatchError::Deserialize)?;
+        let patch = match fields.patch {
+            None | Some(Value::Null) => return Ok(None),
+            Some(Value::String(encoded)) => {
+                let decoded = general_purpose::STANDARD
+                    .decode(encoded)
+                    .map_err(GuestPatchError::Base64)?;
+                serde_json::from_slice(&decoded).map_err(GuestPatchError::Deserialize)?
+            }
+            Some(patch) => patch,
+        };
+
+        match fields.patch_type.as_deref() {
+            None | Some("JSONPatch") => serde_json::from_value(patch)
+                .map(GuestPatch::JsonPatch)
+                .map(Some)
+                .map_err(GuestPatchError::Deserialize),
+            Some("MergePatch") => Ok(Some(GuestPatch::MergePatch(patch))),
+            Some(patch_type) => Err(GuestPatchError::UnsupportedType(patch_type.to_string())),
+        }
+    }
+
+    /// Ensure the patch can be applied to the object of the request, and convert it
+    /// into the base64 encoded JSONPatch expected by the Kubernetes API server.
+    ///
+    /// The JSON Patch operations are kept as they are, while a merge patch is turned into
+    /// the JSON Patch operations producing the same object, see [`diff`].
+    /// Returns `None` when the patch doesn't change the object.
+    pub fn normalize(
+        &self,
+        object: Option<&Value>,
+        array_keys: &ArrayKeys,
+    ) -> Result<Option<String>, GuestPatchError> {
+        let object = object.ok_or(GuestPatchError::MissingObject)?;
+        let (_, patch) = self.apply(object, array_keys)?;
+        encode(&patch).map_err(GuestPatchError::Serialize)
+    }
+
+    /// Apply the patch to the object, returns the patched object together with
+    /// the equivalent JSON Patch, see [`normalize`](GuestPatch::normalize)
+    pub fn apply(
+        &self,
+        object: &Value,
+        array_keys: &ArrayKeys,
+    ) -> Result<(Value, json_patch::Patch), GuestPatchError> {
+        let mut patched = object.clone();
+
+        let patch = match self {
+            GuestPatch::JsonPatch(patch) => {
+                json_patch::patch(&mut patched, &patch.0).map_err(GuestPatchError::Apply)?;
+                patch.clone()
+            }
+            GuestPatch::MergePatch(merge_patch) => {
+                json_patch::merge(&mut patched, merge_patch);
+                diff(object, &patched, array_keys)
+            }
+        };
+
+        Ok((patched, patch))
+    }
+}
+
+/// Encode the patch in the format expected by the `patch` field of the
+/// `AdmissionResponse`. Returns `None` when the patch is empty
+pub(crate) fn encode(patch: &json_patch::Patch) -> Result<Option<String>, serde_json::Error> {
+    if patch.0.is_empty() {
+        return Ok(None);
+    }
+    let patch = serde_json::to_string(patch)?;
+    Ok(Some(general_purpose::STANDARD.encode(patch)))
+}
+
+/// The fields of the Kubernetes objects that mutating policies should not change:
+/// the ones defining the identity of the object, plus its status
+pub const RECOMMENDED_PROTECTED_PATHS: &[&str] = &[
+    "/apiVersion",
+    "/kind",
+    "/metadata/name",
+    "/metadata/namespace",
+    "/metadata/uid",
+    "/status",
+];
+
+/// The JSON pointers of the fields that cannot be changed by the mutating policies.
+///
+/// A protected field cannot be added, removed or changed, neither directly nor by
+/// replacing one of its parents.
+///
+/// No field is protected by default, the protection is opted in via
+/// [`EvaluationContext::protected_paths`](crate::evaluation_context::EvaluationContext::protected_paths),
+/// see [`ProtectedPaths::recommended`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProtectedPaths(Vec<String>);
+
+impl ProtectedPaths {
+    pub fn new<I, S>(paths: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        ProtectedPaths(paths.into_iter().map(Into::into).collect())
+    }
+
+    /// Protect the [`RECOMMENDED_PROTECTED_PATHS`]
+    pub fn recommended() -> Self {
+        ProtectedPaths::new(RECOMMENDED_PROTECTED_PATHS.iter().copied())
+    }
+
+    /// Returns the protected paths whose value differs between the original object
+    /// and the patched one
+    pub fn violations(&self, original: &Value, patched: &Value) -> Vec<String> {
+        self.0
+            .iter()
+            .filter(|path| original.pointer(path) != patched.pointer(path))
+            .cloned()
+            .collect()
+    }
+}
+
+/// The location being compared, both as a JSON pointer and as the pattern
+/// used to look up the array keys
+#[derive(Default)]
+struct Path {
+    pointer: PointerBuf,
+    pattern: String,
+}
+
+impl Path {
+    /// Descend into `token`, returns the length of the pattern to be given back to `pop`
+    fn push(&mut self, token: &str, is_index: bool) -> usize {
+        let length = self.pattern.len();
+        self.pointer.push_back(token);
+        self.pattern.push('/');
+        if is_index {
+            self.pattern.push('*');
+        } else {
+            self.pattern
+                .push_str(&token.replace('~', "~0").replace('/', "~1"));
+        }
+        length
+    }
+
+    fn pop(&mut self, length: usize) {
+        self.pointer.pop_back();
+        self.pattern.truncate(length);
+    }
+
+    fn child(&self, index: usize) -> PointerBuf {
+        let mut child = self.pointer.clone();
+        child.push_back(index.to_string());
+        child
+    }
+}
+
+struct Differ<'a> {
+    array_keys: &'a ArrayKeys,
+    operations: &'a mut Vec<PatchOperation>,
+}
+
+impl Differ<'_> {
+    fn diff(&mut self, from: &Value, to: &Value, path: &mut Path) {
+        if from == to {
+            return;
+        }
+
+        match (from, to) {
+            (Value::Object(from), Value::Object(to)) => {
+                for key in from.keys().filter(|key| !to.contains_key(*key)) {
+                    let length = path.push(key, false);
+                    self.remove(path.pointer.clone());
+                    path.pop(length);
+                }
+                for (key, to_value) in to {
+                    let length = path.push(key, false);
+                    match from.get(key) {
+                        Some(from_value) => self.diff(from_value, to_value, path),
+                        None => self.add(path.pointer.clone(), to_value),
+                    }
+                    path.pop(length);
+                }
+            }
+            (Value::Array(from), Value::Array(to)) => {
+                match self.array_keys.key_for(&path.pattern) {
+                    Some(key) if is_keyed(from, key) && is_keyed(to, key) => {
+                        self.diff_keyed_arrays(from, to, key, path)
+                    }
+                    _ => self.diff_arrays(from, to, path),
+                }
+            }
+            _ => self.replace(path.pointer.clone(), to),
+        }
+    }
+
+    /// Compare the arrays element by element
+    fn diff_arrays(&mut self, from: &[Value], to: &[Value], path: &mut Path) {
+        for (index, (from_value, to_value)) in from.iter().zip(to).enumerate() {
+            let length = path.push(&index.to_string(), true);
+            self.diff(from_value, to_value, path);
+            path.pop(length);
+        }
+        for index in (to.len()..from.len()).rev() {
+            self.remove(path.child(index));
+        }
+        for (index, to_value) in to.iter().enumerate().skip(from.len()) {
+            self.add(path.child(index), to_value);
+        }
+    }
+
+    /// Align the elements of the arrays by key. The elements that are no longer
+    /// present are removed first, then the array is rebuilt following the order
+    /// of `to`, adding and moving elements where needed
+    fn diff_keyed_arrays(&mut self, from: &[Value], to: &[Value], key: &str, path: &mut Path) {
+        let to_keys: HashSet<String> = to.iter().map(|value| value[key].to_string()).collect();
+
+        let mut current: Vec<&Value> = from.iter().collect();
+        for index in (0..current.len()).rev() {
+            if !to_keys.contains(&current[index][key].to_string()) {
+                self.remove(path.child(index));
+                current.remove(index);
+            }
+        }
+
+        for (index, to_value) in to.iter().enumerate() {
+            let position = current
+                .iter()
+                .skip(index)
+                .position(|value| value[key] == to_value[key])
+                .map(|position| position + index);
+            match position {
+                Some(position) => {
+                    if position != index {
+                        self.move_to(path.child(position), path.child(index));
+                        let value = current.remove(position);
+                        current.insert(index, value);
+                    }
+                    let length = path.push(&index.to_string(), true);
+                    self.diff(current[index], to_value, path);
+                    path.pop(length);
+                }
+                None => {
+                    self.add(path.child(index), to_value);
+                    current.insert(index, to_value);
+                }
+            }
+        }
+    }
+
+    fn add(&mut self, path: PointerBuf, value: &Value) {
+        self.operations.push(PatchOperation::Add(AddOperation {
+            path,
+            value: value.clone(),
+        }));
+    }
+
+    fn remove(&mut self, path: PointerBuf) {
+        self.operations
+            .push(PatchOperation::Remove(RemoveOperation { path }));
+    }
+
+    fn replace(&mut self, path: PointerBuf, value: &Value) {
+        self.operations
+            .push(PatchOperation::Replace(ReplaceOperation {
+                path,
+                value: value.clone(),
+            }));
+    }
+
+    fn move_to(&mut self, from: PointerBuf, path: PointerBuf) {
+        self.operations
+            .push(PatchOperation::Move(MoveOperation { from, path }));
+    }
+}
+
+/// Returns true when all the elements are objects with a unique value for the key
+fn is_keyed(values: &[Value], key: &str) -> bool {
+    let mut keys = HashSet::new();
+    values.iter().all(|value| match value.get(key) {
+        Some(key) if !key.is_null() => keys.insert(key.to_string()),
+        _ => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use serde_json::json;
+
+    fn pod(containers: Value) -> Value {
+        json!({
+            "apiVersion": "v1",
+            "kind": "Pod",
+            "metadata": {"name": "nginx", "labels": {"app": "nginx"}},
+            "spec": {"containers": containers}
+        })
+    }
+
+    #[rstest]
+    #[case::equal(json!({"a": 1}), json!({"a": 1}))]
+    #[case::add_field(json!({"a": 1}), json!({"a": 1, "b": {"c": [1, 2]}}))]
+    #[case::remove_field(json!({"a": 1, "b": 2}), json!({"a": 1}))]
+    #[case::replace_scalar(json!({"a": 1}), json!({"a": "1"}))]
+    #[case::replace_root(json!([1, 2]), json!({"a": 1}))]
+    #[case::escaped_keys(json!({"a/b": 1, "c~d": 2}), json!({"a/b": 2}))]
+    #[case::grow_positional(json!([1, 2]), json!([1, 2, 3, 4]))]
+    #[case::shrink_positional(json!([1, 2, 3, 4]), json!([2]))]
+    #[case::add_env_var(
+        pod(json!([{"name": "nginx", "env": [{"name": "A", "value": "1"}]}])),
+        pod(json!([{"name": "nginx", "env": [{"name": "B", "value": "2"}, {"name": "A", "value": "1"}]}]))
+    )]
+    #[case::remove_container(
+        pod(json!([{"name": "a"}, {"name": "b"}, {"name": "c"}])),
+        pod(json!([{"name": "a"}, {"name": "c"}]))
+    )]
+    #[case::reorder_containers(
+        pod(json!([{"name": "a"}, {"name": "b"}, {"name": "c"}])),
+        pod(json!([{"name": "c", "image": "busybox"}, {"name": "a"}, {"name": "b"}]))
+    )]
+    #[case::replace_containers(
+        pod(json!([{"name": "a"}, {"name": "b"}])),
+        pod(json!([{"name": "d"}, {"name": "b", "image": "nginx"}, {"name": "e"}]))
+    )]
+    #[case::duplicate_keys(
+        pod(json!([{"name": "a"}, {"name": "a", "image": "nginx"}])),
+        pod(json!([{"name": "a", "image": "busybox"}]))
+    )]
+    #[case::mixed_elements(json!([{"name": "a"}, 1]), json!([1, {"name": "a"}]))]
+    fn apply_diff(#[case] from: Value, #[case] to: Value) {
+        let patch = diff(&from, &to, &ArrayKeys::default());
+
+        let mut patched = from.clone();
+        json_patch::patch(&mut patched, &patch.0).unwrap();
+        assert_eq!(to, patched);
+    }
+
+    fn decode(patch: &str) -> Value {
+        serde_json::from_slice(&general_purpose::STANDARD.decode(patch).unwrap()).unwrap()
+    }
+
+    #[rstest]
+    #[case::json_patch(json!({"patch": [{"op": "add", "path": "/b", "value": 2}]}))]
+    #[case::json_patch_base64(json!({
+        "patchType": "JSONPatch",
+        "patch": general_purpose::STANDARD.encode(r#"[{"op": "add", "path": "/b", "value": 2}]"#)
+    }))]
+    #[case::merge_patch(json!({"patch_type": "MergePatch", "patch": {"b": 2}}))]
+    fn normalize_guest_patch(#[case] response: Value) {
+        let patch = GuestPatch::from_response(&response).unwrap().unwrap();
+
+        let normalized = patch
+            .normalize(Some(&json!({"a": 1})), &ArrayKeys::default())
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(
+            json!([{"op": "add", "path": "/b", "value": 2}]),
+            decode(&normalized)
+        );
+    }
+
+    #[test]
+    fn merge_patch_aligns_arrays_by_key() {
+        let from = pod(json!([{"name": "a"}, {"name": "b"}]));
+        let merge_patch = GuestPatch::MergePatch(json!({
+            "metadata": {"labels": null},
+            "spec": {"containers": [{"name": "a"}, {"name": "b"}, {"name": "c"}]}
+        }));
+
+        let normalized = merge_patch
+            .normalize(Some(&from), &ArrayKeys::default())
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(
+            json!([
+                {"op": "remove", "path": "/metadata/labels"},
+                {"op": "add", "path": "/spec/containers/2", "value": {"name": "c"}}
+            ]),
+            decode(&normalized)
+        );
+    }
+
+    #[test]
+    fn response_without_patch() {
+        assert_eq!(
+            None,
+            GuestPatch::from_response(&json!({"accepted": true})).unwrap()
+        );
+    }
+
+    #[test]
+    fn reject_unsupported_patch_type() {
+        assert!(matches!(
+            GuestPatch::from_response(&json!({"patch_type": "StrategicMergePatch", "patch": {}})),
+            Err(GuestPatchError::UnsupportedType(patch_type)) if patch_type == "StrategicMergePatch"
+        ));
+    }
+
+    #[rstest]
+    #[case::missing_path(Some(json!({"a": 1})))]
+    #[case::missing_object(None)]
+    fn reject_patch_not_matching_the_object(#[case] object: Option<Value>) {
+        let patch =
+            GuestPatch::from_response(&json!({"patch": [{"op": "remove", "path": "/missing"}]}))
+                .unwrap()
+                .unwrap();
+
+        assert!(patch
+            .normalize(object.as_ref(), &ArrayKeys::default())
+            .is_err());
+    }
+
+    #[rstest]
+    #[case::labels_changed(json!({"metadata": {"labels": {"app": "busybox"}}}), vec![])]
+    #[case::renamed(json!({"metadata": {"name": "busybox"}}), vec!["/metadata/name"])]
+    #[case::namespace_added(json!({"metadata": {"namespace": "default"}}), vec!["/metadata/namespace"])]
+    #[case::uid_added(json!({"metadata": {"uid": "1234"}}), vec!["/metadata/uid"])]
+    #[case::status_added(json!({"status": {"phase": "Running"}}), vec!["/status"])]
+    #[case::parent_removed(json!({"metadata": null}), vec!["/metadata/name"])]
+    fn protected_paths_violations(#[case] merge_patch: Value, #[case] expected: Vec<&str>) {
+        let original = pod(json!([]));
+        let mut patched = original.clone();
+        json_patch::merge(&mut patched, &merge_patch);
+
+        assert_eq!(
+            expected,
+            ProtectedPaths::recommended().violations(&original, &patched)
+        );
+    }
+
+    #[test]
+    fn no_path_is_protected_by_default() {
+        let original = pod(json!([]));
+        let mut patched = original.clone();
+        json_patch::merge(
+            &mut patched,
+            &json!({"metadata": {"name": "busybox"}, "status": {"phase": "Running"}}),
+        );
+
+        assert!(ProtectedPaths::default()
+            .violations(&original, &patched)
+            .is_empty());
+    }
+
+    #[test]
+    fn protected_paths_are_configurable() {
+        let original = json!({"spec": {"replicas": 1}});
+        let patched = json!({"spec": {"replicas": 2}});
+
+        assert!(ProtectedPaths::default()
+            .violations(&original, &patched)
+            .is_empty());
+        assert_eq!(
+            vec!["/spec/replicas"],
+            ProtectedPaths::new(["/spec/replicas"]).violations(&original, &patched)
+        );
+    }
+
+    #[test]
+    fn adding_an_env_var_produces_a_single_operation() {
+        let from = pod(json!([
+            {"name": "sidecar"},
+            {"name": "nginx", "env": [{"name": "A", "value": "1"}, {"name": "C", "value": "3"}]}
+        ]));
+        let to = pod(json!([
+            {"name": "sidecar"},
+            {"name": "nginx", "env": [
+                {"name": "A", "value": "1"},
+                {"name": "B", "value": "2"},
+                {"name": "C", "value": "3"}
+            ]}
+        ]));
+
+        let patch = diff(&from, &to, &ArrayKeys::default());
+
+        assert_eq!(
+            json!([{"op": "add", "path": "/spec/containers/1/env/1", "value": {"name": "B", "value": "2"}}]),
+            serde_json::to_value(patch).unwrap()
+        );
+    }
+
+    #[test]
+    fn removing_a_container_produces_a_single_operation() {
+        let from = pod(json!([{"name": "a"}, {"name": "b"}, {"name": "c"}]));
+        let to = pod(json!([{"name": "b"}, {"name": "c"}]));
+
+        let patch = diff(&from, &to, &ArrayKeys::default());
+
+        assert_eq!(
+            json!([{"op": "remove", "path": "/spec/containers/0"}]),
+            serde_json::to_value(patch).unwrap()
+        );
+    }
+
+    #[test]
+    fn array_keys_are_configurable_per_path() {
+        let from = json!({"ports": [{"port": 80}, {"port": 443}]});
+        let to = json!({"ports": [{"port": 443}]});
+
+        let keyed = diff(&from, &to, &ArrayKeys::default().key("/ports", "port"));
+        assert_eq!(
+            json!([{"op": "remove", "path": "/ports/0"}]),
+            serde_json::to_value(keyed).unwrap()
+        );
+
+        let positional = diff(&from, &to, &ArrayKeys::default().positional("/ports"));
+        assert_eq!(
+            json!([
+                {"op": "replace", "path": "/ports/0/port", "value": 443},
+                {"op": "remove", "path": "/ports/1"}
+            ]),
+            serde_json::to_value(positional).unwrap()
+        );
+    }
+}
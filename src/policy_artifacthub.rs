@@ -521,6 +521,7 @@ mod tests {
             execution_mode: Default::default(),
             policy_type: PolicyType::Kubernetes,
             minimum_kubewarden_version: None,
+            settings_schema: None,
         }
     }
 
@@ -585,6 +586,7 @@ mod tests {
             context_aware_resources,
             execution_mode: Default::default(),
             minimum_kubewarden_version: None,
+            settings_schema: None,
             policy_type: Default::default(),
         }
     }
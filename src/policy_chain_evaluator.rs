@@ -0,0 +1,17 @@
+//! Evaluate a chain of mutating policies.
+//!
+//! The policies of a chain are evaluated in order: each policy receives the object
+//! produced by the mutations of the policies that come before it. The evaluation
+//! stops as soon as a policy rejects the request.
+//!
+//! When the request is accepted, the chain returns a single patch: the difference
+//! between the original object and the one produced by the whole chain. The changes
+//! done by each policy are reported too, see [`PolicyChainStep`].
+//!
+//! See [`PolicyChainEvaluatorBuilder`] for more details.
+
+mod evaluator;
+mod policy_chain_evaluator_builder;
+
+pub use evaluator::{PolicyChainEvaluator, PolicyChainResponse, PolicyChainStep};
+pub use policy_chain_evaluator_builder::PolicyChainEvaluatorBuilder;
@@ -0,0 +1,517 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose, Engine as _};
+use json_patch::PatchOperation;
+use k8s_openapi::apimachinery::pkg::runtime::RawExtension;
+use tracing::{debug, warn};
+
+use crate::admission_request::AdmissionRequest;
+use crate::admission_response::{AdmissionResponse, PatchType};
+use crate::evaluation_context::{EvaluationContext, PolicyMode};
+use crate::patch::{self, ArrayKeys};
+use crate::policy_evaluator::{PolicyEvaluatorPre, PolicySettings, ValidateRequest};
+
+/// A policy that is part of a chain
+pub(crate) struct PolicyChainLink {
+    pub(crate) name: String,
+    pub(crate) evaluator_pre: PolicyEvaluatorPre,
+    pub(crate) settings: PolicySettings,
+}
+
+/// The outcome of the evaluation of a policy that is part of a chain
+#[derive(Clone, Debug)]
+pub struct PolicyChainStep {
+    /// The name of the policy
+    pub policy: String,
+    /// The response produced by the policy. Its patch is relative to the object
+    /// produced by the policies evaluated before it
+    pub response: AdmissionResponse,
+    /// The JSON pointers of the paths changed by the policy
+    pub mutated_paths: Vec<String>,
+}
+
+/// The outcome of the evaluation of a policy chain
+#[derive(Clone, Debug)]
+pub struct PolicyChainResponse {
+    /// The verdict of the chain. When the request is accepted, its patch is the difference
+    /// between the original object and the one produced by the whole chain
+    pub response: AdmissionResponse,
+    /// The policies that have been evaluated, in order
+    pub steps: Vec<PolicyChainStep>,
+}
+
+/// Evaluates a request against a chain of policies, see the
+/// [module documentation](crate::policy_chain_evaluator) for more details.
+///
+/// Instances of this struct are created via the
+/// [`PolicyChainEvaluatorBuilder`](crate::policy_chain_evaluator::PolicyChainEvaluatorBuilder).
+pub struct PolicyChainEvaluator {
+    pub(crate) policies: Vec<PolicyChainLink>,
+}
+
+impl PolicyChainEvaluator {
+    /// Validate the request against the policy chain.
+    ///
+    /// Each policy is given the object mutated by the policies evaluated before it. The
+    /// evaluation stops at the first rejection, the final response then carries the
+    /// status of the policy that rejected the request.
+    ///
+    /// The warnings and the audit annotations produced by the evaluated policies are
+    /// merged into the final response.
+    ///
+    /// The [`PolicyMode`] of the `EvaluationContext` applies to the verdict of the
+    /// chain, the policies are always evaluated in protect mode.
+    #[tracing::instrument(skip(self, request))]
+    pub fn validate(
+        &self,
+        request: ValidateRequest,
+        eval_ctx: &EvaluationContext,
+    ) -> PolicyChainResponse {
+        let uid = request.uid().to_string();
+        let original = request_object(&request);
+
+        let mut object = original.clone();
+        let mut request = request;
+        let mut steps: Vec<PolicyChainStep> = Vec::new();
+
+        for policy in &self.policies {
+            let mut response = self.evaluate_policy(policy, &request, eval_ctx);
+            let mut mutated_paths = Vec::new();
+
+            if let (true, Some(patch)) = (response.allowed, response.patch.as_deref()) {
+                match apply_patch(object.as_mut(), patch) {
+                    Ok(paths) => {
+                        mutated_paths = paths;
+                        if let Some(object) = &object {
+                            request = with_object(&request, object.clone());
+                        }
+                    }
+                    Err(e) => {
+                        warn!(policy = policy.name, error = %e, "cannot apply the patch of policy chain member");
+                        response = AdmissionResponse::reject_internal_server_error(
+                            uid.clone(),
+                            format!("cannot apply the patch of policy `{}`: {e}", policy.name),
+                        );
+                    }
+                }
+            }
+
+            let allowed = response.allowed;
+            steps.push(PolicyChainStep {
+                policy: policy.name.clone(),
+                response,
+                mutated_paths,
+            });
+            if !allowed {
+                break;
+            }
+        }
+
+        let response = build_response(
+            uid.clone(),
+            original.as_ref(),
+            object.as_ref(),
+            &steps,
+            &eval_ctx.array_keys,
+        )
+        .unwrap_or_else(|e| AdmissionResponse::reject_internal_server_error(uid, e.to_string()));
+        let response = match eval_ctx.policy_mode {
+            PolicyMode::Protect => response,
+            PolicyMode::Monitor => response.into_monitor_response(),
+        };
+
+        PolicyChainResponse { response, steps }
+    }
+
+    fn evaluate_policy(
+        &self,
+        policy: &PolicyChainLink,
+        request: &ValidateRequest,
+        eval_ctx: &EvaluationContext,
+    ) -> AdmissionResponse {
+        let policy_eval_ctx = EvaluationContext {
+            policy_id: format!("{}/{}", eval_ctx.policy_id, policy.name),
+            // the patch of each policy is needed to compute the one of the chain
+            policy_mode: PolicyMode::Protect,
+            ..eval_ctx.clone()
+        };
+
+        let mut evaluator = match policy.evaluator_pre.rehydrate(&policy_eval_ctx) {
+            Ok(evaluator) => evaluator,
+            Err(e) => {
+                warn!(policy = policy.name, error = %e, "cannot rehydrate policy chain member");
+                return AdmissionResponse::reject_internal_server_error(
+                    request.uid().to_string(),
+                    e.to_string(),
+                );
+            }
+        };
+
+        let response = evaluator.validate(request.clone(), &policy.settings);
+        debug!(
+            policy = policy.name,
+            allowed = response.allowed,
+            mutated = response.patch.is_some(),
+            "policy chain member evaluated"
+        );
+
+        response
+    }
+}
+
+impl fmt::Debug for PolicyChainEvaluator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PolicyChainEvaluator")
+            .field(
+                "policies",
+                &self.policies.iter().map(|p| &p.name).collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+/// The object that can be mutated by the policies. This is `None` for DELETE operations
+fn request_object(request: &ValidateRequest) -> Option<serde_json::Value> {
+    match request {
+        ValidateRequest::Raw(raw_req) => Some(raw_req.clone()),
+        ValidateRequest::AdmissionRequest(adm_req) => {
+            adm_req.object.as_ref().map(|object| object.0.clone())
+        }
+    }
+}
+
+/// Build a copy of the request holding the given object
+fn with_object(request: &ValidateRequest, object: serde_json::Value) -> ValidateRequest {
+    match request {
+        ValidateRequest::Raw(_) => ValidateRequest::Raw(object),
+        ValidateRequest::AdmissionRequest(adm_req) => {
+            ValidateRequest::AdmissionRequest(AdmissionRequest {
+                object: Some(RawExtension(object)),
+                ..adm_req.clone()
+            })
+        }
+    }
+}
+
+/// Apply the base64 encoded JSONPatch to the object, returns the paths
+/// changed by the patch
+fn apply_patch(object: Option<&mut serde_json::Value>, patch: &str) -> Result<Vec<String>> {
+    let object = object.ok_or_else(|| anyhow!("the request does not have an object to patch"))?;
+
+    let patch = general_purpose::STANDARD
+        .decode(patch)
+        .map_err(|e| anyhow!("the patch is not base64 encoded: {e}"))?;
+    let patch: json_patch::Patch =
+        serde_json::from_slice(&patch).map_err(|e| anyhow!("invalid JSONPatch: {e}"))?;
+
+    json_patch::patch(object, &patch.0)?;
+
+    Ok(patched_paths(&patch))
+}
+
+/// The paths touched by the patch, including the sources of the move and copy operations
+fn patched_paths(patch: &json_patch::Patch) -> Vec<String> {
+    let mut paths = Vec::new();
+    for operation in &patch.0 {
+        match operation {
+            PatchOperation::Add(op) => paths.push(op.path.to_string()),
+            PatchOperation::Remove(op) => paths.push(op.path.to_string()),
+            PatchOperation::Replace(op) => paths.push(op.path.to_string()),
+            PatchOperation::Move(op) => {
+                paths.push(op.from.to_string());
+                paths.push(op.path.to_string());
+            }
+            PatchOperation::Copy(op) => {
+                paths.push(op.from.to_string());
+                paths.push(op.path.to_string());
+            }
+            PatchOperation::Test(op) => paths.push(op.path.to_string()),
+        }
+    }
+    paths
+}
+
+fn build_response(
+    uid: String,
+    original: Option<&serde_json::Value>,
+    mutated: Option<&serde_json::Value>,
+    steps: &[PolicyChainStep],
+    array_keys: &ArrayKeys,
+) -> Result<AdmissionResponse> {
+    let mut warnings: Vec<String> = Vec::new();
+    let mut audit_annotations: HashMap<String, String> = HashMap::new();
+
+    for step in steps {
+        warnings.extend(step.response.warnings.clone().unwrap_or_default());
+        for (key, value) in step.response.audit_annotations.clone().unwrap_or_default() {
+            audit_annotations.entry(key).or_insert(value);
+        }
+    }
+
+    let mut response = AdmissionResponse {
+        uid,
+        allowed: true,
+        warnings: (!warnings.is_empty()).then_some(warnings),
+        audit_annotations: (!audit_annotations.is_empty()).then_some(audit_annotations),
+        ..Default::default()
+    };
+
+    if let Some(rejection) = steps.iter().find(|step| !step.response.allowed) {
+        response.allowed = false;
+        response.status = rejection.response.status.clone();
+        return Ok(response);
+    }
+
+    if let (Some(original), Some(mutated)) = (original, mutated) {
+        let diff = patch::diff(original, mutated, array_keys);
+        if !diff.0.is_empty() {
+            let diff = serde_json::to_string(&diff)?;
+            response.patch = Some(general_purpose::STANDARD.encode(diff));
+            response.patch_type = Some(PatchType::JSONPatch);
+        }
+    }
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy_chain_evaluator::PolicyChainEvaluatorBuilder;
+    use crate::policy_evaluator::policy_evaluator_builder::PolicyEvaluatorBuilder;
+    use crate::policy_evaluator::PolicyExecutionMode;
+    use crate::test_fixtures::wasi_printing;
+    use serde_json::json;
+
+    fn encode_patch(patch: serde_json::Value) -> String {
+        general_purpose::STANDARD.encode(patch.to_string())
+    }
+
+    fn decode_patch(patch: &str) -> serde_json::Value {
+        serde_json::from_slice(&general_purpose::STANDARD.decode(patch).unwrap()).unwrap()
+    }
+
+    fn step(policy: &str, response: AdmissionResponse) -> PolicyChainStep {
+        PolicyChainStep {
+            policy: policy.to_string(),
+            response,
+            mutated_paths: Vec::new(),
+        }
+    }
+
+    fn accept(warning: &str) -> AdmissionResponse {
+        AdmissionResponse {
+            uid: "uid".to_string(),
+            allowed: true,
+            warnings: Some(vec![warning.to_string()]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn apply_patch_and_report_paths() {
+        let mut object = json!({"metadata": {"name": "nginx"}});
+        let patch = encode_patch(json!([
+            {"op": "add", "path": "/metadata/labels", "value": {"team": "a"}},
+            {"op": "replace", "path": "/metadata/name", "value": "busybox"},
+        ]));
+
+        let paths = apply_patch(Some(&mut object), &patch).unwrap();
+
+        assert_eq!(vec!["/metadata/labels", "/metadata/name"], paths);
+        assert_eq!(
+            json!({"metadata": {"name": "busybox", "labels": {"team": "a"}}}),
+            object
+        );
+    }
+
+    #[test]
+    fn apply_patch_reports_the_sources_of_moved_and_copied_paths() {
+        let mut object = json!({"metadata": {"name": "nginx", "labels": {"team": "a"}}});
+        let patch = encode_patch(json!([
+            {"op": "move", "from": "/metadata/labels", "path": "/metadata/annotations"},
+            {"op": "copy", "from": "/metadata/name", "path": "/metadata/generateName"},
+        ]));
+
+        let paths = apply_patch(Some(&mut object), &patch).unwrap();
+
+        assert_eq!(
+            vec![
+                "/metadata/labels",
+                "/metadata/annotations",
+                "/metadata/name",
+                "/metadata/generateName"
+            ],
+            paths
+        );
+    }
+
+    #[test]
+    fn apply_patch_without_object() {
+        let patch = encode_patch(json!([]));
+
+        assert!(apply_patch(None, &patch).is_err());
+    }
+
+    #[test]
+    fn apply_invalid_patch() {
+        let mut object = json!({});
+        let patch = encode_patch(json!([
+            {"op": "replace", "path": "/spec/replicas", "value": 1},
+        ]));
+
+        assert!(apply_patch(Some(&mut object), &patch).is_err());
+    }
+
+    #[test]
+    fn replace_object_of_admission_request() {
+        let request: AdmissionRequest = serde_json::from_value(json!({
+            "uid": "uid",
+            "kind": {"group": "", "version": "v1", "kind": "Pod"},
+            "resource": {"group": "", "version": "v1", "resource": "pods"},
+            "operation": "CREATE",
+            "userInfo": {},
+            "object": {"metadata": {"name": "nginx"}}
+        }))
+        .unwrap();
+        let request = ValidateRequest::AdmissionRequest(request);
+
+        let mutated = with_object(&request, json!({"metadata": {"name": "busybox"}}));
+
+        assert_eq!(
+            Some(json!({"metadata": {"name": "busybox"}})),
+            request_object(&mutated)
+        );
+        assert_eq!("uid", mutated.uid());
+    }
+
+    #[test]
+    fn build_response_with_combined_patch() {
+        let original = json!({"metadata": {"name": "nginx"}});
+        let mutated = json!({"metadata": {"name": "nginx", "labels": {"team": "a"}}});
+        let steps = vec![
+            step("a", accept("a warning")),
+            step("b", accept("b warning")),
+        ];
+
+        let response = build_response(
+            "uid".to_string(),
+            Some(&original),
+            Some(&mutated),
+            &steps,
+            &ArrayKeys::default(),
+        )
+        .unwrap();
+
+        assert!(response.allowed);
+        assert_eq!(Some(PatchType::JSONPatch), response.patch_type);
+        assert_eq!(
+            json!([{"op": "add", "path": "/metadata/labels", "value": {"team": "a"}}]),
+            decode_patch(&response.patch.unwrap())
+        );
+        assert_eq!(
+            Some(vec!["a warning".to_string(), "b warning".to_string()]),
+            response.warnings
+        );
+    }
+
+    #[test]
+    fn build_response_without_mutations() {
+        let original = json!({"metadata": {"name": "nginx"}});
+        let steps = vec![step("a", accept("a warning"))];
+
+        let response = build_response(
+            "uid".to_string(),
+            Some(&original),
+            Some(&original),
+            &steps,
+            &ArrayKeys::default(),
+        )
+        .unwrap();
+
+        assert!(response.allowed);
+        assert!(response.patch.is_none());
+        assert!(response.patch_type.is_none());
+    }
+
+    #[test]
+    fn build_rejection_response() {
+        let original = json!({"metadata": {"name": "nginx"}});
+        let mutated = json!({"metadata": {"name": "busybox"}});
+        let steps = vec![
+            step("a", accept("a warning")),
+            step(
+                "b",
+                AdmissionResponse::reject("uid".to_string(), "b says no".to_string(), 400),
+            ),
+        ];
+
+        let response = build_response(
+            "uid".to_string(),
+            Some(&original),
+            Some(&mutated),
+            &steps,
+            &ArrayKeys::default(),
+        )
+        .unwrap();
+
+        assert!(!response.allowed);
+        assert!(response.patch.is_none());
+        let status = response.status.expect("status should be set");
+        assert_eq!(Some("b says no".to_string()), status.message);
+        assert_eq!(Some(400), status.code);
+        assert_eq!(Some(vec!["a warning".to_string()]), response.warnings);
+    }
+
+    #[test]
+    fn chain_of_mutating_policies() {
+        let original = json!({"metadata": {"name": "nginx"}});
+        let labeled = json!({"metadata": {"name": "nginx", "labels": {"a": "true"}}});
+        let annotated = json!({
+            "metadata": {"name": "nginx", "labels": {"a": "true"}, "annotations": {"b": "true"}}
+        });
+        let mutating_policy = |mutated_object: &serde_json::Value| {
+            let output = json!({"accepted": true, "mutated_object": mutated_object}).to_string();
+            PolicyEvaluatorBuilder::new()
+                .execution_mode(PolicyExecutionMode::Wasi)
+                .policy_contents(wasi_printing(&output).as_bytes())
+                .build_pre()
+                .expect("cannot build PolicyEvaluatorPre")
+        };
+        let chain = PolicyChainEvaluatorBuilder::new()
+            .policy("a", mutating_policy(&labeled), PolicySettings::new())
+            .policy("b", mutating_policy(&annotated), PolicySettings::new())
+            .build()
+            .expect("cannot build policy chain");
+        let eval_ctx = EvaluationContext {
+            policy_id: "chain".to_string(),
+            ..Default::default()
+        };
+
+        let chain_response = chain.validate(ValidateRequest::Raw(original.clone()), &eval_ctx);
+
+        let response = chain_response.response;
+        assert!(response.allowed);
+        let mut patched = original;
+        let patch: json_patch::Patch =
+            serde_json::from_value(decode_patch(&response.patch.expect("patch should be set")))
+                .unwrap();
+        json_patch::patch(&mut patched, &patch).unwrap();
+        assert_eq!(annotated, patched);
+
+        let steps: Vec<(&str, &[String])> = chain_response
+            .steps
+            .iter()
+            .map(|step| (step.policy.as_str(), step.mutated_paths.as_slice()))
+            .collect();
+        assert_eq!(
+            vec![
+                ("a", ["/metadata/labels".to_string()].as_slice()),
+                ("b", ["/metadata/annotations".to_string()].as_slice()),
+            ],
+            steps
+        );
+    }
+}
@@ -0,0 +1,98 @@
+use std::collections::HashSet;
+
+use crate::errors::PolicyChainEvaluatorBuilderError;
+use crate::policy_chain_evaluator::evaluator::{PolicyChainEvaluator, PolicyChainLink};
+use crate::policy_evaluator::{PolicyEvaluatorPre, PolicySettings};
+
+/// Helper Struct that creates a `PolicyChainEvaluator` object
+///
+/// The policies are evaluated in the same order they are added to the builder.
+#[derive(Default)]
+pub struct PolicyChainEvaluatorBuilder {
+    policies: Vec<PolicyChainLink>,
+}
+
+impl PolicyChainEvaluatorBuilder {
+    /// Create a new PolicyChainEvaluatorBuilder object
+    pub fn new() -> PolicyChainEvaluatorBuilder {
+        PolicyChainEvaluatorBuilder::default()
+    }
+
+    /// Append a policy to the chain. The `name` is used to report the changes
+    /// done by the policy.
+    #[must_use]
+    pub fn policy(
+        mut self,
+        name: &str,
+        evaluator_pre: PolicyEvaluatorPre,
+        settings: PolicySettings,
+    ) -> Self {
+        self.policies.push(PolicyChainLink {
+            name: name.to_string(),
+            evaluator_pre,
+            settings,
+        });
+        self
+    }
+
+    /// Create the instance of `PolicyChainEvaluator` to be used
+    pub fn build(self) -> Result<PolicyChainEvaluator, PolicyChainEvaluatorBuilderError> {
+        if self.policies.is_empty() {
+            return Err(PolicyChainEvaluatorBuilderError::NoPolicies);
+        }
+
+        let mut names = HashSet::new();
+        for policy in &self.policies {
+            if !names.insert(policy.name.as_str()) {
+                return Err(PolicyChainEvaluatorBuilderError::DuplicatePolicy(
+                    policy.name.clone(),
+                ));
+            }
+        }
+
+        Ok(PolicyChainEvaluator {
+            policies: self.policies,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::wapc_endless_loop_pre;
+
+    #[test]
+    fn build_policy_chain_evaluator() {
+        let chain = PolicyChainEvaluatorBuilder::new()
+            .policy("b", wapc_endless_loop_pre(), PolicySettings::new())
+            .policy("a", wapc_endless_loop_pre(), PolicySettings::new())
+            .build()
+            .expect("cannot build policy chain");
+
+        let names: Vec<&str> = chain.policies.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(vec!["b", "a"], names);
+    }
+
+    #[test]
+    fn reject_duplicate_policy() {
+        let result = PolicyChainEvaluatorBuilder::new()
+            .policy("a", wapc_endless_loop_pre(), PolicySettings::new())
+            .policy("a", wapc_endless_loop_pre(), PolicySettings::new())
+            .build();
+
+        assert!(matches!(
+            result,
+            Err(PolicyChainEvaluatorBuilderError::DuplicatePolicy(name)) if name == "a"
+        ));
+    }
+
+    #[test]
+    fn reject_empty_chain() {
+        let result = PolicyChainEvaluatorBuilder::new().build();
+
+        assert!(matches!(
+            result,
+            Err(PolicyChainEvaluatorBuilderError::NoPolicies)
+        ));
+    }
+}
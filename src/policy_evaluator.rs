@@ -1,11 +1,28 @@
+mod cancellation;
+mod epoch_ticker;
 pub mod errors;
+pub(crate) mod evaluation_report;
 mod evaluator;
 pub mod policy_evaluator_builder;
+mod policy_evaluator_pool;
 mod policy_evaluator_pre;
+mod policy_info;
+mod precompiled_cache;
+mod self_tests;
+mod settings_schema;
 mod stack_pre;
+mod swappable_policy;
 
+pub use cancellation::CancellationHandle;
+pub use evaluation_report::{EvaluationReport, HostCallbackRecord, HostCallbackStats};
 pub use evaluator::PolicyEvaluator;
+pub use policy_evaluator_pool::{
+    PolicyEvaluatorPool, PolicyEvaluatorPoolStats, PooledPolicyEvaluator,
+};
 pub use policy_evaluator_pre::PolicyEvaluatorPre;
+pub use policy_info::{PolicyAbiVersion, PolicyInfo};
+pub use self_tests::{read_self_tests, ExpectedVerdict, SelfTest, SelfTestFailure};
+pub use swappable_policy::{PolicyVersion, SwappablePolicy};
 
 use anyhow::{anyhow, Result};
 use serde::Serialize;
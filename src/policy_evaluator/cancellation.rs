@@ -0,0 +1,164 @@
+use std::future::Future;
+use std::pin::pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use futures::future::{select, Either};
+use tokio::sync::Notify;
+
+/// Cancels the evaluation being done by a [`PolicyEvaluator`](crate::policy_evaluator::PolicyEvaluator),
+/// see [`PolicyEvaluator::cancellation_handle`](crate::policy_evaluator::PolicyEvaluator::cancellation_handle).
+///
+/// The handle can be cloned and moved to other threads. The cancellation is scoped to a
+/// single evaluation: it is cleared when an evaluation starts, hence a cancellation
+/// requested while no evaluation is running has no effect.
+#[derive(Clone, Debug)]
+pub struct CancellationHandle {
+    cancelled: Arc<AtomicBool>,
+    notify: Arc<Notify>,
+}
+
+impl CancellationHandle {
+    pub(crate) fn new() -> Self {
+        Self {
+            cancelled: Arc::new(AtomicBool::new(false)),
+            notify: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Cancel the evaluation in flight.
+    ///
+    /// The host callbacks waiting for a response are aborted right away, while the
+    /// guest code is interrupted at the next epoch tick. This requires either
+    /// [epoch interruptions](crate::policy_evaluator_builder::PolicyEvaluatorBuilder::enable_epoch_interruptions)
+    /// or a [timeout](crate::policy_evaluator_builder::PolicyEvaluatorBuilder::timeout) to be set.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        self.notify.notify_waiters();
+    }
+
+    /// Returns true when the evaluation has been cancelled
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Clear the cancellation, returns true when the evaluation had been cancelled.
+    /// This is done when an evaluation starts and once it has been reported
+    pub(crate) fn reset(&self) -> bool {
+        self.cancelled.swap(false, Ordering::SeqCst)
+    }
+
+    /// The flag set when the evaluation is cancelled
+    pub(crate) fn flag(&self) -> Arc<AtomicBool> {
+        self.cancelled.clone()
+    }
+
+    /// Drive the future to completion, unless the evaluation is cancelled first.
+    /// In that case the future is dropped and `None` is returned
+    pub(crate) async fn run_until_cancelled<F: Future>(&self, future: F) -> Option<F::Output> {
+        let cancelled = pin!(self.cancelled());
+        let future = pin!(future);
+
+        match select(future, cancelled).await {
+            Either::Left((output, _)) => Some(output),
+            Either::Right(_) => None,
+        }
+    }
+
+    async fn cancelled(&self) {
+        // the future must be created before checking the flag, otherwise
+        // a cancellation happening in between would be lost
+        let notified = self.notify.notified();
+        if self.is_cancelled() {
+            return;
+        }
+        notified.await;
+    }
+
+    /// Build the callback invoked by wasmtime once the epoch deadline of a store is
+    /// reached. The guest is interrupted once `deadline` ticks have elapsed, or as
+    /// soon as the evaluation is cancelled.
+    ///
+    /// The store must be given an epoch deadline of a single tick, the callback then
+    /// extends it one tick at a time.
+    pub(crate) fn epoch_deadline_callback<T>(
+        &self,
+        deadline: u64,
+    ) -> impl FnMut(wasmtime::StoreContextMut<T>) -> wasmtime::Result<wasmtime::UpdateDeadline>
+           + Send
+           + Sync
+           + 'static {
+        burrego::epoch_deadline_callback(deadline, Some(self.flag()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn pending_futures_are_dropped_on_cancellation() {
+        let handle = CancellationHandle::new();
+
+        let cancel_handle = handle.clone();
+        let output = handle
+            .run_until_cancelled(async move {
+                cancel_handle.cancel();
+                futures::future::pending::<()>().await;
+            })
+            .await;
+
+        assert!(output.is_none());
+        assert!(handle.reset());
+        assert!(!handle.is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn futures_run_to_completion() {
+        let handle = CancellationHandle::new();
+
+        assert_eq!(Some(42), handle.run_until_cancelled(async { 42 }).await);
+        assert!(!handle.reset());
+    }
+
+    #[test]
+    fn cancelled_evaluation_is_interrupted_at_next_tick() {
+        let mut config = wasmtime::Config::default();
+        config.epoch_interruption(true);
+        let engine = wasmtime::Engine::new(&config).unwrap();
+        let module = wasmtime::Module::new(
+            &engine,
+            r#"(module (func (export "run") (loop $endless br $endless)))"#,
+        )
+        .unwrap();
+
+        let handle = CancellationHandle::new();
+        let mut store = wasmtime::Store::new(&engine, ());
+        store.set_epoch_deadline(1);
+        store.epoch_deadline_callback(handle.epoch_deadline_callback(u64::MAX));
+        let instance = wasmtime::Instance::new(&mut store, &module, &[]).unwrap();
+        let run = instance
+            .get_typed_func::<(), ()>(&mut store, "run")
+            .unwrap();
+
+        let ticker_engine = engine.clone();
+        let ticker_handle = handle.clone();
+        let ticker = std::thread::spawn(move || {
+            for tick in 0..100 {
+                std::thread::sleep(std::time::Duration::from_millis(10));
+                if tick == 5 {
+                    ticker_handle.cancel();
+                }
+                ticker_engine.increment_epoch();
+            }
+        });
+
+        let err = run.call(&mut store, ()).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<wasmtime::Trap>(),
+            Some(wasmtime::Trap::Interrupt)
+        ));
+        assert!(handle.is_cancelled());
+        ticker.join().unwrap();
+    }
+}
@@ -0,0 +1,132 @@
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+/// How often the epoch of the engines is incremented
+pub(crate) const EPOCH_TICK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The state of the ticker thread, shared by all the engines
+struct Ticker {
+    /// The engines being ticked. Only weak references are kept, this allows the
+    /// engines to be dropped once all the policies using them are gone
+    engines: Vec<wasmtime::EngineWeak>,
+    /// The number of ticker threads running, this is never more than one
+    threads: usize,
+}
+
+static TICKER: Mutex<Ticker> = Mutex::new(Ticker {
+    engines: Vec::new(),
+    threads: 0,
+});
+
+fn ticker() -> MutexGuard<'static, Ticker> {
+    TICKER.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Convert the timeout into a number of epoch ticks.
+///
+/// The deadline starts counting from the next tick, which can happen at any time
+/// during the current interval. An extra tick is added, this ensures the guest is
+/// never interrupted before the timeout has elapsed.
+pub(crate) fn timeout_to_ticks(timeout: Duration) -> u64 {
+    let ticks = timeout
+        .as_nanos()
+        .div_ceil(EPOCH_TICK_INTERVAL.as_nanos())
+        .saturating_add(1);
+    u64::try_from(ticks).unwrap_or(u64::MAX)
+}
+
+/// Ensure the epoch of the given engine is incremented every [`EPOCH_TICK_INTERVAL`].
+///
+/// A single thread ticks all the engines, no matter how many of them are registered.
+/// The thread is started on demand, and terminates once all the engines have been dropped.
+pub(crate) fn ensure_ticking(engine: &wasmtime::Engine) -> std::io::Result<()> {
+    let mut ticker = ticker();
+
+    ticker.engines.retain(|weak| weak.upgrade().is_some());
+    let already_ticking = ticker
+        .engines
+        .iter()
+        .filter_map(wasmtime::EngineWeak::upgrade)
+        .any(|ticking_engine| wasmtime::Engine::same(&ticking_engine, engine));
+    if !already_ticking {
+        ticker.engines.push(engine.weak());
+    }
+
+    if ticker.threads == 0 {
+        thread::Builder::new()
+            .name("epoch-ticker".to_string())
+            .spawn(tick)?;
+        ticker.threads += 1;
+    }
+
+    Ok(())
+}
+
+/// The loop run by the ticker thread
+fn tick() {
+    loop {
+        thread::sleep(EPOCH_TICK_INTERVAL);
+
+        let mut ticker = ticker();
+        ticker.engines.retain(|weak| match weak.upgrade() {
+            Some(engine) => {
+                engine.increment_epoch();
+                true
+            }
+            None => false,
+        });
+        if ticker.engines.is_empty() {
+            ticker.threads -= 1;
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::zero(Duration::ZERO, 1)]
+    #[case::less_than_a_tick(Duration::from_millis(1), 2)]
+    #[case::exact(Duration::from_millis(100), 11)]
+    #[case::rounded_up(Duration::from_millis(101), 12)]
+    fn convert_timeout_to_ticks(#[case] timeout: Duration, #[case] expected: u64) {
+        assert_eq!(expected, timeout_to_ticks(timeout));
+    }
+
+    #[test]
+    fn engines_are_ticked_once() {
+        let mut config = wasmtime::Config::default();
+        config.epoch_interruption(true);
+        let engine = wasmtime::Engine::new(&config).unwrap();
+
+        ensure_ticking(&engine).unwrap();
+        ensure_ticking(&engine.clone()).unwrap();
+
+        let count = ticker()
+            .engines
+            .iter()
+            .filter_map(wasmtime::EngineWeak::upgrade)
+            .filter(|e| wasmtime::Engine::same(e, &engine))
+            .count();
+        assert_eq!(1, count);
+    }
+
+    #[test]
+    fn a_single_thread_ticks_all_the_engines() {
+        let mut config = wasmtime::Config::default();
+        config.epoch_interruption(true);
+        let engines: Vec<wasmtime::Engine> = (0..20)
+            .map(|_| wasmtime::Engine::new(&config).unwrap())
+            .collect();
+
+        for engine in &engines {
+            ensure_ticking(engine).unwrap();
+        }
+
+        assert_eq!(1, ticker().threads);
+    }
+}
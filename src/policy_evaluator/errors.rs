@@ -19,6 +19,12 @@ pub enum InvalidUserInputError {
     )]
     EngineForModule,
 
-    #[error("must specify execution mode")]
+    #[error("must specify execution mode, or enable its detection")]
     ExecutionMode,
+
+    #[error("cannot specify execution mode and enable its detection at the same time")]
+    ExecutionModeAndDetection,
+
+    #[error("cannot specify `timeout` and enable epoch interruptions at the same time")]
+    TimeoutAndEpochDeadlines,
 }
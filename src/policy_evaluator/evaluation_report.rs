@@ -0,0 +1,187 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// A host capability invoked by the policy while being evaluated
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HostCallbackRecord {
+    /// The host capability, like `oci`, `sigstore`, `kubernetes`, `net` or `crypto`
+    pub capability: String,
+    /// The operation requested by the policy, like `v1/manifest_digest`
+    pub operation: String,
+    /// Time spent by the host to fulfill the request
+    pub duration: Duration,
+    /// Whether the response has been served from a cache
+    pub was_cached: bool,
+    /// Whether the request has been fulfilled successfully
+    pub success: bool,
+}
+
+/// Aggregated statistics about the invocations of a host capability
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HostCallbackStats {
+    /// Number of invocations
+    pub count: u64,
+    /// Number of invocations served from a cache
+    pub cached: u64,
+    /// Number of invocations that failed
+    pub errors: u64,
+    /// Time spent fulfilling all the invocations
+    pub total_duration: Duration,
+    /// Time spent fulfilling the slowest invocation
+    pub max_duration: Duration,
+}
+
+/// Details about an evaluation, returned by
+/// [`PolicyEvaluator::validate_with_report`](crate::policy_evaluator::PolicyEvaluator::validate_with_report)
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EvaluationReport {
+    /// Wall time of the whole evaluation
+    pub total_duration: Duration,
+    /// Time spent running the guest code. This is the total time minus the
+    /// time spent inside of host callbacks, hence it includes the time spent
+    /// by the host to exchange data with the guest
+    pub guest_duration: Duration,
+    /// Time spent by the host to fulfill the host callbacks
+    pub host_callbacks_duration: Duration,
+    /// The host callbacks done by the policy, in the order they have been made.
+    ///
+    /// Rego policies cannot make host callbacks, the Kubernetes resources they have access
+    /// to are fetched before the evaluation and are reported as a single `kubernetes`
+    /// callback
+    pub host_callbacks: Vec<HostCallbackRecord>,
+    /// Peak size, in bytes, of the linear memory of the policy.
+    ///
+    /// The linear memory of waPC and Rego policies is kept across evaluations, hence this
+    /// is the peak since the creation of the evaluator, or since its last reset.
+    pub peak_memory: Option<usize>,
+    /// Fuel consumed by the evaluation, see
+    /// [`PolicyEvaluator::fuel_consumed`](crate::policy_evaluator::PolicyEvaluator::fuel_consumed)
+    pub fuel_consumed: Option<u64>,
+}
+
+impl EvaluationReport {
+    /// Statistics about the host callbacks, aggregated by capability
+    pub fn host_callbacks_by_capability(&self) -> BTreeMap<&str, HostCallbackStats> {
+        let mut stats: BTreeMap<&str, HostCallbackStats> = BTreeMap::new();
+
+        for record in &self.host_callbacks {
+            let entry = stats.entry(record.capability.as_str()).or_default();
+            entry.count += 1;
+            if record.was_cached {
+                entry.cached += 1;
+            }
+            if !record.success {
+                entry.errors += 1;
+            }
+            entry.total_duration += record.duration;
+            entry.max_duration = entry.max_duration.max(record.duration);
+        }
+
+        stats
+    }
+}
+
+/// Collects the host callbacks made by a policy. The callbacks are recorded only
+/// between the invocation of [`start`](HostCallbackRecorder::start) and
+/// [`finish`](HostCallbackRecorder::finish), otherwise they are ignored.
+#[derive(Debug, Default)]
+pub(crate) struct HostCallbackRecorder {
+    records: Mutex<Option<Vec<HostCallbackRecord>>>,
+}
+
+impl HostCallbackRecorder {
+    pub(crate) fn start(&self) {
+        *self.lock() = Some(Vec::new());
+    }
+
+    pub(crate) fn finish(&self) -> Vec<HostCallbackRecord> {
+        self.lock().take().unwrap_or_default()
+    }
+
+    pub(crate) fn record(&self, record: HostCallbackRecord) {
+        if let Some(records) = self.lock().as_mut() {
+            records.push(record);
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<Vec<HostCallbackRecord>>> {
+        self.records
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(
+        capability: &str,
+        millis: u64,
+        was_cached: bool,
+        success: bool,
+    ) -> HostCallbackRecord {
+        HostCallbackRecord {
+            capability: capability.to_string(),
+            operation: "op".to_string(),
+            duration: Duration::from_millis(millis),
+            was_cached,
+            success,
+        }
+    }
+
+    #[test]
+    fn recorder_ignores_callbacks_when_not_started() {
+        let recorder = HostCallbackRecorder::default();
+        recorder.record(record("oci", 1, false, true));
+        assert!(recorder.finish().is_empty());
+
+        recorder.start();
+        recorder.record(record("oci", 1, false, true));
+        assert_eq!(1, recorder.finish().len());
+
+        recorder.record(record("oci", 1, false, true));
+        assert!(recorder.finish().is_empty());
+    }
+
+    #[test]
+    fn aggregate_by_capability() {
+        let report = EvaluationReport {
+            host_callbacks: vec![
+                record("oci", 10, false, true),
+                record("sigstore", 100, false, false),
+                record("oci", 30, true, true),
+            ],
+            ..Default::default()
+        };
+
+        let stats = report.host_callbacks_by_capability();
+
+        assert_eq!(
+            BTreeMap::from([
+                (
+                    "oci",
+                    HostCallbackStats {
+                        count: 2,
+                        cached: 1,
+                        errors: 0,
+                        total_duration: Duration::from_millis(40),
+                        max_duration: Duration::from_millis(30),
+                    }
+                ),
+                (
+                    "sigstore",
+                    HostCallbackStats {
+                        count: 1,
+                        cached: 0,
+                        errors: 1,
+                        total_duration: Duration::from_millis(100),
+                        max_duration: Duration::from_millis(100),
+                    }
+                ),
+            ]),
+            stats
+        );
+    }
+}
@@ -1,11 +1,21 @@
 use kubewarden_policy_sdk::metadata::ProtocolVersion;
 use kubewarden_policy_sdk::settings::SettingsValidationResponse;
 use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use crate::admission_response::AdmissionResponse;
 use crate::errors::PolicyEvaluatorError;
-use crate::evaluation_context::EvaluationContext;
-use crate::policy_evaluator::{PolicySettings, ValidateRequest};
+use crate::evaluation_context::{EvaluationContext, PolicyMode};
+use crate::policy_evaluator::evaluation_report::{
+    EvaluationReport, HostCallbackRecord, HostCallbackRecorder,
+};
+use crate::policy_evaluator::policy_info::{PolicyAbiVersion, PolicyInfo};
+use crate::policy_evaluator::settings_schema::SettingsSchema;
+use crate::policy_evaluator::{
+    CancellationHandle, PolicyExecutionMode, PolicySettings, RegoPolicyExecutionMode,
+    ValidateRequest,
+};
 use crate::runtimes::rego::Runtime as BurregoRuntime;
 use crate::runtimes::wapc::Runtime as WapcRuntime;
 use crate::runtimes::wasi_cli::Runtime as WasiRuntime;
@@ -14,44 +24,285 @@ use crate::runtimes::Runtime;
 pub struct PolicyEvaluator {
     runtime: Runtime,
     eval_ctx: EvaluationContext,
+    host_callback_recorder: Arc<HostCallbackRecorder>,
+    settings_schema: Option<Arc<SettingsSchema>>,
+    timeout: Option<Duration>,
+    cancellation: CancellationHandle,
+    exported_functions: Arc<[String]>,
 }
 
 impl PolicyEvaluator {
-    pub(crate) fn new(runtime: Runtime, eval_ctx: &EvaluationContext) -> Self {
+    pub(crate) fn new(
+        runtime: Runtime,
+        eval_ctx: &EvaluationContext,
+        host_callback_recorder: Arc<HostCallbackRecorder>,
+        settings_schema: Option<Arc<SettingsSchema>>,
+        timeout: Option<Duration>,
+        cancellation: CancellationHandle,
+        exported_functions: Arc<[String]>,
+    ) -> Self {
         Self {
             runtime,
             eval_ctx: eval_ctx.to_owned(),
+            host_callback_recorder,
+            settings_schema,
+            timeout,
+            cancellation,
+            exported_functions,
         }
     }
 
+    /// Returns a handle that can be used, from another thread or task, to cancel the
+    /// evaluation being done by this `PolicyEvaluator`. This is useful when the outcome
+    /// of the evaluation is no longer needed, for example because the API server
+    /// abandoned the admission request.
+    ///
+    /// The host callbacks made by the policy are aborted, and the guest code is
+    /// interrupted, see [`CancellationHandle::cancel`]. An interrupted stack is reset,
+    /// like it happens when the evaluation exceeds its deadline. The request is rejected.
+    ///
+    /// The cancellation applies only to the evaluation in flight: it is cleared when
+    /// the next evaluation starts.
+    pub fn cancellation_handle(&self) -> CancellationHandle {
+        self.cancellation.clone()
+    }
+
+    /// Evaluate the request, blocking the current thread until the host capabilities
+    /// requested by the policy are fulfilled.
+    ///
+    /// The callback handler is run by a tokio runtime: this must not be invoked from an
+    /// async context driven by a current-thread runtime, which would not make progress
+    /// while blocked. Use [`validate_async`](PolicyEvaluator::validate_async) there.
     #[tracing::instrument(skip(request))]
     pub fn validate(
         &mut self,
         request: ValidateRequest,
         settings: &PolicySettings,
     ) -> AdmissionResponse {
-        match self.runtime {
-            Runtime::Wapc(ref mut wapc_stack) => {
-                WapcRuntime(wapc_stack).validate(settings, &request)
-            }
+        self.cancellation.reset();
+        let evaluation_start = Instant::now();
+        let response = match self.runtime {
+            Runtime::Wapc(ref mut wapc_stack) => WapcRuntime(wapc_stack).validate(
+                settings,
+                &request,
+                &self.eval_ctx.protected_paths,
+                &self.eval_ctx.array_keys,
+            ),
             Runtime::Rego(ref mut burrego_evaluator) => {
+                let start = Instant::now();
                 let kube_ctx = burrego_evaluator.build_kubernetes_context(
                     self.eval_ctx.callback_channel.as_ref(),
                     &self.eval_ctx.ctx_aware_resources_allow_list,
                 );
+                record_kubernetes_context(
+                    &self.host_callback_recorder,
+                    &self.eval_ctx,
+                    start,
+                    kube_ctx.is_ok(),
+                );
+                match kube_ctx {
+                    Ok(ctx) => BurregoRuntime(burrego_evaluator).validate(
+                        settings,
+                        &request,
+                        &ctx,
+                        &self.eval_ctx.protected_paths,
+                        &self.eval_ctx.array_keys,
+                    ),
+                    Err(e) => {
+                        AdmissionResponse::reject(request.uid().to_string(), e.to_string(), 500)
+                    }
+                }
+            }
+            Runtime::Cli(ref mut cli_stack) => WasiRuntime(cli_stack).validate(
+                settings,
+                &request,
+                &self.eval_ctx.protected_paths,
+                &self.eval_ctx.array_keys,
+            ),
+        };
+
+        let response = self.report_timeout(response, evaluation_start.elapsed());
+        let response = self.report_cancellation(response);
+        self.apply_policy_mode(response)
+    }
+
+    /// Asynchronous version of [`validate`](PolicyEvaluator::validate).
+    ///
+    /// The host capabilities requested by the policy are fulfilled without blocking the
+    /// thread running the evaluation. This requires the `PolicyEvaluator` to be created
+    /// with [async support](crate::policy_evaluator_builder::PolicyEvaluatorBuilder::enable_async_support)
+    /// enabled, otherwise the evaluation falls back to the synchronous one.
+    ///
+    /// Rego policies do not perform host callbacks while being evaluated: only the
+    /// Kubernetes resources required by the policy are fetched asynchronously, the
+    /// evaluation of the policy is synchronous.
+    #[tracing::instrument(skip(request))]
+    pub async fn validate_async(
+        &mut self,
+        request: ValidateRequest,
+        settings: &PolicySettings,
+    ) -> AdmissionResponse {
+        self.cancellation.reset();
+        let evaluation_start = Instant::now();
+        let response = match self.runtime {
+            Runtime::Wapc(ref mut wapc_stack) => {
+                WapcRuntime(wapc_stack)
+                    .validate_async(
+                        settings,
+                        &request,
+                        &self.eval_ctx.protected_paths,
+                        &self.eval_ctx.array_keys,
+                    )
+                    .await
+            }
+            Runtime::Rego(ref mut burrego_evaluator) => {
+                let start = Instant::now();
+                let kube_ctx = burrego_evaluator
+                    .build_kubernetes_context_async(
+                        self.eval_ctx.callback_channel.as_ref(),
+                        &self.eval_ctx.ctx_aware_resources_allow_list,
+                    )
+                    .await;
+                record_kubernetes_context(
+                    &self.host_callback_recorder,
+                    &self.eval_ctx,
+                    start,
+                    kube_ctx.is_ok(),
+                );
                 match kube_ctx {
-                    Ok(ctx) => BurregoRuntime(burrego_evaluator).validate(settings, &request, &ctx),
+                    Ok(ctx) => BurregoRuntime(burrego_evaluator).validate(
+                        settings,
+                        &request,
+                        &ctx,
+                        &self.eval_ctx.protected_paths,
+                        &self.eval_ctx.array_keys,
+                    ),
                     Err(e) => {
                         AdmissionResponse::reject(request.uid().to_string(), e.to_string(), 500)
                     }
                 }
             }
-            Runtime::Cli(ref mut cli_stack) => WasiRuntime(cli_stack).validate(settings, &request),
+            Runtime::Cli(ref mut cli_stack) => {
+                WasiRuntime(cli_stack)
+                    .validate_async(
+                        settings,
+                        &request,
+                        &self.eval_ctx.protected_paths,
+                        &self.eval_ctx.array_keys,
+                    )
+                    .await
+            }
+        };
+
+        let response = self.report_timeout(response, evaluation_start.elapsed());
+        let response = self.report_cancellation(response);
+        self.apply_policy_mode(response)
+    }
+
+    /// Replace the rejection produced by the runtime when the evaluation has been
+    /// interrupted because it exceeded the [timeout](crate::policy_evaluator_builder::PolicyEvaluatorBuilder::timeout)
+    fn report_timeout(&self, response: AdmissionResponse, elapsed: Duration) -> AdmissionResponse {
+        match self.timeout {
+            // the deadline is never reached before the timeout has elapsed, this tells it
+            // apart from the interruptions caused by fuel exhaustion or memory limits
+            Some(timeout) if self.was_interrupted() && elapsed >= timeout => {
+                AdmissionResponse::reject(
+                    response.uid,
+                    format!(
+                        "policy evaluation timed out after {:.3}s, the timeout is {:.3}s",
+                        elapsed.as_secs_f64(),
+                        timeout.as_secs_f64()
+                    ),
+                    500,
+                )
+            }
+            _ => response,
+        }
+    }
+
+    /// Replace the response of the runtime when the evaluation has been cancelled
+    /// via its [`CancellationHandle`]
+    fn report_cancellation(&self, response: AdmissionResponse) -> AdmissionResponse {
+        if self.cancellation.reset() {
+            AdmissionResponse::reject(response.uid, "policy evaluation cancelled".to_string(), 500)
+        } else {
+            response
+        }
+    }
+
+    /// Enforce the [`PolicyMode`] set inside of the `EvaluationContext`
+    fn apply_policy_mode(&self, response: AdmissionResponse) -> AdmissionResponse {
+        match self.eval_ctx.policy_mode {
+            PolicyMode::Protect => response,
+            PolicyMode::Monitor => response.into_monitor_response(),
         }
     }
 
+    /// Like [`validate`](PolicyEvaluator::validate), but also returns an [`EvaluationReport`]
+    /// with details about the evaluation: how long it took, the host callbacks made by
+    /// the policy and the resources it consumed.
+    pub fn validate_with_report(
+        &mut self,
+        request: ValidateRequest,
+        settings: &PolicySettings,
+    ) -> (AdmissionResponse, EvaluationReport) {
+        self.host_callback_recorder.start();
+        let start = Instant::now();
+        let response = self.validate(request, settings);
+        let report = self.build_report(start.elapsed());
+
+        (response, report)
+    }
+
+    /// Asynchronous version of [`validate_with_report`](PolicyEvaluator::validate_with_report)
+    pub async fn validate_async_with_report(
+        &mut self,
+        request: ValidateRequest,
+        settings: &PolicySettings,
+    ) -> (AdmissionResponse, EvaluationReport) {
+        self.host_callback_recorder.start();
+        let start = Instant::now();
+        let response = self.validate_async(request, settings).await;
+        let report = self.build_report(start.elapsed());
+
+        (response, report)
+    }
+
+    fn build_report(&self, total_duration: Duration) -> EvaluationReport {
+        let host_callbacks = self.host_callback_recorder.finish();
+        let host_callbacks_duration: Duration = host_callbacks.iter().map(|r| r.duration).sum();
+
+        let peak_memory = match &self.runtime {
+            Runtime::Wapc(wapc_stack) => Some(wapc_stack.peak_memory()),
+            Runtime::Rego(burrego_evaluator) => Some(burrego_evaluator.evaluator.peak_memory()),
+            Runtime::Cli(cli_stack) => cli_stack.peak_memory(),
+        };
+
+        EvaluationReport {
+            total_duration,
+            guest_duration: total_duration.saturating_sub(host_callbacks_duration),
+            host_callbacks_duration,
+            host_callbacks,
+            peak_memory,
+            fuel_consumed: self.fuel_consumed(),
+        }
+    }
+
+    /// Validate the settings of the policy.
+    ///
+    /// When the metadata of the policy provides a
+    /// [settings schema](crate::policy_metadata::Metadata::settings_schema), the settings
+    /// are validated against it first. The policy is invoked only when the settings
+    /// match the schema.
     #[tracing::instrument]
     pub fn validate_settings(&mut self, settings: &PolicySettings) -> SettingsValidationResponse {
+        if let Some(settings_schema) = &self.settings_schema {
+            let response = settings_schema.validate(settings);
+            if !response.valid {
+                return response;
+            }
+        }
+
         let settings_str = match serde_json::to_string(settings) {
             Ok(settings) => settings,
             Err(err) => {
@@ -62,7 +313,8 @@ impl PolicyEvaluator {
             }
         };
 
-        match self.runtime {
+        self.cancellation.reset();
+        let response = match self.runtime {
             Runtime::Wapc(ref mut wapc_stack) => {
                 WapcRuntime(wapc_stack).validate_settings(settings_str)
             }
@@ -72,6 +324,41 @@ impl PolicyEvaluator {
             Runtime::Cli(ref mut cli_stack) => {
                 WasiRuntime(cli_stack).validate_settings(settings_str)
             }
+        };
+
+        if self.cancellation.reset() {
+            return SettingsValidationResponse {
+                valid: false,
+                message: Some("settings validation cancelled".to_string()),
+            };
+        }
+        response
+    }
+
+    /// The amount of fuel consumed by the last evaluation, this can be used to size
+    /// the budget given via
+    /// [`enable_fuel_metering`](crate::policy_evaluator_builder::PolicyEvaluatorBuilder::enable_fuel_metering).
+    ///
+    /// This is `None` when fuel metering is not enabled, or when no evaluation has been done yet.
+    pub fn fuel_consumed(&self) -> Option<u64> {
+        match &self.runtime {
+            Runtime::Wapc(wapc_stack) => wapc_stack.fuel_consumed(),
+            Runtime::Rego(burrego_evaluator) => burrego_evaluator.evaluator.fuel_consumed(),
+            Runtime::Cli(cli_stack) => cli_stack.fuel_consumed(),
+        }
+    }
+
+    /// Returns true when the last evaluation has been interrupted by the host. This happens
+    /// when the policy exceeds its execution deadline, runs out of fuel or exceeds its
+    /// memory limit.
+    ///
+    /// The stack of the policy is reset after an interruption, however embedders might
+    /// prefer to discard the evaluator altogether.
+    pub fn was_interrupted(&self) -> bool {
+        match &self.runtime {
+            Runtime::Wapc(wapc_stack) => wapc_stack.interrupted,
+            Runtime::Rego(burrego_evaluator) => burrego_evaluator.interrupted,
+            Runtime::Cli(cli_stack) => cli_stack.interrupted(),
         }
     }
 
@@ -80,9 +367,74 @@ impl PolicyEvaluator {
             Runtime::Wapc(ref mut wapc_stack) => Ok(WapcRuntime(wapc_stack)
                 .protocol_version()
                 .map_err(PolicyEvaluatorError::InvokeWapcProtocolVersion)?),
-            _ => Err(PolicyEvaluatorError::InvalidProtocolVersion()),
+            Runtime::Cli(ref mut cli_stack) => Ok(WasiRuntime(cli_stack)
+                .protocol_version()
+                .map_err(PolicyEvaluatorError::InvokeWasiProtocolVersion)?),
+            Runtime::Rego(_) => Err(PolicyEvaluatorError::InvalidProtocolVersion()),
         }
     }
+
+    /// Introspect the policy, this allows tooling to handle all the kind of policies
+    /// uniformly. The protocol version of waPC and WASI policies is obtained by invoking
+    /// the guest, while the OPA ABI version of Rego policies is read from the module.
+    pub fn policy_info(&mut self) -> Result<PolicyInfo, PolicyEvaluatorError> {
+        let execution_mode = match &self.runtime {
+            Runtime::Wapc(_) => PolicyExecutionMode::KubewardenWapc,
+            Runtime::Cli(_) => PolicyExecutionMode::Wasi,
+            Runtime::Rego(burrego_evaluator) => match burrego_evaluator.policy_execution_mode {
+                RegoPolicyExecutionMode::Opa => PolicyExecutionMode::Opa,
+                RegoPolicyExecutionMode::Gatekeeper => PolicyExecutionMode::OpaGatekeeper,
+            },
+        };
+
+        let (abi_version, entrypoints) = match self.runtime {
+            Runtime::Rego(ref mut burrego_evaluator) => {
+                let (major, minor) = burrego_evaluator
+                    .evaluator
+                    .opa_abi_version()
+                    .map_err(PolicyEvaluatorError::OpaAbiVersion)?;
+                let mut entrypoints: Vec<String> = burrego_evaluator
+                    .evaluator
+                    .entrypoints()
+                    .into_keys()
+                    .collect();
+                entrypoints.sort();
+                (PolicyAbiVersion::Opa { major, minor }, entrypoints)
+            }
+            Runtime::Wapc(_) | Runtime::Cli(_) => (
+                PolicyAbiVersion::Kubewarden(self.protocol_version()?),
+                self.exported_functions.to_vec(),
+            ),
+        };
+
+        Ok(PolicyInfo {
+            execution_mode,
+            abi_version,
+            entrypoints,
+        })
+    }
+}
+
+/// Rego policies do not make host callbacks, the Kubernetes resources they are
+/// granted access to are fetched before the evaluation. This is reported as a single
+/// `kubernetes` callback.
+fn record_kubernetes_context(
+    recorder: &HostCallbackRecorder,
+    eval_ctx: &EvaluationContext,
+    start: Instant,
+    success: bool,
+) {
+    if eval_ctx.ctx_aware_resources_allow_list.is_empty() {
+        return;
+    }
+
+    recorder.record(HostCallbackRecord {
+        capability: "kubernetes".to_string(),
+        operation: "build_context".to_string(),
+        duration: start.elapsed(),
+        was_cached: false,
+        success,
+    });
 }
 
 impl fmt::Debug for PolicyEvaluator {
@@ -1,13 +1,27 @@
-use std::path::Path;
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
 use std::result::Result;
+use std::time::Duration;
 
-use wasmtime_provider::wasmtime;
+use wasmparser::{Parser, Payload};
 
 use crate::errors::PolicyEvaluatorBuilderError;
+use crate::evaluation_context::EvaluationContext;
 use crate::policy_evaluator::errors::InvalidUserInputError;
-use crate::policy_evaluator::{stack_pre::StackPre, PolicyEvaluatorPre, PolicyExecutionMode};
+use crate::policy_evaluator::{
+    epoch_ticker,
+    precompiled_cache::{module_digest, PrecompiledCache},
+    read_self_tests,
+    settings_schema::SettingsSchema,
+    stack_pre::StackPre,
+    PolicyEvaluatorPre, PolicyExecutionMode, SelfTest,
+};
+use crate::policy_metadata::Metadata;
 use crate::runtimes::{rego, wapc, wasi_cli};
 
+pub(crate) use burrego::FuelLimits;
+pub use burrego::ResourceLimits;
+
 /// Configure behavior of wasmtime [epoch-based interruptions](https://docs.rs/wasmtime/latest/wasmtime/struct.Config.html#method.epoch_interruption)
 ///
 /// There are two kind of deadlines that apply to waPC modules:
@@ -24,6 +38,9 @@ pub(crate) struct EpochDeadlines {
     pub wapc_func: u64,
 }
 
+/// The magic number every binary WebAssembly module starts with
+const WASM_MAGIC_NUMBER: &[u8] = b"\0asm";
+
 /// Helper Struct that creates a `PolicyEvaluator` object
 #[derive(Default)]
 pub struct PolicyEvaluatorBuilder {
@@ -32,8 +49,15 @@ pub struct PolicyEvaluatorBuilder {
     policy_contents: Option<Vec<u8>>,
     policy_module: Option<wasmtime::Module>,
     execution_mode: Option<PolicyExecutionMode>,
+    detect_execution_mode: bool,
     wasmtime_cache: bool,
+    precompiled_cache_dir: Option<PathBuf>,
     epoch_deadlines: Option<EpochDeadlines>,
+    timeout: Option<Duration>,
+    fuel_limits: Option<FuelLimits>,
+    resource_limits: ResourceLimits,
+    async_support: bool,
+    self_tests_context: Option<EvaluationContext>,
 }
 
 impl PolicyEvaluatorBuilder {
@@ -92,6 +116,25 @@ impl PolicyEvaluatorBuilder {
         self
     }
 
+    /// Detect the execution mode of the policy, instead of setting it via
+    /// [`execution_mode`](PolicyEvaluatorBuilder::execution_mode).
+    ///
+    /// The execution mode is read from the Kubewarden metadata embedded into the policy.
+    /// When the policy has not been annotated, the execution mode is inferred from the
+    /// functions exported by the module:
+    ///
+    /// * `__guest_call`: waPC policy
+    /// * `opa_eval_ctx_new`: Rego policy. OPA and Gatekeeper policies cannot be told apart,
+    ///   hence an error is returned
+    /// * `_start`: WASI policy
+    ///
+    /// The exports cannot be inspected when the policy is provided as a WAT file.
+    #[must_use]
+    pub fn detect_execution_mode(mut self) -> Self {
+        self.detect_execution_mode = true;
+        self
+    }
+
     /// Enable Wasmtime cache feature
     #[must_use]
     pub fn enable_wasmtime_cache(mut self) -> PolicyEvaluatorBuilder {
@@ -99,6 +142,27 @@ impl PolicyEvaluatorBuilder {
         self
     }
 
+    /// Store the precompiled policy modules inside of the given directory, and load
+    /// them from there on subsequent builds. This makes the creation of the
+    /// `PolicyEvaluatorPre` much faster, since the policy doesn't have to be compiled again.
+    ///
+    /// Unlike the [Wasmtime cache](PolicyEvaluatorBuilder::enable_wasmtime_cache), the
+    /// cache entries are plain files named after the sha256 digest of the policy and a
+    /// fingerprint of the [`wasmtime::Engine`] configuration. Entries produced by an
+    /// incompatible engine are never loaded, and they are evicted when the policy is
+    /// compiled again. Entries that have not been used for 30 days are evicted too.
+    ///
+    /// The directory is created when it doesn't exist. The cache is used only when the
+    /// policy is provided via `policy_file` or `policy_contents`.
+    ///
+    /// **Warning:** the contents of the directory are trusted, ensure it cannot be
+    /// written by untrusted users.
+    #[must_use]
+    pub fn precompiled_cache_dir(mut self, path: &Path) -> Self {
+        self.precompiled_cache_dir = Some(path.to_path_buf());
+        self
+    }
+
     /// Enable Wasmtime [epoch-based interruptions](wasmtime::Config::epoch_interruption) and set
     /// the deadlines to be enforced
     ///
@@ -131,6 +195,97 @@ impl PolicyEvaluatorBuilder {
         self
     }
 
+    /// Interrupt the evaluation of the policy once it takes longer than `timeout`.
+    /// The same limit applies to the initialization code of the policy.
+    ///
+    /// This is a wall-clock alternative to
+    /// [`enable_epoch_interruptions`](PolicyEvaluatorBuilder::enable_epoch_interruptions):
+    /// the timeout is converted into epoch ticks, and a thread incrementing the epoch
+    /// of the [`wasmtime::Engine`] is started. A single thread is shared by all the
+    /// policies using the same engine, the thread stops once the engine is dropped.
+    ///
+    /// The request is rejected when the timeout is exceeded, the rejection message
+    /// reports how long the evaluation took.
+    ///
+    /// **Warning:** when providing an instance of `wasmtime::Engine` via the
+    /// `engine` helper, ensure the `wasmtime::Engine` has been created with the
+    /// `epoch_interruption` feature enabled, and that its epoch is not incremented
+    /// by the embedder too
+    #[must_use]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Enable Wasmtime [fuel consumption](wasmtime::Config::consume_fuel) and set the amount
+    /// of fuel the guest is allowed to consume
+    ///
+    /// Unlike epoch-based interruptions, fuel consumption doesn't depend on an external
+    /// thread ticking the engine: the same evaluation always consumes the same amount
+    /// of fuel, regardless of the load of the node.
+    ///
+    /// * `init_fuel`: the fuel that can be consumed by the initialization code of the
+    ///   policy, like the `wapc_init` function of waPC policies. WASI policies have no
+    ///   such a code, they perform all their work inside of the evaluation
+    /// * `func_fuel`: the fuel that can be consumed by each evaluation of the policy
+    ///
+    /// The evaluation is interrupted once the fuel is exhausted, the request is then rejected.
+    /// The amount of fuel consumed by an evaluation is reported by
+    /// [`PolicyEvaluator::fuel_consumed`](crate::policy_evaluator::PolicyEvaluator::fuel_consumed).
+    ///
+    /// **Warning:** when providing an instance of `wasmtime::Engine` via the
+    /// `engine` helper, ensure the `wasmtime::Engine` has been created with the
+    /// `consume_fuel` feature enabled
+    #[must_use]
+    pub fn enable_fuel_metering(mut self, init_fuel: u64, func_fuel: u64) -> Self {
+        self.fuel_limits = Some(FuelLimits {
+            init: init_fuel,
+            func: func_fuel,
+        });
+        self
+    }
+
+    /// Limit the resources that can be allocated by the policy while being evaluated.
+    /// The limits are enforced via a [`wasmtime::ResourceLimiter`] on every
+    /// `wasmtime::Store` used by the policy.
+    ///
+    /// The evaluation is interrupted as soon as the policy attempts to grow its memory
+    /// beyond `memory_size`, the request is then rejected. Attempts to grow a table
+    /// beyond `table_elements` are denied, which is something the policy can handle.
+    #[must_use]
+    pub fn resource_limits(mut self, limits: ResourceLimits) -> Self {
+        self.resource_limits = limits;
+        self
+    }
+
+    /// Enable the asynchronous evaluation of waPC and WASI policies, see
+    /// [`PolicyEvaluator::validate_async`](crate::policy_evaluator::PolicyEvaluator::validate_async).
+    ///
+    /// When enabled, the host capabilities requested by the policy are fulfilled without
+    /// blocking the thread running the evaluation.
+    ///
+    /// **Warning:** when providing an instance of `wasmtime::Engine` via the
+    /// `engine` helper, ensure the `wasmtime::Engine` has been created with the
+    /// [`async_support`](wasmtime::Config::async_support) feature enabled. This feature must
+    /// not be enabled when evaluating Rego policies, their evaluation is always synchronous.
+    #[must_use]
+    pub fn enable_async_support(mut self) -> Self {
+        self.async_support = true;
+        self
+    }
+
+    /// Run the self tests embedded into the policy while building the
+    /// `PolicyEvaluatorPre`, using the given `EvaluationContext`. The build fails
+    /// when one of them doesn't pass.
+    ///
+    /// Self tests are not run unless requested, see
+    /// [`PolicyEvaluatorPre::self_test`] to run them at a later stage.
+    #[must_use]
+    pub fn run_self_tests(mut self, eval_ctx: EvaluationContext) -> Self {
+        self.self_tests_context = Some(eval_ctx);
+        self
+    }
+
     /// Ensure the configuration provided to the build is correct
     fn validate_user_input(&self) -> Result<(), InvalidUserInputError> {
         if self.policy_file.is_some() && self.policy_contents.is_some() {
@@ -154,8 +309,14 @@ impl PolicyEvaluatorBuilder {
             return Err(InvalidUserInputError::EngineForModule);
         }
 
-        if self.execution_mode.is_none() {
-            return Err(InvalidUserInputError::ExecutionMode);
+        match (self.execution_mode, self.detect_execution_mode) {
+            (None, false) => return Err(InvalidUserInputError::ExecutionMode),
+            (Some(_), true) => return Err(InvalidUserInputError::ExecutionModeAndDetection),
+            _ => {}
+        }
+
+        if self.epoch_deadlines.is_some() && self.timeout.is_some() {
+            return Err(InvalidUserInputError::TimeoutAndEpochDeadlines);
         }
 
         Ok(())
@@ -166,27 +327,58 @@ impl PolicyEvaluatorBuilder {
         self.validate_user_input()
             .map_err(PolicyEvaluatorBuilderError::InvalidUserInput)?;
 
-        let engine = self.build_engine()?;
-        let module = self.build_module(&engine)?;
+        let policy_bytes = self.read_policy_bytes()?;
+        let metadata = Self::read_metadata(policy_bytes.as_deref())?;
+        let self_tests = Self::read_self_tests(policy_bytes.as_deref())?;
+        let execution_mode = match self.execution_mode {
+            Some(execution_mode) => execution_mode,
+            None => {
+                self.detect_policy_execution_mode(metadata.as_ref(), policy_bytes.as_deref())?
+            }
+        };
 
-        let execution_mode = self.execution_mode.unwrap();
+        let engine = self.build_engine(execution_mode)?;
+        let module = self.build_module(&engine, policy_bytes.as_deref())?;
+        let exported_functions = exported_functions(&module);
+
+        let epoch_deadlines = self.epoch_deadlines();
+        if self.timeout.is_some() {
+            epoch_ticker::ensure_ticking(&engine)
+                .map_err(PolicyEvaluatorBuilderError::EpochTicker)?;
+        }
 
         let stack_pre = match execution_mode {
             PolicyExecutionMode::KubewardenWapc => {
-                let wapc_stack_pre = wapc::StackPre::new(engine, module, self.epoch_deadlines)
-                    .map_err(PolicyEvaluatorBuilderError::NewWapcStackPre)?;
+                let wapc_stack_pre = wapc::StackPre::new(
+                    engine,
+                    module,
+                    epoch_deadlines,
+                    self.fuel_limits,
+                    self.resource_limits,
+                    self.async_support,
+                )
+                .map_err(PolicyEvaluatorBuilderError::NewWapcStackPre)?;
                 StackPre::from(wapc_stack_pre)
             }
             PolicyExecutionMode::Wasi => {
-                let wasi_stack_pre = wasi_cli::StackPre::new(engine, module, self.epoch_deadlines)
-                    .map_err(PolicyEvaluatorBuilderError::NewWasiStackPre)?;
+                let wasi_stack_pre = wasi_cli::StackPre::new(
+                    engine,
+                    module,
+                    epoch_deadlines,
+                    self.fuel_limits,
+                    self.resource_limits,
+                    self.async_support,
+                )
+                .map_err(PolicyEvaluatorBuilderError::NewWasiStackPre)?;
                 StackPre::from(wasi_stack_pre)
             }
             PolicyExecutionMode::Opa | PolicyExecutionMode::OpaGatekeeper => {
                 let rego_stack_pre = rego::StackPre::new(
                     engine,
                     module,
-                    self.epoch_deadlines,
+                    epoch_deadlines,
+                    self.fuel_limits,
+                    self.resource_limits,
                     0, // currently the entrypoint is hard coded to this value
                     execution_mode
                         .try_into()
@@ -196,10 +388,75 @@ impl PolicyEvaluatorBuilder {
             }
         };
 
-        Ok(PolicyEvaluatorPre::new(stack_pre))
+        let settings_schema = metadata
+            .as_ref()
+            .and_then(|metadata| metadata.settings_schema.as_ref())
+            .map(SettingsSchema::new)
+            .transpose()
+            .map_err(PolicyEvaluatorBuilderError::SettingsSchema)?;
+
+        let policy_evaluator_pre = PolicyEvaluatorPre::new(
+            stack_pre,
+            metadata,
+            settings_schema,
+            self.timeout,
+            policy_bytes.as_deref().map(module_digest),
+            exported_functions,
+            self_tests,
+        );
+
+        if let Some(eval_ctx) = &self.self_tests_context {
+            policy_evaluator_pre
+                .self_test(eval_ctx)
+                .map_err(PolicyEvaluatorBuilderError::SelfTestFailed)?;
+        }
+
+        Ok(policy_evaluator_pre)
+    }
+
+    /// The epoch deadlines to be enforced, either set explicitly or derived from the timeout
+    fn epoch_deadlines(&self) -> Option<EpochDeadlines> {
+        self.epoch_deadlines.or_else(|| {
+            self.timeout.map(|timeout| {
+                let ticks = epoch_ticker::timeout_to_ticks(timeout);
+                EpochDeadlines {
+                    wapc_init: ticks,
+                    wapc_func: ticks,
+                }
+            })
+        })
+    }
+
+    /// Detect the execution mode, see [`detect_execution_mode`](PolicyEvaluatorBuilder::detect_execution_mode)
+    fn detect_policy_execution_mode(
+        &self,
+        metadata: Option<&Metadata>,
+        policy_bytes: Option<&[u8]>,
+    ) -> Result<PolicyExecutionMode, PolicyEvaluatorBuilderError> {
+        if let Some(metadata) = metadata {
+            return Ok(metadata.execution_mode);
+        }
+
+        let exports: Vec<String> = match (&self.policy_module, policy_bytes) {
+            (Some(module), _) => module.exports().map(|e| e.name().to_string()).collect(),
+            (None, Some(bytes)) if bytes.starts_with(WASM_MAGIC_NUMBER) => {
+                read_exports(bytes).map_err(PolicyEvaluatorBuilderError::ReadModuleExports)?
+            }
+            _ => return Err(PolicyEvaluatorBuilderError::UnknownExecutionMode),
+        };
+
+        execution_mode_from_exports(&exports)
     }
 
-    fn build_engine(&self) -> Result<wasmtime::Engine, PolicyEvaluatorBuilderError> {
+    fn build_engine(
+        &self,
+        execution_mode: PolicyExecutionMode,
+    ) -> Result<wasmtime::Engine, PolicyEvaluatorBuilderError> {
+        let is_rego_policy = matches!(
+            execution_mode,
+            PolicyExecutionMode::Opa | PolicyExecutionMode::OpaGatekeeper
+        );
+
         self.engine
             .as_ref()
             .map_or_else(
@@ -208,9 +465,15 @@ impl PolicyEvaluatorBuilder {
                     if self.wasmtime_cache {
                         wasmtime_config.cache_config_load_default()?;
                     }
-                    if self.epoch_deadlines.is_some() {
+                    if self.epoch_deadlines.is_some() || self.timeout.is_some() {
                         wasmtime_config.epoch_interruption(true);
                     }
+                    if self.fuel_limits.is_some() {
+                        wasmtime_config.consume_fuel(true);
+                    }
+                    if self.async_support && !is_rego_policy {
+                        wasmtime_config.async_support(true);
+                    }
 
                     wasmtime::Engine::new(&wasmtime_config)
                 },
@@ -219,28 +482,118 @@ impl PolicyEvaluatorBuilder {
             .map_err(PolicyEvaluatorBuilderError::WasmtimeEngineBuild)
     }
 
+    /// Returns the raw bytes of the policy, when the policy has been provided
+    /// either via `policy_file` or `policy_contents`
+    fn read_policy_bytes(&self) -> Result<Option<Cow<'_, [u8]>>, PolicyEvaluatorBuilderError> {
+        if let Some(file) = &self.policy_file {
+            return std::fs::read(file)
+                .map(|contents| Some(Cow::Owned(contents)))
+                .map_err(PolicyEvaluatorBuilderError::ReadPolicyFile);
+        }
+
+        Ok(self.policy_contents.as_deref().map(Cow::Borrowed))
+    }
+
+    /// Reads the Kubewarden metadata embedded into the policy. This is possible only
+    /// when the policy is provided in its binary form.
+    ///
+    /// Malformed metadata is an error, `None` is returned only when the policy has not
+    /// been annotated.
+    fn read_metadata(
+        policy_bytes: Option<&[u8]>,
+    ) -> Result<Option<Metadata>, PolicyEvaluatorBuilderError> {
+        match policy_bytes {
+            Some(bytes) if bytes.starts_with(WASM_MAGIC_NUMBER) => {
+                Metadata::from_contents(bytes).map_err(PolicyEvaluatorBuilderError::Metadata)
+            }
+            // the policy is either provided as a pre-built Module, or as a WAT file
+            _ => Ok(None),
+        }
+    }
+
+    fn read_self_tests(
+        policy_bytes: Option<&[u8]>,
+    ) -> Result<Vec<SelfTest>, PolicyEvaluatorBuilderError> {
+        match policy_bytes {
+            Some(bytes) if bytes.starts_with(WASM_MAGIC_NUMBER) => {
+                read_self_tests(bytes).map_err(PolicyEvaluatorBuilderError::SelfTests)
+            }
+            _ => Ok(Vec::new()),
+        }
+    }
+
     fn build_module(
         &self,
         engine: &wasmtime::Engine,
+        policy_bytes: Option<&[u8]>,
     ) -> Result<wasmtime::Module, PolicyEvaluatorBuilderError> {
         if let Some(m) = &self.policy_module {
             // it's fine to clone a Module, this is a cheap operation that just
             // copies its internal reference. See wasmtime docs
             Ok(m.clone())
         } else {
-            match &self.policy_file {
-                Some(file) => wasmtime::Module::from_file(engine, file)
-                    .map_err(PolicyEvaluatorBuilderError::WasmModuleBuild),
-                None => wasmtime::Module::new(engine, self.policy_contents.as_ref().unwrap())
-                    .map_err(PolicyEvaluatorBuilderError::WasmModuleBuild),
+            // `validate_user_input` ensures either the file or the contents are provided
+            let policy_bytes = policy_bytes.unwrap_or_default();
+            if let Some(dir) = &self.precompiled_cache_dir {
+                return PrecompiledCache::open(dir)?.load_or_compile(engine, policy_bytes);
+            }
+            wasmtime::Module::new(engine, policy_bytes)
+                .map_err(PolicyEvaluatorBuilderError::WasmModuleBuild)
+        }
+    }
+}
+
+/// Returns the names of the functions, and of the other items, exported by the module
+fn read_exports(policy_bytes: &[u8]) -> Result<Vec<String>, wasmparser::BinaryReaderError> {
+    let mut exports = Vec::new();
+    for payload in Parser::new(0).parse_all(policy_bytes) {
+        if let Payload::ExportSection(reader) = payload? {
+            for export in reader {
+                exports.push(export?.name.to_string());
             }
         }
     }
+    Ok(exports)
+}
+
+/// Returns the names of the functions exported by the compiled module
+fn exported_functions(module: &wasmtime::Module) -> Vec<String> {
+    module
+        .exports()
+        .filter(|export| matches!(export.ty(), wasmtime::ExternType::Func(_)))
+        .map(|export| export.name().to_string())
+        .collect()
+}
+
+/// Infer the execution mode from the names exported by the module. The waPC entrypoint
+/// is checked first, since waPC policies can export the `_start` function too
+fn execution_mode_from_exports(
+    exports: &[String],
+) -> Result<PolicyExecutionMode, PolicyEvaluatorBuilderError> {
+    let has_export = |name: &str| exports.iter().any(|export| export == name);
+
+    if has_export("__guest_call") {
+        Ok(PolicyExecutionMode::KubewardenWapc)
+    } else if has_export("opa_eval_ctx_new") {
+        Err(PolicyEvaluatorBuilderError::AmbiguousExecutionMode)
+    } else if has_export("_start") {
+        Ok(PolicyExecutionMode::Wasi)
+    } else {
+        Err(PolicyEvaluatorBuilderError::UnknownExecutionMode)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::constants::{
+        KUBEWARDEN_CUSTOM_SECTION_METADATA, KUBEWARDEN_CUSTOM_SECTION_SELF_TESTS,
+    };
+    use crate::errors::PolicySelfTestError;
+    use crate::policy_evaluator::{PolicyAbiVersion, PolicyInfo, PolicySettings, ValidateRequest};
+    use crate::test_fixtures::{wasi_printing, with_custom_section, WASI_START_ONLY};
+    use kubewarden_policy_sdk::metadata::ProtocolVersion;
+    use rstest::rstest;
 
     #[test]
     fn build_policy_evaluator_pre() {
@@ -257,4 +610,399 @@ mod tests {
 
         _ = policy_evaluator_builder.build_pre().unwrap();
     }
+
+    #[test]
+    fn wapc_evaluation_is_interrupted_when_fuel_is_exhausted() {
+        let wat = include_bytes!("../../tests/data/endless_wasm/wapc_endless_loop.wat");
+
+        let policy_evaluator_pre = PolicyEvaluatorBuilder::new()
+            .execution_mode(PolicyExecutionMode::KubewardenWapc)
+            .policy_contents(wat)
+            .enable_fuel_metering(1_000, 1_000)
+            .build_pre()
+            .expect("cannot build PolicyEvaluatorPre");
+        let eval_ctx = EvaluationContext {
+            policy_id: "fuel".to_string(),
+            callback_channel: None,
+            ctx_aware_resources_allow_list: Default::default(),
+            policy_mode: Default::default(),
+            clock: Default::default(),
+            protected_paths: Default::default(),
+            array_keys: Default::default(),
+        };
+        let mut policy_evaluator = policy_evaluator_pre
+            .rehydrate(&eval_ctx)
+            .expect("cannot rehydrate policy evaluator");
+
+        // This triggers an endless loop inside of wasm, the fuel stops it
+        let response = policy_evaluator.validate(
+            ValidateRequest::Raw(serde_json::json!({})),
+            &PolicySettings::default(),
+        );
+
+        assert!(!response.allowed);
+        assert!(policy_evaluator.was_interrupted());
+        assert_eq!(Some(1_000), policy_evaluator.fuel_consumed());
+    }
+
+    #[test]
+    fn timeout_and_epoch_interruptions_are_mutually_exclusive() {
+        let wat = include_bytes!("../../tests/data/endless_wasm/wapc_endless_loop.wat");
+
+        let policy_evaluator_builder = PolicyEvaluatorBuilder::new()
+            .execution_mode(PolicyExecutionMode::KubewardenWapc)
+            .policy_contents(wat)
+            .enable_epoch_interruptions(1, 2)
+            .timeout(Duration::from_secs(1));
+
+        assert!(matches!(
+            policy_evaluator_builder.build_pre(),
+            Err(PolicyEvaluatorBuilderError::InvalidUserInput(
+                InvalidUserInputError::TimeoutAndEpochDeadlines
+            ))
+        ));
+    }
+
+    #[test]
+    fn evaluation_is_interrupted_by_timeout() {
+        let wat = include_bytes!("../../tests/data/endless_wasm/wapc_endless_loop.wat");
+        let timeout = Duration::from_millis(100);
+
+        let policy_evaluator_pre = PolicyEvaluatorBuilder::new()
+            .execution_mode(PolicyExecutionMode::KubewardenWapc)
+            .policy_contents(wat)
+            .timeout(timeout)
+            .build_pre()
+            .expect("cannot build PolicyEvaluatorPre");
+        let eval_ctx = EvaluationContext {
+            policy_id: "timeout".to_string(),
+            callback_channel: None,
+            ctx_aware_resources_allow_list: Default::default(),
+            policy_mode: Default::default(),
+            clock: Default::default(),
+            protected_paths: Default::default(),
+            array_keys: Default::default(),
+        };
+        let mut policy_evaluator = policy_evaluator_pre
+            .rehydrate(&eval_ctx)
+            .expect("cannot rehydrate policy evaluator");
+
+        // This triggers an endless loop inside of wasm, no ticker has to be started
+        let start = std::time::Instant::now();
+        let response = policy_evaluator.validate(
+            ValidateRequest::Raw(serde_json::json!({})),
+            &PolicySettings::default(),
+        );
+
+        assert!(start.elapsed() >= timeout);
+        assert!(!response.allowed);
+        assert!(policy_evaluator.was_interrupted());
+        let message = response.status.unwrap().message.unwrap();
+        assert!(message.contains("timed out after"), "{message}");
+    }
+
+    const WASI_ENDLESS_LOOP: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (func (export "_start")
+            (loop $endless
+              br $endless)))
+    "#;
+
+    #[rstest]
+    #[case::wasi(PolicyExecutionMode::Wasi, WASI_ENDLESS_LOOP.as_bytes())]
+    #[case::wapc(
+        PolicyExecutionMode::KubewardenWapc,
+        include_bytes!("../../tests/data/endless_wasm/wapc_endless_loop.wat")
+    )]
+    fn evaluation_is_cancelled(
+        #[case] execution_mode: PolicyExecutionMode,
+        #[case] policy_contents: &[u8],
+    ) {
+        let timeout = Duration::from_secs(30);
+
+        let policy_evaluator_pre = PolicyEvaluatorBuilder::new()
+            .execution_mode(execution_mode)
+            .policy_contents(policy_contents)
+            .timeout(timeout)
+            .build_pre()
+            .expect("cannot build PolicyEvaluatorPre");
+        let eval_ctx = EvaluationContext {
+            policy_id: "cancellation".to_string(),
+            callback_channel: None,
+            ctx_aware_resources_allow_list: Default::default(),
+            policy_mode: Default::default(),
+            clock: Default::default(),
+            protected_paths: Default::default(),
+            array_keys: Default::default(),
+        };
+        let mut policy_evaluator = policy_evaluator_pre
+            .rehydrate(&eval_ctx)
+            .expect("cannot rehydrate policy evaluator");
+
+        let cancellation_handle = policy_evaluator.cancellation_handle();
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            cancellation_handle.cancel();
+        });
+
+        let start = std::time::Instant::now();
+        let response = policy_evaluator.validate(
+            ValidateRequest::Raw(serde_json::json!({})),
+            &PolicySettings::default(),
+        );
+        canceller.join().unwrap();
+
+        assert!(start.elapsed() < timeout);
+        assert!(!response.allowed);
+        assert!(policy_evaluator.was_interrupted());
+        assert_eq!(
+            Some("policy evaluation cancelled".to_string()),
+            response.status.unwrap().message
+        );
+        assert!(!policy_evaluator.cancellation_handle().is_cancelled());
+    }
+
+    #[test]
+    fn cancellation_does_not_outlive_the_evaluation() {
+        let policy_evaluator_pre = PolicyEvaluatorBuilder::new()
+            .execution_mode(PolicyExecutionMode::Wasi)
+            .policy_contents(wasi_printing(r#"{"accepted":true}"#).as_bytes())
+            .timeout(Duration::from_secs(30))
+            .build_pre()
+            .expect("cannot build PolicyEvaluatorPre");
+        let eval_ctx = EvaluationContext {
+            policy_id: "cancellation".to_string(),
+            callback_channel: None,
+            ctx_aware_resources_allow_list: Default::default(),
+            policy_mode: Default::default(),
+            clock: Default::default(),
+            protected_paths: Default::default(),
+            array_keys: Default::default(),
+        };
+        let mut policy_evaluator = policy_evaluator_pre
+            .rehydrate(&eval_ctx)
+            .expect("cannot rehydrate policy evaluator");
+
+        let response = policy_evaluator.validate(
+            ValidateRequest::Raw(serde_json::json!({})),
+            &PolicySettings::default(),
+        );
+        assert!(response.allowed);
+
+        // the evaluation is already done, the next one must not be affected
+        policy_evaluator.cancellation_handle().cancel();
+        let response = policy_evaluator.validate(
+            ValidateRequest::Raw(serde_json::json!({})),
+            &PolicySettings::default(),
+        );
+
+        assert!(response.allowed);
+        assert!(!policy_evaluator.cancellation_handle().is_cancelled());
+    }
+
+    #[test]
+    fn wasi_policy_info() {
+        // the protocol version reported by the policy
+        let wat = wasi_printing(r#""v1""#);
+
+        let policy_evaluator_pre = PolicyEvaluatorBuilder::new()
+            .execution_mode(PolicyExecutionMode::Wasi)
+            .policy_contents(wat.as_bytes())
+            .build_pre()
+            .expect("cannot build PolicyEvaluatorPre");
+        let eval_ctx = EvaluationContext {
+            policy_id: "policy-info".to_string(),
+            callback_channel: None,
+            ctx_aware_resources_allow_list: Default::default(),
+            policy_mode: Default::default(),
+            clock: Default::default(),
+            protected_paths: Default::default(),
+            array_keys: Default::default(),
+        };
+        let mut policy_evaluator = policy_evaluator_pre
+            .rehydrate(&eval_ctx)
+            .expect("cannot rehydrate policy evaluator");
+
+        assert_eq!(
+            PolicyInfo {
+                execution_mode: PolicyExecutionMode::Wasi,
+                abi_version: PolicyAbiVersion::Kubewarden(ProtocolVersion::V1),
+                entrypoints: vec!["_start".to_string()],
+            },
+            policy_evaluator.policy_info().unwrap()
+        );
+    }
+
+    #[test]
+    fn build_policy_evaluator_pre_with_precompiled_cache() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let wat = include_bytes!("../../tests/data/endless_wasm/wapc_endless_loop.wat");
+
+        for _ in 0..2 {
+            PolicyEvaluatorBuilder::new()
+                .execution_mode(PolicyExecutionMode::KubewardenWapc)
+                .policy_contents(wat)
+                .precompiled_cache_dir(cache_dir.path())
+                .build_pre()
+                .expect("cannot build PolicyEvaluatorPre");
+        }
+
+        assert_eq!(1, std::fs::read_dir(cache_dir.path()).unwrap().count());
+    }
+
+    #[rstest]
+    #[case::wapc(&["__guest_call", "_start"], Some(PolicyExecutionMode::KubewardenWapc))]
+    #[case::wasi(&["_start", "memory"], Some(PolicyExecutionMode::Wasi))]
+    #[case::rego(&["opa_eval_ctx_new", "memory"], None)]
+    #[case::unknown(&["memory"], None)]
+    fn detect_execution_mode_from_exports(
+        #[case] exports: &[&str],
+        #[case] expected: Option<PolicyExecutionMode>,
+    ) {
+        let exports: Vec<String> = exports.iter().map(|e| e.to_string()).collect();
+        assert_eq!(expected, execution_mode_from_exports(&exports).ok());
+    }
+
+    #[test]
+    fn rego_execution_mode_is_ambiguous() {
+        assert!(matches!(
+            execution_mode_from_exports(&["opa_eval_ctx_new".to_string()]),
+            Err(PolicyEvaluatorBuilderError::AmbiguousExecutionMode)
+        ));
+    }
+
+    #[test]
+    fn detect_execution_mode_of_module() {
+        let engine = wasmtime::Engine::default();
+        let wat = include_bytes!("../../tests/data/endless_wasm/wapc_endless_loop.wat");
+        let module = wasmtime::Module::new(&engine, wat).expect("cannot compile WAT to wasm");
+
+        let policy_evaluator_builder = PolicyEvaluatorBuilder::new()
+            .detect_execution_mode()
+            .policy_module(module)
+            .engine(engine);
+
+        assert_eq!(
+            PolicyExecutionMode::KubewardenWapc,
+            policy_evaluator_builder
+                .detect_policy_execution_mode(None, None)
+                .unwrap()
+        );
+        policy_evaluator_builder
+            .build_pre()
+            .expect("cannot build PolicyEvaluatorPre");
+    }
+
+    #[test]
+    fn detect_execution_mode_of_binary_module() {
+        let wasi_program = WASI_START_ONLY;
+
+        assert_eq!(
+            vec!["_start".to_string()],
+            read_exports(wasi_program).unwrap()
+        );
+
+        let policy_evaluator_builder = PolicyEvaluatorBuilder::new()
+            .detect_execution_mode()
+            .policy_contents(wasi_program);
+        assert_eq!(
+            PolicyExecutionMode::Wasi,
+            policy_evaluator_builder
+                .detect_policy_execution_mode(None, Some(wasi_program))
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn malformed_metadata_is_rejected() {
+        let policy = with_custom_section(
+            WASI_START_ONLY,
+            KUBEWARDEN_CUSTOM_SECTION_METADATA,
+            b"not json",
+        );
+
+        let result = PolicyEvaluatorBuilder::new()
+            .execution_mode(PolicyExecutionMode::Wasi)
+            .policy_contents(&policy)
+            .build_pre();
+
+        assert!(matches!(
+            result,
+            Err(PolicyEvaluatorBuilderError::Metadata(_))
+        ));
+    }
+
+    #[test]
+    fn missing_metadata_is_not_an_error() {
+        let policy_evaluator_pre = PolicyEvaluatorBuilder::new()
+            .execution_mode(PolicyExecutionMode::Wasi)
+            .policy_contents(WASI_START_ONLY)
+            .build_pre()
+            .expect("cannot build PolicyEvaluatorPre");
+
+        assert!(policy_evaluator_pre.metadata().is_none());
+    }
+
+    #[rstest]
+    #[case::not_requested(false)]
+    #[case::requested(true)]
+    fn self_tests_are_run_on_request(#[case] run_self_tests: bool) {
+        // the WASI program doesn't write anything to stdout, hence the
+        // request is rejected and the self test fails
+        let self_tests = serde_json::json!([{
+            "name": "allowed",
+            "request": {"hello": "world"},
+            "raw": true,
+            "expected": {"allowed": true}
+        }]);
+        let policy = with_custom_section(
+            WASI_START_ONLY,
+            KUBEWARDEN_CUSTOM_SECTION_SELF_TESTS,
+            self_tests.to_string().as_bytes(),
+        );
+
+        let mut policy_evaluator_builder = PolicyEvaluatorBuilder::new()
+            .execution_mode(PolicyExecutionMode::Wasi)
+            .policy_contents(&policy);
+        if run_self_tests {
+            policy_evaluator_builder = policy_evaluator_builder.run_self_tests(EvaluationContext {
+                policy_id: "self-tests".to_string(),
+                ..Default::default()
+            });
+        }
+
+        let result = policy_evaluator_builder.build_pre();
+        if run_self_tests {
+            assert!(matches!(
+                result,
+                Err(PolicyEvaluatorBuilderError::SelfTestFailed(
+                    PolicySelfTestError::Failed(failures)
+                )) if failures.len() == 1
+            ));
+        } else {
+            assert_eq!(
+                result
+                    .expect("cannot build PolicyEvaluatorPre")
+                    .self_tests()
+                    .len(),
+                1
+            );
+        }
+    }
+
+    #[test]
+    fn execution_mode_and_detection_are_mutually_exclusive() {
+        let policy_evaluator_builder = PolicyEvaluatorBuilder::new()
+            .execution_mode(PolicyExecutionMode::Wasi)
+            .detect_execution_mode()
+            .policy_contents(b"");
+
+        assert!(matches!(
+            policy_evaluator_builder.build_pre(),
+            Err(PolicyEvaluatorBuilderError::InvalidUserInput(
+                InvalidUserInputError::ExecutionModeAndDetection
+            ))
+        ));
+    }
 }
@@ -0,0 +1,470 @@
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore, TryAcquireError};
+use tracing::debug;
+
+use crate::errors::PolicyEvaluatorPoolError;
+use crate::evaluation_context::EvaluationContext;
+use crate::policy_evaluator::{PolicyEvaluator, PolicyEvaluatorPre};
+use crate::runtimes::callback::block_on;
+
+/// Statistics about the usage of a [`PolicyEvaluatorPool`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PolicyEvaluatorPoolStats {
+    /// The maximum number of evaluators the pool can hold
+    pub max_size: usize,
+    /// Number of evaluators that are ready to be checked out
+    pub idle: usize,
+    /// Number of evaluators currently checked out
+    pub in_use: usize,
+    /// Number of evaluators rehydrated since the creation of the pool
+    pub created: u64,
+    /// Number of times an idle evaluator has been checked out
+    pub reused: u64,
+    /// Number of evaluators that have been discarded instead of being
+    /// returned to the pool
+    pub discarded: u64,
+}
+
+struct PoolState {
+    idle: Vec<PolicyEvaluator>,
+    in_use: usize,
+    created: u64,
+    reused: u64,
+    discarded: u64,
+}
+
+struct PoolInner {
+    evaluator_pre: PolicyEvaluatorPre,
+    eval_ctx: EvaluationContext,
+    max_size: usize,
+    /// Bounds the number of evaluators that can be checked out at the same time
+    semaphore: Arc<Semaphore>,
+    state: Mutex<PoolState>,
+}
+
+impl PoolInner {
+    fn state(&self) -> MutexGuard<'_, PoolState> {
+        // The state is always left consistent, hence it's safe to keep using it
+        // even when another thread panicked while holding the lock
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// A bounded pool of [`PolicyEvaluator`] objects, all of them rehydrated from
+/// the same [`PolicyEvaluatorPre`].
+///
+/// The pool allows concurrent requests to share one compiled policy: each request
+/// checks out an evaluator, which is automatically returned to the pool once the
+/// [`PooledPolicyEvaluator`] guard is dropped.
+///
+/// Evaluators are rehydrated lazily, when no idle evaluator is available, or ahead
+/// of time via [`prewarm`](PolicyEvaluatorPool::prewarm). Evaluators whose last
+/// evaluation has been [interrupted](PolicyEvaluator::was_interrupted) are
+/// discarded instead of being returned to the pool.
+///
+/// The pool is cheap to clone, all the clones share the same evaluators.
+#[derive(Clone)]
+pub struct PolicyEvaluatorPool {
+    inner: Arc<PoolInner>,
+}
+
+impl PolicyEvaluatorPool {
+    /// Create a new pool holding at most `max_size` evaluators. All the evaluators
+    /// are rehydrated using the given `EvaluationContext`.
+    ///
+    /// A `max_size` of 0 is treated as 1.
+    pub fn new(
+        evaluator_pre: PolicyEvaluatorPre,
+        eval_ctx: &EvaluationContext,
+        max_size: usize,
+    ) -> Self {
+        let max_size = max_size.max(1);
+
+        PolicyEvaluatorPool {
+            inner: Arc::new(PoolInner {
+                evaluator_pre,
+                eval_ctx: eval_ctx.to_owned(),
+                max_size,
+                semaphore: Arc::new(Semaphore::new(max_size)),
+                state: Mutex::new(PoolState {
+                    idle: Vec::with_capacity(max_size),
+                    in_use: 0,
+                    created: 0,
+                    reused: 0,
+                    discarded: 0,
+                }),
+            }),
+        }
+    }
+
+    /// Rehydrate evaluators ahead of time, until the pool holds `count` of them.
+    /// The pool never grows beyond its maximum size.
+    pub fn prewarm(&self, count: usize) -> Result<(), PolicyEvaluatorPoolError> {
+        let count = count.min(self.inner.max_size);
+
+        loop {
+            {
+                let state = self.inner.state();
+                if state.idle.len() + state.in_use >= count {
+                    return Ok(());
+                }
+            }
+
+            // rehydrate without holding the lock, this operation can take some time
+            let evaluator = self.rehydrate()?;
+
+            let mut state = self.inner.state();
+            if state.idle.len() + state.in_use >= self.inner.max_size {
+                // the pool has been filled meanwhile
+                return Ok(());
+            }
+            state.created += 1;
+            state.idle.push(evaluator);
+        }
+    }
+
+    /// Check out an evaluator, waiting until one becomes available when all the
+    /// evaluators of the pool are in use
+    pub async fn get(&self) -> Result<PooledPolicyEvaluator, PolicyEvaluatorPoolError> {
+        let permit = self
+            .inner
+            .semaphore
+            .clone()
+            .acquire_owned()
+            .await
+            .map_err(|_| PolicyEvaluatorPoolError::Closed)?;
+
+        self.checkout(permit)
+    }
+
+    /// Check out an evaluator, blocking the current thread until one becomes
+    /// available when all the evaluators of the pool are in use.
+    ///
+    /// When invoked from a worker of a multi-thread tokio runtime, the worker hands
+    /// over its other tasks while waiting. This must not be invoked from an async
+    /// context driven by a current-thread runtime, use [`get`](PolicyEvaluatorPool::get)
+    /// instead.
+    pub fn get_blocking(&self) -> Result<PooledPolicyEvaluator, PolicyEvaluatorPoolError> {
+        block_on(self.get())
+    }
+
+    /// Check out an evaluator without waiting. An error is returned when all
+    /// the evaluators of the pool are in use
+    pub fn try_get(&self) -> Result<PooledPolicyEvaluator, PolicyEvaluatorPoolError> {
+        let permit = self
+            .inner
+            .semaphore
+            .clone()
+            .try_acquire_owned()
+            .map_err(|e| match e {
+                TryAcquireError::NoPermits => PolicyEvaluatorPoolError::Exhausted,
+                TryAcquireError::Closed => PolicyEvaluatorPoolError::Closed,
+            })?;
+
+        self.checkout(permit)
+    }
+
+    /// Returns the statistics of the pool
+    pub fn stats(&self) -> PolicyEvaluatorPoolStats {
+        let state = self.inner.state();
+        PolicyEvaluatorPoolStats {
+            max_size: self.inner.max_size,
+            idle: state.idle.len(),
+            in_use: state.in_use,
+            created: state.created,
+            reused: state.reused,
+            discarded: state.discarded,
+        }
+    }
+
+    fn checkout(
+        &self,
+        permit: OwnedSemaphorePermit,
+    ) -> Result<PooledPolicyEvaluator, PolicyEvaluatorPoolError> {
+        let idle = {
+            let mut state = self.inner.state();
+            // account for the evaluator right away, this prevents `prewarm` from
+            // growing the pool beyond its maximum size while we rehydrate
+            state.in_use += 1;
+            let idle = state.idle.pop();
+            if idle.is_some() {
+                state.reused += 1;
+            }
+            idle
+        };
+
+        let evaluator = match idle {
+            Some(evaluator) => evaluator,
+            None => {
+                // rehydrate without holding the lock, this operation can take some time
+                let rehydrated = self.rehydrate();
+                let mut state = self.inner.state();
+                match rehydrated {
+                    Ok(evaluator) => {
+                        state.created += 1;
+                        evaluator
+                    }
+                    Err(e) => {
+                        state.in_use -= 1;
+                        return Err(e);
+                    }
+                }
+            }
+        };
+
+        Ok(PooledPolicyEvaluator {
+            evaluator: Some(evaluator),
+            pool: self.inner.clone(),
+            _permit: permit,
+        })
+    }
+
+    fn rehydrate(&self) -> Result<PolicyEvaluator, PolicyEvaluatorPoolError> {
+        self.inner
+            .evaluator_pre
+            .rehydrate(&self.inner.eval_ctx)
+            .map_err(PolicyEvaluatorPoolError::Rehydrate)
+    }
+}
+
+impl fmt::Debug for PolicyEvaluatorPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PolicyEvaluatorPool")
+            .field("policy_id", &self.inner.eval_ctx.policy_id)
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+/// A [`PolicyEvaluator`] checked out from a [`PolicyEvaluatorPool`].
+///
+/// The evaluator is returned to the pool when this guard is dropped, unless
+/// its last evaluation has been interrupted or it has been explicitly
+/// [discarded](PooledPolicyEvaluator::discard).
+pub struct PooledPolicyEvaluator {
+    // this is always set, it's taken only when the guard is dropped
+    evaluator: Option<PolicyEvaluator>,
+    pool: Arc<PoolInner>,
+    // released after the evaluator has been returned to the pool
+    _permit: OwnedSemaphorePermit,
+}
+
+impl PooledPolicyEvaluator {
+    /// Do not return the evaluator to the pool, the pool will rehydrate a
+    /// new evaluator when needed
+    pub fn discard(mut self) {
+        if let Some(evaluator) = self.evaluator.take() {
+            self.release(evaluator, true);
+        }
+    }
+
+    fn release(&self, evaluator: PolicyEvaluator, discard: bool) {
+        let mut state = self.pool.state();
+        state.in_use -= 1;
+
+        if discard {
+            state.discarded += 1;
+            debug!(
+                policy_id = self.pool.eval_ctx.policy_id,
+                "policy evaluator discarded"
+            );
+        } else {
+            // a cancellation requested while the evaluator was checked out must
+            // not leak to the next user of the evaluator
+            evaluator.cancellation_handle().reset();
+            state.idle.push(evaluator);
+        }
+    }
+}
+
+impl Deref for PooledPolicyEvaluator {
+    type Target = PolicyEvaluator;
+
+    fn deref(&self) -> &Self::Target {
+        self.evaluator
+            .as_ref()
+            .expect("the evaluator is set until the guard is dropped")
+    }
+}
+
+impl DerefMut for PooledPolicyEvaluator {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.evaluator
+            .as_mut()
+            .expect("the evaluator is set until the guard is dropped")
+    }
+}
+
+impl Drop for PooledPolicyEvaluator {
+    fn drop(&mut self) {
+        if let Some(evaluator) = self.evaluator.take() {
+            let discard = evaluator.was_interrupted();
+            self.release(evaluator, discard);
+        }
+    }
+}
+
+impl fmt::Debug for PooledPolicyEvaluator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PooledPolicyEvaluator")
+            .field("evaluator", &self.evaluator)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy_evaluator::{PolicySettings, ValidateRequest};
+    use crate::test_fixtures::wapc_endless_loop_builder;
+    use std::{thread, time::Duration};
+
+    fn eval_ctx() -> EvaluationContext {
+        EvaluationContext {
+            policy_id: "pool".to_string(),
+            callback_channel: None,
+            ctx_aware_resources_allow_list: Default::default(),
+            policy_mode: Default::default(),
+            clock: Default::default(),
+            protected_paths: Default::default(),
+            array_keys: Default::default(),
+        }
+    }
+
+    fn build_pool(max_size: usize) -> (PolicyEvaluatorPool, wasmtime::Engine) {
+        let mut engine_conf = wasmtime::Config::default();
+        engine_conf.epoch_interruption(true);
+        let engine = wasmtime::Engine::new(&engine_conf).expect("cannot create wasmtime engine");
+
+        let evaluator_pre = wapc_endless_loop_builder(engine.clone())
+            .enable_epoch_interruptions(10, 10)
+            .build_pre()
+            .expect("cannot build PolicyEvaluatorPre");
+
+        let pool = PolicyEvaluatorPool::new(evaluator_pre, &eval_ctx(), max_size);
+        (pool, engine)
+    }
+
+    #[test]
+    fn evaluators_are_reused() {
+        let (pool, _) = build_pool(2);
+
+        let evaluator = pool.try_get().expect("cannot get evaluator");
+        assert_eq!(1, pool.stats().in_use);
+        drop(evaluator);
+
+        let _evaluator = pool.try_get().expect("cannot get evaluator");
+        let stats = pool.stats();
+        assert_eq!(1, stats.created);
+        assert_eq!(1, stats.reused);
+        assert_eq!(0, stats.idle);
+        assert_eq!(1, stats.in_use);
+    }
+
+    #[test]
+    fn pool_is_bounded() {
+        let (pool, _) = build_pool(2);
+
+        let first = pool.try_get().expect("cannot get evaluator");
+        let _second = pool.try_get().expect("cannot get evaluator");
+        assert!(matches!(
+            pool.try_get(),
+            Err(PolicyEvaluatorPoolError::Exhausted)
+        ));
+
+        drop(first);
+        let _third = pool.try_get().expect("cannot get evaluator");
+        assert_eq!(2, pool.stats().created);
+    }
+
+    #[test]
+    fn prewarm() {
+        let (pool, _) = build_pool(2);
+
+        pool.prewarm(5).expect("cannot prewarm pool");
+
+        let stats = pool.stats();
+        assert_eq!(2, stats.idle);
+        assert_eq!(2, stats.created);
+    }
+
+    #[test]
+    fn discard() {
+        let (pool, _) = build_pool(2);
+
+        let evaluator = pool.try_get().expect("cannot get evaluator");
+        evaluator.discard();
+
+        let stats = pool.stats();
+        assert_eq!(0, stats.idle);
+        assert_eq!(0, stats.in_use);
+        assert_eq!(1, stats.discarded);
+    }
+
+    #[test]
+    fn cancellation_is_cleared_when_evaluators_are_returned() {
+        let (pool, _) = build_pool(1);
+
+        let evaluator = pool.try_get().expect("cannot get evaluator");
+        evaluator.cancellation_handle().cancel();
+        drop(evaluator);
+
+        let evaluator = pool.try_get().expect("cannot get evaluator");
+        assert!(!evaluator.cancellation_handle().is_cancelled());
+        assert_eq!(1, pool.stats().reused);
+    }
+
+    #[test]
+    fn interrupted_evaluators_are_discarded() {
+        let (pool, engine) = build_pool(1);
+        let mut evaluator = pool.try_get().expect("cannot get evaluator");
+
+        // Start a thread that ticks the epoch timer of the wasmtime engine
+        let ticker = thread::spawn(move || {
+            for _ in 0..100 {
+                thread::sleep(Duration::from_millis(10));
+                engine.increment_epoch();
+            }
+        });
+
+        // This triggers an endless loop inside of wasm, which is interrupted
+        // once the epoch deadline is reached
+        let response = evaluator.validate(
+            ValidateRequest::Raw(serde_json::json!({})),
+            &PolicySettings::default(),
+        );
+        assert!(!response.allowed);
+        assert!(evaluator.was_interrupted());
+
+        drop(evaluator);
+        ticker.join().unwrap();
+
+        let stats = pool.stats();
+        assert_eq!(0, stats.idle);
+        assert_eq!(1, stats.discarded);
+    }
+
+    #[tokio::test]
+    async fn get_waits_for_an_evaluator_to_be_returned() {
+        let (pool, _) = build_pool(1);
+        let evaluator = pool.get().await.expect("cannot get evaluator");
+
+        let waiting_pool = pool.clone();
+        let waiter = tokio::spawn(async move { waiting_pool.get().await.map(|_| ()) });
+
+        // give the waiter a chance to run
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+        assert!(!waiter.is_finished());
+
+        drop(evaluator);
+        waiter.await.unwrap().expect("cannot get evaluator");
+        assert_eq!(1, pool.stats().reused);
+    }
+}
@@ -1,8 +1,14 @@
 use std::result::Result;
+use std::sync::Arc;
+use std::time::Duration;
 
-use crate::errors::PolicyEvaluatorPreError;
-use crate::evaluation_context::EvaluationContext;
-use crate::policy_evaluator::{stack_pre::StackPre, PolicyEvaluator};
+use crate::errors::{PolicyEvaluatorPreError, PolicySelfTestError};
+use crate::evaluation_context::{EvaluationContext, PolicyMode};
+use crate::policy_evaluator::{
+    evaluation_report::HostCallbackRecorder, settings_schema::SettingsSchema, stack_pre::StackPre,
+    CancellationHandle, PolicyEvaluator, SelfTest, SelfTestFailure,
+};
+use crate::policy_metadata::Metadata;
 use crate::runtimes::{rego, wapc, wasi_cli, Runtime};
 
 /// This struct provides a way to quickly allocate a `PolicyEvaluator`
@@ -11,11 +17,101 @@ use crate::runtimes::{rego, wapc, wasi_cli, Runtime};
 /// See the [`rehydrate`](PolicyEvaluatorPre::rehydrate) method.
 pub struct PolicyEvaluatorPre {
     stack_pre: StackPre,
+    metadata: Option<Metadata>,
+    settings_schema: Option<Arc<SettingsSchema>>,
+    timeout: Option<Duration>,
+    digest: Option<String>,
+    exported_functions: Arc<[String]>,
+    self_tests: Vec<SelfTest>,
 }
 
 impl PolicyEvaluatorPre {
-    pub(crate) fn new(stack_pre: StackPre) -> Self {
-        PolicyEvaluatorPre { stack_pre }
+    pub(crate) fn new(
+        stack_pre: StackPre,
+        metadata: Option<Metadata>,
+        settings_schema: Option<SettingsSchema>,
+        timeout: Option<Duration>,
+        digest: Option<String>,
+        exported_functions: Vec<String>,
+        self_tests: Vec<SelfTest>,
+    ) -> Self {
+        PolicyEvaluatorPre {
+            stack_pre,
+            metadata,
+            settings_schema: settings_schema.map(Arc::new),
+            timeout,
+            digest,
+            exported_functions: exported_functions.into(),
+            self_tests,
+        }
+    }
+
+    /// The Kubewarden metadata embedded into the policy.
+    ///
+    /// This is `None` when the policy has not been annotated, or when the
+    /// `PolicyEvaluatorPre` has been created from a pre-built `wasmtime::Module`.
+    pub fn metadata(&self) -> Option<&Metadata> {
+        self.metadata.as_ref()
+    }
+
+    /// The sha256 digest of the policy module, hex encoded.
+    ///
+    /// This is `None` when the `PolicyEvaluatorPre` has been created from a
+    /// pre-built `wasmtime::Module`.
+    pub fn digest(&self) -> Option<&str> {
+        self.digest.as_deref()
+    }
+
+    /// The self tests embedded into the `io.kubewarden.self_tests` custom section
+    /// of the policy.
+    ///
+    /// This is empty when the policy doesn't provide self tests, or when the
+    /// `PolicyEvaluatorPre` has been created from a pre-built `wasmtime::Module`.
+    pub fn self_tests(&self) -> &[SelfTest] {
+        &self.self_tests
+    }
+
+    /// Run the self tests embedded into the policy, see [`self_tests`](PolicyEvaluatorPre::self_tests).
+    /// This should be done before the policy is put into service: an error is returned
+    /// when the verdict, or the patch, produced by the policy differs from the expected one.
+    ///
+    /// The self tests are always evaluated in `protect` mode, regardless of the
+    /// policy mode set inside of the `EvaluationContext`.
+    pub fn self_test(&self, eval_ctx: &EvaluationContext) -> Result<(), PolicySelfTestError> {
+        if self.self_tests.is_empty() {
+            return Ok(());
+        }
+
+        let eval_ctx = EvaluationContext {
+            policy_mode: PolicyMode::Protect,
+            ..eval_ctx.to_owned()
+        };
+        let mut policy_evaluator = self
+            .rehydrate(&eval_ctx)
+            .map_err(PolicySelfTestError::Rehydrate)?;
+
+        let mut failures = Vec::new();
+        for self_test in &self.self_tests {
+            let request = self_test.validate_request().map_err(|error| {
+                PolicySelfTestError::InvalidRequest {
+                    name: self_test.name.clone(),
+                    error,
+                }
+            })?;
+            let response = policy_evaluator.validate(request, &self_test.settings);
+            if let Err(reason) = self_test.check(&response) {
+                failures.push(SelfTestFailure {
+                    name: self_test.name.clone(),
+                    reason,
+                });
+            }
+        }
+
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(PolicySelfTestError::Failed(failures))
+        }
     }
 
     /// Create a `PolicyEvaluator` instance. The creation of the instance is achieved by
@@ -29,23 +125,45 @@ impl PolicyEvaluatorPre {
         &self,
         eval_ctx: &EvaluationContext,
     ) -> Result<PolicyEvaluator, PolicyEvaluatorPreError> {
+        let host_callback_recorder = Arc::new(HostCallbackRecorder::default());
+        let cancellation = CancellationHandle::new();
+
         let runtime = match &self.stack_pre {
             StackPre::Wapc(stack_pre) => {
-                let wapc_stack = wapc::WapcStack::new_from_pre(stack_pre, eval_ctx)
-                    .map_err(PolicyEvaluatorPreError::RehydrateWapc)?;
+                let wapc_stack = wapc::WapcStack::new_from_pre(
+                    stack_pre,
+                    eval_ctx,
+                    host_callback_recorder.clone(),
+                    cancellation.clone(),
+                )
+                .map_err(PolicyEvaluatorPreError::RehydrateWapc)?;
                 Runtime::Wapc(wapc_stack)
             }
             StackPre::Wasi(stack_pre) => {
-                let wasi_stack = wasi_cli::Stack::new_from_pre(stack_pre, eval_ctx);
+                let wasi_stack = wasi_cli::Stack::new_from_pre(
+                    stack_pre,
+                    eval_ctx,
+                    host_callback_recorder.clone(),
+                    cancellation.clone(),
+                );
                 Runtime::Cli(wasi_stack)
             }
             StackPre::Rego(stack_pre) => {
-                let rego_stack = rego::Stack::new_from_pre(stack_pre)
-                    .map_err(PolicyEvaluatorPreError::RehydrateRego)?;
+                let rego_stack =
+                    rego::Stack::new_from_pre(stack_pre, eval_ctx.clock, cancellation.clone())
+                        .map_err(PolicyEvaluatorPreError::RehydrateRego)?;
                 Runtime::Rego(rego_stack)
             }
         };
 
-        Ok(PolicyEvaluator::new(runtime, eval_ctx))
+        Ok(PolicyEvaluator::new(
+            runtime,
+            eval_ctx,
+            host_callback_recorder,
+            self.settings_schema.clone(),
+            self.timeout,
+            cancellation,
+            self.exported_functions.clone(),
+        ))
     }
 }
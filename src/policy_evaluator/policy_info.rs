@@ -0,0 +1,27 @@
+use kubewarden_policy_sdk::metadata::ProtocolVersion;
+use serde::Serialize;
+
+use crate::policy_evaluator::PolicyExecutionMode;
+
+/// Information about a policy, obtained by introspecting its Wasm module.
+///
+/// See [`PolicyEvaluator::policy_info`](crate::policy_evaluator::PolicyEvaluator::policy_info).
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PolicyInfo {
+    pub execution_mode: PolicyExecutionMode,
+    pub abi_version: PolicyAbiVersion,
+    /// The entrypoints of the policy. These are the OPA entrypoints for Rego policies,
+    /// and the functions exported by the Wasm module for the other policies
+    pub entrypoints: Vec<String>,
+}
+
+/// The version of the ABI implemented by a policy
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PolicyAbiVersion {
+    /// The Kubewarden protocol, implemented by waPC and WASI policies
+    Kubewarden(ProtocolVersion),
+    /// The OPA Wasm ABI, implemented by Rego policies
+    Opa { major: i32, minor: i32 },
+}
@@ -0,0 +1,340 @@
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use sha2::{Digest, Sha256};
+use tracing::{debug, warn};
+
+use crate::errors::PolicyEvaluatorBuilderError;
+
+/// Extension of the files holding the precompiled modules
+const PRECOMPILED_EXTENSION: &str = "cwasm";
+
+/// Entries that have not been used for this long are evicted when the cache is opened
+const ENTRY_MAX_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// An on-disk store of precompiled policy modules.
+///
+/// Each entry is saved as `<module sha256>-<engine fingerprint>.cwasm`. The fingerprint
+/// is derived from [`wasmtime::Engine::precompile_compatibility_hash`], hence it changes
+/// whenever the engine is configured in a different way or wasmtime is upgraded.
+///
+/// Stale entries are evicted when a module is compiled again: all the entries of the
+/// same module built with a different fingerprint are removed. Entries that cannot
+/// be loaded are removed too.
+///
+/// The entries of modules that are no longer used are evicted when the cache is
+/// opened, once they have not been used for [`ENTRY_MAX_AGE`].
+pub(crate) struct PrecompiledCache {
+    dir: PathBuf,
+}
+
+impl PrecompiledCache {
+    /// Open the cache stored inside of the given directory, the directory is
+    /// created when it doesn't exist. The entries that have not been used for
+    /// [`ENTRY_MAX_AGE`] are evicted
+    pub(crate) fn open(dir: &Path) -> Result<Self, PolicyEvaluatorBuilderError> {
+        fs::create_dir_all(dir).map_err(PolicyEvaluatorBuilderError::PrecompiledCache)?;
+
+        let cache = PrecompiledCache {
+            dir: dir.to_path_buf(),
+        };
+        cache.evict_unused_entries(SystemTime::now() - ENTRY_MAX_AGE);
+
+        Ok(cache)
+    }
+
+    /// Returns the module built from `wasm`, loading it from the cache when possible.
+    /// Otherwise the module is compiled and the result is stored inside of the cache.
+    ///
+    /// Failing to write to the cache is not fatal, the compiled module is returned anyway.
+    pub(crate) fn load_or_compile(
+        &self,
+        engine: &wasmtime::Engine,
+        wasm: &[u8],
+    ) -> Result<wasmtime::Module, PolicyEvaluatorBuilderError> {
+        let digest = module_digest(wasm);
+        let fingerprint = engine_fingerprint(engine);
+        let path = self.entry_path(&digest, &fingerprint);
+
+        if path.is_file() {
+            // SAFETY: the cache directory is owned by the embedder, its entries are
+            // written only by `store`. Moreover, wasmtime ensures the precompiled
+            // module is compatible with the engine before loading it.
+            match unsafe { wasmtime::Module::deserialize_file(engine, &path) } {
+                Ok(module) => {
+                    debug!(path = %path.display(), "precompiled module loaded from cache");
+                    // the modification time tracks when the entry was last used
+                    if let Err(e) = touch(&path) {
+                        warn!(path = %path.display(), error = %e, "cannot update the modification time of precompiled module");
+                    }
+                    return Ok(module);
+                }
+                Err(e) => {
+                    warn!(path = %path.display(), error = %e, "cannot load precompiled module, removing it from cache");
+                    let _ = fs::remove_file(&path);
+                }
+            }
+        }
+
+        let precompiled = engine
+            .precompile_module(wasm)
+            .map_err(PolicyEvaluatorBuilderError::WasmModuleBuild)?;
+
+        if let Err(e) = self.store(&path, &precompiled) {
+            warn!(path = %path.display(), error = %e, "cannot write precompiled module to cache");
+        }
+        self.evict_stale_entries(&digest, &fingerprint);
+
+        // SAFETY: the bytes have just been produced by the same engine
+        unsafe { wasmtime::Module::deserialize(engine, &precompiled) }
+            .map_err(PolicyEvaluatorBuilderError::WasmModuleBuild)
+    }
+
+    fn entry_path(&self, digest: &str, fingerprint: &str) -> PathBuf {
+        self.dir
+            .join(format!("{digest}-{fingerprint}.{PRECOMPILED_EXTENSION}"))
+    }
+
+    /// Write the entry to a temporary file first, then rename it. This ensures
+    /// concurrent readers never see a partially written entry
+    fn store(&self, path: &Path, precompiled: &[u8]) -> std::io::Result<()> {
+        let tmp_path = path.with_extension(format!(
+            "{PRECOMPILED_EXTENSION}.tmp-{}",
+            std::process::id()
+        ));
+
+        fs::write(&tmp_path, precompiled)
+            .and_then(|_| fs::rename(&tmp_path, path))
+            .inspect_err(|_| {
+                let _ = fs::remove_file(&tmp_path);
+            })
+    }
+
+    /// Remove the entries of the module that have been produced by an engine
+    /// with a different fingerprint
+    fn evict_stale_entries(&self, digest: &str, fingerprint: &str) {
+        let current = format!("{digest}-{fingerprint}.{PRECOMPILED_EXTENSION}");
+        let prefix = format!("{digest}-");
+        let suffix = format!(".{PRECOMPILED_EXTENSION}");
+
+        self.evict_entries(|name, _| {
+            name != current && name.starts_with(&prefix) && name.ends_with(&suffix)
+        });
+    }
+
+    /// Remove the entries, including the temporary files left behind by interrupted
+    /// writes, that have not been used since `threshold`
+    fn evict_unused_entries(&self, threshold: SystemTime) {
+        let extension = format!(".{PRECOMPILED_EXTENSION}");
+        let tmp_extension = format!(".{PRECOMPILED_EXTENSION}.tmp-");
+
+        self.evict_entries(|name, entry| {
+            let is_entry = name.ends_with(&extension) || name.contains(&tmp_extension);
+            let last_used = entry.metadata().and_then(|metadata| metadata.modified());
+            is_entry && last_used.is_ok_and(|last_used| last_used < threshold)
+        });
+    }
+
+    /// Remove the entries of the cache matching the predicate, which is given
+    /// the name of the file and its directory entry
+    fn evict_entries(&self, predicate: impl Fn(&str, &fs::DirEntry) -> bool) {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!(error = %e, "cannot list precompiled module cache");
+                return;
+            }
+        };
+
+        for entry in entries.flatten() {
+            let file_name = entry.file_name();
+            let Some(name) = file_name.to_str() else {
+                continue;
+            };
+            if predicate(name, &entry) {
+                debug!(entry = name, "evicting precompiled module");
+                if let Err(e) = fs::remove_file(entry.path()) {
+                    warn!(entry = name, error = %e, "cannot evict precompiled module");
+                }
+            }
+        }
+    }
+}
+
+/// Set the modification time of the file to the current time
+fn touch(path: &Path) -> std::io::Result<()> {
+    fs::File::options()
+        .append(true)
+        .open(path)?
+        .set_modified(SystemTime::now())
+}
+
+/// The sha256 digest of the module, hex encoded
+pub(crate) fn module_digest(wasm: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(wasm))
+}
+
+/// A fingerprint of the engine configuration, modules precompiled by engines
+/// with the same fingerprint are compatible with each other
+fn engine_fingerprint(engine: &wasmtime::Engine) -> String {
+    let mut hasher = Sha256Hasher(Sha256::new());
+    engine.precompile_compatibility_hash().hash(&mut hasher);
+    format!("{:x}", hasher.0.finalize())
+}
+
+/// Feeds the data being hashed into a sha256 digest. Unlike the one of `DefaultHasher`,
+/// the algorithm does not change across Rust releases, hence the fingerprints stay stable
+struct Sha256Hasher(Sha256);
+
+impl Hasher for Sha256Hasher {
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.update(bytes);
+    }
+
+    fn finish(&self) -> u64 {
+        let digest = self.0.clone().finalize();
+        let mut prefix = [0; 8];
+        prefix.copy_from_slice(&digest[..8]);
+        u64::from_be_bytes(prefix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WAT: &[u8] = include_bytes!("../../tests/data/endless_wasm/wapc_endless_loop.wat");
+
+    fn cache_entries(dir: &Path) -> Vec<String> {
+        let mut entries: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        entries.sort();
+        entries
+    }
+
+    fn epoch_engine() -> wasmtime::Engine {
+        let mut config = wasmtime::Config::default();
+        config.epoch_interruption(true);
+        wasmtime::Engine::new(&config).unwrap()
+    }
+
+    #[test]
+    fn compile_and_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = PrecompiledCache::open(&dir.path().join("cache")).unwrap();
+        let engine = wasmtime::Engine::default();
+
+        cache.load_or_compile(&engine, WAT).unwrap();
+
+        let expected = format!(
+            "{}-{}.{PRECOMPILED_EXTENSION}",
+            module_digest(WAT),
+            engine_fingerprint(&engine)
+        );
+        assert_eq!(vec![expected], cache_entries(&cache.dir));
+
+        // the module is now loaded from the cache
+        let module = cache.load_or_compile(&engine, WAT).unwrap();
+        assert!(module.get_export("__guest_call").is_some());
+    }
+
+    #[test]
+    fn fingerprint_depends_on_engine_config() {
+        assert_eq!(
+            engine_fingerprint(&wasmtime::Engine::default()),
+            engine_fingerprint(&wasmtime::Engine::default())
+        );
+        assert_ne!(
+            engine_fingerprint(&wasmtime::Engine::default()),
+            engine_fingerprint(&epoch_engine())
+        );
+    }
+
+    #[test]
+    fn evict_stale_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = PrecompiledCache::open(dir.path()).unwrap();
+
+        let unrelated = format!("unrelated-0000000000000000.{PRECOMPILED_EXTENSION}");
+        fs::write(dir.path().join(&unrelated), b"").unwrap();
+
+        cache
+            .load_or_compile(&wasmtime::Engine::default(), WAT)
+            .unwrap();
+        let engine = epoch_engine();
+        cache.load_or_compile(&engine, WAT).unwrap();
+
+        let mut expected = vec![
+            format!(
+                "{}-{}.{PRECOMPILED_EXTENSION}",
+                module_digest(WAT),
+                engine_fingerprint(&engine)
+            ),
+            unrelated,
+        ];
+        expected.sort();
+        assert_eq!(expected, cache_entries(dir.path()));
+    }
+
+    #[test]
+    fn evict_unused_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let old = SystemTime::now() - ENTRY_MAX_AGE - Duration::from_secs(60);
+
+        let unused = format!("unused-0000000000000000.{PRECOMPILED_EXTENSION}");
+        let leftover = format!("leftover-0000000000000000.{PRECOMPILED_EXTENSION}.tmp-42");
+        let unrelated = "unrelated.txt".to_string();
+        for name in [&unused, &leftover, &unrelated] {
+            let file = fs::File::create(dir.path().join(name)).unwrap();
+            file.set_modified(old).unwrap();
+        }
+        let recent = format!("recent-0000000000000000.{PRECOMPILED_EXTENSION}");
+        fs::write(dir.path().join(&recent), b"").unwrap();
+
+        PrecompiledCache::open(dir.path()).unwrap();
+
+        assert_eq!(vec![recent, unrelated], cache_entries(dir.path()));
+    }
+
+    #[test]
+    fn loading_an_entry_marks_it_as_used() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = PrecompiledCache::open(dir.path()).unwrap();
+        let engine = wasmtime::Engine::default();
+        cache.load_or_compile(&engine, WAT).unwrap();
+
+        let path = cache.entry_path(&module_digest(WAT), &engine_fingerprint(&engine));
+        let old = SystemTime::now() - ENTRY_MAX_AGE - Duration::from_secs(60);
+        fs::File::options()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+
+        cache.load_or_compile(&engine, WAT).unwrap();
+        PrecompiledCache::open(dir.path()).unwrap();
+
+        assert!(path.is_file());
+    }
+
+    #[test]
+    fn replace_corrupted_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = PrecompiledCache::open(dir.path()).unwrap();
+        let engine = wasmtime::Engine::default();
+
+        let path = cache.entry_path(&module_digest(WAT), &engine_fingerprint(&engine));
+        fs::write(&path, b"not a precompiled module").unwrap();
+
+        let module = cache.load_or_compile(&engine, WAT).unwrap();
+        assert!(module.get_export("__guest_call").is_some());
+
+        let entry = fs::read(&path).unwrap();
+        assert_ne!(b"not a precompiled module".as_slice(), entry.as_slice());
+    }
+}
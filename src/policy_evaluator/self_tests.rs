@@ -0,0 +1,252 @@
+use std::fmt;
+
+use base64::{engine::general_purpose, Engine as _};
+use serde::{Deserialize, Serialize};
+
+use crate::admission_response::AdmissionResponse;
+use crate::constants::KUBEWARDEN_CUSTOM_SECTION_SELF_TESTS;
+use crate::errors::MetadataError;
+use crate::policy_evaluator::{PolicySettings, ValidateRequest};
+use crate::policy_metadata::read_custom_section;
+
+/// A sample request embedded by the policy author into the
+/// `io.kubewarden.self_tests` custom section of the policy, together with the
+/// verdict the policy is expected to produce.
+///
+/// See [`PolicyEvaluatorPre::self_test`](crate::policy_evaluator::PolicyEvaluatorPre::self_test).
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SelfTest {
+    pub name: String,
+    #[serde(default)]
+    pub settings: PolicySettings,
+    /// The request to be validated, either a Kubernetes `AdmissionRequest`
+    /// or a raw JSON object
+    pub request: serde_json::Value,
+    /// When set, the request is given to the policy as a raw JSON object
+    #[serde(default)]
+    pub raw: bool,
+    pub expected: ExpectedVerdict,
+}
+
+/// The verdict a policy is expected to produce
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ExpectedVerdict {
+    pub allowed: bool,
+    /// The JSONPatch the policy is expected to produce, as a list of operations.
+    /// The policy must not produce a patch when this is not set
+    #[serde(default)]
+    pub patch: Option<serde_json::Value>,
+}
+
+/// A self test whose outcome differs from the expected one
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelfTestFailure {
+    pub name: String,
+    pub reason: String,
+}
+
+impl fmt::Display for SelfTestFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}`: {}", self.name, self.reason)
+    }
+}
+
+impl SelfTest {
+    /// Build the request to be given to the policy
+    pub(crate) fn validate_request(&self) -> Result<ValidateRequest, serde_json::Error> {
+        if self.raw {
+            return Ok(ValidateRequest::Raw(self.request.clone()));
+        }
+        serde_json::from_value(self.request.clone()).map(ValidateRequest::AdmissionRequest)
+    }
+
+    /// Compare the response of the policy with the expected verdict, returns the
+    /// reason of the failure when they differ
+    pub(crate) fn check(&self, response: &AdmissionResponse) -> Result<(), String> {
+        if response.allowed != self.expected.allowed {
+            let message = response
+                .status
+                .as_ref()
+                .and_then(|status| status.message.as_deref())
+                .unwrap_or("no message provided");
+            return Err(if self.expected.allowed {
+                format!("expected the request to be allowed, it has been rejected: {message}")
+            } else {
+                "expected the request to be rejected, it has been allowed".to_string()
+            });
+        }
+
+        let patch = response.patch.as_deref().map(decode_patch).transpose()?;
+        if patch != self.expected.patch {
+            return Err(format!(
+                "expected patch {}, got {}",
+                display_patch(self.expected.patch.as_ref()),
+                display_patch(patch.as_ref())
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Read the self tests embedded into the `io.kubewarden.self_tests` custom section
+/// of the policy. An empty list is returned when the section is not present.
+pub fn read_self_tests(policy: &[u8]) -> Result<Vec<SelfTest>, MetadataError> {
+    read_custom_section(policy, KUBEWARDEN_CUSTOM_SECTION_SELF_TESTS).map(Option::unwrap_or_default)
+}
+
+fn decode_patch(patch: &str) -> Result<serde_json::Value, String> {
+    let patch = general_purpose::STANDARD
+        .decode(patch)
+        .map_err(|e| format!("the patch is not base64 encoded: {e}"))?;
+    serde_json::from_slice(&patch).map_err(|e| format!("invalid JSONPatch: {e}"))
+}
+
+fn display_patch(patch: Option<&serde_json::Value>) -> String {
+    patch.map_or_else(|| "none".to_string(), serde_json::Value::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::PolicySelfTestError;
+    use crate::evaluation_context::EvaluationContext;
+    use crate::policy_evaluator::PolicyExecutionMode;
+    use crate::policy_evaluator_builder::PolicyEvaluatorBuilder;
+    use crate::test_fixtures::{with_custom_section, WASI_START_ONLY};
+    use serde_json::json;
+
+    fn self_test(expected: ExpectedVerdict) -> SelfTest {
+        SelfTest {
+            name: "sample".to_string(),
+            settings: PolicySettings::default(),
+            request: json!({}),
+            raw: true,
+            expected,
+        }
+    }
+
+    fn response(allowed: bool, patch: Option<serde_json::Value>) -> AdmissionResponse {
+        AdmissionResponse {
+            allowed,
+            patch: patch.map(|patch| general_purpose::STANDARD.encode(patch.to_string())),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn verdict_matches() {
+        let patch = json!([{"op": "add", "path": "/foo", "value": "bar"}]);
+        let test = self_test(ExpectedVerdict {
+            allowed: true,
+            patch: Some(patch.clone()),
+        });
+
+        assert!(test.check(&response(true, Some(patch))).is_ok());
+    }
+
+    #[test]
+    fn verdict_differs() {
+        let test = self_test(ExpectedVerdict {
+            allowed: false,
+            patch: None,
+        });
+
+        assert_eq!(
+            Err("expected the request to be rejected, it has been allowed".to_string()),
+            test.check(&response(true, None))
+        );
+    }
+
+    #[test]
+    fn patch_differs() {
+        let test = self_test(ExpectedVerdict {
+            allowed: true,
+            patch: None,
+        });
+
+        assert_eq!(
+            Err(r#"expected patch none, got [{"op":"remove","path":"/foo"}]"#.to_string()),
+            test.check(&response(
+                true,
+                Some(json!([{"op": "remove", "path": "/foo"}]))
+            ))
+        );
+    }
+
+    #[test]
+    fn read_self_tests_from_custom_section() {
+        let module = with_self_tests(
+            WASM_MODULE_HEADER,
+            json!([{
+                "name": "reject everything",
+                "request": {"hello": "world"},
+                "raw": true,
+                "expected": {"allowed": false}
+            }]),
+        );
+
+        let self_tests = read_self_tests(&module).unwrap();
+
+        assert_eq!(1, self_tests.len());
+        assert_eq!("reject everything", self_tests[0].name);
+        assert!(!self_tests[0].expected.allowed);
+        assert!(read_self_tests(WASM_MODULE_HEADER).unwrap().is_empty());
+    }
+
+    #[test]
+    fn run_self_tests() {
+        // the WASI program doesn't write anything to stdout, hence all
+        // the requests are rejected
+        let policy = with_self_tests(
+            WASI_START_ONLY,
+            json!([
+                {
+                    "name": "rejected",
+                    "request": {"hello": "world"},
+                    "raw": true,
+                    "expected": {"allowed": false}
+                },
+                {
+                    "name": "allowed",
+                    "request": {"hello": "world"},
+                    "raw": true,
+                    "expected": {"allowed": true}
+                }
+            ]),
+        );
+
+        let policy_evaluator_pre = PolicyEvaluatorBuilder::new()
+            .execution_mode(PolicyExecutionMode::Wasi)
+            .policy_contents(&policy)
+            .build_pre()
+            .expect("cannot build PolicyEvaluatorPre");
+        assert_eq!(2, policy_evaluator_pre.self_tests().len());
+
+        let err = policy_evaluator_pre
+            .self_test(&EvaluationContext {
+                policy_id: "self-tests".to_string(),
+                ..Default::default()
+            })
+            .unwrap_err();
+
+        let PolicySelfTestError::Failed(failures) = err else {
+            panic!("unexpected error: {err}");
+        };
+        assert_eq!(1, failures.len());
+        assert_eq!("allowed", failures[0].name);
+    }
+
+    const WASM_MODULE_HEADER: &[u8] = &[0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+
+    /// Append the `io.kubewarden.self_tests` custom section to the module
+    fn with_self_tests(module: &[u8], self_tests: serde_json::Value) -> Vec<u8> {
+        with_custom_section(
+            module,
+            KUBEWARDEN_CUSTOM_SECTION_SELF_TESTS,
+            self_tests.to_string().as_bytes(),
+        )
+    }
+}
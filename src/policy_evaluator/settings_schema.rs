@@ -0,0 +1,114 @@
+use kubewarden_policy_sdk::settings::SettingsValidationResponse;
+
+use crate::policy_evaluator::PolicySettings;
+
+/// Validates the settings of a policy against the JSON Schema shipped inside of
+/// its metadata, see [`Metadata::settings_schema`](crate::policy_metadata::Metadata::settings_schema)
+pub(crate) struct SettingsSchema {
+    validator: jsonschema::Validator,
+}
+
+impl SettingsSchema {
+    pub(crate) fn new(schema: &serde_json::Value) -> Result<Self, String> {
+        let validator = jsonschema::validator_for(schema).map_err(|e| e.to_string())?;
+        Ok(SettingsSchema { validator })
+    }
+
+    /// Validate the settings. All the violations are reported inside of the message
+    /// of the response, each one of them prefixed by the JSON pointer of the
+    /// offending value
+    pub(crate) fn validate(&self, settings: &PolicySettings) -> SettingsValidationResponse {
+        let settings = serde_json::Value::Object(settings.to_owned());
+
+        let violations: Vec<String> = self
+            .validator
+            .iter_errors(&settings)
+            .map(|error| {
+                let pointer = error.instance_path.to_string();
+                let pointer = if pointer.is_empty() {
+                    "/".to_string()
+                } else {
+                    pointer
+                };
+                format!("{pointer}: {error}")
+            })
+            .collect();
+
+        if violations.is_empty() {
+            SettingsValidationResponse {
+                valid: true,
+                message: None,
+            }
+        } else {
+            SettingsValidationResponse {
+                valid: false,
+                message: Some(format!(
+                    "settings do not match the schema: {}",
+                    violations.join("; ")
+                )),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn schema() -> SettingsSchema {
+        SettingsSchema::new(&json!({
+            "type": "object",
+            "properties": {
+                "replicas": { "type": "integer" },
+                "registries": { "type": "array", "items": { "type": "string" } }
+            },
+            "required": ["registries"]
+        }))
+        .expect("cannot build schema")
+    }
+
+    fn settings(value: serde_json::Value) -> PolicySettings {
+        value.as_object().unwrap().to_owned()
+    }
+
+    #[test]
+    fn valid_settings() {
+        let response = schema().validate(&settings(json!({
+            "replicas": 2,
+            "registries": ["ghcr.io"]
+        })));
+
+        assert!(response.valid);
+        assert!(response.message.is_none());
+    }
+
+    #[test]
+    fn all_violations_are_reported() {
+        let response = schema().validate(&settings(json!({
+            "replicas": "two",
+            "registries": ["ghcr.io", 1]
+        })));
+
+        assert!(!response.valid);
+        let message = response.message.unwrap();
+        assert!(message.contains("/replicas: "), "{message}");
+        assert!(message.contains("/registries/1: "), "{message}");
+    }
+
+    #[test]
+    fn violations_of_the_root_object() {
+        let response = schema().validate(&settings(json!({})));
+
+        assert!(!response.valid);
+        assert_eq!(
+            "settings do not match the schema: /: \"registries\" is a required property",
+            response.message.unwrap()
+        );
+    }
+
+    #[test]
+    fn invalid_schema() {
+        assert!(SettingsSchema::new(&json!({ "type": 12 })).is_err());
+    }
+}
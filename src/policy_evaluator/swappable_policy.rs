@@ -0,0 +1,214 @@
+use std::fmt;
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+use tracing::info;
+
+use crate::errors::SwappablePolicyError;
+use crate::evaluation_context::EvaluationContext;
+use crate::policy_evaluator::{PolicyEvaluator, PolicyEvaluatorPre, PolicySettings};
+
+/// A version of the policy installed into a [`SwappablePolicy`]
+pub struct PolicyVersion {
+    evaluator_pre: PolicyEvaluatorPre,
+    version: String,
+}
+
+impl PolicyVersion {
+    /// The `PolicyEvaluatorPre` of this version of the policy
+    pub fn evaluator_pre(&self) -> &PolicyEvaluatorPre {
+        &self.evaluator_pre
+    }
+
+    /// The version given when the policy has been installed, like the tag of
+    /// the OCI artifact it has been pulled from
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    /// The sha256 digest of the policy module, see [`PolicyEvaluatorPre::digest`]
+    pub fn digest(&self) -> Option<&str> {
+        self.evaluator_pre.digest()
+    }
+}
+
+impl fmt::Debug for PolicyVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PolicyVersion")
+            .field("version", &self.version)
+            .field("digest", &self.digest())
+            .finish()
+    }
+}
+
+/// Holds the active version of a policy, which can be replaced at runtime without
+/// disrupting the evaluations in flight.
+///
+/// The active [`PolicyEvaluatorPre`] is kept behind an atomic pointer: new evaluators
+/// are always rehydrated from the version that is active at the time of the call.
+/// Evaluators rehydrated from a previous version keep working until they are dropped,
+/// the previous version is released once all of them are gone.
+///
+/// A new version is installed only after it passes its self tests and accepts the
+/// settings of the policy, see [`install`](SwappablePolicy::install).
+pub struct SwappablePolicy {
+    active: ArcSwap<PolicyVersion>,
+    settings: PolicySettings,
+}
+
+impl SwappablePolicy {
+    /// Create a new `SwappablePolicy`, the given `evaluator_pre` becomes the active
+    /// version of the policy. The `settings` are used to validate the versions
+    /// installed later on.
+    pub fn new(evaluator_pre: PolicyEvaluatorPre, version: &str, settings: PolicySettings) -> Self {
+        SwappablePolicy {
+            active: ArcSwap::from_pointee(PolicyVersion {
+                evaluator_pre,
+                version: version.to_string(),
+            }),
+            settings,
+        }
+    }
+
+    /// The version of the policy currently active
+    pub fn active(&self) -> Arc<PolicyVersion> {
+        self.active.load_full()
+    }
+
+    /// The settings of the policy
+    pub fn settings(&self) -> &PolicySettings {
+        &self.settings
+    }
+
+    /// Rehydrate a `PolicyEvaluator` from the version of the policy currently active
+    pub fn rehydrate(
+        &self,
+        eval_ctx: &EvaluationContext,
+    ) -> Result<PolicyEvaluator, SwappablePolicyError> {
+        self.active
+            .load()
+            .evaluator_pre
+            .rehydrate(eval_ctx)
+            .map_err(SwappablePolicyError::Rehydrate)
+    }
+
+    /// Replace the active version of the policy. The new version must pass its
+    /// [self tests](PolicyEvaluatorPre::self_test) and accept the settings of the policy,
+    /// otherwise an error is returned and the active version is left untouched.
+    ///
+    /// Returns the version that has been replaced.
+    pub fn install(
+        &self,
+        evaluator_pre: PolicyEvaluatorPre,
+        version: &str,
+        eval_ctx: &EvaluationContext,
+    ) -> Result<Arc<PolicyVersion>, SwappablePolicyError> {
+        evaluator_pre
+            .self_test(eval_ctx)
+            .map_err(|error| SwappablePolicyError::SelfTest {
+                version: version.to_string(),
+                error,
+            })?;
+
+        let mut evaluator = evaluator_pre
+            .rehydrate(eval_ctx)
+            .map_err(SwappablePolicyError::Rehydrate)?;
+        let settings_validation = evaluator.validate_settings(&self.settings);
+        if !settings_validation.valid {
+            return Err(SwappablePolicyError::InvalidSettings {
+                version: version.to_string(),
+                message: settings_validation.message.unwrap_or_default(),
+            });
+        }
+
+        let new_version = Arc::new(PolicyVersion {
+            evaluator_pre,
+            version: version.to_string(),
+        });
+        info!(
+            policy_id = eval_ctx.policy_id,
+            version,
+            digest = new_version.digest(),
+            "new policy version installed"
+        );
+
+        Ok(self.active.swap(new_version))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy_evaluator::policy_evaluator_builder::PolicyEvaluatorBuilder;
+    use crate::policy_evaluator::PolicyExecutionMode;
+    use crate::test_fixtures::wasi_printing;
+
+    fn build_evaluator_pre(settings_validation_response: &str) -> PolicyEvaluatorPre {
+        PolicyEvaluatorBuilder::new()
+            .execution_mode(PolicyExecutionMode::Wasi)
+            .policy_contents(wasi_printing(settings_validation_response).as_bytes())
+            .build_pre()
+            .expect("cannot build PolicyEvaluatorPre")
+    }
+
+    fn eval_ctx() -> EvaluationContext {
+        EvaluationContext {
+            policy_id: "swappable".to_string(),
+            callback_channel: None,
+            ctx_aware_resources_allow_list: Default::default(),
+            policy_mode: Default::default(),
+            clock: Default::default(),
+            protected_paths: Default::default(),
+            array_keys: Default::default(),
+        }
+    }
+
+    #[test]
+    fn install_new_version() {
+        let policy = SwappablePolicy::new(
+            build_evaluator_pre(r#"{"valid":true}"#),
+            "v1",
+            PolicySettings::default(),
+        );
+        let mut old_evaluator = policy.rehydrate(&eval_ctx()).unwrap();
+
+        let new_pre = build_evaluator_pre(r#"{"valid":true }"#);
+        let new_digest = new_pre.digest().map(str::to_string);
+        let previous = policy.install(new_pre, "v2", &eval_ctx()).unwrap();
+
+        assert_eq!("v1", previous.version());
+        let active = policy.active();
+        assert_eq!("v2", active.version());
+        assert_eq!(new_digest.as_deref(), active.digest());
+        assert_ne!(previous.digest(), active.digest());
+
+        // evaluators of the previous version keep working
+        assert!(
+            old_evaluator
+                .validate_settings(&PolicySettings::default())
+                .valid
+        );
+    }
+
+    #[test]
+    fn reject_version_not_accepting_settings() {
+        let policy = SwappablePolicy::new(
+            build_evaluator_pre(r#"{"valid":true}"#),
+            "v1",
+            PolicySettings::default(),
+        );
+
+        let result = policy.install(
+            build_evaluator_pre(r#"{"valid":false,"message":"boom"}"#),
+            "v2",
+            &eval_ctx(),
+        );
+
+        assert!(matches!(
+            result,
+            Err(SwappablePolicyError::InvalidSettings { version, message })
+                if version == "v2" && message == "boom"
+        ));
+        assert_eq!("v1", policy.active().version());
+    }
+}
@@ -0,0 +1,16 @@
+//! Evaluate a group of policies, combining their verdicts with a boolean expression.
+//!
+//! A policy group is made of several validating policies, called members. Each member
+//! is identified by a unique name, which is then referenced inside of the expression
+//! that computes the final verdict. For example, given the `signed` and `trusted_registry`
+//! members, the `signed || trusted_registry` expression accepts all the requests that
+//! satisfy at least one of the two policies.
+//!
+//! See [`PolicyGroupEvaluatorBuilder`] for more details.
+
+mod evaluator;
+mod expression;
+mod policy_group_evaluator_builder;
+
+pub use evaluator::PolicyGroupEvaluator;
+pub use policy_group_evaluator_builder::PolicyGroupEvaluatorBuilder;
@@ -0,0 +1,285 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use tracing::{debug, warn};
+
+use crate::admission_response::{
+    AdmissionResponse, AdmissionResponseStatus, StatusCause, StatusDetails,
+};
+use crate::evaluation_context::{EvaluationContext, PolicyMode};
+use crate::policy_evaluator::{PolicyEvaluatorPre, PolicySettings, ValidateRequest};
+use crate::policy_group_evaluator::expression::Expression;
+
+/// Message used to reject a request when the policy group has not been given a custom one
+pub(crate) const DEFAULT_REJECTION_MESSAGE: &str = "the request was rejected by the policy group";
+
+/// A validating policy that is part of a policy group
+pub(crate) struct PolicyGroupMember {
+    pub(crate) evaluator_pre: PolicyEvaluatorPre,
+    pub(crate) settings: PolicySettings,
+}
+
+/// The verdict produced by a member of the group
+struct MemberEvaluation {
+    name: String,
+    response: AdmissionResponse,
+}
+
+/// Evaluates a request against a group of validating policies, see the
+/// [module documentation](crate::policy_group_evaluator) for more details.
+///
+/// Instances of this struct are created via the
+/// [`PolicyGroupEvaluatorBuilder`](crate::policy_group_evaluator::PolicyGroupEvaluatorBuilder).
+pub struct PolicyGroupEvaluator {
+    pub(crate) expression: Expression,
+    pub(crate) message: String,
+    pub(crate) members: BTreeMap<String, PolicyGroupMember>,
+}
+
+impl PolicyGroupEvaluator {
+    /// Validate the request against the policy group.
+    ///
+    /// The members of the group are evaluated lazily: a member is rehydrated and
+    /// evaluated only when its verdict is needed by the expression. As a result, the
+    /// evaluation short-circuits as soon as the final verdict is known.
+    ///
+    /// The warnings and the audit annotations produced by the evaluated members are
+    /// merged into the final response. When the group rejects the request, the
+    /// message of each member that rejected the request is reported inside of the
+    /// `status.details.causes` field of the response.
+    ///
+    /// The [`PolicyMode`] of the `EvaluationContext` applies to the verdict of the
+    /// group, the members are always evaluated in protect mode.
+    #[tracing::instrument(skip(self, request))]
+    pub fn validate(
+        &self,
+        request: ValidateRequest,
+        eval_ctx: &EvaluationContext,
+    ) -> AdmissionResponse {
+        let mut evaluations: Vec<MemberEvaluation> = Vec::new();
+
+        let allowed = self.expression.evaluate(&mut |name: &str| {
+            // a member can be referenced more than once inside of the expression,
+            // there's no need to evaluate it again
+            if let Some(evaluation) = evaluations.iter().find(|e| e.name == name) {
+                return evaluation.response.allowed;
+            }
+
+            let response = self.evaluate_member(name, &request, eval_ctx);
+            let allowed = response.allowed;
+            evaluations.push(MemberEvaluation {
+                name: name.to_string(),
+                response,
+            });
+            allowed
+        });
+
+        let response = self.build_response(request.uid().to_string(), allowed, evaluations);
+        match eval_ctx.policy_mode {
+            PolicyMode::Protect => response,
+            PolicyMode::Monitor => response.into_monitor_response(),
+        }
+    }
+
+    fn evaluate_member(
+        &self,
+        name: &str,
+        request: &ValidateRequest,
+        eval_ctx: &EvaluationContext,
+    ) -> AdmissionResponse {
+        let uid = request.uid().to_string();
+        // the builder ensures all the members referenced by the expression exist
+        let member = &self.members[name];
+
+        let member_eval_ctx = EvaluationContext {
+            policy_id: format!("{}/{}", eval_ctx.policy_id, name),
+            // the verdict of each member is needed to compute the one of the group
+            policy_mode: PolicyMode::Protect,
+            ..eval_ctx.clone()
+        };
+
+        let mut evaluator = match member.evaluator_pre.rehydrate(&member_eval_ctx) {
+            Ok(evaluator) => evaluator,
+            Err(e) => {
+                warn!(member = name, error = %e, "cannot rehydrate policy group member");
+                return AdmissionResponse::reject_internal_server_error(uid, e.to_string());
+            }
+        };
+
+        let response = evaluator.validate(request.clone(), &member.settings);
+        debug!(
+            member = name,
+            allowed = response.allowed,
+            "policy group member evaluated"
+        );
+
+        if response.patch.is_some() {
+            // this can happen when the member doesn't have metadata, hence it
+            // was not possible to reject it at build time
+            return AdmissionResponse::reject_internal_server_error(
+                uid,
+                format!("policy group member `{name}` attempted to mutate the request"),
+            );
+        }
+
+        response
+    }
+
+    fn build_response(
+        &self,
+        uid: String,
+        allowed: bool,
+        evaluations: Vec<MemberEvaluation>,
+    ) -> AdmissionResponse {
+        let mut warnings: Vec<String> = Vec::new();
+        let mut audit_annotations: HashMap<String, String> = HashMap::new();
+        let mut causes: Vec<StatusCause> = Vec::new();
+
+        for evaluation in evaluations {
+            let response = evaluation.response;
+            warnings.extend(response.warnings.unwrap_or_default());
+            for (key, value) in response.audit_annotations.unwrap_or_default() {
+                audit_annotations.entry(key).or_insert(value);
+            }
+
+            if !response.allowed {
+                causes.push(StatusCause {
+                    message: response.status.and_then(|status| status.message),
+                    field: Some(format!("spec.policies.{}", evaluation.name)),
+                    ..Default::default()
+                });
+            }
+        }
+
+        let status = if allowed {
+            None
+        } else {
+            Some(AdmissionResponseStatus {
+                message: Some(self.message.clone()),
+                details: Some(StatusDetails {
+                    causes,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            })
+        };
+
+        AdmissionResponse {
+            uid,
+            allowed,
+            status,
+            warnings: (!warnings.is_empty()).then_some(warnings),
+            audit_annotations: (!audit_annotations.is_empty()).then_some(audit_annotations),
+            ..Default::default()
+        }
+    }
+}
+
+impl fmt::Debug for PolicyGroupEvaluator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PolicyGroupEvaluator")
+            .field("expression", &self.expression)
+            .field("members", &self.members.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rejection(message: &str) -> AdmissionResponse {
+        AdmissionResponse {
+            warnings: Some(vec![format!("{message} warning")]),
+            ..AdmissionResponse::reject("uid".to_string(), message.to_string(), 400)
+        }
+    }
+
+    fn policy_group(message: &str) -> PolicyGroupEvaluator {
+        PolicyGroupEvaluator {
+            expression: Expression::parse("a || b").unwrap(),
+            message: message.to_string(),
+            members: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn build_rejection_response() {
+        let group = policy_group("not allowed");
+        let evaluations = vec![
+            MemberEvaluation {
+                name: "a".to_string(),
+                response: rejection("a says no"),
+            },
+            MemberEvaluation {
+                name: "b".to_string(),
+                response: rejection("b says no"),
+            },
+        ];
+
+        let response = group.build_response("uid".to_string(), false, evaluations);
+
+        assert!(!response.allowed);
+        assert_eq!(
+            Some(vec![
+                "a says no warning".to_string(),
+                "b says no warning".to_string()
+            ]),
+            response.warnings
+        );
+        let status = response.status.expect("status should be set");
+        assert_eq!(Some("not allowed".to_string()), status.message);
+        let causes = status.details.expect("details should be set").causes;
+        assert_eq!(
+            vec![
+                StatusCause {
+                    message: Some("a says no".to_string()),
+                    field: Some("spec.policies.a".to_string()),
+                    ..Default::default()
+                },
+                StatusCause {
+                    message: Some("b says no".to_string()),
+                    field: Some("spec.policies.b".to_string()),
+                    ..Default::default()
+                },
+            ],
+            causes
+        );
+    }
+
+    #[test]
+    fn build_accept_response() {
+        let group = policy_group("not allowed");
+        let evaluations = vec![
+            MemberEvaluation {
+                name: "a".to_string(),
+                response: rejection("a says no"),
+            },
+            MemberEvaluation {
+                name: "b".to_string(),
+                response: AdmissionResponse {
+                    uid: "uid".to_string(),
+                    allowed: true,
+                    audit_annotations: Some(HashMap::from([(
+                        "key".to_string(),
+                        "value".to_string(),
+                    )])),
+                    ..Default::default()
+                },
+            },
+        ];
+
+        let response = group.build_response("uid".to_string(), true, evaluations);
+
+        assert!(response.allowed);
+        assert!(response.status.is_none());
+        assert_eq!(
+            Some(vec!["a says no warning".to_string()]),
+            response.warnings
+        );
+        assert_eq!(
+            Some(HashMap::from([("key".to_string(), "value".to_string())])),
+            response.audit_annotations
+        );
+    }
+}
@@ -0,0 +1,408 @@
+use std::collections::BTreeSet;
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+use crate::errors::PolicyGroupExpressionError;
+
+/// The maximum nesting depth of an expression. Expressions are parsed and evaluated
+/// recursively, deeper expressions could overflow the stack.
+const MAX_NESTING_DEPTH: usize = 100;
+
+/// A boolean expression combining the verdicts of the members of a policy group.
+///
+/// The grammar is the following one, `not` has the highest precedence, followed by `and`
+/// and then by `or`:
+///
+/// ```text
+/// expression := term ( ( "or" | "||" ) term )*
+/// term       := factor ( ( "and" | "&&" ) factor )*
+/// factor     := ( "not" | "!" ) factor | "(" expression ")" | member
+/// member     := [a-zA-Z_][a-zA-Z0-9_-]*
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Expression {
+    Member(String),
+    Not(Box<Expression>),
+    And(Box<Expression>, Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+}
+
+impl Expression {
+    pub(crate) fn parse(expression: &str) -> Result<Expression, PolicyGroupExpressionError> {
+        let tokens = tokenize(expression)?;
+        if tokens.is_empty() {
+            return Err(PolicyGroupExpressionError::Empty);
+        }
+
+        let mut parser = ExpressionParser {
+            tokens: &tokens,
+            position: 0,
+            depth: 0,
+        };
+        let (parsed, _) = parser.parse_or()?;
+        match parser.peek() {
+            None => Ok(parsed),
+            Some(token) => Err(PolicyGroupExpressionError::UnexpectedToken {
+                token: token.kind.to_string(),
+                offset: token.offset,
+            }),
+        }
+    }
+
+    /// Returns the names of all the members referenced by the expression
+    pub(crate) fn members(&self) -> BTreeSet<&str> {
+        let mut members = BTreeSet::new();
+        self.collect_members(&mut members);
+        members
+    }
+
+    fn collect_members<'a>(&'a self, members: &mut BTreeSet<&'a str>) {
+        match self {
+            Expression::Member(name) => {
+                members.insert(name.as_str());
+            }
+            Expression::Not(expr) => expr.collect_members(members),
+            Expression::And(lhs, rhs) | Expression::Or(lhs, rhs) => {
+                lhs.collect_members(members);
+                rhs.collect_members(members);
+            }
+        }
+    }
+
+    /// Evaluate the expression. The verdict of each member is obtained by invoking
+    /// `verdict`, which is called lazily: the evaluation short-circuits as soon as the
+    /// outcome of an `and`/`or` operation is known.
+    pub(crate) fn evaluate<F>(&self, verdict: &mut F) -> bool
+    where
+        F: FnMut(&str) -> bool,
+    {
+        match self {
+            Expression::Member(name) => verdict(name),
+            Expression::Not(expr) => !expr.evaluate(verdict),
+            Expression::And(lhs, rhs) => lhs.evaluate(verdict) && rhs.evaluate(verdict),
+            Expression::Or(lhs, rhs) => lhs.evaluate(verdict) || rhs.evaluate(verdict),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TokenKind {
+    Identifier(String),
+    And,
+    Or,
+    Not,
+    OpenParen,
+    CloseParen,
+}
+
+impl std::fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TokenKind::Identifier(name) => write!(f, "{name}"),
+            TokenKind::And => write!(f, "and"),
+            TokenKind::Or => write!(f, "or"),
+            TokenKind::Not => write!(f, "not"),
+            TokenKind::OpenParen => write!(f, "("),
+            TokenKind::CloseParen => write!(f, ")"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    offset: usize,
+}
+
+fn is_identifier_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '-'
+}
+
+/// Returns true when the given name can be used to reference a group member
+/// inside of an expression
+pub(crate) fn is_valid_member_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if is_identifier_start(c) => {}
+        _ => return false,
+    }
+    chars.all(is_identifier_char) && !matches!(name, "and" | "or" | "not")
+}
+
+fn expect_char(
+    chars: &mut Peekable<CharIndices>,
+    expected: char,
+    offset: usize,
+) -> Result<(), PolicyGroupExpressionError> {
+    match chars.next() {
+        Some((_, c)) if c == expected => Ok(()),
+        _ => Err(PolicyGroupExpressionError::InvalidCharacter {
+            character: expected,
+            offset,
+        }),
+    }
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, PolicyGroupExpressionError> {
+    let mut tokens = Vec::new();
+    let mut chars = expression.char_indices().peekable();
+
+    while let Some((offset, c)) = chars.next() {
+        let kind = match c {
+            c if c.is_whitespace() => continue,
+            '(' => TokenKind::OpenParen,
+            ')' => TokenKind::CloseParen,
+            '!' => TokenKind::Not,
+            '&' => {
+                expect_char(&mut chars, '&', offset)?;
+                TokenKind::And
+            }
+            '|' => {
+                expect_char(&mut chars, '|', offset)?;
+                TokenKind::Or
+            }
+            c if is_identifier_start(c) => {
+                let mut end = offset + c.len_utf8();
+                while let Some((pos, next)) = chars.peek() {
+                    if !is_identifier_char(*next) {
+                        break;
+                    }
+                    end = pos + next.len_utf8();
+                    chars.next();
+                }
+                match &expression[offset..end] {
+                    "and" => TokenKind::And,
+                    "or" => TokenKind::Or,
+                    "not" => TokenKind::Not,
+                    name => TokenKind::Identifier(name.to_string()),
+                }
+            }
+            character => {
+                return Err(PolicyGroupExpressionError::InvalidCharacter { character, offset })
+            }
+        };
+        tokens.push(Token { kind, offset });
+    }
+
+    Ok(tokens)
+}
+
+struct ExpressionParser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+    /// The number of `not` operators and parentheses being parsed
+    depth: usize,
+}
+
+/// An expression, together with the depth of its tree
+type Parsed = (Expression, usize);
+
+/// Returns the depth of a node having a child of the given depth, ensuring it
+/// doesn't exceed the limit
+fn nested_depth(depth: usize, offset: usize) -> Result<usize, PolicyGroupExpressionError> {
+    let depth = depth + 1;
+    if depth > MAX_NESTING_DEPTH {
+        return Err(PolicyGroupExpressionError::NestingTooDeep {
+            limit: MAX_NESTING_DEPTH,
+            offset,
+        });
+    }
+    Ok(depth)
+}
+
+impl ExpressionParser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Result<&Token, PolicyGroupExpressionError> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .ok_or(PolicyGroupExpressionError::UnexpectedEnd)?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    /// Returns the offset of the next token, when it's of the given kind
+    fn next_offset(&self, kind: &TokenKind) -> Option<usize> {
+        self.peek()
+            .filter(|token| &token.kind == kind)
+            .map(|token| token.offset)
+    }
+
+    /// Guard the recursion done when parsing a `not` operator or a parenthesized
+    /// expression. Must be paired with a call to `leave`
+    fn enter(&mut self, offset: usize) -> Result<(), PolicyGroupExpressionError> {
+        self.depth = nested_depth(self.depth, offset)?;
+        Ok(())
+    }
+
+    fn leave(&mut self) {
+        self.depth -= 1;
+    }
+
+    fn parse_or(&mut self) -> Result<Parsed, PolicyGroupExpressionError> {
+        let (mut expr, mut depth) = self.parse_and()?;
+        while let Some(offset) = self.next_offset(&TokenKind::Or) {
+            self.position += 1;
+            let (rhs, rhs_depth) = self.parse_and()?;
+            depth = nested_depth(depth.max(rhs_depth), offset)?;
+            expr = Expression::Or(Box::new(expr), Box::new(rhs));
+        }
+        Ok((expr, depth))
+    }
+
+    fn parse_and(&mut self) -> Result<Parsed, PolicyGroupExpressionError> {
+        let (mut expr, mut depth) = self.parse_not()?;
+        while let Some(offset) = self.next_offset(&TokenKind::And) {
+            self.position += 1;
+            let (rhs, rhs_depth) = self.parse_not()?;
+            depth = nested_depth(depth.max(rhs_depth), offset)?;
+            expr = Expression::And(Box::new(expr), Box::new(rhs));
+        }
+        Ok((expr, depth))
+    }
+
+    fn parse_not(&mut self) -> Result<Parsed, PolicyGroupExpressionError> {
+        let token = self.next()?;
+        let offset = token.offset;
+        match &token.kind {
+            TokenKind::Not => {
+                self.enter(offset)?;
+                let (expr, depth) = self.parse_not()?;
+                self.leave();
+                Ok((
+                    Expression::Not(Box::new(expr)),
+                    nested_depth(depth, offset)?,
+                ))
+            }
+            TokenKind::OpenParen => {
+                self.enter(offset)?;
+                let parsed = self.parse_or()?;
+                self.leave();
+                let closing = self.next()?;
+                if closing.kind != TokenKind::CloseParen {
+                    return Err(PolicyGroupExpressionError::UnexpectedToken {
+                        token: closing.kind.to_string(),
+                        offset: closing.offset,
+                    });
+                }
+                Ok(parsed)
+            }
+            TokenKind::Identifier(name) => Ok((Expression::Member(name.clone()), 1)),
+            kind => Err(PolicyGroupExpressionError::UnexpectedToken {
+                token: kind.to_string(),
+                offset,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+    use std::collections::HashMap;
+
+    fn member(name: &str) -> Box<Expression> {
+        Box::new(Expression::Member(name.to_string()))
+    }
+
+    #[rstest]
+    #[case::single_member("a", Expression::Member("a".to_string()))]
+    #[case::and_keyword("a and b", Expression::And(member("a"), member("b")))]
+    #[case::and_symbol("a && b", Expression::And(member("a"), member("b")))]
+    #[case::or_keyword("a or b", Expression::Or(member("a"), member("b")))]
+    #[case::or_symbol("a || b", Expression::Or(member("a"), member("b")))]
+    #[case::not_keyword("not a", Expression::Not(member("a")))]
+    #[case::not_symbol("!a", Expression::Not(member("a")))]
+    #[case::and_has_precedence_over_or(
+        "a || b && c",
+        Expression::Or(member("a"), Box::new(Expression::And(member("b"), member("c"))))
+    )]
+    #[case::parentheses(
+        "(a || b) && c",
+        Expression::And(Box::new(Expression::Or(member("a"), member("b"))), member("c"))
+    )]
+    #[case::not_has_precedence_over_and(
+        "!a && b",
+        Expression::And(Box::new(Expression::Not(member("a"))), member("b"))
+    )]
+    #[case::names_with_dashes(
+        "signed-images and not_privileged",
+        Expression::And(member("signed-images"), member("not_privileged"))
+    )]
+    fn parse_valid_expression(#[case] input: &str, #[case] expected: Expression) {
+        let expr = Expression::parse(input).expect("cannot parse expression");
+        assert_eq!(expected, expr);
+    }
+
+    #[rstest]
+    #[case::empty("")]
+    #[case::only_spaces("   ")]
+    #[case::dangling_operator("a &&")]
+    #[case::missing_operator("a b")]
+    #[case::single_ampersand("a & b")]
+    #[case::unbalanced_parentheses("(a || b")]
+    #[case::unexpected_closing_parenthesis("a || b)")]
+    #[case::invalid_character("a + b")]
+    #[case::too_many_nots(&format!("{}a", "!".repeat(100_000)))]
+    #[case::too_many_parentheses(&format!("{}a{}", "(".repeat(100_000), ")".repeat(100_000)))]
+    #[case::too_long_chain(&format!("a{}", " && a".repeat(100_000)))]
+    fn parse_invalid_expression(#[case] input: &str) {
+        assert!(Expression::parse(input).is_err());
+    }
+
+    #[rstest]
+    #[case::nots(&format!("{}a", "!".repeat(MAX_NESTING_DEPTH - 1)))]
+    #[case::parentheses(&format!("{}a{}", "(".repeat(MAX_NESTING_DEPTH - 1), ")".repeat(MAX_NESTING_DEPTH - 1)))]
+    #[case::chain(&format!("a{}", " || a".repeat(MAX_NESTING_DEPTH - 1)))]
+    fn parse_expression_at_the_nesting_limit(#[case] input: &str) {
+        assert!(Expression::parse(input).is_ok());
+    }
+
+    #[test]
+    fn list_members() {
+        let expr = Expression::parse("a && (b || !a) && c").unwrap();
+        assert_eq!(BTreeSet::from(["a", "b", "c"]), expr.members());
+    }
+
+    #[rstest]
+    #[case::and_short_circuit("a && b", false, vec!["a"])]
+    #[case::or_short_circuit("b || a", true, vec!["b"])]
+    #[case::or_evaluates_both("a || b", true, vec!["a", "b"])]
+    #[case::not("!a && b", true, vec!["a", "b"])]
+    fn evaluate_is_lazy(
+        #[case] input: &str,
+        #[case] expected: bool,
+        #[case] expected_calls: Vec<&str>,
+    ) {
+        let verdicts = HashMap::from([("a", false), ("b", true)]);
+        let expr = Expression::parse(input).unwrap();
+
+        let mut calls = Vec::new();
+        let result = expr.evaluate(&mut |name: &str| {
+            calls.push(name.to_string());
+            verdicts[name]
+        });
+
+        assert_eq!(expected, result);
+        assert_eq!(expected_calls, calls);
+    }
+
+    #[rstest]
+    #[case("valid", true)]
+    #[case("with-dash_and_underscore1", true)]
+    #[case("1starts_with_digit", false)]
+    #[case("", false)]
+    #[case("and", false)]
+    #[case("has space", false)]
+    fn member_name_validation(#[case] name: &str, #[case] valid: bool) {
+        assert_eq!(valid, is_valid_member_name(name));
+    }
+}
@@ -0,0 +1,172 @@
+use std::collections::BTreeMap;
+
+use crate::errors::PolicyGroupEvaluatorBuilderError;
+use crate::policy_evaluator::{PolicyEvaluatorPre, PolicySettings};
+use crate::policy_group_evaluator::evaluator::{
+    PolicyGroupEvaluator, PolicyGroupMember, DEFAULT_REJECTION_MESSAGE,
+};
+use crate::policy_group_evaluator::expression::{is_valid_member_name, Expression};
+
+/// Helper Struct that creates a `PolicyGroupEvaluator` object
+///
+/// The expression combines the verdicts of the members using the `and` (or `&&`),
+/// `or` (or `||`) and `not` (or `!`) operators. Parentheses can be used to group
+/// sub-expressions. For example: `signed && (trusted_registry || !privileged)`.
+///
+/// Only validating policies can be part of a group. Members whose metadata
+/// declares them as mutating are rejected at build time.
+pub struct PolicyGroupEvaluatorBuilder {
+    expression: String,
+    message: Option<String>,
+    members: Vec<(String, PolicyGroupMember)>,
+}
+
+impl PolicyGroupEvaluatorBuilder {
+    /// Create a new PolicyGroupEvaluatorBuilder object, the given `expression`
+    /// is used to compute the verdict of the group.
+    pub fn new(expression: &str) -> PolicyGroupEvaluatorBuilder {
+        PolicyGroupEvaluatorBuilder {
+            expression: expression.to_string(),
+            message: None,
+            members: Vec::new(),
+        }
+    }
+
+    /// The message returned when the group rejects a request
+    #[must_use]
+    pub fn message(mut self, message: &str) -> Self {
+        self.message = Some(message.to_string());
+        self
+    }
+
+    /// Add a member to the group. The `name` is used to reference the member
+    /// inside of the expression.
+    #[must_use]
+    pub fn member(
+        mut self,
+        name: &str,
+        evaluator_pre: PolicyEvaluatorPre,
+        settings: PolicySettings,
+    ) -> Self {
+        self.members.push((
+            name.to_string(),
+            PolicyGroupMember {
+                evaluator_pre,
+                settings,
+            },
+        ));
+        self
+    }
+
+    /// Create the instance of `PolicyGroupEvaluator` to be used
+    pub fn build(self) -> Result<PolicyGroupEvaluator, PolicyGroupEvaluatorBuilderError> {
+        if self.members.is_empty() {
+            return Err(PolicyGroupEvaluatorBuilderError::NoMembers);
+        }
+
+        let expression = Expression::parse(&self.expression)
+            .map_err(PolicyGroupEvaluatorBuilderError::InvalidExpression)?;
+
+        let mut members = BTreeMap::new();
+        for (name, member) in self.members {
+            if !is_valid_member_name(&name) {
+                return Err(PolicyGroupEvaluatorBuilderError::InvalidMemberName(name));
+            }
+            if member
+                .evaluator_pre
+                .metadata()
+                .is_some_and(|metadata| metadata.mutating)
+            {
+                return Err(PolicyGroupEvaluatorBuilderError::MutatingMember(name));
+            }
+            if members.contains_key(&name) {
+                return Err(PolicyGroupEvaluatorBuilderError::DuplicateMember(name));
+            }
+            members.insert(name, member);
+        }
+
+        if let Some(unknown) = expression
+            .members()
+            .into_iter()
+            .find(|name| !members.contains_key(*name))
+        {
+            return Err(PolicyGroupEvaluatorBuilderError::UnknownMember(
+                unknown.to_string(),
+            ));
+        }
+
+        Ok(PolicyGroupEvaluator {
+            expression,
+            message: self
+                .message
+                .unwrap_or_else(|| DEFAULT_REJECTION_MESSAGE.to_string()),
+            members,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::wapc_endless_loop_pre;
+
+    #[test]
+    fn build_policy_group_evaluator() {
+        let group = PolicyGroupEvaluatorBuilder::new("a && !b")
+            .message("rejected")
+            .member("a", wapc_endless_loop_pre(), PolicySettings::new())
+            .member("b", wapc_endless_loop_pre(), PolicySettings::new())
+            .build()
+            .expect("cannot build policy group");
+
+        assert_eq!("rejected", group.message);
+        assert_eq!(2, group.members.len());
+    }
+
+    #[test]
+    fn reject_unknown_member() {
+        let result = PolicyGroupEvaluatorBuilder::new("a && c")
+            .member("a", wapc_endless_loop_pre(), PolicySettings::new())
+            .build();
+
+        assert!(matches!(
+            result,
+            Err(PolicyGroupEvaluatorBuilderError::UnknownMember(name)) if name == "c"
+        ));
+    }
+
+    #[test]
+    fn reject_duplicate_member() {
+        let result = PolicyGroupEvaluatorBuilder::new("a")
+            .member("a", wapc_endless_loop_pre(), PolicySettings::new())
+            .member("a", wapc_endless_loop_pre(), PolicySettings::new())
+            .build();
+
+        assert!(matches!(
+            result,
+            Err(PolicyGroupEvaluatorBuilderError::DuplicateMember(name)) if name == "a"
+        ));
+    }
+
+    #[test]
+    fn reject_invalid_expression() {
+        let result = PolicyGroupEvaluatorBuilder::new("a &&")
+            .member("a", wapc_endless_loop_pre(), PolicySettings::new())
+            .build();
+
+        assert!(matches!(
+            result,
+            Err(PolicyGroupEvaluatorBuilderError::InvalidExpression(_))
+        ));
+    }
+
+    #[test]
+    fn reject_empty_group() {
+        let result = PolicyGroupEvaluatorBuilder::new("a").build();
+
+        assert!(matches!(
+            result,
+            Err(PolicyGroupEvaluatorBuilderError::NoMembers)
+        ));
+    }
+}
@@ -11,7 +11,11 @@ use serde::{Deserialize, Serialize};
 use validator::{Validate, ValidationError};
 use wasmparser::{Parser, Payload};
 
-use crate::{errors::MetadataError, policy_evaluator::PolicyExecutionMode};
+use crate::{
+    admission_request::{AdmissionRequest, GroupVersionResource},
+    errors::MetadataError,
+    policy_evaluator::PolicyExecutionMode,
+};
 
 #[derive(Deserialize, Serialize, Debug, Clone, Hash, Eq, PartialEq)]
 pub enum Operation {
@@ -159,6 +163,115 @@ impl TryFrom<&NamedRuleWithOperations> for Rule {
     }
 }
 
+impl Rule {
+    /// Returns whether the rule matches the request, see [`Rule::match_request`]
+    pub fn matches(&self, request: &AdmissionRequest) -> bool {
+        self.match_request(request).is_ok()
+    }
+
+    /// Match the rule against the request, using the same logic of the Kubernetes
+    /// API server. When the rule doesn't match, the reason is returned.
+    ///
+    /// The resource originally requested by the user (`requestResource`, the counterpart
+    /// of `requestKind`) is checked first, then the equivalent one the request has been
+    /// converted to (`resource`, the counterpart of `kind`).
+    /// This is the behavior of the API server when `matchPolicy` is set to `Equivalent`,
+    /// which is the default value.
+    pub fn match_request(&self, request: &AdmissionRequest) -> Result<(), RuleMismatch> {
+        let operation_matches = Operation::try_from(request.operation.as_str()).is_ok_and(|op| {
+            op != Operation::All
+                && self
+                    .operations
+                    .iter()
+                    .any(|o| o == &Operation::All || o == &op)
+        });
+        if !operation_matches {
+            return Err(RuleMismatch::Operation(request.operation.clone()));
+        }
+
+        // the resource originally requested by the user, the request might have
+        // been converted to an equivalent one before being sent to the webhook
+        let (requested, requested_sub_resource) = match &request.request_resource {
+            Some(resource) => (resource, request.request_sub_resource.as_deref()),
+            None => (&request.resource, request.sub_resource.as_deref()),
+        };
+
+        self.match_resource(requested, requested_sub_resource)
+            .or_else(|mismatch| {
+                self.match_resource(&request.resource, request.sub_resource.as_deref())
+                    .map_err(|_| mismatch)
+            })
+    }
+
+    fn match_resource(
+        &self,
+        resource: &GroupVersionResource,
+        sub_resource: Option<&str>,
+    ) -> Result<(), RuleMismatch> {
+        if !matches_wildcard(&self.api_groups, &resource.group) {
+            return Err(RuleMismatch::ApiGroup(resource.group.clone()));
+        }
+        if !matches_wildcard(&self.api_versions, &resource.version) {
+            return Err(RuleMismatch::ApiVersion(resource.version.clone()));
+        }
+
+        let sub_resource = sub_resource.unwrap_or_default();
+        let resource_matches = self.resources.iter().any(|rule_resource| {
+            let (rule_res, rule_sub) = rule_resource
+                .split_once('/')
+                .unwrap_or((rule_resource.as_str(), ""));
+            (rule_res == "*" || rule_res == resource.resource)
+                && (rule_sub == "*" || rule_sub == sub_resource)
+        });
+        if !resource_matches {
+            let resource = if sub_resource.is_empty() {
+                resource.resource.clone()
+            } else {
+                format!("{}/{sub_resource}", resource.resource)
+            };
+            return Err(RuleMismatch::Resource(resource));
+        }
+
+        Ok(())
+    }
+}
+
+fn matches_wildcard(values: &[String], value: &str) -> bool {
+    values.iter().any(|v| v == "*" || v == value)
+}
+
+/// The reason why a [`Rule`] doesn't match an `AdmissionRequest`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuleMismatch {
+    /// The operation of the request is not targeted by the rule
+    Operation(String),
+    /// The API group of the request is not targeted by the rule
+    ApiGroup(String),
+    /// The API version of the request is not targeted by the rule
+    ApiVersion(String),
+    /// The resource of the request, including its subresource, is not targeted by the rule
+    Resource(String),
+}
+
+impl Display for RuleMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuleMismatch::Operation(operation) => {
+                write!(f, "operation `{operation}` is not matched by the rule")
+            }
+            RuleMismatch::ApiGroup(group) => {
+                write!(f, "API group `{group}` is not matched by the rule")
+            }
+            RuleMismatch::ApiVersion(version) => {
+                write!(f, "API version `{version}` is not matched by the rule")
+            }
+            RuleMismatch::Resource(resource) => {
+                write!(f, "resource `{resource}` is not matched by the rule")
+            }
+        }
+    }
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone, Validate, PartialEq, Hash, Eq, PartialOrd, Ord)]
 #[serde(rename_all = "camelCase")]
 pub struct ContextAwareResource {
@@ -206,6 +319,11 @@ pub struct Metadata {
     pub context_aware_resources: BTreeSet<ContextAwareResource>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub minimum_kubewarden_version: Option<Version>,
+    /// JSON Schema describing the settings of the policy. When provided, the settings
+    /// are validated against it by the host, before being handed to the policy
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[validate(custom(function = "validate_settings_schema"))]
+    pub settings_schema: Option<serde_json::Value>,
 }
 
 const fn _default_true() -> bool {
@@ -224,6 +342,7 @@ impl Default for Metadata {
             policy_type: PolicyType::Kubernetes,
             context_aware_resources: BTreeSet::new(),
             minimum_kubewarden_version: None,
+            settings_schema: None,
         }
     }
 }
@@ -234,20 +353,51 @@ impl Metadata {
     }
 
     pub fn from_contents(policy: &[u8]) -> std::result::Result<Option<Metadata>, MetadataError> {
-        for payload in Parser::new(0).parse_all(policy) {
-            if let Payload::CustomSection(reader) = payload.map_err(MetadataError::WasmPayload)? {
-                if reader.name() == crate::constants::KUBEWARDEN_CUSTOM_SECTION_METADATA {
-                    return Ok(Some(serde_json::from_slice(reader.data()).map_err(
-                        |e| MetadataError::Deserialize {
-                            section: reader.name().to_string(),
-                            error: e,
-                        },
-                    )?));
-                }
+        read_custom_section(policy, crate::constants::KUBEWARDEN_CUSTOM_SECTION_METADATA)
+    }
+
+    /// Returns whether the policy has to be evaluated against the request, which
+    /// happens when at least one of its rules matches the request.
+    /// See [`Rule::match_request`] for more details.
+    pub fn matches(&self, request: &AdmissionRequest) -> bool {
+        self.rules.iter().any(|rule| rule.matches(request))
+    }
+
+    /// Match each rule of the policy against the request. The outcomes are returned
+    /// in the same order as the rules
+    pub fn match_rules(&self, request: &AdmissionRequest) -> Vec<Result<(), RuleMismatch>> {
+        self.rules
+            .iter()
+            .map(|rule| rule.match_request(request))
+            .collect()
+    }
+}
+
+/// Deserialize the JSON document stored inside of the custom section of the policy
+/// with the given name. Returns `None` when the section is not present
+pub(crate) fn read_custom_section<T: serde::de::DeserializeOwned>(
+    policy: &[u8],
+    name: &str,
+) -> std::result::Result<Option<T>, MetadataError> {
+    for payload in Parser::new(0).parse_all(policy) {
+        if let Payload::CustomSection(reader) = payload.map_err(MetadataError::WasmPayload)? {
+            if reader.name() == name {
+                return serde_json::from_slice(reader.data())
+                    .map(Some)
+                    .map_err(|e| MetadataError::Deserialize {
+                        section: name.to_string(),
+                        error: e,
+                    });
             }
         }
-        Ok(None)
     }
+    Ok(None)
+}
+
+fn validate_settings_schema(schema: &serde_json::Value) -> Result<(), ValidationError> {
+    jsonschema::validator_for(schema)
+        .map(|_| ())
+        .map_err(|_| ValidationError::new("Must specify a valid JSON Schema"))
 }
 
 fn validate_metadata(metadata: &Metadata) -> Result<(), ValidationError> {
@@ -265,6 +415,7 @@ fn validate_metadata(metadata: &Metadata) -> Result<(), ValidationError> {
 mod tests {
     use super::*;
     use assert_json_diff::assert_json_eq;
+    use rstest::rstest;
     use serde_json::json;
 
     #[test]
@@ -394,6 +545,22 @@ mod tests {
         }
     }
 
+    #[test]
+    fn metadata_with_settings_schema() {
+        let mut metadata = Metadata {
+            protocol_version: Some(ProtocolVersion::V1),
+            settings_schema: Some(json!({
+                "type": "object",
+                "properties": { "replicas": { "type": "integer" } }
+            })),
+            ..Default::default()
+        };
+        assert!(metadata.validate().is_ok());
+
+        metadata.settings_schema = Some(json!({ "type": 12 }));
+        assert!(metadata.validate().is_err());
+    }
+
     #[test]
     fn metadata_without_rules() -> Result<(), ()> {
         let metadata = Metadata {
@@ -702,4 +869,143 @@ mod tests {
 
         assert!(metadata.validate().is_err());
     }
+
+    fn admission_request(
+        operation: &str,
+        resource: (&str, &str, &str),
+        sub_resource: Option<&str>,
+    ) -> AdmissionRequest {
+        let (group, version, resource) = resource;
+        serde_json::from_value(json!({
+            "uid": "uid",
+            "kind": {"group": group, "version": version, "kind": "Kind"},
+            "resource": {"group": group, "version": version, "resource": resource},
+            "subResource": sub_resource,
+            "operation": operation,
+            "userInfo": {}
+        }))
+        .expect("cannot build admission request")
+    }
+
+    fn rule(api_groups: &[&str], api_versions: &[&str], resources: &[&str]) -> Rule {
+        Rule {
+            api_groups: api_groups.iter().map(|s| s.to_string()).collect(),
+            api_versions: api_versions.iter().map(|s| s.to_string()).collect(),
+            resources: resources.iter().map(|s| s.to_string()).collect(),
+            operations: vec![Operation::Create, Operation::Update],
+        }
+    }
+
+    #[rstest]
+    #[case::exact(rule(&[""], &["v1"], &["pods"]), None, Ok(()))]
+    #[case::wildcards(rule(&["*"], &["*"], &["*"]), None, Ok(()))]
+    #[case::single_wildcard_ignores_subresources(
+        rule(&[""], &["v1"], &["*"]),
+        Some("status"),
+        Err(RuleMismatch::Resource("pods/status".to_string()))
+    )]
+    #[case::double_wildcard(rule(&[""], &["v1"], &["*/*"]), Some("status"), Ok(()))]
+    #[case::resource_with_wildcard_subresource(
+        rule(&[""], &["v1"], &["pods/*"]),
+        Some("exec"),
+        Ok(())
+    )]
+    #[case::wildcard_resource_with_subresource(
+        rule(&[""], &["v1"], &["*/status"]),
+        Some("status"),
+        Ok(())
+    )]
+    #[case::subresource_not_matched(
+        rule(&[""], &["v1"], &["pods"]),
+        Some("status"),
+        Err(RuleMismatch::Resource("pods/status".to_string()))
+    )]
+    #[case::api_group(
+        rule(&["apps"], &["v1"], &["pods"]),
+        None,
+        Err(RuleMismatch::ApiGroup("".to_string()))
+    )]
+    #[case::api_version(
+        rule(&[""], &["v2"], &["pods"]),
+        None,
+        Err(RuleMismatch::ApiVersion("v1".to_string()))
+    )]
+    fn match_rule_resources(
+        #[case] rule: Rule,
+        #[case] sub_resource: Option<&str>,
+        #[case] expected: Result<(), RuleMismatch>,
+    ) {
+        let request = admission_request("CREATE", ("", "v1", "pods"), sub_resource);
+
+        assert_eq!(expected, rule.match_request(&request));
+    }
+
+    #[rstest]
+    #[case::listed("UPDATE", vec![Operation::Create, Operation::Update], true)]
+    #[case::not_listed("DELETE", vec![Operation::Create, Operation::Update], false)]
+    #[case::all("CONNECT", vec![Operation::All], true)]
+    #[case::unknown("PATCH", vec![Operation::All], false)]
+    fn match_rule_operations(
+        #[case] operation: &str,
+        #[case] operations: Vec<Operation>,
+        #[case] expected: bool,
+    ) {
+        let rule = Rule {
+            operations,
+            ..rule(&["*"], &["*"], &["*"])
+        };
+        let request = admission_request(operation, ("", "v1", "pods"), None);
+
+        assert_eq!(expected, rule.matches(&request));
+        if !expected {
+            assert_eq!(
+                Err(RuleMismatch::Operation(operation.to_string())),
+                rule.match_request(&request)
+            );
+        }
+    }
+
+    #[test]
+    fn match_equivalent_resource() {
+        // the user requested apps/v1beta1 deployments, the request has been
+        // converted to apps/v1 before being sent to the webhook
+        let mut request = admission_request("CREATE", ("apps", "v1", "deployments"), None);
+        request.request_resource = Some(GroupVersionResource {
+            group: "apps".to_string(),
+            version: "v1beta1".to_string(),
+            resource: "deployments".to_string(),
+        });
+
+        assert!(rule(&["apps"], &["v1"], &["deployments"]).matches(&request));
+        assert!(rule(&["apps"], &["v1beta1"], &["deployments"]).matches(&request));
+        assert_eq!(
+            Err(RuleMismatch::ApiVersion("v1beta1".to_string())),
+            rule(&["apps"], &["v2"], &["deployments"]).match_request(&request)
+        );
+    }
+
+    #[test]
+    fn metadata_matches_request() {
+        let metadata = Metadata {
+            protocol_version: Some(ProtocolVersion::V1),
+            rules: vec![
+                rule(&["apps"], &["v1"], &["deployments"]),
+                rule(&[""], &["v1"], &["pods"]),
+            ],
+            ..Default::default()
+        };
+
+        let request = admission_request("CREATE", ("", "v1", "pods"), None);
+        assert!(metadata.matches(&request));
+        assert_eq!(
+            vec![Err(RuleMismatch::ApiGroup("".to_string())), Ok(())],
+            metadata.match_rules(&request)
+        );
+
+        let request = admission_request("CREATE", ("", "v1", "services"), None);
+        assert!(!metadata.matches(&request));
+
+        let no_rules = Metadata::default();
+        assert!(!no_rules.matches(&request));
+    }
 }
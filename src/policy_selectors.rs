@@ -0,0 +1,423 @@
+//! Evaluate the `namespaceSelector` and `objectSelector` of a policy.
+//!
+//! The selectors are evaluated with the same logic of the Kubernetes API server, this
+//! allows to find out whether a policy would be invoked for a given request without
+//! relying on a cluster. See [`PolicySelectors`] for more details.
+
+use std::collections::BTreeMap;
+
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{LabelSelector, LabelSelectorRequirement};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::admission_request::AdmissionRequest;
+use crate::callback_requests::{CallbackRequest, CallbackRequestType, CallbackResponse};
+use crate::errors::PolicySelectorsError;
+
+type Result<T> = std::result::Result<T, PolicySelectorsError>;
+
+/// Plural name of the Kubernetes Namespace resource
+const NAMESPACES_RESOURCE: &str = "namespaces";
+
+/// The selectors restricting the requests a policy is evaluated against
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PolicySelectors {
+    /// Selects the requests based on the labels of the namespace of the object.
+    /// Requests about cluster-wide resources are always selected, with the exception
+    /// of the ones about namespaces: in that case the labels of the namespace itself
+    /// are used.
+    pub namespace_selector: Option<LabelSelector>,
+    /// Selects the requests based on the labels of the object. Both the `object` and
+    /// the `oldObject` of the request are evaluated, the request is selected when at
+    /// least one of them matches.
+    pub object_selector: Option<LabelSelector>,
+}
+
+impl PolicySelectors {
+    /// Returns whether the policy has to be evaluated against the request.
+    ///
+    /// The namespace of the object is fetched via the callback channel, but only when
+    /// its labels are actually needed to evaluate the namespace selector.
+    pub async fn matches(
+        &self,
+        request: &AdmissionRequest,
+        callback_channel: Option<&mpsc::Sender<CallbackRequest>>,
+    ) -> Result<bool> {
+        if !self.matches_object(request)? {
+            return Ok(false);
+        }
+
+        let namespace = match self.namespace_to_fetch(request) {
+            Some(name) => {
+                let callback_channel = callback_channel
+                    .ok_or_else(|| PolicySelectorsError::MissingCallbackChannel(name.clone()))?;
+                Some(fetch_namespace(callback_channel, &name).await?)
+            }
+            None => None,
+        };
+
+        self.matches_namespace(request, namespace.as_ref())
+    }
+
+    /// Evaluate the object selector against the `object` and the `oldObject` of the request
+    pub fn matches_object(&self, request: &AdmissionRequest) -> Result<bool> {
+        let selector = match &self.object_selector {
+            Some(selector) if !is_empty(selector) => selector,
+            _ => return Ok(true),
+        };
+
+        for object in [&request.object, &request.old_object].into_iter().flatten() {
+            // objects that cannot have labels are never selected
+            if let Some(labels) = object_labels(&object.0) {
+                if matches_labels(selector, &labels)? {
+                    return Ok(true);
+                }
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Evaluate the namespace selector. The `namespace` object is used when the labels of
+    /// the namespace of the request are needed, which happens when the request is about
+    /// a namespaced resource. An error is returned when the namespace is needed, but it
+    /// has not been provided.
+    pub fn matches_namespace(
+        &self,
+        request: &AdmissionRequest,
+        namespace: Option<&serde_json::Value>,
+    ) -> Result<bool> {
+        let selector = match &self.namespace_selector {
+            Some(selector) if !is_empty(selector) => selector,
+            _ => return Ok(true),
+        };
+
+        let labels = if is_namespace_creation_or_update(request) {
+            request
+                .object
+                .as_ref()
+                .and_then(|object| object_labels(&object.0))
+                .unwrap_or_default()
+        } else {
+            match namespace_name(request) {
+                Some(name) => namespace
+                    .and_then(object_labels)
+                    .ok_or(PolicySelectorsError::MissingNamespace(name))?,
+                // requests about cluster-wide resources are always selected
+                None => return Ok(true),
+            }
+        };
+
+        matches_labels(selector, &labels)
+    }
+
+    /// The name of the namespace whose labels are needed to evaluate the namespace selector
+    fn namespace_to_fetch(&self, request: &AdmissionRequest) -> Option<String> {
+        match &self.namespace_selector {
+            Some(selector) if !is_empty(selector) && !is_namespace_creation_or_update(request) => {
+                namespace_name(request)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Evaluate the label selector against the given labels. An empty selector
+/// matches everything.
+pub fn matches_labels(selector: &LabelSelector, labels: &BTreeMap<String, String>) -> Result<bool> {
+    let labels_match = selector
+        .match_labels
+        .iter()
+        .flatten()
+        .all(|(key, value)| labels.get(key) == Some(value));
+    if !labels_match {
+        return Ok(false);
+    }
+
+    for requirement in selector.match_expressions.iter().flatten() {
+        if !matches_requirement(requirement, labels)? {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+fn matches_requirement(
+    requirement: &LabelSelectorRequirement,
+    labels: &BTreeMap<String, String>,
+) -> Result<bool> {
+    let values = requirement.values.as_deref().unwrap_or_default();
+    let value = labels.get(&requirement.key);
+
+    match requirement.operator.as_str() {
+        "In" | "NotIn" if values.is_empty() => Err(PolicySelectorsError::MissingValues {
+            key: requirement.key.clone(),
+            operator: requirement.operator.clone(),
+        }),
+        "Exists" | "DoesNotExist" if !values.is_empty() => {
+            Err(PolicySelectorsError::UnexpectedValues {
+                key: requirement.key.clone(),
+                operator: requirement.operator.clone(),
+            })
+        }
+        "In" => Ok(value.is_some_and(|value| values.contains(value))),
+        "NotIn" => Ok(!value.is_some_and(|value| values.contains(value))),
+        "Exists" => Ok(value.is_some()),
+        "DoesNotExist" => Ok(value.is_none()),
+        operator => Err(PolicySelectorsError::UnknownOperator(operator.to_string())),
+    }
+}
+
+fn is_empty(selector: &LabelSelector) -> bool {
+    selector
+        .match_labels
+        .as_ref()
+        .is_none_or(BTreeMap::is_empty)
+        && selector
+            .match_expressions
+            .as_ref()
+            .is_none_or(Vec::is_empty)
+}
+
+/// The labels of a Kubernetes object. This is `None` when the object doesn't have metadata
+fn object_labels(object: &serde_json::Value) -> Option<BTreeMap<String, String>> {
+    let metadata = object.get("metadata")?.as_object()?;
+    let labels = metadata
+        .get("labels")
+        .and_then(|labels| serde_json::from_value(labels.clone()).ok())
+        .unwrap_or_default();
+    Some(labels)
+}
+
+/// When a namespace is created or updated, the namespace selector is evaluated
+/// against the object of the request
+fn is_namespace_creation_or_update(request: &AdmissionRequest) -> bool {
+    request.resource.resource == NAMESPACES_RESOURCE
+        && request
+            .sub_resource
+            .as_deref()
+            .unwrap_or_default()
+            .is_empty()
+        && (request.operation == "CREATE" || request.operation == "UPDATE")
+}
+
+/// The namespace the request is about. This is `None` for cluster-wide resources
+fn namespace_name(request: &AdmissionRequest) -> Option<String> {
+    request
+        .namespace
+        .clone()
+        .filter(|namespace| !namespace.is_empty())
+        .or_else(|| {
+            (request.resource.resource == NAMESPACES_RESOURCE)
+                .then(|| request.name.clone())
+                .flatten()
+        })
+}
+
+async fn fetch_namespace(
+    callback_channel: &mpsc::Sender<CallbackRequest>,
+    name: &str,
+) -> Result<serde_json::Value> {
+    let (tx, rx) = oneshot::channel::<anyhow::Result<CallbackResponse>>();
+    let req = CallbackRequest {
+        request: CallbackRequestType::KubernetesGetResource {
+            api_version: "v1".to_string(),
+            kind: "Namespace".to_string(),
+            name: name.to_string(),
+            namespace: None,
+            disable_cache: false,
+        },
+        response_channel: tx,
+    };
+    callback_channel
+        .send(req)
+        .await
+        .map_err(|e| PolicySelectorsError::CallbackSend(e.to_string()))?;
+
+    let response = rx
+        .await
+        .map_err(|e| PolicySelectorsError::CallbackResponse(e.to_string()))?
+        .map_err(|error| PolicySelectorsError::FetchNamespace {
+            name: name.to_string(),
+            error,
+        })?;
+
+    serde_json::from_slice(&response.payload)
+        .map_err(PolicySelectorsError::CallbackConvertNamespace)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use serde_json::json;
+
+    fn selector(selector: serde_json::Value) -> LabelSelector {
+        serde_json::from_value(selector).expect("cannot build label selector")
+    }
+
+    fn labels(labels: &[(&str, &str)]) -> BTreeMap<String, String> {
+        labels
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn admission_request(
+        operation: &str,
+        resource: &str,
+        namespace: Option<&str>,
+        object: Option<serde_json::Value>,
+        old_object: Option<serde_json::Value>,
+    ) -> AdmissionRequest {
+        serde_json::from_value(json!({
+            "uid": "uid",
+            "kind": {"group": "", "version": "v1", "kind": "Kind"},
+            "resource": {"group": "", "version": "v1", "resource": resource},
+            "name": "name",
+            "namespace": namespace,
+            "operation": operation,
+            "userInfo": {},
+            "object": object,
+            "oldObject": old_object,
+        }))
+        .expect("cannot build admission request")
+    }
+
+    #[rstest]
+    #[case::empty(json!({}), true)]
+    #[case::match_labels(json!({"matchLabels": {"env": "prod"}}), true)]
+    #[case::match_labels_mismatch(json!({"matchLabels": {"env": "dev"}}), false)]
+    #[case::in_op(
+        json!({"matchExpressions": [{"key": "env", "operator": "In", "values": ["dev", "prod"]}]}),
+        true
+    )]
+    #[case::in_missing_label(
+        json!({"matchExpressions": [{"key": "tier", "operator": "In", "values": ["web"]}]}),
+        false
+    )]
+    #[case::not_in(
+        json!({"matchExpressions": [{"key": "env", "operator": "NotIn", "values": ["prod"]}]}),
+        false
+    )]
+    #[case::not_in_missing_label(
+        json!({"matchExpressions": [{"key": "tier", "operator": "NotIn", "values": ["web"]}]}),
+        true
+    )]
+    #[case::exists(json!({"matchExpressions": [{"key": "team", "operator": "Exists"}]}), true)]
+    #[case::does_not_exist(
+        json!({"matchExpressions": [{"key": "team", "operator": "DoesNotExist"}]}),
+        false
+    )]
+    #[case::labels_and_expressions(
+        json!({
+            "matchLabels": {"env": "prod"},
+            "matchExpressions": [{"key": "team", "operator": "DoesNotExist"}]
+        }),
+        false
+    )]
+    fn evaluate_label_selector(#[case] label_selector: serde_json::Value, #[case] expected: bool) {
+        let labels = labels(&[("env", "prod"), ("team", "a")]);
+
+        assert_eq!(
+            expected,
+            matches_labels(&selector(label_selector), &labels).unwrap()
+        );
+    }
+
+    #[rstest]
+    #[case::unknown_operator(json!([{"key": "env", "operator": "Equals", "values": ["a"]}]))]
+    #[case::missing_values(json!([{"key": "env", "operator": "In", "values": []}]))]
+    #[case::unexpected_values(json!([{"key": "env", "operator": "Exists", "values": ["a"]}]))]
+    fn invalid_label_selector(#[case] expressions: serde_json::Value) {
+        let label_selector = selector(json!({ "matchExpressions": expressions }));
+
+        assert!(matches_labels(&label_selector, &labels(&[("env", "a")])).is_err());
+    }
+
+    #[rstest]
+    #[case::object(Some(json!({"metadata": {"labels": {"env": "prod"}}})), None, true)]
+    #[case::old_object(
+        Some(json!({"metadata": {"labels": {"env": "dev"}}})),
+        Some(json!({"metadata": {"labels": {"env": "prod"}}})),
+        true
+    )]
+    #[case::deleted_object(None, Some(json!({"metadata": {"labels": {"env": "prod"}}})), true)]
+    #[case::no_match(Some(json!({"metadata": {"labels": {"env": "dev"}}})), None, false)]
+    #[case::object_without_metadata(Some(json!({"kind": "PodProxyOptions"})), None, false)]
+    fn evaluate_object_selector(
+        #[case] object: Option<serde_json::Value>,
+        #[case] old_object: Option<serde_json::Value>,
+        #[case] expected: bool,
+    ) {
+        let selectors = PolicySelectors {
+            object_selector: Some(selector(json!({"matchLabels": {"env": "prod"}}))),
+            ..Default::default()
+        };
+        let request = admission_request("UPDATE", "pods", Some("default"), object, old_object);
+
+        assert_eq!(expected, selectors.matches_object(&request).unwrap());
+    }
+
+    #[test]
+    fn evaluate_namespace_selector() {
+        let selectors = PolicySelectors {
+            namespace_selector: Some(selector(json!({"matchLabels": {"env": "prod"}}))),
+            ..Default::default()
+        };
+        let prod = json!({"metadata": {"name": "default", "labels": {"env": "prod"}}});
+        let dev = json!({"metadata": {"name": "default", "labels": {"env": "dev"}}});
+
+        let request = admission_request("CREATE", "pods", Some("default"), None, None);
+        assert!(selectors.matches_namespace(&request, Some(&prod)).unwrap());
+        assert!(!selectors.matches_namespace(&request, Some(&dev)).unwrap());
+        assert!(matches!(
+            selectors.matches_namespace(&request, None),
+            Err(PolicySelectorsError::MissingNamespace(name)) if name == "default"
+        ));
+
+        // cluster-wide resources are always selected
+        let request = admission_request("CREATE", "clusterroles", None, None, None);
+        assert!(selectors.matches_namespace(&request, None).unwrap());
+
+        // the labels of a namespace being created are taken from the request
+        let request = admission_request("CREATE", "namespaces", None, Some(prod.clone()), None);
+        assert!(selectors.matches_namespace(&request, None).unwrap());
+        assert!(selectors.namespace_to_fetch(&request).is_none());
+
+        // the namespace being deleted must be fetched
+        let request = admission_request("DELETE", "namespaces", None, None, Some(prod));
+        assert_eq!(
+            Some("name".to_string()),
+            selectors.namespace_to_fetch(&request)
+        );
+    }
+
+    #[tokio::test]
+    async fn fetch_namespace_via_callback_channel() {
+        let selectors = PolicySelectors {
+            namespace_selector: Some(selector(json!({"matchLabels": {"env": "prod"}}))),
+            ..Default::default()
+        };
+        let request = admission_request("CREATE", "pods", Some("default"), None, None);
+
+        let (tx, mut rx) = mpsc::channel::<CallbackRequest>(1);
+        tokio::spawn(async move {
+            let req = rx.recv().await.unwrap();
+            assert!(matches!(
+                req.request,
+                CallbackRequestType::KubernetesGetResource { ref kind, ref name, .. }
+                    if kind == "Namespace" && name == "default"
+            ));
+            let namespace = json!({"metadata": {"name": "default", "labels": {"env": "prod"}}});
+            req.response_channel
+                .send(Ok(CallbackResponse {
+                    payload: serde_json::to_vec(&namespace).unwrap(),
+                    was_cached: false,
+                }))
+                .unwrap();
+        });
+
+        assert!(selectors.matches(&request, Some(&tx)).await.unwrap());
+    }
+}
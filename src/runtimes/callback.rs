@@ -1,4 +1,6 @@
+use std::future::Future;
 use std::sync::Arc;
+use std::time::Instant;
 
 use anyhow::{anyhow, Result};
 use kubewarden_policy_sdk::host_capabilities::{
@@ -6,19 +8,121 @@ use kubewarden_policy_sdk::host_capabilities::{
     kubernetes::{GetResourceRequest, ListAllResourcesRequest, ListResourcesByNamespaceRequest},
     SigstoreVerificationInputV1, SigstoreVerificationInputV2,
 };
+use tokio::runtime::{Handle, RuntimeFlavor};
 use tokio::sync::{mpsc, oneshot, oneshot::Receiver};
 use tracing::{debug, error, warn};
 
 use crate::callback_requests::{CallbackRequest, CallbackRequestType, CallbackResponse};
-use crate::{callback_handler::verify_certificate, evaluation_context::EvaluationContext};
+use crate::policy_evaluator::evaluation_report::{HostCallbackRecord, HostCallbackRecorder};
+use crate::policy_evaluator::CancellationHandle;
+use crate::{
+    callback_handler::{verify_certificate, verify_sigstore_certificate_validity},
+    evaluation_context::EvaluationContext,
+};
 
-/// The callback function used by waPC and Wasi policies to use host capabilities
+/// The callback function used by waPC and Wasi policies to use host capabilities.
+///
+/// This blocks the current thread until the host capability request is fulfilled,
+/// see [`block_on`] for the runtime requirements and [`host_callback_async`] for the
+/// non-blocking version.
 pub(crate) fn host_callback(
     binding: &str,
     namespace: &str,
     operation: &str,
     payload: &[u8],
     eval_ctx: &Arc<EvaluationContext>,
+    recorder: &HostCallbackRecorder,
+    cancellation: &CancellationHandle,
+) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    block_on(host_callback_async(
+        binding,
+        namespace,
+        operation,
+        payload,
+        eval_ctx,
+        recorder,
+        cancellation,
+    ))
+}
+
+/// Drive the future to completion from synchronous code, blocking the current thread.
+///
+/// The future might wait for the callback handler, which is run by a tokio runtime.
+/// When this is invoked from a worker of a multi-thread runtime, the worker hands over
+/// its other tasks via [`tokio::task::block_in_place`]. A current-thread runtime cannot
+/// make progress while its thread is blocked, hence this must not be invoked from an
+/// async context driven by a current-thread runtime: the asynchronous APIs have to
+/// be used there.
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    match Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(|| handle.block_on(future))
+        }
+        _ => futures::executor::block_on(future),
+    }
+}
+
+/// The asynchronous callback function used by waPC and Wasi policies to use host capabilities.
+///
+/// The request is aborted as soon as the evaluation is cancelled, an error is then
+/// returned to the policy.
+pub(crate) async fn host_callback_async(
+    binding: &str,
+    namespace: &str,
+    operation: &str,
+    payload: &[u8],
+    eval_ctx: &Arc<EvaluationContext>,
+    recorder: &HostCallbackRecorder,
+    cancellation: &CancellationHandle,
+) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    let start = Instant::now();
+    let mut was_cached = false;
+    let response = cancellation
+        .run_until_cancelled(dispatch_host_callback(
+            binding,
+            namespace,
+            operation,
+            payload,
+            eval_ctx,
+            &mut was_cached,
+        ))
+        .await
+        .unwrap_or_else(|| {
+            warn!(
+                binding,
+                namespace, operation, "evaluation cancelled, host callback aborted"
+            );
+            Err("evaluation cancelled, host callback aborted".into())
+        });
+
+    recorder.record(HostCallbackRecord {
+        capability: capability_name(binding, namespace, operation).to_string(),
+        operation: operation.to_string(),
+        duration: start.elapsed(),
+        was_cached,
+        success: response.is_ok(),
+    });
+
+    response
+}
+
+/// The name of the host capability targeted by the callback, used when
+/// reporting the callbacks made by the policy
+fn capability_name<'a>(binding: &'a str, namespace: &'a str, operation: &str) -> &'a str {
+    match (binding, namespace, operation) {
+        ("kubewarden", "oci", "v1/verify" | "v2/verify") => "sigstore",
+        ("kubewarden", namespace, _) => namespace,
+        (binding, _, _) => binding,
+    }
+}
+
+async fn dispatch_host_callback(
+    binding: &str,
+    namespace: &str,
+    operation: &str,
+    payload: &[u8],
+    eval_ctx: &Arc<EvaluationContext>,
+    was_cached: &mut bool,
 ) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
     match binding {
         "kubewarden" => match namespace {
@@ -58,11 +162,28 @@ pub(crate) fn host_callback(
                         req,
                         rx,
                         eval_ctx,
+                        was_cached,
                     )
+                    .await
                 }
                 "v2/verify" => {
                     let req: SigstoreVerificationInputV2 =
                         serde_json::from_slice(payload.to_vec().as_ref())?;
+                    // Sigstore checks the certificate against the system clock, the
+                    // evaluation clock has to be enforced by us
+                    if let SigstoreVerificationInputV2::SigstoreCertificateVerify {
+                        certificate,
+                        require_rekor_bundle,
+                        ..
+                    } = &req
+                    {
+                        verify_sigstore_certificate_validity(
+                            certificate,
+                            *require_rekor_bundle,
+                            &eval_ctx.clock,
+                        )
+                        .map_err(|e| format!("Error when verifying certificate: {e}"))?;
+                    }
                     let req_type: CallbackRequestType = req.into();
                     let (tx, rx) = oneshot::channel::<Result<CallbackResponse>>();
                     let req = CallbackRequest {
@@ -77,7 +198,9 @@ pub(crate) fn host_callback(
                         req,
                         rx,
                         eval_ctx,
+                        was_cached,
                     )
+                    .await
                 }
                 "v1/manifest_digest" => {
                     let image: String = serde_json::from_slice(payload.to_vec().as_ref())?;
@@ -100,7 +223,9 @@ pub(crate) fn host_callback(
                         req,
                         rx,
                         eval_ctx,
+                        was_cached,
                     )
+                    .await
                 }
                 "v1/oci_manifest" => {
                     let image: String = serde_json::from_slice(payload.to_vec().as_ref())?;
@@ -123,7 +248,9 @@ pub(crate) fn host_callback(
                         req,
                         rx,
                         eval_ctx,
+                        was_cached,
                     )
+                    .await
                 }
                 "v1/oci_manifest_config" => {
                     let image: String = serde_json::from_slice(payload.to_vec().as_ref())?;
@@ -146,7 +273,9 @@ pub(crate) fn host_callback(
                         req,
                         rx,
                         eval_ctx,
+                        was_cached,
                     )
+                    .await
                 }
                 _ => {
                     error!("unknown operation: {}", operation);
@@ -175,7 +304,9 @@ pub(crate) fn host_callback(
                         req,
                         rx,
                         eval_ctx,
+                        was_cached,
                     )
+                    .await
                 }
                 _ => {
                     error!("unknown operation: {}", operation);
@@ -186,12 +317,13 @@ pub(crate) fn host_callback(
                 "v1/is_certificate_trusted" => {
                     let req: CertificateVerificationRequest =
                         serde_json::from_slice(payload.to_vec().as_ref())?;
-                    let response: CertificateVerificationResponse = match verify_certificate(req) {
-                        Ok(b) => b.into(),
-                        Err(e) => {
-                            return Err(format!("Error when verifying certificate: {e}").into())
-                        }
-                    };
+                    let response: CertificateVerificationResponse =
+                        match verify_certificate(req, &eval_ctx.clock) {
+                            Ok(b) => b.into(),
+                            Err(e) => {
+                                return Err(format!("Error when verifying certificate: {e}").into())
+                            }
+                        };
                     Ok(serde_json::to_vec(&response)?)
                 }
                 _ => {
@@ -235,7 +367,9 @@ pub(crate) fn host_callback(
                         req,
                         rx,
                         eval_ctx,
+                        was_cached,
                     )
+                    .await
                 }
                 "list_resources_all" => {
                     let req: ListAllResourcesRequest =
@@ -271,7 +405,9 @@ pub(crate) fn host_callback(
                         req,
                         rx,
                         eval_ctx,
+                        was_cached,
                     )
+                    .await
                 }
                 "get_resource" => {
                     let req: GetResourceRequest =
@@ -307,7 +443,9 @@ pub(crate) fn host_callback(
                         req,
                         rx,
                         eval_ctx,
+                        was_cached,
                     )
+                    .await
                 }
                 _ => {
                     error!(namespace, operation, "unknown operation");
@@ -345,7 +483,9 @@ pub(crate) fn host_callback(
                     req,
                     rx,
                     eval_ctx,
+                    was_cached,
                 )
+                .await
             }
             "namespaces" => {
                 let req = CallbackRequestType::KubernetesListResourceAll {
@@ -372,7 +512,9 @@ pub(crate) fn host_callback(
                     req,
                     rx,
                     eval_ctx,
+                    was_cached,
                 )
+                .await
             }
             "services" => {
                 let req = CallbackRequestType::KubernetesListResourceAll {
@@ -399,7 +541,9 @@ pub(crate) fn host_callback(
                     req,
                     rx,
                     eval_ctx,
+                    was_cached,
                 )
+                .await
             }
             _ => {
                 error!("unknown namespace: {}", namespace);
@@ -413,13 +557,14 @@ pub(crate) fn host_callback(
     }
 }
 
-fn send_request_and_wait_for_response(
+async fn send_request_and_wait_for_response(
     policy_id: &str,
     binding: &str,
     operation: &str,
     req: CallbackRequest,
     rx: Receiver<Result<CallbackResponse>>,
     eval_ctx: &EvaluationContext,
+    was_cached: &mut bool,
 ) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
     let cb_channel: mpsc::Sender<CallbackRequest> = if let Some(c) =
         eval_ctx.callback_channel.clone()
@@ -435,15 +580,19 @@ fn send_request_and_wait_for_response(
         ))
     }?;
 
+    // fail fast when the callback handler is lagging behind
     let send_result = cb_channel.try_send(req);
     if let Err(e) = send_result {
         return Err(format!("Error sending request over callback channel: {e:?}").into());
     }
 
     // wait for the response
-    match rx.blocking_recv() {
+    match rx.await {
         Ok(msg) => match msg {
-            Ok(resp) => Ok(resp.payload),
+            Ok(resp) => {
+                *was_cached = resp.was_cached;
+                Ok(resp.payload)
+            }
             Err(e) => {
                 error!(
                     policy_id,
@@ -467,3 +616,33 @@ fn send_request_and_wait_for_response(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("kubewarden", "oci", "v1/manifest_digest", "oci")]
+    #[case("kubewarden", "oci", "v2/verify", "sigstore")]
+    #[case("kubewarden", "net", "v1/dns_lookup_host", "net")]
+    #[case("kubewarden", "kubernetes", "get_resource", "kubernetes")]
+    #[case("kubernetes", "ingresses", "list", "kubernetes")]
+    fn capability_names(
+        #[case] binding: &str,
+        #[case] namespace: &str,
+        #[case] operation: &str,
+        #[case] expected: &str,
+    ) {
+        assert_eq!(expected, capability_name(binding, namespace, operation));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn block_on_hands_over_the_runtime_worker() {
+        let (tx, rx) = oneshot::channel();
+        // the task can run only once the single worker of the runtime is handed over
+        tokio::spawn(async move { tx.send(42).unwrap() });
+
+        assert_eq!(42, block_on(rx).unwrap());
+    }
+}
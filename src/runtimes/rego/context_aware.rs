@@ -24,7 +24,7 @@ pub(crate) enum KubernetesContext {
 ///
 /// The resources are returned based on the actual RBAC privileges of the client
 /// used by the runtime.
-pub(crate) fn get_allowed_resources(
+pub(crate) async fn get_allowed_resources(
     callback_channel: &mpsc::Sender<CallbackRequest>,
     allowed_resources: &BTreeSet<ContextAwareResource>,
 ) -> Result<BTreeMap<ContextAwareResource, ObjectList<kube::core::DynamicObject>>> {
@@ -32,14 +32,14 @@ pub(crate) fn get_allowed_resources(
         BTreeMap::new();
 
     for resource in allowed_resources {
-        let resource_list = get_all_resources_by_type(callback_channel, resource)?;
+        let resource_list = get_all_resources_by_type(callback_channel, resource).await?;
         kube_resources.insert(resource.to_owned(), resource_list);
     }
 
     Ok(kube_resources)
 }
 
-fn get_all_resources_by_type(
+async fn get_all_resources_by_type(
     callback_channel: &mpsc::Sender<CallbackRequest>,
     resource_type: &ContextAwareResource,
 ) -> Result<ObjectList<kube::core::DynamicObject>> {
@@ -50,19 +50,19 @@ fn get_all_resources_by_type(
         field_selector: None,
     };
 
-    let response = make_request_via_callback_channel(req_type, callback_channel)?;
+    let response = make_request_via_callback_channel(req_type, callback_channel).await?;
     serde_json::from_slice::<ObjectList<kube::core::DynamicObject>>(&response.payload)
         .map_err(RegoRuntimeError::CallbackConvertList)
 }
 
 /// For each allowed resource, check if the "list all resources" result changed since the given instant
-pub(crate) fn have_allowed_resources_changed_since_instant(
+pub(crate) async fn have_allowed_resources_changed_since_instant(
     callback_channel: &mpsc::Sender<CallbackRequest>,
     allowed_resources: &BTreeSet<ContextAwareResource>,
     since: tokio::time::Instant,
 ) -> Result<bool> {
     for resource in allowed_resources {
-        if has_resource_changed_since(callback_channel, resource, since)? {
+        if has_resource_changed_since(callback_channel, resource, since).await? {
             return Ok(true);
         }
     }
@@ -73,7 +73,7 @@ pub(crate) fn have_allowed_resources_changed_since_instant(
 /// Check if the "list all resources" result changed since the given instant
 /// Note: this function doesn't take label_selector and field_selector into account because
 /// it's used only by gatekeeper policies, which don't use these selectors.
-fn has_resource_changed_since(
+async fn has_resource_changed_since(
     callback_channel: &mpsc::Sender<CallbackRequest>,
     resource_type: &ContextAwareResource,
     since: tokio::time::Instant,
@@ -86,14 +86,14 @@ fn has_resource_changed_since(
         since,
     };
 
-    let response = make_request_via_callback_channel(req_type, callback_channel)?;
+    let response = make_request_via_callback_channel(req_type, callback_channel).await?;
     serde_json::from_slice::<bool>(&response.payload).map_err(RegoRuntimeError::CallbackConvertBool)
 }
 
 /// Creates a map that has ContextAwareResource as key, and its plural name as value.
 /// For example, the key for {`apps/v1`, `Deployment`} will have `deployments` as value.
 /// The map is built by making request via the given callback channel.
-pub(crate) fn get_plural_names(
+pub(crate) async fn get_plural_names(
     callback_channel: &mpsc::Sender<CallbackRequest>,
     allowed_resources: &BTreeSet<ContextAwareResource>,
 ) -> Result<BTreeMap<ContextAwareResource, String>> {
@@ -105,7 +105,7 @@ pub(crate) fn get_plural_names(
             kind: resource.kind.to_owned(),
         };
 
-        let response = make_request_via_callback_channel(req_type, callback_channel)?;
+        let response = make_request_via_callback_channel(req_type, callback_channel).await?;
         let plural_name = serde_json::from_slice::<String>(&response.payload)
             .map_err(RegoRuntimeError::CallbackGetPluralName)?;
 
@@ -117,7 +117,7 @@ pub(crate) fn get_plural_names(
 
 /// Internal helper function that sends a request over the callback channel and returns the
 /// response
-fn make_request_via_callback_channel(
+async fn make_request_via_callback_channel(
     request_type: CallbackRequestType,
     callback_channel: &mpsc::Sender<CallbackRequest>,
 ) -> Result<CallbackResponse> {
@@ -130,7 +130,7 @@ fn make_request_via_callback_channel(
         .try_send(req)
         .map_err(|e| RegoRuntimeError::CallbackSend(e.to_string()))?;
 
-    match rx.blocking_recv() {
+    match rx.await {
         Ok(msg) => msg.map_err(RegoRuntimeError::CallbackRequest),
         Err(e) => Err(RegoRuntimeError::CallbackResponse(e.to_string())),
     }
@@ -210,19 +210,18 @@ pub(crate) mod tests {
             let services_list = object_list_from_dynamic_objects(&services).unwrap();
             let callback_response = CallbackResponse {
                 payload: serde_json::to_vec(&services_list).unwrap(),
+                was_cached: false,
             };
 
             req.response_channel.send(Ok(callback_response)).unwrap();
         });
 
-        tokio::task::spawn_blocking(move || {
-            let actual = get_all_resources_by_type(&callback_tx, &resource).unwrap();
-            let actual_json = serde_json::to_value(actual).unwrap();
-            let expected_json = serde_json::to_value(services_list).unwrap();
-            assert_json_eq!(actual_json, expected_json);
-        })
-        .await
-        .unwrap();
+        let actual = get_all_resources_by_type(&callback_tx, &resource)
+            .await
+            .unwrap();
+        let actual_json = serde_json::to_value(actual).unwrap();
+        let expected_json = serde_json::to_value(services_list).unwrap();
+        assert_json_eq!(actual_json, expected_json);
     }
 
     #[tokio::test(flavor = "multi_thread")]
@@ -259,17 +258,14 @@ pub(crate) mod tests {
 
             let callback_response = CallbackResponse {
                 payload: serde_json::to_vec(&plural_name).unwrap(),
+                was_cached: false,
             };
 
             req.response_channel.send(Ok(callback_response)).unwrap();
         });
 
-        tokio::task::spawn_blocking(move || {
-            let actual = get_plural_names(&callback_tx, &resources).unwrap();
-            assert_eq!(actual, expected_names);
-        })
-        .await
-        .unwrap();
+        let actual = get_plural_names(&callback_tx, &resources).await.unwrap();
+        assert_eq!(actual, expected_names);
     }
     #[rstest]
     #[case(
@@ -335,20 +331,17 @@ pub(crate) mod tests {
 
                 let callback_response = CallbackResponse {
                     payload: serde_json::to_vec(&changed).unwrap(),
+                    was_cached: false,
                 };
 
                 req.response_channel.send(Ok(callback_response)).unwrap();
             }
         });
 
-        tokio::task::spawn_blocking(move || {
-            let resources = resources_with_change_status.keys().cloned().collect();
-            let actual =
-                have_allowed_resources_changed_since_instant(&callback_tx, &resources, since)
-                    .unwrap();
-            assert_json_eq!(expected, actual);
-        })
-        .await
-        .unwrap();
+        let resources = resources_with_change_status.keys().cloned().collect();
+        let actual = have_allowed_resources_changed_since_instant(&callback_tx, &resources, since)
+            .await
+            .unwrap();
+        assert_json_eq!(expected, actual);
     }
 }
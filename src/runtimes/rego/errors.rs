@@ -22,6 +22,9 @@ pub enum RegoRuntimeError {
     #[error("cannot perform a request via callback channel: {0}")]
     CallbackRequest(#[source] wasmtime::Error),
 
+    #[error("evaluation cancelled while fetching the Kubernetes resources")]
+    KubernetesContextCancelled,
+
     #[error("get plural name failure, cannot convert callback response: {0}")]
     CallbackGetPluralName(#[source] serde_json::Error),
 
@@ -55,6 +58,12 @@ pub enum RegoRuntimeError {
     #[error("invalid response from policy: {0}")]
     InvalidResponseWithError(#[source] serde_json::Error),
 
+    #[error("invalid patch returned by the policy: {0}")]
+    InvalidPatch(#[source] crate::errors::GuestPatchError),
+
+    #[error("cannot build the admission response: {0}")]
+    InvalidAdmissionResponse(#[source] crate::errors::ResponseError),
+
     #[error("cannot allocate Rego evaluator: {0}")]
     EvaluatorError(String),
 
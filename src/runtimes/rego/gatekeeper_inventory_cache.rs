@@ -70,7 +70,7 @@ impl GateKeeperInventoryCache {
     /// The inventory is computed and serialized only if it's not already present in the cache.
     /// The inventory is also recreated if the set of resources has changed since the time
     /// the inventory was computed
-    pub fn get_inventory(
+    pub async fn get_inventory(
         &self,
         callback_channel: &mpsc::Sender<CallbackRequest>,
         ctx_aware_resources: &BTreeSet<ContextAwareResource>,
@@ -80,14 +80,20 @@ impl GateKeeperInventoryCache {
             inventories.get(ctx_aware_resources).cloned()
         };
         let inventory = match inventory {
-            None => self.create_and_register_inventory(ctx_aware_resources, callback_channel),
+            None => {
+                self.create_and_register_inventory(ctx_aware_resources, callback_channel)
+                    .await
+            }
             Some(cached_inventory) => {
                 if have_allowed_resources_changed_since_instant(
                     callback_channel,
                     ctx_aware_resources,
                     cached_inventory.cache_time,
-                )? {
+                )
+                .await?
+                {
                     self.create_and_register_inventory(ctx_aware_resources, callback_channel)
+                        .await
                 } else {
                     Ok(cached_inventory)
                 }
@@ -98,13 +104,14 @@ impl GateKeeperInventoryCache {
 
     /// Create the inventory and register it in the cache. A prior entry of the inventory is
     /// automatically removed from the cache.
-    fn create_and_register_inventory(
+    async fn create_and_register_inventory(
         &self,
         ctx_aware_resources: &BTreeSet<ContextAwareResource>,
         callback_channel: &mpsc::Sender<CallbackRequest>,
     ) -> Result<Arc<CachedInventory>> {
         let now = Instant::now();
-        let cluster_resources = get_allowed_resources(callback_channel, ctx_aware_resources)?;
+        let cluster_resources =
+            get_allowed_resources(callback_channel, ctx_aware_resources).await?;
         let inventory = GatekeeperInput {
             inventory: GatekeeperInventory::new(&cluster_resources)?,
         };
@@ -169,6 +176,7 @@ pub(crate) mod tests {
                         assert!(field_selector.is_none());
                         CallbackResponse {
                             payload: serde_json::to_vec(&services_list).unwrap(),
+                            was_cached: false,
                         }
                     }
                     _ => {
@@ -180,32 +188,29 @@ pub(crate) mod tests {
             }
         });
 
-        tokio::task::spawn_blocking(move || {
-            {
-                // ensure the cache is empty
-                let mut inventories = GATEKEEPER_INVENTORY_CACHE.inventories.write().unwrap();
-                inventories.clear();
-            }
+        {
+            // ensure the cache is empty
+            let mut inventories = GATEKEEPER_INVENTORY_CACHE.inventories.write().unwrap();
+            inventories.clear();
+        }
 
-            let resources: BTreeSet<ContextAwareResource> = BTreeSet::from([resource]);
-
-            let cached_inventory = GATEKEEPER_INVENTORY_CACHE
-                .get_inventory(&callback_tx, &resources)
-                .unwrap();
-            assert!(!cached_inventory.is_empty());
-
-            {
-                let inventories = GATEKEEPER_INVENTORY_CACHE.inventories.read().unwrap();
-                let cached_input_json = inventories.get(&resources).unwrap();
-                let actual_inventory =
-                    serde_json::from_slice::<GatekeeperInput>(&cached_input_json.data)
-                        .unwrap()
-                        .inventory;
-                assert_eq!(expected_inventory, actual_inventory);
-            }
-        })
-        .await
-        .unwrap();
+        let resources: BTreeSet<ContextAwareResource> = BTreeSet::from([resource]);
+
+        let cached_inventory = GATEKEEPER_INVENTORY_CACHE
+            .get_inventory(&callback_tx, &resources)
+            .await
+            .unwrap();
+        assert!(!cached_inventory.is_empty());
+
+        {
+            let inventories = GATEKEEPER_INVENTORY_CACHE.inventories.read().unwrap();
+            let cached_input_json = inventories.get(&resources).unwrap();
+            let actual_inventory =
+                serde_json::from_slice::<GatekeeperInput>(&cached_input_json.data)
+                    .unwrap()
+                    .inventory;
+            assert_eq!(expected_inventory, actual_inventory);
+        }
     }
 
     #[tokio::test(flavor = "multi_thread")]
@@ -255,6 +260,7 @@ pub(crate) mod tests {
 
                         CallbackResponse {
                             payload: serde_json::to_vec(&false).unwrap(),
+                            was_cached: false,
                         }
                     }
                     _ => {
@@ -266,14 +272,11 @@ pub(crate) mod tests {
             }
         });
 
-        tokio::task::spawn_blocking(move || {
-            let actual = GATEKEEPER_INVENTORY_CACHE
-                .get_inventory(&callback_tx, &resources)
-                .unwrap();
-            assert_eq!(expected_cached_inventory.data, actual);
-        })
-        .await
-        .unwrap();
+        let actual = GATEKEEPER_INVENTORY_CACHE
+            .get_inventory(&callback_tx, &resources)
+            .await
+            .unwrap();
+        assert_eq!(expected_cached_inventory.data, actual);
     }
 
     #[tokio::test(flavor = "multi_thread")]
@@ -328,6 +331,7 @@ pub(crate) mod tests {
                         assert!(field_selector.is_none());
                         CallbackResponse {
                             payload: serde_json::to_vec(&services_list).unwrap(),
+                            was_cached: false,
                         }
                     }
                     CallbackRequestType::HasKubernetesListResourceAllResultChangedSinceInstant {
@@ -344,6 +348,7 @@ pub(crate) mod tests {
 
                         CallbackResponse {
                             payload: serde_json::to_vec(&true).unwrap(),
+                            was_cached: false,
                         }
                     }
                     _ => {
@@ -355,27 +360,24 @@ pub(crate) mod tests {
             }
         });
 
-        tokio::task::spawn_blocking(move || {
-            let actual = GATEKEEPER_INVENTORY_CACHE
-                .get_inventory(&callback_tx, &resources)
-                .unwrap();
-            assert!(actual != stale_cached_inventory.data);
-            let actual_inventory = serde_json::from_slice::<GatekeeperInput>(&actual).unwrap();
-            assert_eq!(expected_inventory, actual_inventory.inventory);
-
-            {
-                let inventories = GATEKEEPER_INVENTORY_CACHE.inventories.read().unwrap();
-                let actual_inventory = inventories.get(&resources).unwrap();
-                assert!(actual_inventory.cache_time > stale_cached_inventory.cache_time);
-            }
+        let actual = GATEKEEPER_INVENTORY_CACHE
+            .get_inventory(&callback_tx, &resources)
+            .await
+            .unwrap();
+        assert!(actual != stale_cached_inventory.data);
+        let actual_inventory = serde_json::from_slice::<GatekeeperInput>(&actual).unwrap();
+        assert_eq!(expected_inventory, actual_inventory.inventory);
 
-            {
-                let inventories = GATEKEEPER_INVENTORY_CACHE.inventories.read().unwrap();
-                let actual_inventory = inventories.get(&resources).unwrap();
-                assert!(actual_inventory.cache_time > stale_cached_inventory.cache_time);
-            }
-        })
-        .await
-        .unwrap();
+        {
+            let inventories = GATEKEEPER_INVENTORY_CACHE.inventories.read().unwrap();
+            let actual_inventory = inventories.get(&resources).unwrap();
+            assert!(actual_inventory.cache_time > stale_cached_inventory.cache_time);
+        }
+
+        {
+            let inventories = GATEKEEPER_INVENTORY_CACHE.inventories.read().unwrap();
+            let actual_inventory = inventories.get(&resources).unwrap();
+            assert!(actual_inventory.cache_time > stale_cached_inventory.cache_time);
+        }
     }
 }
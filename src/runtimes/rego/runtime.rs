@@ -1,4 +1,5 @@
 use burrego::errors::BurregoError;
+use kubewarden_policy_sdk::response::ValidationResponse as PolicyValidationResponse;
 use kubewarden_policy_sdk::settings::SettingsValidationResponse;
 use serde::Deserialize;
 use serde_json::json;
@@ -9,20 +10,59 @@ use crate::runtimes::rego::{
 };
 use crate::{
     admission_request,
-    admission_response::{AdmissionResponse, AdmissionResponseStatus},
+    admission_response::{
+        AdmissionResponse, AdmissionResponseStatus, CauseType, PolicyResponseOutcome, StatusCause,
+    },
+    patch::{ArrayKeys, GuestPatch, ProtectedPaths},
     policy_evaluator::{PolicySettings, RegoPolicyExecutionMode, ValidateRequest},
 };
 
 pub(crate) struct Runtime<'a>(pub(crate) &'a mut Stack);
 
+/// A violation reported by a Gatekeeper policy
+#[derive(Debug, Deserialize)]
+struct Violation {
+    msg: Option<String>,
+    /// Free-form details of the violation
+    details: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Violations {
+    result: Vec<Violation>,
+}
+
+impl Violation {
+    /// The field-level cause of the violation. This is provided only when the `details`
+    /// of the violation point at the offending field, like
+    /// `{"field": "spec.containers[0].image", "reason": "FieldValueInvalid"}`.
+    /// The `reason` is optional, it's ignored when it's not a string
+    fn cause(&self) -> Option<StatusCause> {
+        let details = self.details.as_ref()?;
+        let field = details.get("field")?.as_str()?;
+        let reason = details
+            .get("reason")
+            .and_then(|reason| serde_json::from_value::<CauseType>(reason.clone()).ok());
+
+        Some(StatusCause {
+            reason,
+            message: self.msg.clone(),
+            field: Some(field.to_string()),
+        })
+    }
+}
+
 impl Runtime<'_> {
     pub fn validate(
         &mut self,
         settings: &PolicySettings,
         request: &ValidateRequest,
         ctx_data: &context_aware::KubernetesContext,
+        protected_paths: &ProtectedPaths,
+        array_keys: &ArrayKeys,
     ) -> AdmissionResponse {
         let uid = request.uid();
+        self.0.interrupted = false;
 
         // OPA and Gatekeeper expect arguments in different ways
         let burrego_evaluation = match self.0.policy_execution_mode {
@@ -55,18 +95,18 @@ impl Runtime<'_> {
                             .and_then(|r| r.get("response"));
 
                         match evaluation_result {
-                            Some(evaluation_result) => {
-                                match serde_json::from_value(evaluation_result.clone()) {
-                                    Ok(evaluation_result) => AdmissionResponse {
-                                        uid: uid.to_string(),
-                                        ..evaluation_result
-                                    },
-                                    Err(err) => AdmissionResponse::reject_internal_server_error(
-                                        uid.to_string(),
-                                        err.to_string(),
-                                    ),
-                                }
-                            }
+                            Some(evaluation_result) => build_opa_admission_response(
+                                request,
+                                evaluation_result,
+                                protected_paths,
+                                array_keys,
+                            )
+                            .unwrap_or_else(|err| {
+                                AdmissionResponse::reject_internal_server_error(
+                                    uid.to_string(),
+                                    err.to_string(),
+                                )
+                            }),
                             None => AdmissionResponse::reject_internal_server_error(
                                 uid.to_string(),
                                 "cannot interpret OPA policy result".to_string(),
@@ -81,15 +121,6 @@ impl Runtime<'_> {
                         // reason. If no violations are reported, the
                         // request is accepted. Otherwise it is
                         // rejected.
-                        #[derive(Debug, Deserialize)]
-                        struct Violation {
-                            msg: Option<String>,
-                        }
-                        #[derive(Debug, Default, Deserialize)]
-                        struct Violations {
-                            result: Vec<Violation>,
-                        }
-
                         let violations: Violations = evaluation_result
                             .get(0)
                             .ok_or_else(|| RegoRuntimeError::InvalidResponse)
@@ -122,6 +153,13 @@ impl Runtime<'_> {
                                 }),
                                 ..Default::default()
                             }
+                            .with_causes(
+                                violations
+                                    .result
+                                    .iter()
+                                    .filter_map(Violation::cause)
+                                    .collect(),
+                            )
                         }
                     }
                 }
@@ -133,8 +171,11 @@ impl Runtime<'_> {
                 );
                 if matches!(
                     err,
-                    burrego::errors::BurregoError::ExecutionDeadlineExceeded
+                    BurregoError::ExecutionDeadlineExceeded
+                        | BurregoError::FuelExhausted
+                        | BurregoError::MemoryLimitExceeded(_)
                 ) {
+                    self.0.interrupted = true;
                     if let Err(reset_error) = self.0.evaluator.reset() {
                         error!(?reset_error, "cannot reset burrego evaluator, further invocations might fail or behave not properly");
                     }
@@ -218,3 +259,181 @@ impl Runtime<'_> {
         }
     }
 }
+
+/// Build the `AdmissionResponse` out of the one returned by an OPA policy. The patch,
+/// when present, can be either a JSON Patch or a merge patch, see [`GuestPatch::from_response`].
+///
+/// The response is then handled like the ones of waPC and WASI policies, see
+/// [`AdmissionResponse::from_policy_validation_response_and_patch`].
+fn build_opa_admission_response(
+    request: &ValidateRequest,
+    evaluation_result: &serde_json::Value,
+    protected_paths: &ProtectedPaths,
+    array_keys: &ArrayKeys,
+) -> Result<AdmissionResponse, RegoRuntimeError> {
+    let guest_patch =
+        GuestPatch::from_response(evaluation_result).map_err(RegoRuntimeError::InvalidPatch)?;
+
+    let mut evaluation_result = evaluation_result.clone();
+    if let Some(evaluation_result) = evaluation_result.as_object_mut() {
+        evaluation_result.remove("patch");
+        evaluation_result.remove("patchType");
+    }
+    let opa_response: AdmissionResponse = serde_json::from_value(evaluation_result)
+        .map_err(RegoRuntimeError::InvalidResponseWithError)?;
+
+    let pol_val_resp = PolicyValidationResponse {
+        accepted: opa_response.allowed,
+        message: opa_response
+            .status
+            .as_ref()
+            .and_then(|status| status.message.clone()),
+        code: opa_response.status.as_ref().and_then(|status| status.code),
+        mutated_object: None,
+        audit_annotations: opa_response.audit_annotations,
+        warnings: opa_response.warnings,
+    };
+    let request_object = match request {
+        ValidateRequest::Raw(raw_req) => Some(raw_req),
+        ValidateRequest::AdmissionRequest(adm_req) => {
+            adm_req.object.as_ref().map(|object| &object.0)
+        }
+    };
+
+    let outcome = AdmissionResponse::from_policy_validation_response_and_patch(
+        request.uid().to_string(),
+        request_object,
+        &pol_val_resp,
+        guest_patch.as_ref(),
+        protected_paths,
+        array_keys,
+    )
+    .map_err(RegoRuntimeError::InvalidAdmissionResponse)?;
+
+    // The status returned by OPA can have more details than the message and the code,
+    // like the causes of the rejection. Keep it, unless the mutation has been rejected
+    match outcome {
+        PolicyResponseOutcome::Evaluated(admission_response) => Ok(AdmissionResponse {
+            status: opa_response.status,
+            ..admission_response
+        }),
+        PolicyResponseOutcome::MutationRejected(admission_response) => Ok(admission_response),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::no_details(json!({"msg": "denied"}), None)]
+    #[case::details_without_field(json!({"msg": "denied", "details": {"missing_labels": ["owner"]}}), None)]
+    #[case::field(
+        json!({"msg": "denied", "details": {"field": "metadata.labels.owner"}}),
+        Some(StatusCause {
+            reason: None,
+            message: Some("denied".to_string()),
+            field: Some("metadata.labels.owner".to_string()),
+        })
+    )]
+    #[case::field_and_reason(
+        json!({"msg": "denied", "details": {"field": "metadata.labels.owner", "reason": "FieldValueRequired"}}),
+        Some(StatusCause {
+            reason: Some(CauseType::FieldValueRequired),
+            message: Some("denied".to_string()),
+            field: Some("metadata.labels.owner".to_string()),
+        })
+    )]
+    #[case::unknown_reason(
+        json!({"msg": "denied", "details": {"field": "spec", "reason": "Boom"}}),
+        Some(StatusCause {
+            reason: Some(CauseType::Other("Boom".to_string())),
+            message: Some("denied".to_string()),
+            field: Some("spec".to_string()),
+        })
+    )]
+    #[case::invalid_reason(
+        json!({"msg": "denied", "details": {"field": "spec", "reason": 42}}),
+        Some(StatusCause {
+            reason: None,
+            message: Some("denied".to_string()),
+            field: Some("spec".to_string()),
+        })
+    )]
+    fn gatekeeper_violation_cause(
+        #[case] violation: serde_json::Value,
+        #[case] expected: Option<StatusCause>,
+    ) {
+        let violation: Violation = serde_json::from_value(violation).unwrap();
+
+        assert_eq!(expected, violation.cause());
+    }
+
+    fn delete_request() -> ValidateRequest {
+        let request: admission_request::AdmissionRequest = serde_json::from_value(json!({
+            "uid": "uid",
+            "kind": {"group": "", "version": "v1", "kind": "Pod"},
+            "resource": {"group": "", "version": "v1", "resource": "pods"},
+            "operation": "DELETE",
+            "userInfo": {},
+            "oldObject": {"metadata": {"name": "nginx"}}
+        }))
+        .unwrap();
+        ValidateRequest::AdmissionRequest(request)
+    }
+
+    #[test]
+    fn opa_mutation_on_delete_operation_is_not_allowed() {
+        let evaluation_result = json!({
+            "uid": "uid",
+            "allowed": true,
+            "patchType": "MergePatch",
+            "patch": {"metadata": {"labels": {"deleted": "true"}}}
+        });
+
+        let response = build_opa_admission_response(
+            &delete_request(),
+            &evaluation_result,
+            &ProtectedPaths::default(),
+            &ArrayKeys::default(),
+        )
+        .unwrap();
+
+        assert!(!response.allowed);
+        assert!(response.patch.is_none());
+        let status = response.status.unwrap();
+        assert_eq!(None, status.code);
+        assert!(status
+            .message
+            .unwrap()
+            .starts_with("Incoming object is null"));
+    }
+
+    #[test]
+    fn opa_rejection_keeps_status_details() {
+        let evaluation_result = json!({
+            "uid": "uid",
+            "allowed": false,
+            "status": {
+                "message": "denied",
+                "code": 400,
+                "details": {"causes": [{"field": "spec", "reason": "FieldValueInvalid"}]}
+            }
+        });
+
+        let response = build_opa_admission_response(
+            &ValidateRequest::Raw(json!({"spec": {}})),
+            &evaluation_result,
+            &ProtectedPaths::default(),
+            &ArrayKeys::default(),
+        )
+        .unwrap();
+
+        assert!(!response.allowed);
+        let status = response.status.unwrap();
+        assert_eq!(Some("denied".to_string()), status.message);
+        assert_eq!(Some(400), status.code);
+        assert_eq!(1, status.details.unwrap().causes.len());
+    }
+}
@@ -3,8 +3,10 @@ use tokio::sync::mpsc;
 
 use crate::{
     callback_requests::CallbackRequest,
-    policy_evaluator::RegoPolicyExecutionMode,
+    evaluation_context::EvaluationClock,
+    policy_evaluator::{CancellationHandle, RegoPolicyExecutionMode},
     policy_metadata::ContextAwareResource,
+    runtimes::callback::block_on,
     runtimes::rego::{
         context_aware,
         errors::{RegoRuntimeError, Result},
@@ -18,25 +20,64 @@ pub(crate) struct Stack {
     pub evaluator: burrego::Evaluator,
     pub entrypoint_id: i32,
     pub policy_execution_mode: RegoPolicyExecutionMode,
+    /// Set when the last evaluation has been interrupted
+    pub interrupted: bool,
+    cancellation: CancellationHandle,
 }
 
 impl Stack {
     /// Create a new `Stack` using a `StackPre` object
-    pub fn new_from_pre(stack_pre: &StackPre) -> Result<Self> {
+    pub fn new_from_pre(
+        stack_pre: &StackPre,
+        clock: EvaluationClock,
+        cancellation: CancellationHandle,
+    ) -> Result<Self> {
         let evaluator = stack_pre
-            .rehydrate()
+            .rehydrate(clock, &cancellation)
             .map_err(|e| RegoRuntimeError::EvaluatorError(e.to_string()))?;
         Ok(Self {
             evaluator,
             entrypoint_id: stack_pre.entrypoint_id,
             policy_execution_mode: stack_pre.policy_execution_mode.clone(),
+            interrupted: false,
+            cancellation,
         })
     }
 
+    /// Build the Kubernetes context to be given to the policy. This blocks the current
+    /// thread until all the Kubernetes resources are fetched via the callback channel,
+    /// see [`block_on`] for the runtime requirements and
+    /// [`build_kubernetes_context_async`](Stack::build_kubernetes_context_async) for the
+    /// non-blocking version.
     pub fn build_kubernetes_context(
         &self,
         callback_channel: Option<&mpsc::Sender<CallbackRequest>>,
         ctx_aware_resources_allow_list: &BTreeSet<ContextAwareResource>,
+    ) -> Result<context_aware::KubernetesContext> {
+        block_on(
+            self.build_kubernetes_context_async(callback_channel, ctx_aware_resources_allow_list),
+        )
+    }
+
+    /// Asynchronous version of [`build_kubernetes_context`](Stack::build_kubernetes_context).
+    /// The requests made via the callback channel are aborted when the evaluation is cancelled
+    pub async fn build_kubernetes_context_async(
+        &self,
+        callback_channel: Option<&mpsc::Sender<CallbackRequest>>,
+        ctx_aware_resources_allow_list: &BTreeSet<ContextAwareResource>,
+    ) -> Result<context_aware::KubernetesContext> {
+        self.cancellation
+            .run_until_cancelled(
+                self.fetch_kubernetes_context(callback_channel, ctx_aware_resources_allow_list),
+            )
+            .await
+            .unwrap_or(Err(RegoRuntimeError::KubernetesContextCancelled))
+    }
+
+    async fn fetch_kubernetes_context(
+        &self,
+        callback_channel: Option<&mpsc::Sender<CallbackRequest>>,
+        ctx_aware_resources_allow_list: &BTreeSet<ContextAwareResource>,
     ) -> Result<context_aware::KubernetesContext> {
         if ctx_aware_resources_allow_list.is_empty() {
             return Ok(context_aware::KubernetesContext::Empty);
@@ -47,16 +88,19 @@ impl Stack {
             Some(chan) => match self.policy_execution_mode {
                 RegoPolicyExecutionMode::Opa => {
                     let cluster_resources =
-                        context_aware::get_allowed_resources(chan, ctx_aware_resources_allow_list)?;
+                        context_aware::get_allowed_resources(chan, ctx_aware_resources_allow_list)
+                            .await?;
                     let plural_names_by_resource =
-                        context_aware::get_plural_names(chan, ctx_aware_resources_allow_list)?;
+                        context_aware::get_plural_names(chan, ctx_aware_resources_allow_list)
+                            .await?;
                     let inventory =
                         OpaInventory::new(&cluster_resources, &plural_names_by_resource)?;
                     Ok(context_aware::KubernetesContext::Opa(inventory))
                 }
                 RegoPolicyExecutionMode::Gatekeeper => {
                     let cached_inventory = GATEKEEPER_INVENTORY_CACHE
-                        .get_inventory(chan, ctx_aware_resources_allow_list)?;
+                        .get_inventory(chan, ctx_aware_resources_allow_list)
+                        .await?;
                     Ok(context_aware::KubernetesContext::Gatekeeper(
                         cached_inventory,
                     ))
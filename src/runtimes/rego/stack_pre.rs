@@ -1,5 +1,8 @@
-use crate::policy_evaluator::RegoPolicyExecutionMode;
-use crate::policy_evaluator_builder::EpochDeadlines;
+use std::sync::Arc;
+
+use crate::evaluation_context::EvaluationClock;
+use crate::policy_evaluator::{CancellationHandle, RegoPolicyExecutionMode};
+use crate::policy_evaluator_builder::{EpochDeadlines, FuelLimits, ResourceLimits};
 use crate::runtimes::rego::errors::{RegoRuntimeError, Result};
 
 /// This struct allows to follow the `StackPre -> Stack`
@@ -18,6 +21,8 @@ pub(crate) struct StackPre {
     engine: wasmtime::Engine,
     module: wasmtime::Module,
     epoch_deadlines: Option<EpochDeadlines>,
+    fuel_limits: Option<FuelLimits>,
+    resource_limits: ResourceLimits,
     pub entrypoint_id: i32,
     pub policy_execution_mode: RegoPolicyExecutionMode,
 }
@@ -27,6 +32,8 @@ impl StackPre {
         engine: wasmtime::Engine,
         module: wasmtime::Module,
         epoch_deadlines: Option<EpochDeadlines>,
+        fuel_limits: Option<FuelLimits>,
+        resource_limits: ResourceLimits,
         entrypoint_id: i32,
         policy_execution_mode: RegoPolicyExecutionMode,
     ) -> Self {
@@ -34,21 +41,35 @@ impl StackPre {
             engine,
             module,
             epoch_deadlines,
+            fuel_limits,
+            resource_limits,
             entrypoint_id,
             policy_execution_mode,
         }
     }
 
-    /// Create a fresh `burrego::Evaluator`
-    pub(crate) fn rehydrate(&self) -> Result<burrego::Evaluator> {
+    /// Create a fresh `burrego::Evaluator`, the Rego builtins reading the current
+    /// time use the given clock. The evaluations are interrupted once the
+    /// `cancellation` is triggered
+    pub(crate) fn rehydrate(
+        &self,
+        clock: EvaluationClock,
+        cancellation: &CancellationHandle,
+    ) -> Result<burrego::Evaluator> {
         let mut builder = burrego::EvaluatorBuilder::default()
             .engine(&self.engine)
             .module(self.module.clone())
-            .host_callbacks(crate::runtimes::rego::new_host_callbacks());
+            .host_callbacks(crate::runtimes::rego::new_host_callbacks())
+            .clock(Arc::new(clock))
+            .cancellation_flag(cancellation.flag())
+            .resource_limits(self.resource_limits);
 
         if let Some(deadlines) = self.epoch_deadlines {
             builder = builder.enable_epoch_interruptions(deadlines.wapc_func);
         }
+        if let Some(fuel_limits) = self.fuel_limits {
+            builder = builder.enable_fuel_metering(fuel_limits.init, fuel_limits.func);
+        }
         let evaluator = builder
             .build()
             .map_err(RegoRuntimeError::RegoEngineBuilder)?;
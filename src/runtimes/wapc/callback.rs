@@ -1,9 +1,13 @@
+use std::future::Future;
+use std::pin::Pin;
 use std::sync::Arc;
 
 use anyhow::Result;
 use tracing::debug;
 
 use crate::evaluation_context::EvaluationContext;
+use crate::policy_evaluator::evaluation_report::HostCallbackRecorder;
+use crate::policy_evaluator::CancellationHandle;
 
 /// A host callback function that can be used by the waPC runtime.
 type HostCallback = Box<
@@ -18,15 +22,71 @@ type HostCallback = Box<
         + Sync,
 >;
 
+/// A host callback function that can be used by the asynchronous waPC runtime.
+type HostCallbackAsync = Box<
+    dyn Fn(
+            u64,
+            String,
+            String,
+            String,
+            Vec<u8>,
+        ) -> Pin<
+            Box<
+                dyn Future<Output = Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>>>
+                    + Send,
+            >,
+        > + Send
+        + Sync,
+>;
+
 /// Returns a host callback function that can be used by the waPC runtime.
 /// The callback function will be able to access the `EvaluationContext` instance.
-pub(crate) fn new_host_callback(eval_ctx: Arc<EvaluationContext>) -> HostCallback {
+pub(crate) fn new_host_callback(
+    eval_ctx: Arc<EvaluationContext>,
+    recorder: Arc<HostCallbackRecorder>,
+    cancellation: CancellationHandle,
+) -> HostCallback {
     Box::new({
         move |wapc_id, binding, namespace, operation, payload| {
             debug!(wapc_id, "invoking host_callback");
             crate::runtimes::callback::host_callback(
-                binding, namespace, operation, payload, &eval_ctx,
+                binding,
+                namespace,
+                operation,
+                payload,
+                &eval_ctx,
+                &recorder,
+                &cancellation,
             )
         }
     })
 }
+
+/// Returns a host callback function that can be used by the asynchronous waPC runtime.
+/// The callback function will be able to access the `EvaluationContext` instance.
+pub(crate) fn new_host_callback_async(
+    eval_ctx: Arc<EvaluationContext>,
+    recorder: Arc<HostCallbackRecorder>,
+    cancellation: CancellationHandle,
+) -> HostCallbackAsync {
+    Box::new({
+        move |wapc_id, binding, namespace, operation, payload| {
+            debug!(wapc_id, "invoking host_callback");
+            let eval_ctx = eval_ctx.clone();
+            let recorder = recorder.clone();
+            let cancellation = cancellation.clone();
+            Box::pin(async move {
+                crate::runtimes::callback::host_callback_async(
+                    &binding,
+                    &namespace,
+                    &operation,
+                    &payload,
+                    &eval_ctx,
+                    &recorder,
+                    &cancellation,
+                )
+                .await
+            })
+        }
+    })
+}
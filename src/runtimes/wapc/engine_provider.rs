@@ -0,0 +1,217 @@
+use std::sync::{Arc, Mutex};
+
+use burrego::{MemoryLimitExceeded, ResourceLimiter};
+use wasi_common::sync::WasiCtxBuilder;
+use wasi_common::WasiCtx;
+use wasmtime::{Instance, Store, TypedFunc};
+
+use crate::policy_evaluator::CancellationHandle;
+use crate::runtimes::wapc::{
+    errors::{Result, WapcRuntimeError},
+    stack_pre::{GuestCode, StackPre},
+};
+
+/// The functions defining the initialization code of a waPC module. They are
+/// invoked, when exported, right after the module is instantiated
+const INIT_FUNCTIONS: [&str; 2] = ["_start", "wapc_init"];
+
+/// The function invoked by the host to run a waPC function of the guest
+const GUEST_CALL_FUNCTION: &str = "__guest_call";
+
+/// The parameters of the `__guest_call` function: the length of the name of the
+/// waPC function to be invoked and the length of its payload
+type GuestCallParams = (i32, i32);
+
+/// The error type of the `wapc` engine provider traits
+type ProviderResult<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// The state of the waPC protocol, owned by the `wapc` host
+pub(crate) enum ModuleState {
+    Sync(Arc<wapc::ModuleState>),
+    Async(Arc<wapc::ModuleStateAsync>),
+}
+
+/// The data associated with the `wasmtime::Store` of a waPC module
+pub(crate) struct Context {
+    pub(crate) wasi_ctx: WasiCtx,
+    pub(crate) cancellation: CancellationHandle,
+    pub(crate) limiter: ResourceLimiter,
+    /// Set by the `wapc` host when the module is initialized
+    pub(crate) module_state: Option<ModuleState>,
+}
+
+/// Statistics about the last guest code run by an [`EngineProvider`]. The provider
+/// is owned by the `wapc` host, these are shared with the [`WapcStack`](super::WapcStack)
+#[derive(Default)]
+pub(crate) struct InvocationStats {
+    /// The amount of fuel consumed. This is `None` when fuel metering is not enabled
+    pub(crate) fuel_consumed: Option<u64>,
+    /// The peak size, in bytes, of the linear memory of the module
+    pub(crate) peak_memory: usize,
+    /// Set when the guest has been interrupted by the host
+    pub(crate) interruption: Option<WapcRuntimeError>,
+}
+
+/// A `wapc` engine provider running the waPC module on top of wasmtime, with the
+/// execution and the resource limits of the [`StackPre`] enforced
+pub(crate) struct EngineProvider {
+    stack_pre: StackPre,
+    store: Store<Context>,
+    guest_call: Option<TypedFunc<GuestCallParams, i32>>,
+    stats: Arc<Mutex<InvocationStats>>,
+}
+
+impl EngineProvider {
+    pub(crate) fn new(
+        stack_pre: &StackPre,
+        cancellation: CancellationHandle,
+        stats: Arc<Mutex<InvocationStats>>,
+    ) -> Self {
+        let ctx = Context {
+            wasi_ctx: WasiCtxBuilder::new().inherit_stdio().build(),
+            cancellation,
+            limiter: ResourceLimiter::new(stack_pre.resource_limits()),
+            module_state: None,
+        };
+
+        Self {
+            stack_pre: stack_pre.to_owned(),
+            store: stack_pre.build_store(ctx),
+            guest_call: None,
+            stats,
+        }
+    }
+
+    /// Run the initialization code of the module and look up its `__guest_call` function.
+    ///
+    /// When the stack is synchronous, this never awaits: the future can be driven
+    /// by a blocking executor.
+    async fn init_instance(&mut self, module_state: ModuleState) -> Result<()> {
+        self.store.data_mut().module_state = Some(module_state);
+        self.stack_pre
+            .set_execution_limits(&mut self.store, GuestCode::Init)?;
+
+        let instance = if self.stack_pre.async_support() {
+            self.stack_pre.rehydrate_async(&mut self.store).await?
+        } else {
+            self.stack_pre.rehydrate(&mut self.store)?
+        };
+        let init_result = self.run_init_functions(&instance).await;
+        self.record_stats(GuestCode::Init, init_result.as_ref().err());
+        init_result.map_err(WapcRuntimeError::GuestInit)?;
+
+        let guest_call = instance
+            .get_typed_func::<GuestCallParams, i32>(&mut self.store, GUEST_CALL_FUNCTION)
+            .map_err(WapcRuntimeError::WasmMissingGuestCallFn)?;
+        self.guest_call = Some(guest_call);
+
+        Ok(())
+    }
+
+    async fn run_init_functions(&mut self, instance: &Instance) -> wasmtime::Result<()> {
+        for name in INIT_FUNCTIONS {
+            let Some(init_fn) = instance.get_func(&mut self.store, name) else {
+                continue;
+            };
+            let init_fn = init_fn.typed::<(), ()>(&self.store)?;
+            let init_result = if self.stack_pre.async_support() {
+                init_fn.call_async(&mut self.store, ()).await
+            } else {
+                init_fn.call(&mut self.store, ())
+            };
+
+            if let Err(e) = init_result {
+                // TinyGo programs might terminate their `_start` function via `proc_exit(0)`
+                if matches!(e.downcast_ref::<wasi_common::I32Exit>(), Some(exit) if exit.0 == 0) {
+                    continue;
+                }
+                return Err(e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Invoke the `__guest_call` function of the module
+    ///
+    /// When the stack is synchronous, this never awaits: the future can be driven
+    /// by a blocking executor.
+    async fn call_guest(&mut self, op_length: i32, msg_length: i32) -> ProviderResult<i32> {
+        let guest_call = self.guest_call.ok_or(WapcRuntimeError::NotInitialized)?;
+        self.stack_pre
+            .set_execution_limits(&mut self.store, GuestCode::Func)?;
+
+        let call_result = if self.stack_pre.async_support() {
+            guest_call
+                .call_async(&mut self.store, (op_length, msg_length))
+                .await
+        } else {
+            guest_call.call(&mut self.store, (op_length, msg_length))
+        };
+        self.record_stats(GuestCode::Func, call_result.as_ref().err());
+
+        call_result.map_err(Into::into)
+    }
+
+    /// Record the statistics of the guest code that has just been run.
+    ///
+    /// The `wapc` host turns the errors of the guest into plain messages, hence the
+    /// interruptions caused by the host are recorded too: the stack reports them instead
+    fn record_stats(&self, guest_code: GuestCode, err: Option<&wasmtime::Error>) {
+        let mut stats = self
+            .stats
+            .lock()
+            .expect("cannot lock the waPC invocation stats");
+        stats.fuel_consumed = self.stack_pre.fuel_consumed(&self.store, guest_code);
+        stats.peak_memory = self.store.data().limiter.peak_memory();
+        stats.interruption = err.and_then(interruption_error);
+    }
+}
+
+impl wapc::WebAssemblyEngineProvider for EngineProvider {
+    fn init(&mut self, host: Arc<wapc::ModuleState>) -> ProviderResult<()> {
+        futures::executor::block_on(self.init_instance(ModuleState::Sync(host)))?;
+        Ok(())
+    }
+
+    fn call(&mut self, op_length: i32, msg_length: i32) -> ProviderResult<i32> {
+        futures::executor::block_on(self.call_guest(op_length, msg_length))
+    }
+
+    fn replace(&mut self, _bytes: &[u8]) -> ProviderResult<()> {
+        Err(WapcRuntimeError::ReplaceNotSupported.into())
+    }
+}
+
+#[async_trait::async_trait]
+impl wapc::WebAssemblyEngineProviderAsync for EngineProvider {
+    async fn init(&mut self, host: Arc<wapc::ModuleStateAsync>) -> ProviderResult<()> {
+        self.init_instance(ModuleState::Async(host)).await?;
+        Ok(())
+    }
+
+    async fn call(&mut self, op_length: i32, msg_length: i32) -> ProviderResult<i32> {
+        self.call_guest(op_length, msg_length).await
+    }
+
+    async fn replace(&mut self, _bytes: &[u8]) -> ProviderResult<()> {
+        Err(WapcRuntimeError::ReplaceNotSupported.into())
+    }
+}
+
+/// Returns the error to be reported when the guest has been interrupted by the host
+fn interruption_error(err: &wasmtime::Error) -> Option<WapcRuntimeError> {
+    if let Some(MemoryLimitExceeded { desired, limit }) = err.downcast_ref::<MemoryLimitExceeded>()
+    {
+        return Some(WapcRuntimeError::MemoryLimitExceeded {
+            desired: *desired,
+            limit: *limit,
+        });
+    }
+
+    match err.downcast_ref::<wasmtime::Trap>() {
+        Some(wasmtime::Trap::Interrupt) => Some(WapcRuntimeError::ExecutionDeadlineExceeded),
+        Some(wasmtime::Trap::OutOfFuel) => Some(WapcRuntimeError::FuelExhausted),
+        _ => None,
+    }
+}
@@ -18,11 +18,65 @@ pub enum WapcRuntimeError {
     },
 
     #[error("cannot invoke 'protocol_version' waPC function : {0}")]
-    InvokeProtocolVersion(#[source] wapc::errors::Error),
+    InvokeProtocolVersion(#[source] Box<WapcRuntimeError>),
 
-    #[error("cannot build Wasmtime engine: {0}")]
-    WasmtimeEngineBuilder(#[source] wasmtime_provider::errors::Error),
+    #[error("guest code interrupted, execution deadline exceeded")]
+    ExecutionDeadlineExceeded,
+
+    #[error("guest code interrupted, fuel exhausted")]
+    FuelExhausted,
+
+    #[error(
+        "guest code interrupted, memory limit exceeded: cannot grow memory to {desired} bytes, the limit is {limit} bytes"
+    )]
+    MemoryLimitExceeded { desired: usize, limit: usize },
+
+    #[error("cannot invoke waPC guest function: {0}")]
+    GuestCall(#[source] wapc::errors::Error),
+
+    #[error("cannot run the initialization code of the waPC module: {0}")]
+    GuestInit(#[source] wasmtime::Error),
 
     #[error("cannot build Wapc host: {0}")]
     WapcHostBuilder(#[source] wapc::errors::Error),
+
+    #[error("the waPC module has not been initialized")]
+    NotInitialized,
+
+    #[error("the replacement of the waPC module is not supported")]
+    ReplaceNotSupported,
+
+    #[error("cannot find `__guest_call` function inside of module: {0}")]
+    WasmMissingGuestCallFn(#[source] wasmtime::Error),
+
+    #[error("cannot define host function '{name}': {error}")]
+    WasmHostFuncDefinitionError { name: String, error: String },
+
+    #[error("cannot set the fuel of the store: {0}")]
+    WasmSetFuel(#[source] wasmtime::Error),
+
+    #[error("cannot add to linker: {0}")]
+    WasmLinkerError(#[source] wasmtime::Error),
+
+    #[error("cannot instantiate module: {0}")]
+    WasmInstantiate(#[source] wasmtime::Error),
+
+    #[error("cannot find 'memory' export")]
+    WasmMemExport,
+
+    #[error("the guest memory range is out of bounds")]
+    GuestMemoryOutOfBounds,
+}
+
+impl WapcRuntimeError {
+    /// Returns true when the error has been caused by the host interrupting
+    /// the execution of the guest
+    pub(crate) fn is_interruption(&self) -> bool {
+        matches!(
+            self,
+            WapcRuntimeError::ExecutionDeadlineExceeded
+                | WapcRuntimeError::FuelExhausted
+                | WapcRuntimeError::MemoryLimitExceeded { .. }
+        )
+    }
 }
@@ -0,0 +1,389 @@
+use std::sync::Arc;
+
+use tracing::info;
+use wasmtime::{Caller, Linker, Memory};
+
+use crate::runtimes::wapc::{
+    engine_provider::{Context, ModuleState},
+    errors::{Result, WapcRuntimeError},
+};
+
+/// The namespace of the host functions imported by waPC modules
+const HOST_NAMESPACE: &str = "wapc";
+
+/// The parameters of the `__host_call` function: the pointer and the length
+/// of the binding, namespace, operation and payload
+type HostCallParams = (i32, i32, i32, i32, i32, i32, i32, i32);
+
+/// A `__host_call` invocation, with its arguments read from the guest memory
+struct HostCall {
+    binding: String,
+    namespace: String,
+    operation: String,
+    payload: Vec<u8>,
+}
+
+/// Add the waPC host functions to the linker.
+/// The functions are the ones listed at https://wapc.io/docs/spec/#required-host-exports
+///
+/// The state of the waPC protocol is owned by the `wapc` crate, these functions only move
+/// the data between the memory of the guest and the [`ModuleState`].
+pub(crate) fn add_to_linker(linker: &mut Linker<Context>, async_support: bool) -> Result<()> {
+    if async_support {
+        add_async_functions_to_linker(linker)
+    } else {
+        add_sync_functions_to_linker(linker)
+    }
+}
+
+fn add_sync_functions_to_linker(linker: &mut Linker<Context>) -> Result<()> {
+    linker
+        .func_wrap(
+            HOST_NAMESPACE,
+            "__guest_request",
+            |mut caller: Caller<'_, Context>, op_ptr: i32, ptr: i32| {
+                let state = sync_module_state(&caller)?;
+                let Some(invocation) = state.get_guest_request() else {
+                    return Ok(());
+                };
+                write_guest_memory(&mut caller, op_ptr, invocation.operation.as_bytes())?;
+                write_guest_memory(&mut caller, ptr, &invocation.msg)
+            },
+        )
+        .map_err(|e| host_func_definition_error("__guest_request", e))?;
+
+    linker
+        .func_wrap(
+            HOST_NAMESPACE,
+            "__guest_response",
+            |mut caller: Caller<'_, Context>, ptr: i32, len: i32| {
+                let response = read_guest_memory(&mut caller, ptr, len)?;
+                sync_module_state(&caller)?.set_guest_response(response);
+                Ok(())
+            },
+        )
+        .map_err(|e| host_func_definition_error("__guest_response", e))?;
+
+    linker
+        .func_wrap(
+            HOST_NAMESPACE,
+            "__guest_error",
+            |mut caller: Caller<'_, Context>, ptr: i32, len: i32| {
+                let error = read_guest_memory(&mut caller, ptr, len)?;
+                sync_module_state(&caller)?
+                    .set_guest_error(String::from_utf8_lossy(&error).to_string());
+                Ok(())
+            },
+        )
+        .map_err(|e| host_func_definition_error("__guest_error", e))?;
+
+    linker
+        .func_wrap(
+            HOST_NAMESPACE,
+            "__host_call",
+            |mut caller: Caller<'_, Context>,
+             bd_ptr: i32,
+             bd_len: i32,
+             ns_ptr: i32,
+             ns_len: i32,
+             op_ptr: i32,
+             op_len: i32,
+             ptr: i32,
+             len: i32| {
+                let call = read_host_call(
+                    &mut caller,
+                    (bd_ptr, bd_len, ns_ptr, ns_len, op_ptr, op_len, ptr, len),
+                )?;
+                sync_module_state(&caller)?
+                    .do_host_call(
+                        &call.binding,
+                        &call.namespace,
+                        &call.operation,
+                        &call.payload,
+                    )
+                    .map_err(|e| wasmtime::Error::msg(e.to_string()))
+            },
+        )
+        .map_err(|e| host_func_definition_error("__host_call", e))?;
+
+    linker
+        .func_wrap(
+            HOST_NAMESPACE,
+            "__host_response",
+            |mut caller: Caller<'_, Context>, ptr: i32| {
+                if let Some(response) = sync_module_state(&caller)?.get_host_response() {
+                    write_guest_memory(&mut caller, ptr, &response)?;
+                }
+                Ok(())
+            },
+        )
+        .map_err(|e| host_func_definition_error("__host_response", e))?;
+
+    linker
+        .func_wrap(
+            HOST_NAMESPACE,
+            "__host_response_len",
+            |caller: Caller<'_, Context>| {
+                Ok(sync_module_state(&caller)?
+                    .get_host_response()
+                    .map_or(0, |response| response.len() as i32))
+            },
+        )
+        .map_err(|e| host_func_definition_error("__host_response_len", e))?;
+
+    linker
+        .func_wrap(
+            HOST_NAMESPACE,
+            "__host_error",
+            |mut caller: Caller<'_, Context>, ptr: i32| {
+                if let Some(error) = sync_module_state(&caller)?.get_host_error() {
+                    write_guest_memory(&mut caller, ptr, error.as_bytes())?;
+                }
+                Ok(())
+            },
+        )
+        .map_err(|e| host_func_definition_error("__host_error", e))?;
+
+    linker
+        .func_wrap(
+            HOST_NAMESPACE,
+            "__host_error_len",
+            |caller: Caller<'_, Context>| {
+                Ok(sync_module_state(&caller)?
+                    .get_host_error()
+                    .map_or(0, |error| error.len() as i32))
+            },
+        )
+        .map_err(|e| host_func_definition_error("__host_error_len", e))?;
+
+    add_console_log_to_linker(linker)
+}
+
+fn add_async_functions_to_linker(linker: &mut Linker<Context>) -> Result<()> {
+    linker
+        .func_wrap_async(
+            HOST_NAMESPACE,
+            "__guest_request",
+            |mut caller: Caller<'_, Context>, (op_ptr, ptr): (i32, i32)| {
+                Box::new(async move {
+                    let state = async_module_state(&caller)?;
+                    let Some(invocation) = state.get_guest_request().await else {
+                        return Ok(());
+                    };
+                    write_guest_memory(&mut caller, op_ptr, invocation.operation.as_bytes())?;
+                    write_guest_memory(&mut caller, ptr, &invocation.msg)?;
+                    Ok::<_, wasmtime::Error>(())
+                })
+            },
+        )
+        .map_err(|e| host_func_definition_error("__guest_request", e))?;
+
+    linker
+        .func_wrap_async(
+            HOST_NAMESPACE,
+            "__guest_response",
+            |mut caller: Caller<'_, Context>, (ptr, len): (i32, i32)| {
+                Box::new(async move {
+                    let response = read_guest_memory(&mut caller, ptr, len)?;
+                    async_module_state(&caller)?
+                        .set_guest_response(response)
+                        .await;
+                    Ok::<_, wasmtime::Error>(())
+                })
+            },
+        )
+        .map_err(|e| host_func_definition_error("__guest_response", e))?;
+
+    linker
+        .func_wrap_async(
+            HOST_NAMESPACE,
+            "__guest_error",
+            |mut caller: Caller<'_, Context>, (ptr, len): (i32, i32)| {
+                Box::new(async move {
+                    let error = read_guest_memory(&mut caller, ptr, len)?;
+                    async_module_state(&caller)?
+                        .set_guest_error(String::from_utf8_lossy(&error).to_string())
+                        .await;
+                    Ok::<_, wasmtime::Error>(())
+                })
+            },
+        )
+        .map_err(|e| host_func_definition_error("__guest_error", e))?;
+
+    linker
+        .func_wrap_async(
+            HOST_NAMESPACE,
+            "__host_call",
+            |mut caller: Caller<'_, Context>, params: HostCallParams| {
+                Box::new(async move {
+                    let call = read_host_call(&mut caller, params)?;
+                    async_module_state(&caller)?
+                        .do_host_call(call.binding, call.namespace, call.operation, call.payload)
+                        .await
+                        .map_err(|e| wasmtime::Error::msg(e.to_string()))
+                })
+            },
+        )
+        .map_err(|e| host_func_definition_error("__host_call", e))?;
+
+    linker
+        .func_wrap_async(
+            HOST_NAMESPACE,
+            "__host_response",
+            |mut caller: Caller<'_, Context>, (ptr,): (i32,)| {
+                Box::new(async move {
+                    let response = async_module_state(&caller)?.get_host_response().await;
+                    if let Some(response) = response {
+                        write_guest_memory(&mut caller, ptr, &response)?;
+                    }
+                    Ok::<_, wasmtime::Error>(())
+                })
+            },
+        )
+        .map_err(|e| host_func_definition_error("__host_response", e))?;
+
+    linker
+        .func_wrap_async(
+            HOST_NAMESPACE,
+            "__host_response_len",
+            |caller: Caller<'_, Context>, (): ()| {
+                Box::new(async move {
+                    Ok::<_, wasmtime::Error>(
+                        async_module_state(&caller)?
+                            .get_host_response()
+                            .await
+                            .map_or(0, |response| response.len() as i32),
+                    )
+                })
+            },
+        )
+        .map_err(|e| host_func_definition_error("__host_response_len", e))?;
+
+    linker
+        .func_wrap_async(
+            HOST_NAMESPACE,
+            "__host_error",
+            |mut caller: Caller<'_, Context>, (ptr,): (i32,)| {
+                Box::new(async move {
+                    let error = async_module_state(&caller)?.get_host_error().await;
+                    if let Some(error) = error {
+                        write_guest_memory(&mut caller, ptr, error.as_bytes())?;
+                    }
+                    Ok::<_, wasmtime::Error>(())
+                })
+            },
+        )
+        .map_err(|e| host_func_definition_error("__host_error", e))?;
+
+    linker
+        .func_wrap_async(
+            HOST_NAMESPACE,
+            "__host_error_len",
+            |caller: Caller<'_, Context>, (): ()| {
+                Box::new(async move {
+                    Ok::<_, wasmtime::Error>(
+                        async_module_state(&caller)?
+                            .get_host_error()
+                            .await
+                            .map_or(0, |error| error.len() as i32),
+                    )
+                })
+            },
+        )
+        .map_err(|e| host_func_definition_error("__host_error_len", e))?;
+
+    add_console_log_to_linker(linker)
+}
+
+fn add_console_log_to_linker(linker: &mut Linker<Context>) -> Result<()> {
+    linker
+        .func_wrap(
+            HOST_NAMESPACE,
+            "__console_log",
+            |mut caller: Caller<'_, Context>, ptr: i32, len: i32| {
+                let msg = read_guest_memory(&mut caller, ptr, len)?;
+                info!("{}", String::from_utf8_lossy(&msg));
+                Ok(())
+            },
+        )
+        .map_err(|e| host_func_definition_error("__console_log", e))?;
+    Ok(())
+}
+
+/// The waPC state of a synchronous module, set once the module is initialized
+fn sync_module_state(caller: &Caller<'_, Context>) -> Result<Arc<wapc::ModuleState>> {
+    match &caller.data().module_state {
+        Some(ModuleState::Sync(state)) => Ok(state.clone()),
+        _ => Err(WapcRuntimeError::NotInitialized),
+    }
+}
+
+/// The waPC state of an asynchronous module, set once the module is initialized
+fn async_module_state(caller: &Caller<'_, Context>) -> Result<Arc<wapc::ModuleStateAsync>> {
+    match &caller.data().module_state {
+        Some(ModuleState::Async(state)) => Ok(state.clone()),
+        _ => Err(WapcRuntimeError::NotInitialized),
+    }
+}
+
+/// Read the arguments of a `__host_call` invocation from the guest memory
+fn read_host_call(
+    caller: &mut Caller<'_, Context>,
+    (bd_ptr, bd_len, ns_ptr, ns_len, op_ptr, op_len, ptr, len): HostCallParams,
+) -> wasmtime::Result<HostCall> {
+    let binding = read_guest_memory(caller, bd_ptr, bd_len)?;
+    let namespace = read_guest_memory(caller, ns_ptr, ns_len)?;
+    let operation = read_guest_memory(caller, op_ptr, op_len)?;
+    let payload = read_guest_memory(caller, ptr, len)?;
+
+    Ok(HostCall {
+        binding: String::from_utf8(binding)?,
+        namespace: String::from_utf8(namespace)?,
+        operation: String::from_utf8(operation)?,
+        payload,
+    })
+}
+
+fn guest_memory(caller: &mut Caller<'_, Context>) -> Result<Memory> {
+    caller
+        .get_export("memory")
+        .and_then(|export| export.into_memory())
+        .ok_or(WapcRuntimeError::WasmMemExport)
+}
+
+/// Copy `len` bytes, starting at `ptr`, out of the guest memory.
+///
+/// Pointers and lengths are given by the guest as `i32`, they are reinterpreted
+/// as the `u32` values used by the 32-bit linear memory. The range is checked
+/// against the size of the memory before anything is allocated by the host.
+fn read_guest_memory(
+    caller: &mut Caller<'_, Context>,
+    ptr: i32,
+    len: i32,
+) -> wasmtime::Result<Vec<u8>> {
+    let memory = guest_memory(caller)?;
+    let start = ptr as u32 as usize;
+    let end = start
+        .checked_add(len as u32 as usize)
+        .filter(|end| *end <= memory.data_size(&*caller))
+        .ok_or(WapcRuntimeError::GuestMemoryOutOfBounds)?;
+
+    Ok(memory.data(&*caller)[start..end].to_vec())
+}
+
+fn write_guest_memory(
+    caller: &mut Caller<'_, Context>,
+    ptr: i32,
+    data: &[u8],
+) -> wasmtime::Result<()> {
+    let memory = guest_memory(caller)?;
+    memory.write(&mut *caller, ptr as u32 as usize, data)?;
+    Ok(())
+}
+
+fn host_func_definition_error(name: &str, error: wasmtime::Error) -> WapcRuntimeError {
+    WapcRuntimeError::WasmHostFuncDefinitionError {
+        name: name.to_string(),
+        error: error.to_string(),
+    }
+}
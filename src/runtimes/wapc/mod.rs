@@ -1,5 +1,7 @@
 mod callback;
+mod engine_provider;
 pub mod errors;
+mod host_functions;
 mod runtime;
 mod stack;
 mod stack_pre;
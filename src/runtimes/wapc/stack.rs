@@ -1,65 +1,356 @@
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use crate::evaluation_context::EvaluationContext;
+use crate::policy_evaluator::evaluation_report::HostCallbackRecorder;
+use crate::policy_evaluator::CancellationHandle;
+use crate::runtimes::callback::block_on;
 use crate::runtimes::wapc::{
-    callback::new_host_callback,
+    callback::{new_host_callback, new_host_callback_async},
+    engine_provider::{EngineProvider, InvocationStats},
     errors::{Result, WapcRuntimeError},
 };
 
 use super::StackPre;
 
+/// The waPC host, which can be either synchronous or asynchronous
+enum WapcHost {
+    Sync(wapc::WapcHost),
+    Async(wapc::WapcHostAsync),
+}
+
 pub(crate) struct WapcStack {
-    wapc_host: wapc::WapcHost,
+    wapc_host: WapcHost,
     stack_pre: StackPre,
     eval_ctx: Arc<EvaluationContext>,
+    host_callback_recorder: Arc<HostCallbackRecorder>,
+    cancellation: CancellationHandle,
+    stats: Arc<Mutex<InvocationStats>>,
+    /// Set when the last invocation of the `validate` function has been interrupted
+    pub(crate) interrupted: bool,
 }
 
 impl WapcStack {
-    pub(crate) fn new_from_pre(stack_pre: &StackPre, eval_ctx: &EvaluationContext) -> Result<Self> {
+    pub(crate) fn new_from_pre(
+        stack_pre: &StackPre,
+        eval_ctx: &EvaluationContext,
+        host_callback_recorder: Arc<HostCallbackRecorder>,
+        cancellation: CancellationHandle,
+    ) -> Result<Self> {
         let eval_ctx = Arc::new(eval_ctx.to_owned());
-        let wapc_host = Self::wapc_host_from_pre(stack_pre, eval_ctx.clone())?;
+        let stats = Arc::new(Mutex::new(InvocationStats::default()));
+        let wapc_host = futures::executor::block_on(wapc_host_from_pre(
+            stack_pre,
+            eval_ctx.clone(),
+            host_callback_recorder.clone(),
+            cancellation.clone(),
+            stats.clone(),
+        ))?;
 
         Ok(Self {
             wapc_host,
             stack_pre: stack_pre.to_owned(),
-            eval_ctx: eval_ctx.to_owned(),
+            eval_ctx,
+            host_callback_recorder,
+            cancellation,
+            stats,
+            interrupted: false,
         })
     }
 
-    /// Provision a new wapc_host. Useful for starting from a clean slate
-    /// after an epoch deadline interruption is raised.
+    /// Provision a new waPC host, together with a new instance of the module.
     ///
-    /// This method takes care of de-registering the old wapc_host and
-    /// registering the new one inside of the global WAPC_POLICY_MAPPING
-    /// variable.
+    /// This must be done after the guest has been interrupted, because of an epoch
+    /// deadline, of fuel exhaustion or of the memory limit. No cleanup code is run inside of the guest,
+    /// the state kept inside of the `wasmtime::Store` could be broken: for example,
+    /// a Mutex locked by the interrupted invocation would stay locked forever.
     pub(crate) fn reset(&mut self) -> Result<()> {
-        // Create a new wapc_host
-        let new_wapc_host = Self::wapc_host_from_pre(&self.stack_pre, self.eval_ctx.clone())?;
+        futures::executor::block_on(self.reset_async())
+    }
 
-        self.wapc_host = new_wapc_host;
+    /// Asynchronous version of [`reset`](WapcStack::reset)
+    pub(crate) async fn reset_async(&mut self) -> Result<()> {
+        self.wapc_host = wapc_host_from_pre(
+            &self.stack_pre,
+            self.eval_ctx.clone(),
+            self.host_callback_recorder.clone(),
+            self.cancellation.clone(),
+            self.stats.clone(),
+        )
+        .await?;
 
         Ok(())
     }
 
-    /// Invokes the given waPC function using the provided payload
-    pub(crate) fn call(
+    /// The amount of fuel consumed by the last invocation. This is `None` when
+    /// fuel metering is not enabled
+    pub(crate) fn fuel_consumed(&self) -> Option<u64> {
+        self.stats().fuel_consumed
+    }
+
+    /// The peak size, in bytes, of the linear memory of the module. The memory is kept
+    /// across invocations, hence this is the peak since the module has been instantiated
+    pub(crate) fn peak_memory(&self) -> usize {
+        self.stats().peak_memory
+    }
+
+    /// Invokes the given waPC function using the provided payload.
+    ///
+    /// When the stack is asynchronous, the current thread is blocked until
+    /// the invocation is done, see [`block_on`].
+    pub(crate) fn call(&self, op: &str, payload: &[u8]) -> Result<Vec<u8>> {
+        let call_result = match &self.wapc_host {
+            WapcHost::Sync(host) => host.call(op, payload),
+            WapcHost::Async(host) => block_on(host.call(op, payload)),
+        };
+        self.call_outcome(call_result)
+    }
+
+    /// Invokes the given waPC function using the provided payload.
+    ///
+    /// When the stack is synchronous, the invocation is done in a blocking fashion.
+    pub(crate) async fn call_async(&self, op: &str, payload: &[u8]) -> Result<Vec<u8>> {
+        let call_result = match &self.wapc_host {
+            WapcHost::Sync(host) => host.call(op, payload),
+            WapcHost::Async(host) => host.call(op, payload).await,
+        };
+        self.call_outcome(call_result)
+    }
+
+    /// The `wapc` host reports every failure as a message: when the guest has been
+    /// interrupted by the host, the typed interruption recorded by the engine
+    /// provider is returned instead
+    fn call_outcome(
         &self,
-        op: &str,
-        payload: &[u8],
-    ) -> std::result::Result<Vec<u8>, wapc::errors::Error> {
-        self.wapc_host.call(op, payload)
-    }
-
-    /// Create a new `WapcHost` by rehydrating the `StackPre`. This is faster than creating the
-    /// `WasmtimeEngineProvider` from scratch
-    fn wapc_host_from_pre(
-        pre: &StackPre,
-        eval_ctx: Arc<EvaluationContext>,
-    ) -> Result<wapc::WapcHost> {
-        let engine_provider = pre.rehydrate()?;
-        let wapc_host =
-            wapc::WapcHost::new(Box::new(engine_provider), Some(new_host_callback(eval_ctx)))
-                .map_err(WapcRuntimeError::WapcHostBuilder)?;
-        Ok(wapc_host)
+        call_result: std::result::Result<Vec<u8>, wapc::errors::Error>,
+    ) -> Result<Vec<u8>> {
+        call_result.map_err(|e| {
+            self.stats()
+                .interruption
+                .take()
+                .unwrap_or(WapcRuntimeError::GuestCall(e))
+        })
+    }
+
+    fn stats(&self) -> std::sync::MutexGuard<'_, InvocationStats> {
+        self.stats
+            .lock()
+            .expect("cannot lock the waPC invocation stats")
+    }
+}
+
+/// Create a new `WapcHost` by rehydrating the `StackPre`, this runs the initialization
+/// code of the module.
+///
+/// When the stack is synchronous, this never awaits: the future can be driven
+/// by a blocking executor.
+async fn wapc_host_from_pre(
+    pre: &StackPre,
+    eval_ctx: Arc<EvaluationContext>,
+    host_callback_recorder: Arc<HostCallbackRecorder>,
+    cancellation: CancellationHandle,
+    stats: Arc<Mutex<InvocationStats>>,
+) -> Result<WapcHost> {
+    let engine_provider = EngineProvider::new(pre, cancellation.clone(), stats.clone());
+    let wapc_host = if pre.async_support() {
+        wapc::WapcHostAsync::new(
+            Box::new(engine_provider),
+            Some(new_host_callback_async(
+                eval_ctx,
+                host_callback_recorder,
+                cancellation,
+            )),
+        )
+        .await
+        .map(WapcHost::Async)
+    } else {
+        wapc::WapcHost::new(
+            Box::new(engine_provider),
+            Some(new_host_callback(
+                eval_ctx,
+                host_callback_recorder,
+                cancellation,
+            )),
+        )
+        .map(WapcHost::Sync)
+    };
+
+    wapc_host.map_err(|e| {
+        stats
+            .lock()
+            .expect("cannot lock the waPC invocation stats")
+            .interruption
+            .take()
+            .unwrap_or(WapcRuntimeError::WapcHostBuilder(e))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy_evaluator_builder::{FuelLimits, ResourceLimits};
+
+    const ENDLESS_LOOP_WAT: &[u8] =
+        include_bytes!("../../../tests/data/endless_wasm/wapc_endless_loop.wat");
+
+    /// A waPC module that answers every invocation with the payload it received
+    const ECHO_WAT: &str = r#"
+        (module
+          (import "wapc" "__guest_request" (func $guest_request (param i32 i32)))
+          (import "wapc" "__guest_response" (func $guest_response (param i32 i32)))
+          (memory (export "memory") 1)
+          (func (export "__guest_call") (param $op_len i32) (param $payload_len i32) (result i32)
+            ;; the operation is written at offset 0, the payload at offset 1024
+            (call $guest_request (i32.const 0) (i32.const 1024))
+            (call $guest_response (i32.const 1024) (local.get $payload_len))
+            i32.const 1))
+    "#;
+
+    /// A waPC module that grows its memory by 10 pages on every invocation
+    const MEMORY_GROW_WAT: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (func (export "__guest_call") (param i32 i32) (result i32)
+            (drop (memory.grow (i32.const 10)))
+            i32.const 0))
+    "#;
+
+    /// A waPC module that hands to the host a response lying past the end of its memory
+    const OUT_OF_BOUNDS_RESPONSE_WAT: &str = r#"
+        (module
+          (import "wapc" "__guest_response" (func $guest_response (param i32 i32)))
+          (memory (export "memory") 1)
+          (func (export "__guest_call") (param i32 i32) (result i32)
+            ;; pointer 0xFFFFFF00, beyond 2GiB, with a length wrapping around the address space
+            (call $guest_response (i32.const -256) (i32.const 512))
+            i32.const 1))
+    "#;
+
+    /// Size of a WebAssembly memory page
+    const WASM_PAGE_SIZE: usize = 64 * 1024;
+
+    fn build_stack(
+        wat: impl AsRef<[u8]>,
+        fuel_limits: Option<FuelLimits>,
+        resource_limits: ResourceLimits,
+    ) -> WapcStack {
+        let mut config = wasmtime::Config::default();
+        config.consume_fuel(fuel_limits.is_some());
+        let engine = wasmtime::Engine::new(&config).expect("cannot create wasmtime engine");
+        let module = wasmtime::Module::new(&engine, wat).expect("cannot compile WAT to wasm");
+
+        let stack_pre = StackPre::new(engine, module, None, fuel_limits, resource_limits, false)
+            .expect("cannot create StackPre");
+
+        let eval_ctx = EvaluationContext {
+            policy_id: "test".to_string(),
+            callback_channel: None,
+            ctx_aware_resources_allow_list: Default::default(),
+            policy_mode: Default::default(),
+            clock: Default::default(),
+            protected_paths: Default::default(),
+            array_keys: Default::default(),
+        };
+        WapcStack::new_from_pre(
+            &stack_pre,
+            &eval_ctx,
+            Default::default(),
+            CancellationHandle::new(),
+        )
+        .expect("cannot create waPC stack")
+    }
+
+    fn fuel_limits(fuel: u64) -> Option<FuelLimits> {
+        Some(FuelLimits {
+            init: fuel,
+            func: fuel,
+        })
+    }
+
+    #[test]
+    fn guest_response() {
+        let stack = build_stack(ECHO_WAT, None, ResourceLimits::default());
+
+        let response = stack
+            .call("echo", b"hello")
+            .expect("the call should not fail");
+
+        assert_eq!(b"hello".to_vec(), response);
+    }
+
+    #[test]
+    fn fuel_exhausted() {
+        let mut stack = build_stack(
+            ENDLESS_LOOP_WAT,
+            fuel_limits(10_000),
+            ResourceLimits::default(),
+        );
+
+        // If fuel consumption doesn't work, this unit test will never complete
+        let result = stack.call("run", b"");
+
+        assert!(matches!(result, Err(WapcRuntimeError::FuelExhausted)));
+        assert_eq!(Some(10_000), stack.fuel_consumed());
+
+        // the stack can be used again once it has been reset
+        stack.reset().expect("cannot reset the stack");
+        assert!(matches!(
+            stack.call("run", b""),
+            Err(WapcRuntimeError::FuelExhausted)
+        ));
+    }
+
+    #[test]
+    fn fuel_consumed() {
+        let stack = build_stack(ECHO_WAT, fuel_limits(10_000), ResourceLimits::default());
+
+        stack.call("echo", b"").expect("the call should not fail");
+
+        let fuel_consumed = stack.fuel_consumed().expect("fuel consumed should be set");
+        assert!(fuel_consumed < 10_000);
+    }
+
+    #[test]
+    fn memory_limit_exceeded() {
+        let resource_limits = ResourceLimits {
+            memory_size: Some(2 * WASM_PAGE_SIZE),
+            ..Default::default()
+        };
+        let stack = build_stack(MEMORY_GROW_WAT, None, resource_limits);
+
+        let result = stack.call("grow", b"");
+
+        assert!(matches!(
+            result,
+            Err(WapcRuntimeError::MemoryLimitExceeded {
+                desired,
+                limit,
+            }) if desired == 11 * WASM_PAGE_SIZE && limit == 2 * WASM_PAGE_SIZE
+        ));
+    }
+
+    #[test]
+    fn peak_memory() {
+        let resource_limits = ResourceLimits {
+            memory_size: Some(16 * WASM_PAGE_SIZE),
+            ..Default::default()
+        };
+        let stack = build_stack(MEMORY_GROW_WAT, None, resource_limits);
+
+        // the guest doesn't set any error message
+        assert!(matches!(
+            stack.call("grow", b""),
+            Err(WapcRuntimeError::GuestCall(_))
+        ));
+        assert_eq!(11 * WASM_PAGE_SIZE, stack.peak_memory());
+    }
+
+    #[test]
+    fn guest_memory_out_of_bounds() {
+        let stack = build_stack(OUT_OF_BOUNDS_RESPONSE_WAT, None, ResourceLimits::default());
+
+        assert!(matches!(
+            stack.call("respond", b""),
+            Err(WapcRuntimeError::GuestCall(_))
+        ));
     }
 }
@@ -1,41 +1,150 @@
-use wasmtime_provider::wasmtime;
+use wasmtime::{Engine, InstancePre, Linker, Module, Store};
 
-use crate::policy_evaluator_builder::EpochDeadlines;
-use crate::runtimes::wapc::errors::{Result, WapcRuntimeError};
+use crate::policy_evaluator_builder::{EpochDeadlines, FuelLimits, ResourceLimits};
+use crate::runtimes::wapc::{
+    engine_provider::Context,
+    errors::{Result, WapcRuntimeError},
+    host_functions,
+};
 
-/// Reduce allocation time of new `WasmtimeProviderEngine`, see the `rehydrate` method
+/// The guest code being run, each kind of code has its own execution limits
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum GuestCode {
+    /// The initialization code, defined inside of the `wapc_init` or the `_start` functions
+    Init,
+    /// A waPC function invoked by the host
+    Func,
+}
+
+/// Reduce the allocation time of a waPC Stack. This is done by leveraging `wasmtime::InstancePre`.
 #[derive(Clone)]
 pub(crate) struct StackPre {
-    engine_provider_pre: wasmtime_provider::WasmtimeEngineProviderPre,
+    engine: Engine,
+    instance_pre: InstancePre<Context>,
+    epoch_deadlines: Option<EpochDeadlines>,
+    fuel_limits: Option<FuelLimits>,
+    resource_limits: ResourceLimits,
+    async_support: bool,
 }
 
 impl StackPre {
+    /// Create a new `StackPre`. When `async_support` is set, the `wasmtime::Engine` must
+    /// have been created with [async support](wasmtime::Config::async_support) enabled
     pub(crate) fn new(
-        engine: wasmtime::Engine,
-        module: wasmtime::Module,
+        engine: Engine,
+        module: Module,
         epoch_deadlines: Option<EpochDeadlines>,
+        fuel_limits: Option<FuelLimits>,
+        resource_limits: ResourceLimits,
+        async_support: bool,
     ) -> Result<Self> {
-        let mut builder = wasmtime_provider::WasmtimeEngineProviderBuilder::new()
-            .engine(engine)
-            .module(module);
-        if let Some(deadlines) = epoch_deadlines {
-            builder = builder.enable_epoch_interruptions(deadlines.wapc_init, deadlines.wapc_func);
-        }
+        let mut linker = Linker::<Context>::new(&engine);
+        // waPC modules built with TinyGo import the WASI functions too
+        wasi_common::sync::add_to_linker(&mut linker, |c: &mut Context| &mut c.wasi_ctx)
+            .map_err(WapcRuntimeError::WasmLinkerError)?;
+        host_functions::add_to_linker(&mut linker, async_support)?;
 
-        let engine_provider_pre = builder
-            .build_pre()
-            .map_err(WapcRuntimeError::WasmtimeEngineBuilder)?;
+        let instance_pre = linker
+            .instantiate_pre(&module)
+            .map_err(WapcRuntimeError::WasmInstantiate)?;
         Ok(Self {
-            engine_provider_pre,
+            engine,
+            instance_pre,
+            epoch_deadlines,
+            fuel_limits,
+            resource_limits,
+            async_support,
         })
     }
 
-    /// Allocate a new `WasmtimeEngineProvider` instance by using a pre-allocated instance
-    pub(crate) fn rehydrate(&self) -> Result<wasmtime_provider::WasmtimeEngineProvider> {
-        let engine = self
-            .engine_provider_pre
-            .rehydrate()
-            .map_err(WapcRuntimeError::WasmtimeEngineBuilder)?;
-        Ok(engine)
+    /// Returns true when the stack has been created to run on top of an
+    /// asynchronous `wasmtime::Engine`
+    pub(crate) fn async_support(&self) -> bool {
+        self.async_support
+    }
+
+    /// The limits enforced on the resources allocated by the waPC module
+    pub(crate) fn resource_limits(&self) -> ResourceLimits {
+        self.resource_limits
+    }
+
+    /// Create a brand new `wasmtime::Store`, to be used by a new instance of the module
+    pub(crate) fn build_store(&self, ctx: Context) -> Store<Context> {
+        let mut store = Store::new(&self.engine, ctx);
+        store.limiter(|ctx| &mut ctx.limiter);
+        store
+    }
+
+    /// Set the execution limits of the given guest code: the code is interrupted once
+    /// it exceeds its epoch deadline, or once it exhausts its fuel. When epoch
+    /// interruptions are enabled, the code is interrupted also when the evaluation
+    /// is cancelled.
+    ///
+    /// Unlike WASI programs, waPC modules keep their store across invocations, hence
+    /// this must be done before each invocation.
+    pub(crate) fn set_execution_limits(
+        &self,
+        store: &mut Store<Context>,
+        guest_code: GuestCode,
+    ) -> Result<()> {
+        if let Some(deadlines) = self.epoch_deadlines {
+            let deadline = match guest_code {
+                GuestCode::Init => deadlines.wapc_init,
+                GuestCode::Func => deadlines.wapc_func,
+            };
+            let cancellation = store.data().cancellation.clone();
+            store.set_epoch_deadline(1);
+            store.epoch_deadline_callback(cancellation.epoch_deadline_callback(deadline));
+        }
+        if let Some(fuel_limits) = self.fuel_limits {
+            let fuel = match guest_code {
+                GuestCode::Init => fuel_limits.init,
+                GuestCode::Func => fuel_limits.func,
+            };
+            store
+                .set_fuel(fuel)
+                .map_err(WapcRuntimeError::WasmSetFuel)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the amount of fuel consumed by the guest code that has just been
+    /// run. This is `None` when fuel metering is not enabled
+    pub(crate) fn fuel_consumed(
+        &self,
+        store: &Store<Context>,
+        guest_code: GuestCode,
+    ) -> Option<u64> {
+        let fuel_limits = self.fuel_limits?;
+        let fuel = match guest_code {
+            GuestCode::Init => fuel_limits.init,
+            GuestCode::Func => fuel_limits.func,
+        };
+        store
+            .get_fuel()
+            .ok()
+            .map(|remaining| fuel.saturating_sub(remaining))
+    }
+
+    /// Allocate a new `wasmtime::Instance` that is bound to the given `wasmtime::Store`.
+    /// It's recommended to provide a brand new `wasmtime::Store` created by the
+    /// `build_store` method
+    pub(crate) fn rehydrate(&self, store: &mut Store<Context>) -> Result<wasmtime::Instance> {
+        self.instance_pre
+            .instantiate(store)
+            .map_err(WapcRuntimeError::WasmInstantiate)
+    }
+
+    /// Asynchronous version of [`rehydrate`](StackPre::rehydrate), must be used when
+    /// the stack has async support enabled
+    pub(crate) async fn rehydrate_async(
+        &self,
+        store: &mut Store<Context>,
+    ) -> Result<wasmtime::Instance> {
+        self.instance_pre
+            .instantiate_async(store)
+            .await
+            .map_err(WapcRuntimeError::WasmInstantiate)
     }
 }
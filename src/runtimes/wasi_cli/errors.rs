@@ -14,6 +14,27 @@ pub enum WasiRuntimeError {
     #[error("cannot define host function '{name}': {error}")]
     WasmHostFuncDefinitionError { name: String, error: String },
 
+    #[error("guest code interrupted, execution deadline exceeded")]
+    ExecutionDeadlineExceeded,
+
+    #[error("guest code interrupted, fuel exhausted")]
+    FuelExhausted,
+
+    #[error(
+        "guest code interrupted, memory limit exceeded: cannot grow memory to {desired} bytes, the limit is {limit} bytes"
+    )]
+    MemoryLimitExceeded { desired: usize, limit: usize },
+
+    #[error("cannot set the fuel of the store: {0}")]
+    WasmSetFuel(#[source] wasmtime::Error),
+
+    #[error("cannot create ProtocolVersion object from {stdout:?}: {error}")]
+    CreateProtocolVersion {
+        stdout: String,
+        #[source]
+        error: wasmtime::Error,
+    },
+
     #[error("cannot find `_start` function inside of module: {0}")]
     WasmMissingStartFn(#[source] wasmtime::Error),
 
@@ -46,3 +67,16 @@ pub enum WasiRuntimeError {
     #[error("host_call: cannot get write access to STDIN")]
     WasiWriteAccessStdin(),
 }
+
+impl WasiRuntimeError {
+    /// Returns true when the error has been caused by the host interrupting
+    /// the execution of the guest
+    pub(crate) fn is_interruption(&self) -> bool {
+        matches!(
+            self,
+            WasiRuntimeError::ExecutionDeadlineExceeded
+                | WasiRuntimeError::FuelExhausted
+                | WasiRuntimeError::MemoryLimitExceeded { .. }
+        )
+    }
+}
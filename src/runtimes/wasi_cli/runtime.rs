@@ -1,82 +1,69 @@
+use kubewarden_policy_sdk::metadata::ProtocolVersion;
 use kubewarden_policy_sdk::response::ValidationResponse as PolicyValidationResponse;
 use kubewarden_policy_sdk::settings::SettingsValidationResponse;
 use serde_json::json;
 use tracing::{error, warn};
 
-use crate::admission_response::AdmissionResponse;
+use crate::admission_response::{causes_from_response, AdmissionResponse, StatusCause};
+use crate::patch::{ArrayKeys, GuestPatch, ProtectedPaths};
 use crate::policy_evaluator::{PolicySettings, ValidateRequest};
+use crate::runtimes::wasi_cli::errors::WasiRuntimeError;
 use crate::runtimes::wasi_cli::stack::{RunResult, Stack};
 
-pub(crate) struct Runtime<'a>(pub(crate) &'a Stack);
+pub(crate) struct Runtime<'a>(pub(crate) &'a mut Stack);
+
+/// Arguments given to the WASI program to perform a validation
+const VALIDATE_ARGS: [&str; 2] = ["policy.wasm", "validate"];
+
+/// Arguments given to the WASI program to obtain the version of the Kubewarden
+/// protocol it implements
+const PROTOCOL_VERSION_ARGS: [&str; 2] = ["policy.wasm", "protocol-version"];
 
 impl Runtime<'_> {
     pub fn validate(
-        &self,
+        &mut self,
         settings: &PolicySettings,
         request: &ValidateRequest,
+        protected_paths: &ProtectedPaths,
+        array_keys: &ArrayKeys,
     ) -> AdmissionResponse {
-        let validate_params = json!({
-            "request": request,
-            "settings": settings,
-        });
-
-        let input = match serde_json::to_vec(&validate_params) {
-            Ok(s) => s,
+        let input = match serialize_validate_params(settings, request) {
+            Ok(input) => input,
             Err(e) => {
-                error!(
-                    error = e.to_string().as_str(),
-                    "cannot serialize validation params"
-                );
                 return AdmissionResponse::reject_internal_server_error(
                     request.uid().to_string(),
                     e.to_string(),
-                );
+                )
             }
         };
-        let args = ["policy.wasm", "validate"];
 
-        match self.0.run(&input, &args) {
-            Ok(RunResult { stdout, stderr }) => {
-                if !stderr.is_empty() {
-                    warn!(
-                        request = request.uid().to_string(),
-                        operation = "validate",
-                        "stderr: {:?}",
-                        stderr
-                    )
-                }
-                match serde_json::from_slice::<PolicyValidationResponse>(stdout.as_bytes()) {
-                    Ok(pvr) => {
-                        let req_json_value = serde_json::to_value(request)
-                            .expect("cannot convert request to json value");
-                        let req_obj = match request {
-                            ValidateRequest::Raw(_) => Some(&req_json_value),
-                            ValidateRequest::AdmissionRequest(_) => req_json_value.get("object"),
-                        };
-
-                        AdmissionResponse::from_policy_validation_response(
-                            request.uid().to_string(),
-                            req_obj,
-                            &pvr,
-                        )
-                    }
-                    .unwrap_or_else(|e| {
-                        AdmissionResponse::reject_internal_server_error(
-                            request.uid().to_string(),
-                            format!("Cannot convert policy validation response: {e}"),
-                        )
-                    }),
-                    Err(e) => AdmissionResponse::reject_internal_server_error(
-                        request.uid().to_string(),
-                        format!("Cannot deserialize policy validation response: {e}"),
-                    ),
-                }
+        let run_result = self.0.run(&input, &VALIDATE_ARGS);
+        build_admission_response(request, run_result, protected_paths, array_keys)
+    }
+
+    /// Asynchronous version of [`validate`](Runtime::validate)
+    pub async fn validate_async(
+        &mut self,
+        settings: &PolicySettings,
+        request: &ValidateRequest,
+        protected_paths: &ProtectedPaths,
+        array_keys: &ArrayKeys,
+    ) -> AdmissionResponse {
+        let input = match serialize_validate_params(settings, request) {
+            Ok(input) => input,
+            Err(e) => {
+                return AdmissionResponse::reject_internal_server_error(
+                    request.uid().to_string(),
+                    e.to_string(),
+                )
             }
-            Err(e) => AdmissionResponse::reject(request.uid().to_string(), e.to_string(), 500),
-        }
+        };
+
+        let run_result = self.0.run_async(&input, &VALIDATE_ARGS).await;
+        build_admission_response(request, run_result, protected_paths, array_keys)
     }
 
-    pub fn validate_settings(&self, settings: String) -> SettingsValidationResponse {
+    pub fn validate_settings(&mut self, settings: String) -> SettingsValidationResponse {
         let args = ["policy.wasm", "validate-settings"];
 
         match self.0.run(settings.as_bytes(), &args) {
@@ -98,4 +85,109 @@ impl Runtime<'_> {
             },
         }
     }
+
+    pub fn protocol_version(&mut self) -> Result<ProtocolVersion, WasiRuntimeError> {
+        let RunResult { stdout, stderr } = self.0.run(&[], &PROTOCOL_VERSION_ARGS)?;
+        if !stderr.is_empty() {
+            warn!(operation = "protocol-version", "stderr: {:?}", stderr)
+        }
+        ProtocolVersion::try_from(stdout.clone().into_bytes())
+            .map_err(|error| WasiRuntimeError::CreateProtocolVersion { stdout, error })
+    }
+}
+
+/// Serialize the input given to the WASI program when performing a validation.
+fn serialize_validate_params(
+    settings: &PolicySettings,
+    request: &ValidateRequest,
+) -> Result<Vec<u8>, serde_json::Error> {
+    let validate_params = json!({
+        "request": request,
+        "settings": settings,
+    });
+
+    serde_json::to_vec(&validate_params).inspect_err(|e| {
+        error!(
+            error = e.to_string().as_str(),
+            "cannot serialize validation params"
+        );
+    })
+}
+
+/// The response written by the WASI program
+struct PolicyResponse {
+    validation_response: PolicyValidationResponse,
+    /// The patch the policy might provide instead of the mutated object
+    guest_patch: Option<GuestPatch>,
+    /// The field-level causes of the rejection
+    causes: Vec<StatusCause>,
+}
+
+/// Parse the response written by the WASI program
+fn parse_policy_validation_response(stdout: &[u8]) -> anyhow::Result<PolicyResponse> {
+    let response: serde_json::Value = serde_json::from_slice(stdout)?;
+    let guest_patch = GuestPatch::from_response(&response)?;
+    let causes = causes_from_response(&response)?;
+    let validation_response = serde_json::from_value(response)?;
+    Ok(PolicyResponse {
+        validation_response,
+        guest_patch,
+        causes,
+    })
+}
+
+/// Convert the outcome of the WASI program into an `AdmissionResponse`
+fn build_admission_response(
+    request: &ValidateRequest,
+    run_result: Result<RunResult, WasiRuntimeError>,
+    protected_paths: &ProtectedPaths,
+    array_keys: &ArrayKeys,
+) -> AdmissionResponse {
+    match run_result {
+        Ok(RunResult { stdout, stderr }) => {
+            if !stderr.is_empty() {
+                warn!(
+                    request = request.uid().to_string(),
+                    operation = "validate",
+                    "stderr: {:?}",
+                    stderr
+                )
+            }
+            match parse_policy_validation_response(stdout.as_bytes()) {
+                Ok(PolicyResponse {
+                    validation_response,
+                    guest_patch,
+                    causes,
+                }) => {
+                    let req_json_value = serde_json::to_value(request)
+                        .expect("cannot convert request to json value");
+                    let req_obj = match request {
+                        ValidateRequest::Raw(_) => Some(&req_json_value),
+                        ValidateRequest::AdmissionRequest(_) => req_json_value.get("object"),
+                    };
+
+                    AdmissionResponse::from_policy_validation_response_and_patch(
+                        request.uid().to_string(),
+                        req_obj,
+                        &validation_response,
+                        guest_patch.as_ref(),
+                        protected_paths,
+                        array_keys,
+                    )
+                    .map(|outcome| outcome.into_response().with_causes(causes))
+                }
+                .unwrap_or_else(|e| {
+                    AdmissionResponse::reject_internal_server_error(
+                        request.uid().to_string(),
+                        format!("Cannot convert policy validation response: {e}"),
+                    )
+                }),
+                Err(e) => AdmissionResponse::reject_internal_server_error(
+                    request.uid().to_string(),
+                    format!("Cannot deserialize policy validation response: {e}"),
+                ),
+            }
+        }
+        Err(e) => AdmissionResponse::reject(request.uid().to_string(), e.to_string(), 500),
+    }
 }
@@ -1,3 +1,4 @@
+use burrego::{MemoryLimitExceeded, ResourceLimiter};
 use std::io::Cursor;
 use std::sync::{Arc, RwLock};
 use tracing::debug;
@@ -6,6 +7,9 @@ use wasi_common::sync::WasiCtxBuilder;
 use wasi_common::WasiCtx;
 
 use crate::evaluation_context::EvaluationContext;
+use crate::policy_evaluator::evaluation_report::HostCallbackRecorder;
+use crate::policy_evaluator::CancellationHandle;
+use crate::runtimes::callback::block_on;
 use crate::runtimes::wasi_cli::{
     errors::WasiRuntimeError, stack_pre::StackPre, wasi_pipe::WasiPipe,
 };
@@ -16,11 +20,19 @@ pub(crate) struct Context {
     pub(crate) wasi_ctx: WasiCtx,
     pub(crate) stdin_pipe: Arc<RwLock<WasiPipe>>,
     pub(crate) eval_ctx: Arc<EvaluationContext>,
+    pub(crate) host_callback_recorder: Arc<HostCallbackRecorder>,
+    pub(crate) cancellation: CancellationHandle,
+    pub(crate) limiter: ResourceLimiter,
 }
 
 pub(crate) struct Stack {
     stack_pre: StackPre,
     eval_ctx: Arc<EvaluationContext>,
+    host_callback_recorder: Arc<HostCallbackRecorder>,
+    cancellation: CancellationHandle,
+    fuel_consumed: Option<u64>,
+    peak_memory: Option<usize>,
+    interrupted: bool,
 }
 
 pub(crate) struct RunResult {
@@ -29,19 +41,122 @@ pub(crate) struct RunResult {
 }
 
 impl Stack {
-    pub(crate) fn new_from_pre(stack_pre: &StackPre, eval_ctx: &EvaluationContext) -> Self {
+    pub(crate) fn new_from_pre(
+        stack_pre: &StackPre,
+        eval_ctx: &EvaluationContext,
+        host_callback_recorder: Arc<HostCallbackRecorder>,
+        cancellation: CancellationHandle,
+    ) -> Self {
         Self {
             stack_pre: stack_pre.to_owned(),
             eval_ctx: Arc::new(eval_ctx.to_owned()),
+            host_callback_recorder,
+            cancellation,
+            fuel_consumed: None,
+            peak_memory: None,
+            interrupted: false,
         }
     }
 
-    /// Run a WASI program with the given input and args
+    /// The amount of fuel consumed by the last program run. This is `None` when
+    /// fuel metering is not enabled
+    pub(crate) fn fuel_consumed(&self) -> Option<u64> {
+        self.fuel_consumed
+    }
+
+    /// The peak size, in bytes, of the linear memory used by the last program run
+    pub(crate) fn peak_memory(&self) -> Option<usize> {
+        self.peak_memory
+    }
+
+    /// Returns true when the last program run has been interrupted by the host
+    pub(crate) fn interrupted(&self) -> bool {
+        self.interrupted
+    }
+
+    /// Run a WASI program with the given input and args.
+    ///
+    /// When the stack has async support enabled, the current thread is
+    /// blocked until the program is done, see [`block_on`].
     pub(crate) fn run(
-        &self,
+        &mut self,
         input: &[u8],
         args: &[&str],
     ) -> std::result::Result<RunResult, WasiRuntimeError> {
+        if self.stack_pre.async_support() {
+            return block_on(self.run_async(input, args));
+        }
+
+        self.fuel_consumed = None;
+        self.peak_memory = None;
+        self.interrupted = false;
+        let (ctx, output_pipes) = self.build_context(input, args)?;
+
+        let mut store = self.stack_pre.build_store(ctx)?;
+        let instance = self.stack_pre.rehydrate(&mut store)?;
+        let start_fn = instance
+            .get_typed_func::<(), ()>(&mut store, "_start")
+            .map_err(WasiRuntimeError::WasmMissingStartFn)?;
+        let evaluation_result = start_fn.call(&mut store, ());
+        self.fuel_consumed = self.stack_pre.fuel_consumed(&store);
+        self.peak_memory = Some(store.data().limiter.peak_memory());
+
+        // Dropping the store, this is no longer needed, plus it's keeping
+        // references to the WritePipe(s) that we need exclusive access to.
+        drop(store);
+
+        let run_result = output_pipes.into_run_result(evaluation_result);
+        self.interrupted = run_result
+            .as_ref()
+            .is_err_and(WasiRuntimeError::is_interruption);
+        run_result
+    }
+
+    /// Asynchronous version of [`run`](Stack::run).
+    ///
+    /// When the stack doesn't have async support enabled, the program is run
+    /// in a blocking fashion.
+    pub(crate) async fn run_async(
+        &mut self,
+        input: &[u8],
+        args: &[&str],
+    ) -> std::result::Result<RunResult, WasiRuntimeError> {
+        if !self.stack_pre.async_support() {
+            return self.run(input, args);
+        }
+
+        self.fuel_consumed = None;
+        self.peak_memory = None;
+        self.interrupted = false;
+        let (ctx, output_pipes) = self.build_context(input, args)?;
+
+        let mut store = self.stack_pre.build_store(ctx)?;
+        let instance = self.stack_pre.rehydrate_async(&mut store).await?;
+        let start_fn = instance
+            .get_typed_func::<(), ()>(&mut store, "_start")
+            .map_err(WasiRuntimeError::WasmMissingStartFn)?;
+        let evaluation_result = start_fn.call_async(&mut store, ()).await;
+        self.fuel_consumed = self.stack_pre.fuel_consumed(&store);
+        self.peak_memory = Some(store.data().limiter.peak_memory());
+
+        // Dropping the store, this is no longer needed, plus it's keeping
+        // references to the WritePipe(s) that we need exclusive access to.
+        drop(store);
+
+        let run_result = output_pipes.into_run_result(evaluation_result);
+        self.interrupted = run_result
+            .as_ref()
+            .is_err_and(WasiRuntimeError::is_interruption);
+        run_result
+    }
+
+    /// Build the `Context` of the WASI program, together with the pipes used to
+    /// collect its output
+    fn build_context(
+        &self,
+        input: &[u8],
+        args: &[&str],
+    ) -> std::result::Result<(Context, OutputPipes), WasiRuntimeError> {
         let stdout_pipe = WritePipe::new_in_memory();
         let stderr_pipe = WritePipe::new_in_memory();
         let stdin_pipe: Arc<RwLock<WasiPipe>> = Arc::new(RwLock::new(WasiPipe::new(input)));
@@ -59,25 +174,43 @@ impl Stack {
             wasi_ctx,
             stdin_pipe,
             eval_ctx: self.eval_ctx.clone(),
+            host_callback_recorder: self.host_callback_recorder.clone(),
+            cancellation: self.cancellation.clone(),
+            limiter: ResourceLimiter::new(self.stack_pre.resource_limits()),
         };
 
-        let mut store = self.stack_pre.build_store(ctx);
-        let instance = self.stack_pre.rehydrate(&mut store)?;
-        let start_fn = instance
-            .get_typed_func::<(), ()>(&mut store, "_start")
-            .map_err(WasiRuntimeError::WasmMissingStartFn)?;
-        let evaluation_result = start_fn.call(&mut store, ());
+        Ok((
+            ctx,
+            OutputPipes {
+                stdout: stdout_pipe,
+                stderr: stderr_pipe,
+            },
+        ))
+    }
+}
 
-        // Dropping the store, this is no longer needed, plus it's keeping
-        // references to the WritePipe(s) that we need exclusive access to.
-        drop(store);
+/// The pipes used to collect the output of a WASI program
+struct OutputPipes {
+    stdout: WritePipe<Cursor<Vec<u8>>>,
+    stderr: WritePipe<Cursor<Vec<u8>>>,
+}
 
-        let stderr = pipe_to_string("stderr", stderr_pipe)?.trim().to_string();
+impl OutputPipes {
+    /// Build the `RunResult` of the WASI program. This must be invoked once the
+    /// `wasmtime::Store` used by the program has been dropped.
+    fn into_run_result(
+        self,
+        evaluation_result: wasmtime::Result<()>,
+    ) -> std::result::Result<RunResult, WasiRuntimeError> {
+        let stderr = pipe_to_string("stderr", self.stderr)?.trim().to_string();
 
         if let Err(err) = evaluation_result {
+            if let Some(interruption) = interruption_error(&err) {
+                return Err(interruption);
+            }
             if let Some(exit_error) = err.downcast_ref::<wasi_common::I32Exit>() {
                 if exit_error.0 == EXIT_SUCCESS {
-                    let stdout = pipe_to_string("stdout", stdout_pipe)?;
+                    let stdout = pipe_to_string("stdout", self.stdout)?;
                     return Ok(RunResult { stdout, stderr });
                 } else {
                     debug!(
@@ -93,11 +226,29 @@ impl Stack {
             return Err(WasiRuntimeError::WasiEvaluation { stderr, error: err });
         }
 
-        let stdout = pipe_to_string("stdout", stdout_pipe)?;
+        let stdout = pipe_to_string("stdout", self.stdout)?;
         Ok(RunResult { stdout, stderr })
     }
 }
 
+/// Returns the error to be reported when the WASI program has been
+/// interrupted by the host
+fn interruption_error(err: &wasmtime::Error) -> Option<WasiRuntimeError> {
+    if let Some(MemoryLimitExceeded { desired, limit }) = err.downcast_ref::<MemoryLimitExceeded>()
+    {
+        return Some(WasiRuntimeError::MemoryLimitExceeded {
+            desired: *desired,
+            limit: *limit,
+        });
+    }
+
+    match err.downcast_ref::<wasmtime::Trap>() {
+        Some(wasmtime::Trap::Interrupt) => Some(WasiRuntimeError::ExecutionDeadlineExceeded),
+        Some(wasmtime::Trap::OutOfFuel) => Some(WasiRuntimeError::FuelExhausted),
+        _ => None,
+    }
+}
+
 fn pipe_to_string(
     name: &str,
     pipe: WritePipe<Cursor<Vec<u8>>>,
@@ -116,3 +267,130 @@ fn pipe_to_string(
         }),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy_evaluator_builder::{FuelLimits, ResourceLimits};
+
+    const ENDLESS_LOOP_WAT: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (func (export "_start")
+            (loop $endless
+              br $endless)))
+    "#;
+
+    const NOOP_WAT: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (func (export "_start")))
+    "#;
+
+    const MEMORY_GROW_WAT: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (func (export "_start")
+            (drop (memory.grow (i32.const 10)))))
+    "#;
+
+    /// Size of a WebAssembly memory page
+    const WASM_PAGE_SIZE: usize = 64 * 1024;
+
+    fn build_stack(
+        wat: &str,
+        fuel_limits: Option<FuelLimits>,
+        resource_limits: ResourceLimits,
+    ) -> Stack {
+        let mut config = wasmtime::Config::default();
+        config.consume_fuel(fuel_limits.is_some());
+        let engine = wasmtime::Engine::new(&config).expect("cannot create wasmtime engine");
+        let module = wasmtime::Module::new(&engine, wat).expect("cannot compile WAT to wasm");
+
+        let stack_pre = StackPre::new(engine, module, None, fuel_limits, resource_limits, false)
+            .expect("cannot create StackPre");
+
+        let eval_ctx = EvaluationContext {
+            policy_id: "test".to_string(),
+            callback_channel: None,
+            ctx_aware_resources_allow_list: Default::default(),
+            policy_mode: Default::default(),
+            clock: Default::default(),
+            protected_paths: Default::default(),
+            array_keys: Default::default(),
+        };
+        Stack::new_from_pre(
+            &stack_pre,
+            &eval_ctx,
+            Default::default(),
+            CancellationHandle::new(),
+        )
+    }
+
+    fn fuel_limits(fuel: u64) -> Option<FuelLimits> {
+        Some(FuelLimits {
+            init: fuel,
+            func: fuel,
+        })
+    }
+
+    #[test]
+    fn fuel_exhausted() {
+        let mut stack = build_stack(
+            ENDLESS_LOOP_WAT,
+            fuel_limits(10_000),
+            ResourceLimits::default(),
+        );
+
+        // If fuel consumption doesn't work, this unit test will never complete
+        let result = stack.run(b"", &["policy.wasm"]);
+
+        assert!(matches!(result, Err(WasiRuntimeError::FuelExhausted)));
+        assert_eq!(Some(10_000), stack.fuel_consumed());
+    }
+
+    #[test]
+    fn fuel_consumed() {
+        let mut stack = build_stack(NOOP_WAT, fuel_limits(10_000), ResourceLimits::default());
+
+        stack
+            .run(b"", &["policy.wasm"])
+            .expect("the program should not fail");
+
+        let fuel_consumed = stack.fuel_consumed().expect("fuel consumed should be set");
+        assert!(fuel_consumed < 10_000);
+    }
+
+    #[test]
+    fn memory_limit_exceeded() {
+        let resource_limits = ResourceLimits {
+            memory_size: Some(2 * WASM_PAGE_SIZE),
+            ..Default::default()
+        };
+        let mut stack = build_stack(MEMORY_GROW_WAT, None, resource_limits);
+
+        let result = stack.run(b"", &["policy.wasm"]);
+
+        assert!(matches!(
+            result,
+            Err(WasiRuntimeError::MemoryLimitExceeded {
+                desired,
+                limit,
+            }) if desired == 11 * WASM_PAGE_SIZE && limit == 2 * WASM_PAGE_SIZE
+        ));
+    }
+
+    #[test]
+    fn memory_growth_within_limit() {
+        let resource_limits = ResourceLimits {
+            memory_size: Some(16 * WASM_PAGE_SIZE),
+            ..Default::default()
+        };
+        let mut stack = build_stack(MEMORY_GROW_WAT, None, resource_limits);
+
+        stack
+            .run(b"", &["policy.wasm"])
+            .expect("the program should not fail");
+        assert_eq!(Some(11 * WASM_PAGE_SIZE), stack.peak_memory());
+    }
+}
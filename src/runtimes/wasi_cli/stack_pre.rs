@@ -1,11 +1,15 @@
 use std::io::Write;
+use std::sync::RwLock;
 
-use wasmtime::{AsContext, Engine, InstancePre, Linker, Memory, Module, StoreContext};
+use wasmtime::{AsContext, Caller, Engine, InstancePre, Linker, Memory, Module, StoreContext};
 
 use crate::runtimes::wasi_cli::errors::{Result, WasiRuntimeError};
 
-use crate::policy_evaluator_builder::EpochDeadlines;
-use crate::runtimes::{callback::host_callback, wasi_cli::stack::Context};
+use crate::policy_evaluator_builder::{EpochDeadlines, FuelLimits, ResourceLimits};
+use crate::runtimes::{
+    callback::{host_callback, host_callback_async},
+    wasi_cli::{stack::Context, wasi_pipe::WasiPipe},
+};
 
 /// Reduce the allocation time of a Wasi Stack. This is done by leveraging `wasmtime::InstancePre`.
 #[derive(Clone)]
@@ -13,18 +17,30 @@ pub(crate) struct StackPre {
     engine: Engine,
     instance_pre: InstancePre<Context>,
     epoch_deadlines: Option<EpochDeadlines>,
+    fuel_limits: Option<FuelLimits>,
+    resource_limits: ResourceLimits,
+    async_support: bool,
 }
 
 impl StackPre {
+    /// Create a new `StackPre`. When `async_support` is set, the `wasmtime::Engine` must
+    /// have been created with [async support](wasmtime::Config::async_support) enabled
     pub(crate) fn new(
         engine: Engine,
         module: Module,
         epoch_deadlines: Option<EpochDeadlines>,
+        fuel_limits: Option<FuelLimits>,
+        resource_limits: ResourceLimits,
+        async_support: bool,
     ) -> Result<Self> {
         let mut linker = Linker::<Context>::new(&engine);
         wasi_common::sync::add_to_linker(&mut linker, |c: &mut Context| &mut c.wasi_ctx)
             .map_err(WasiRuntimeError::WasmLinkerError)?;
-        add_host_call_to_linker(&mut linker)?;
+        if async_support {
+            add_async_host_call_to_linker(&mut linker)?;
+        } else {
+            add_host_call_to_linker(&mut linker)?;
+        }
 
         let instance_pre = linker
             .instantiate_pre(&module)
@@ -33,17 +49,51 @@ impl StackPre {
             engine,
             instance_pre,
             epoch_deadlines,
+            fuel_limits,
+            resource_limits,
+            async_support,
         })
     }
 
-    /// Create a brand new `wasmtime::Store` to be used during an evaluation
-    pub(crate) fn build_store(&self, ctx: Context) -> wasmtime::Store<Context> {
+    /// Returns true when the stack has been created to run on top of an
+    /// asynchronous `wasmtime::Engine`
+    pub(crate) fn async_support(&self) -> bool {
+        self.async_support
+    }
+
+    /// The limits enforced on the resources allocated by the WASI program
+    pub(crate) fn resource_limits(&self) -> ResourceLimits {
+        self.resource_limits
+    }
+
+    /// Create a brand new `wasmtime::Store` to be used during an evaluation. When epoch
+    /// interruptions are enabled, the program is interrupted also when the evaluation
+    /// is cancelled
+    pub(crate) fn build_store(&self, ctx: Context) -> Result<wasmtime::Store<Context>> {
+        let cancellation = ctx.cancellation.clone();
         let mut store = wasmtime::Store::new(&self.engine, ctx);
+        store.limiter(|ctx| &mut ctx.limiter);
         if let Some(deadline) = self.epoch_deadlines {
-            store.set_epoch_deadline(deadline.wapc_func);
+            store.set_epoch_deadline(1);
+            store.epoch_deadline_callback(cancellation.epoch_deadline_callback(deadline.wapc_func));
+        }
+        if let Some(fuel_limits) = self.fuel_limits {
+            store
+                .set_fuel(fuel_limits.func)
+                .map_err(WasiRuntimeError::WasmSetFuel)?;
         }
 
+        Ok(store)
+    }
+
+    /// Returns the amount of fuel consumed by the program that ran inside of the
+    /// given `wasmtime::Store`. This is `None` when fuel metering is not enabled
+    pub(crate) fn fuel_consumed(&self, store: &wasmtime::Store<Context>) -> Option<u64> {
+        let fuel_limits = self.fuel_limits?;
         store
+            .get_fuel()
+            .ok()
+            .map(|remaining| fuel_limits.func.saturating_sub(remaining))
     }
 
     /// Allocate a new `wasmtime::Instance` that is bound to the given `wasmtime::Store`.
@@ -57,6 +107,30 @@ impl StackPre {
             .instantiate(store)
             .map_err(WasiRuntimeError::WasmInstantiate)
     }
+
+    /// Asynchronous version of [`rehydrate`](StackPre::rehydrate), must be used when
+    /// the stack has async support enabled
+    pub(crate) async fn rehydrate_async(
+        &self,
+        store: &mut wasmtime::Store<Context>,
+    ) -> Result<wasmtime::Instance> {
+        self.instance_pre
+            .instantiate_async(store)
+            .await
+            .map_err(WasiRuntimeError::WasmInstantiate)
+    }
+}
+
+/// The parameters of the `host.call` function: the pointer and the length
+/// of the binding, namespace, operation and payload
+type HostCallParams = (i32, i32, i32, i32, i32, i32, i32, i32);
+
+/// A `host.call` invocation, with its arguments read from the guest memory
+struct HostCall {
+    binding: String,
+    namespace: String,
+    operation: String,
+    payload: Vec<u8>,
 }
 
 fn add_host_call_to_linker(linker: &mut wasmtime::Linker<Context>) -> Result<()> {
@@ -73,41 +147,60 @@ fn add_host_call_to_linker(linker: &mut wasmtime::Linker<Context>) -> Result<()>
              op_len: i32,
              ptr: i32,
              len: i32| {
-                let memory_export = caller
-                    .get_export("memory")
-                    .ok_or_else(|| WasiRuntimeError::WasiMemExport)?;
-                let memory = memory_export
-                    .into_memory()
-                    .ok_or_else(|| WasiRuntimeError::WasiMemExportCannotConvert)?;
-
-                let stdin = caller.data().stdin_pipe.as_ref();
-
-                let vec = get_vec_from_memory(caller.as_context(), memory, ptr, len);
-                let bd_vec = get_vec_from_memory(caller.as_context(), memory, bd_ptr, bd_len);
-                let bd = std::str::from_utf8(&bd_vec).map_err(WasiRuntimeError::WasiMemOpToUtF8)?;
-                let ns_vec = get_vec_from_memory(caller.as_context(), memory, ns_ptr, ns_len);
-                let ns = std::str::from_utf8(&ns_vec).map_err(WasiRuntimeError::WasiMemOpToUtF8)?;
-                let op_vec = get_vec_from_memory(caller.as_context(), memory, op_ptr, op_len);
-                let op = std::str::from_utf8(&op_vec).map_err(WasiRuntimeError::WasiMemOpToUtF8)?;
-
-                let host_callback_response =
-                    host_callback(bd, ns, op, &vec, &caller.data().eval_ctx);
-
-                // return 1 if the host callback failed, 0 otherwise
-                let func_return_value = host_callback_response.is_err() as i32;
-
-                let response_msg = match host_callback_response {
-                    Ok(r) => r,
-                    Err(e) => e.to_string().as_bytes().to_owned(),
-                };
-
-                let mut stdin_pipe = stdin
-                    .write()
-                    .map_err(|_| WasiRuntimeError::WasiWriteAccessStdin())?;
-                let _ = stdin_pipe
-                    .write(&response_msg)
-                    .map_err(|_| WasiRuntimeError::WasiCannotWriteStdin())?;
-                Ok(func_return_value)
+                let call = read_host_call(
+                    &mut caller,
+                    (bd_ptr, bd_len, ns_ptr, ns_len, op_ptr, op_len, ptr, len),
+                )?;
+
+                let host_callback_response = host_callback(
+                    &call.binding,
+                    &call.namespace,
+                    &call.operation,
+                    &call.payload,
+                    &caller.data().eval_ctx,
+                    &caller.data().host_callback_recorder,
+                    &caller.data().cancellation,
+                );
+
+                Ok(write_host_call_response(
+                    &caller.data().stdin_pipe,
+                    host_callback_response,
+                )?)
+            },
+        )
+        .map_err(|e| WasiRuntimeError::WasmHostFuncDefinitionError {
+            name: "host.call".to_string(),
+            error: e.to_string(),
+        })?;
+    Ok(())
+}
+
+fn add_async_host_call_to_linker(linker: &mut wasmtime::Linker<Context>) -> Result<()> {
+    linker
+        .func_wrap_async(
+            "host",
+            "call",
+            |mut caller: wasmtime::Caller<'_, Context>, params: HostCallParams| {
+                Box::new(async move {
+                    let call = read_host_call(&mut caller, params)?;
+                    let eval_ctx = caller.data().eval_ctx.clone();
+                    let recorder = caller.data().host_callback_recorder.clone();
+                    let cancellation = caller.data().cancellation.clone();
+
+                    let host_callback_response = host_callback_async(
+                        &call.binding,
+                        &call.namespace,
+                        &call.operation,
+                        &call.payload,
+                        &eval_ctx,
+                        &recorder,
+                        &cancellation,
+                    )
+                    .await;
+
+                    write_host_call_response(&caller.data().stdin_pipe, host_callback_response)
+                        .map_err(wasmtime::Error::from)
+                })
             },
         )
         .map_err(|e| WasiRuntimeError::WasmHostFuncDefinitionError {
@@ -117,6 +210,59 @@ fn add_host_call_to_linker(linker: &mut wasmtime::Linker<Context>) -> Result<()>
     Ok(())
 }
 
+/// Read the arguments of a `host.call` invocation from the guest memory
+fn read_host_call(
+    caller: &mut Caller<'_, Context>,
+    (bd_ptr, bd_len, ns_ptr, ns_len, op_ptr, op_len, ptr, len): HostCallParams,
+) -> Result<HostCall> {
+    let memory_export = caller
+        .get_export("memory")
+        .ok_or_else(|| WasiRuntimeError::WasiMemExport)?;
+    let memory = memory_export
+        .into_memory()
+        .ok_or_else(|| WasiRuntimeError::WasiMemExportCannotConvert)?;
+
+    let payload = get_vec_from_memory(caller.as_context(), memory, ptr, len);
+    let bd_vec = get_vec_from_memory(caller.as_context(), memory, bd_ptr, bd_len);
+    let binding = std::str::from_utf8(&bd_vec).map_err(WasiRuntimeError::WasiMemOpToUtF8)?;
+    let ns_vec = get_vec_from_memory(caller.as_context(), memory, ns_ptr, ns_len);
+    let namespace = std::str::from_utf8(&ns_vec).map_err(WasiRuntimeError::WasiMemOpToUtF8)?;
+    let op_vec = get_vec_from_memory(caller.as_context(), memory, op_ptr, op_len);
+    let operation = std::str::from_utf8(&op_vec).map_err(WasiRuntimeError::WasiMemOpToUtF8)?;
+
+    Ok(HostCall {
+        binding: binding.to_owned(),
+        namespace: namespace.to_owned(),
+        operation: operation.to_owned(),
+        payload,
+    })
+}
+
+/// Write the outcome of the host callback to the STDIN of the guest.
+///
+/// Returns the value to be given back to the guest: 1 if the host callback
+/// failed, 0 otherwise
+fn write_host_call_response(
+    stdin: &RwLock<WasiPipe>,
+    host_callback_response: std::result::Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>>,
+) -> Result<i32> {
+    // return 1 if the host callback failed, 0 otherwise
+    let func_return_value = host_callback_response.is_err() as i32;
+
+    let response_msg = match host_callback_response {
+        Ok(r) => r,
+        Err(e) => e.to_string().as_bytes().to_owned(),
+    };
+
+    let mut stdin_pipe = stdin
+        .write()
+        .map_err(|_| WasiRuntimeError::WasiWriteAccessStdin())?;
+    let _ = stdin_pipe
+        .write(&response_msg)
+        .map_err(|_| WasiRuntimeError::WasiCannotWriteStdin())?;
+    Ok(func_return_value)
+}
+
 fn get_vec_from_memory<'a, T: 'a>(
     store: impl Into<StoreContext<'a, T>>,
     mem: Memory,
@@ -0,0 +1,78 @@
+//! Policies and helpers shared by the unit tests
+
+use crate::policy_evaluator::policy_evaluator_builder::PolicyEvaluatorBuilder;
+use crate::policy_evaluator::{PolicyEvaluatorPre, PolicyExecutionMode};
+
+/// A minimal WASI program, encoded in binary form: it only exports `_start`,
+/// which doesn't write anything to stdout
+pub(crate) const WASI_START_ONLY: &[u8] = &[
+    0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // magic number and version
+    0x01, 0x04, 0x01, 0x60, 0x00, 0x00, // type section: () -> ()
+    0x03, 0x02, 0x01, 0x00, // function section
+    0x07, 0x0a, 0x01, 0x06, b'_', b's', b't', b'a', b'r', b't', 0x00, 0x00, // exports
+    0x0a, 0x04, 0x01, 0x02, 0x00, 0x0b, // code section
+];
+
+/// A builder of the waPC policy stuck into an endless loop, using the given engine
+pub(crate) fn wapc_endless_loop_builder(engine: wasmtime::Engine) -> PolicyEvaluatorBuilder {
+    let wat = include_bytes!("../tests/data/endless_wasm/wapc_endless_loop.wat");
+    let module = wasmtime::Module::new(&engine, wat).expect("cannot compile WAT to wasm");
+
+    PolicyEvaluatorBuilder::new()
+        .execution_mode(PolicyExecutionMode::KubewardenWapc)
+        .policy_module(module)
+        .engine(engine)
+}
+
+/// The waPC policy stuck into an endless loop, built with the default engine
+pub(crate) fn wapc_endless_loop_pre() -> PolicyEvaluatorPre {
+    wapc_endless_loop_builder(wasmtime::Engine::default())
+        .build_pre()
+        .expect("cannot build PolicyEvaluatorPre")
+}
+
+/// A WASI program, in WAT format, writing the given output to stdout
+/// regardless of its arguments and of its input
+pub(crate) fn wasi_printing(output: &str) -> String {
+    let escaped = output.replace('"', "\\\"");
+    format!(
+        r#"
+        (module
+          (import "wasi_snapshot_preview1" "fd_write"
+            (func $fd_write (param i32 i32 i32 i32) (result i32)))
+          (memory (export "memory") 1)
+          (data (i32.const 16) "{escaped}")
+          (func (export "_start")
+            (i32.store (i32.const 0) (i32.const 16))
+            (i32.store (i32.const 4) (i32.const {len}))
+            (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))))
+        "#,
+        len = output.len()
+    )
+}
+
+/// Append a custom section to the given binary module
+pub(crate) fn with_custom_section(module: &[u8], name: &str, data: &[u8]) -> Vec<u8> {
+    let mut payload = leb128(name.len());
+    payload.extend_from_slice(name.as_bytes());
+    payload.extend_from_slice(data);
+
+    let mut module = module.to_vec();
+    module.push(0x00); // custom section id
+    module.extend(leb128(payload.len()));
+    module.extend(payload);
+    module
+}
+
+fn leb128(mut value: usize) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            encoded.push(byte);
+            return encoded;
+        }
+        encoded.push(byte | 0x80);
+    }
+}
@@ -28,6 +28,29 @@ pub(crate) fn build_policy_evaluator(
         .enable_wasmtime_cache()
         .enable_epoch_interruptions(1, 2);
 
+    rehydrate_policy_evaluator(policy_evaluator_builder, eval_ctx)
+}
+
+pub(crate) fn build_async_policy_evaluator(
+    execution_mode: PolicyExecutionMode,
+    policy: &Policy,
+    eval_ctx: &EvaluationContext,
+) -> PolicyEvaluator {
+    let policy_evaluator_builder = PolicyEvaluatorBuilder::new()
+        .execution_mode(execution_mode)
+        .policy_file(&policy.local_path)
+        .expect("cannot read policy file")
+        .enable_wasmtime_cache()
+        .enable_epoch_interruptions(1, 2)
+        .enable_async_support();
+
+    rehydrate_policy_evaluator(policy_evaluator_builder, eval_ctx)
+}
+
+fn rehydrate_policy_evaluator(
+    policy_evaluator_builder: PolicyEvaluatorBuilder,
+    eval_ctx: &EvaluationContext,
+) -> PolicyEvaluator {
     let policy_evaluator_pre = policy_evaluator_builder
         .build_pre()
         .expect("cannot build policy evaluator pre");
@@ -25,13 +25,18 @@ use policy_evaluator::{
     admission_response::AdmissionResponseStatus,
     callback_handler::CallbackHandlerBuilder,
     callback_requests::{CallbackRequest, CallbackRequestType, CallbackResponse},
-    evaluation_context::EvaluationContext,
+    constants::{
+        KUBEWARDEN_AUDIT_ANNOTATION_MONITOR_MESSAGE, KUBEWARDEN_AUDIT_ANNOTATION_MONITOR_PATCH,
+    },
+    evaluation_context::{EvaluationContext, PolicyMode},
     policy_evaluator::PolicySettings,
     policy_evaluator::{PolicyExecutionMode, ValidateRequest},
     policy_metadata::ContextAwareResource,
 };
 
-use crate::common::{build_policy_evaluator, fetch_policy, load_request_data};
+use crate::common::{
+    build_async_policy_evaluator, build_policy_evaluator, fetch_policy, load_request_data,
+};
 use crate::k8s_mock::{rego_scenario, wapc_and_wasi_scenario};
 
 async fn setup_callback_handler(
@@ -199,6 +204,10 @@ async fn test_policy_evaluator(
         policy_id: "test".to_owned(),
         callback_channel: None,
         ctx_aware_resources_allow_list: Default::default(),
+        policy_mode: Default::default(),
+        clock: Default::default(),
+        protected_paths: Default::default(),
+        array_keys: Default::default(),
     };
 
     let mut policy_evaluator = build_policy_evaluator(execution_mode, &policy, &eval_ctx);
@@ -244,6 +253,89 @@ async fn test_policy_evaluator(
     }
 }
 
+#[rstest]
+#[case::wapc(
+    PolicyExecutionMode::KubewardenWapc,
+    "ghcr.io/kubewarden/tests/pod-privileged:v0.2.1",
+    json!({}),
+    "pod_with_privileged_containers.json",
+    Some("Privileged container is not allowed"),
+    false
+)]
+#[case::gatekeeper(
+    PolicyExecutionMode::OpaGatekeeper,
+    "ghcr.io/kubewarden/tests/disallow-service-loadbalancer:v0.1.5",
+    json!({}),
+    "service_loadbalancer.json",
+    Some("Service of type LoadBalancer are not allowed"),
+    false
+)]
+#[case::wasi_mutating(
+    PolicyExecutionMode::Wasi,
+    "ghcr.io/kubewarden/tests/go-wasi-template:v0.1.0",
+    json!({
+        "requiredAnnotations": {
+            "fluxcd.io/cat": "felix"
+        }
+    }),
+    "service_clusterip.json",
+    None,
+    true
+)]
+#[tokio::test]
+async fn test_policy_evaluator_monitor_mode(
+    #[case] execution_mode: PolicyExecutionMode,
+    #[case] policy_uri: &str,
+    #[case] settings: serde_json::Value,
+    #[case] request_file_path: &str,
+    #[case] rejection_message: Option<&str>,
+    #[case] mutating: bool,
+) {
+    let tempdir = tempfile::TempDir::new().expect("cannot create tempdir");
+    let policy = fetch_policy(policy_uri, tempdir).await;
+
+    let eval_ctx = EvaluationContext {
+        policy_id: "test".to_owned(),
+        callback_channel: None,
+        ctx_aware_resources_allow_list: Default::default(),
+        policy_mode: PolicyMode::Monitor,
+        clock: Default::default(),
+        protected_paths: Default::default(),
+        array_keys: Default::default(),
+    };
+
+    let mut policy_evaluator = build_policy_evaluator(execution_mode, &policy, &eval_ctx);
+
+    let request_data = load_request_data(request_file_path);
+    let admission_request: AdmissionRequest =
+        serde_json::from_slice(&request_data).expect("cannot deserialize admission request");
+
+    let serde_json::Value::Object(settings) = settings else {
+        panic!("settings must be an object")
+    };
+    let admission_response = policy_evaluator.validate(
+        ValidateRequest::AdmissionRequest(admission_request),
+        &settings,
+    );
+
+    assert!(admission_response.allowed);
+    assert!(admission_response.status.is_none());
+    assert!(admission_response.patch.is_none());
+    assert!(admission_response.patch_type.is_none());
+
+    let audit_annotations = admission_response.audit_annotations.unwrap_or_default();
+    assert_eq!(
+        rejection_message,
+        audit_annotations
+            .get(KUBEWARDEN_AUDIT_ANNOTATION_MONITOR_MESSAGE)
+            .map(String::as_str)
+    );
+    assert_eq!(
+        mutating,
+        audit_annotations.contains_key(KUBEWARDEN_AUDIT_ANNOTATION_MONITOR_PATCH)
+    );
+}
+
 #[test_log::test(rstest)]
 #[case::wasi(
     PolicyExecutionMode::Wasi,
@@ -308,6 +400,10 @@ async fn test_runtime_context_aware<F, Fut>(
                 kind: "Service".to_owned(),
             },
         ]),
+        policy_mode: Default::default(),
+        clock: Default::default(),
+        protected_paths: Default::default(),
+        array_keys: Default::default(),
     };
 
     let request_data = load_request_data(request_file_path);
@@ -329,6 +425,90 @@ async fn test_runtime_context_aware<F, Fut>(
         .expect("cannot send shutdown signal");
 }
 
+#[test_log::test(rstest)]
+#[case::wasi(
+    PolicyExecutionMode::Wasi,
+    "ghcr.io/kubewarden/tests/go-wasi-context-aware-test-policy:latest",
+    "app_deployment.json",
+    wapc_and_wasi_scenario
+)]
+#[case::wapc(
+    PolicyExecutionMode::KubewardenWapc,
+    "ghcr.io/kubewarden/tests/context-aware-test-policy:v0.1.0",
+    "app_deployment.json",
+    wapc_and_wasi_scenario
+)]
+#[case::opa(
+    PolicyExecutionMode::Opa,
+    "ghcr.io/kubewarden/tests/context-aware-test-opa-policy:v0.1.0",
+    "app_deployment.json",
+    rego_scenario
+)]
+#[tokio::test(flavor = "multi_thread")]
+async fn test_runtime_context_aware_async<F, Fut>(
+    #[case] execution_mode: PolicyExecutionMode,
+    #[case] policy_uri: &str,
+    #[case] request_file_path: &str,
+    #[case] scenario: F,
+) where
+    F: FnOnce(Handle<Request<Body>, Response<Body>>) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    use kube::client::Body;
+
+    let tempdir = tempfile::TempDir::new().expect("cannot create tempdir");
+    let policy = fetch_policy(policy_uri, tempdir).await;
+
+    let (mocksvc, handle) = tower_test::mock::pair::<Request<Body>, Response<Body>>();
+    let client = Client::new(mocksvc, "default");
+    scenario(handle).await;
+
+    let (callback_handler_shutdown_channel_tx, callback_handler_channel) =
+        setup_callback_handler(Some(client)).await;
+
+    let eval_ctx = EvaluationContext {
+        policy_id: "test".to_owned(),
+        callback_channel: Some(callback_handler_channel),
+        ctx_aware_resources_allow_list: BTreeSet::from([
+            ContextAwareResource {
+                api_version: "v1".to_owned(),
+                kind: "Namespace".to_owned(),
+            },
+            ContextAwareResource {
+                api_version: "apps/v1".to_owned(),
+                kind: "Deployment".to_owned(),
+            },
+            ContextAwareResource {
+                api_version: "v1".to_owned(),
+                kind: "Service".to_owned(),
+            },
+        ]),
+        policy_mode: Default::default(),
+        clock: Default::default(),
+        protected_paths: Default::default(),
+        array_keys: Default::default(),
+    };
+
+    let request_data = load_request_data(request_file_path);
+    let request: AdmissionRequest =
+        serde_json::from_slice(&request_data).expect("cannot deserialize request");
+
+    // no need to use spawn_blocking, the evaluation doesn't block the tokio runtime
+    let mut policy_evaluator = build_async_policy_evaluator(execution_mode, &policy, &eval_ctx);
+    let admission_response = policy_evaluator
+        .validate_async(
+            ValidateRequest::AdmissionRequest(request),
+            &PolicySettings::default(),
+        )
+        .await;
+
+    assert!(admission_response.allowed, "the admission request should have been accepted, it has been rejected with this details: {:?}", admission_response);
+
+    callback_handler_shutdown_channel_tx
+        .send(())
+        .expect("cannot send shutdown signal");
+}
+
 #[rstest]
 #[case::policy(
     "ghcr.io/kubewarden/tests/context-aware-test-policy:latest",
@@ -354,6 +534,10 @@ async fn test_oci_manifest_capability(
         policy_id: "test".to_owned(),
         callback_channel: Some(callback_handler_channel),
         ctx_aware_resources_allow_list: Default::default(),
+        policy_mode: Default::default(),
+        clock: Default::default(),
+        protected_paths: Default::default(),
+        array_keys: Default::default(),
     };
 
     let cb_channel: mpsc::Sender<CallbackRequest> = eval_ctx
@@ -443,6 +627,10 @@ async fn test_oci_manifest_and_config_capability(
         policy_id: "test".to_owned(),
         callback_channel: Some(callback_handler_channel),
         ctx_aware_resources_allow_list: Default::default(),
+        policy_mode: Default::default(),
+        clock: Default::default(),
+        protected_paths: Default::default(),
+        array_keys: Default::default(),
     };
 
     let cb_channel: mpsc::Sender<CallbackRequest> = eval_ctx
@@ -506,6 +694,10 @@ async fn test_oci_digest_capability() {
         policy_id: "test".to_owned(),
         callback_channel: Some(callback_handler_channel),
         ctx_aware_resources_allow_list: Default::default(),
+        policy_mode: Default::default(),
+        clock: Default::default(),
+        protected_paths: Default::default(),
+        array_keys: Default::default(),
     };
 
     let cb_channel: mpsc::Sender<CallbackRequest> = eval_ctx